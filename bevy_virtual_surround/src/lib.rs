@@ -0,0 +1,276 @@
+//! Binaural 3D audio for bevy: [`BinauralEmitter`]s are rendered around the
+//! [`BinauralListener`] with a [`BinauralScene`] of virtual-surround, played through `bevy_audio`.
+//! Add the [`VirtualSurroundPlugin`] after the `AudioPlugin` and `AssetPlugin`, e.g. after the
+//! `DefaultPlugins`.
+
+mod render;
+
+use crate::render::{BinauralStream, Command};
+use bevy::app::{App, Plugin, PostUpdate};
+use bevy::asset::Assets;
+use bevy::audio::{AddAudioSource, AudioPlayer};
+use bevy::ecs::prelude::*;
+use bevy::transform::components::{GlobalTransform, Transform};
+use bevy::transform::TransformSystem;
+use ringbuf::{Producer, RingBuffer};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use virtual_surround::hrir::KEMAR_HEAD_RADIUS_CM;
+use virtual_surround::hrtf::SphericalHead;
+use virtual_surround::{BinauralScene, ConvolutionStrategy, SourcePosition};
+
+pub use crate::render::{BinauralDecoder, BinauralOutput};
+
+/// changes the audio thread hasn't picked up yet, the ECS sends one per emitter per frame
+const COMMAND_QUEUE: usize = 4096;
+
+/// Renders every [`BinauralEmitter`] around the [`BinauralListener`] to the default output of
+/// `bevy_audio`
+pub struct VirtualSurroundPlugin {
+    scene: Mutex<Option<BinauralScene>>,
+}
+
+impl VirtualSurroundPlugin {
+    /// render with `scene`, e.g. with the HRTF of the player, or doppler enabled. Its sources
+    /// are left alone, the plugin adds one per emitter.
+    pub fn new(scene: BinauralScene) -> Self {
+        VirtualSurroundPlugin {
+            scene: Mutex::new(Some(scene)),
+        }
+    }
+}
+
+impl Default for VirtualSurroundPlugin {
+    /// a spherical head model of the size of KEMAR at 48khz, which works without any HRTF at hand
+    fn default() -> Self {
+        let head = SphericalHead::new(KEMAR_HEAD_RADIUS_CM, 48000);
+        VirtualSurroundPlugin::new(
+            BinauralScene::new(head, ConvolutionStrategy::default().factory())
+                .expect("Spherical head model of KEMAR can't fail"),
+        )
+    }
+}
+
+impl Plugin for VirtualSurroundPlugin {
+    fn build(&self, app: &mut App) {
+        let scene = self
+            .scene
+            .lock()
+            .unwrap()
+            .take()
+            .expect("VirtualSurroundPlugin can only be built once");
+        let sample_rate = scene.sample_rate();
+        let (commands, consumer) = RingBuffer::new(COMMAND_QUEUE).split();
+
+        app.add_audio_source::<BinauralOutput>();
+        let output = app
+            .world_mut()
+            .resource_mut::<Assets<BinauralOutput>>()
+            .add(BinauralOutput::new(BinauralStream::new(scene, consumer)));
+        app.world_mut().spawn(AudioPlayer(output));
+
+        app.insert_resource(BinauralAudio {
+            sample_rate,
+            commands,
+            pending: VecDeque::new(),
+        })
+        .add_systems(
+            PostUpdate,
+            sync_emitters.after(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+/// The ears of the player, emitters are heard from where they are relative to its
+/// [`GlobalTransform`]: -Z is in front, +Y above and +X to the right, in meters. Without one the
+/// listener is at the origin.
+#[derive(Component, Debug, Default, Copy, Clone)]
+#[require(Transform)]
+pub struct BinauralListener;
+
+/// A mono clip played at the [`GlobalTransform`] of its entity, at the
+/// [`sample_rate`](BinauralAudio::sample_rate) of the scene. It starts playing when it's added
+/// and stops when it's removed or its entity despawned, changing it restarts it.
+#[derive(Component, Debug, Clone)]
+#[require(Transform)]
+pub struct BinauralEmitter {
+    pub clip: Arc<[f32]>,
+    pub looping: bool,
+}
+
+impl BinauralEmitter {
+    /// play `clip` once
+    pub fn new(clip: impl Into<Arc<[f32]>>) -> Self {
+        BinauralEmitter {
+            clip: clip.into(),
+            looping: false,
+        }
+    }
+
+    /// play `clip` over and over
+    pub fn looping(clip: impl Into<Arc<[f32]>>) -> Self {
+        BinauralEmitter {
+            clip: clip.into(),
+            looping: true,
+        }
+    }
+}
+
+/// Queue to the audio thread of the [`VirtualSurroundPlugin`]
+#[derive(Resource)]
+pub struct BinauralAudio {
+    sample_rate: usize,
+    commands: Producer<Command>,
+    /// adds and removes the queue had no room for, sent first next time
+    pending: VecDeque<Command>,
+}
+
+impl BinauralAudio {
+    /// rate the clips of emitters have to be at
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    /// send what's pending, in order
+    fn flush(&mut self) {
+        while let Some(command) = self.pending.pop_front() {
+            if let Err(command) = self.commands.push(command) {
+                self.pending.push_front(command);
+                break;
+            }
+        }
+    }
+
+    fn send(&mut self, command: Command) {
+        self.flush();
+        let command = if self.pending.is_empty() {
+            match self.commands.push(command) {
+                Ok(()) => return,
+                Err(command) => command,
+            }
+        } else {
+            command
+        };
+
+        // a full queue means the audio thread is stuck, moves are sent again next frame but adds
+        // and removes wait until there's room
+        if !matches!(command, Command::Move(..)) {
+            self.pending.push_back(command);
+        }
+    }
+}
+
+/// Position of `emitter` as heard by `listener`, ignoring the scale of the listener
+pub fn relative_position(listener: &GlobalTransform, emitter: &GlobalTransform) -> SourcePosition {
+    let (_, rotation, translation) = listener.to_scale_rotation_translation();
+    let local = rotation.inverse() * (emitter.translation() - translation);
+    // the scene needs some distance, right at the ears is as loud as it gets anyway
+    let distance = local.length().max(f32::EPSILON);
+
+    SourcePosition::new(
+        (-local.x).atan2(-local.z).to_degrees(),
+        (local.y / distance).clamp(-1.0, 1.0).asin().to_degrees(),
+        distance,
+    )
+}
+
+fn sync_emitters(
+    mut audio: ResMut<BinauralAudio>,
+    listener: Query<&GlobalTransform, With<BinauralListener>>,
+    emitters: Query<(Entity, Ref<BinauralEmitter>, &GlobalTransform)>,
+    mut removed: RemovedComponents<BinauralEmitter>,
+) {
+    audio.flush();
+    for entity in removed.read() {
+        audio.send(Command::Remove(entity));
+    }
+
+    let listener = listener.single().copied().unwrap_or_default();
+    for (entity, emitter, transform) in &emitters {
+        let position = relative_position(&listener, transform);
+        if emitter.is_changed() {
+            audio.send(Command::Add {
+                entity,
+                clip: emitter.clip.clone(),
+                looping: emitter.looping,
+                position,
+            });
+        } else {
+            audio.send(Command::Move(entity, position));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{relative_position, BinauralAudio};
+    use crate::render::Command;
+    use bevy::ecs::entity::Entity;
+    use bevy::math::Quat;
+    use bevy::transform::components::{GlobalTransform, Transform};
+    use ringbuf::RingBuffer;
+    use std::collections::VecDeque;
+    use std::f32::consts::FRAC_PI_2;
+    use virtual_surround::SourcePosition;
+
+    #[test]
+    fn emitters_are_placed_around_the_listener() {
+        let at = |x, y, z| GlobalTransform::from(Transform::from_xyz(x, y, z));
+        let listener = GlobalTransform::IDENTITY;
+
+        let front = relative_position(&listener, &at(0.0, 0.0, -2.0));
+        assert!(front.azimuth.abs() < 1e-3 && front.elevation.abs() < 1e-3);
+        assert!((front.distance - 2.0).abs() < 1e-5);
+        assert!((relative_position(&listener, &at(-1.0, 0.0, 0.0)).azimuth - 90.0).abs() < 1e-3);
+        assert!((relative_position(&listener, &at(0.0, 3.0, 0.0)).elevation - 90.0).abs() < 1e-3);
+
+        // turned left, what was to the left is in front
+        let turned = GlobalTransform::from(
+            Transform::from_xyz(1.0, 0.0, 0.0).with_rotation(Quat::from_rotation_y(FRAC_PI_2)),
+        );
+        let ahead = relative_position(&turned, &at(-1.0, 0.0, 0.0));
+        assert!(ahead.azimuth.abs() < 1e-3, "{:?}", ahead);
+        assert!((ahead.distance - 2.0).abs() < 1e-5);
+        assert_eq!(
+            relative_position(&turned, &at(1.0, 0.0, 0.0)).distance,
+            f32::EPSILON
+        );
+    }
+
+    #[test]
+    fn adds_and_removes_wait_for_a_full_queue() {
+        let (commands, mut consumer) = RingBuffer::new(1).split();
+        let mut audio = BinauralAudio {
+            sample_rate: 48000,
+            commands,
+            pending: VecDeque::new(),
+        };
+        let entity = Entity::from_raw(1);
+        let position = SourcePosition::new(0.0, 0.0, 1.0);
+
+        audio.send(Command::Move(entity, position));
+        audio.send(Command::Add {
+            entity,
+            clip: vec![0f32; 4].into(),
+            looping: false,
+            position,
+        });
+        audio.send(Command::Move(entity, position));
+        audio.send(Command::Remove(entity));
+        assert_eq!(audio.pending.len(), 2);
+
+        // the moves are dropped, the add and remove arrive in order as the queue drains
+        let mut received = vec![];
+        for _ in 0..4 {
+            if let Some(command) = consumer.pop() {
+                received.push(command);
+            }
+            audio.flush();
+        }
+        assert!(matches!(
+            received[..],
+            [Command::Move(..), Command::Add { .. }, Command::Remove(..)]
+        ));
+        assert!(audio.pending.is_empty());
+    }
+}
@@ -0,0 +1,268 @@
+use bevy::asset::Asset;
+use bevy::audio::{Decodable, Source};
+use bevy::ecs::entity::Entity;
+use bevy::reflect::TypePath;
+use ringbuf::Consumer;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use virtual_surround::{BinauralScene, SourceHandle, SourcePosition, BLOCK_SIZE};
+
+/// changes to the emitters, sent from the ECS to the audio thread
+pub(crate) enum Command {
+    /// also replaces the source of an emitter which already plays
+    Add {
+        entity: Entity,
+        clip: Arc<[f32]>,
+        looping: bool,
+        position: SourcePosition,
+    },
+    Move(Entity, SourcePosition),
+    Remove(Entity),
+}
+
+struct Emitter {
+    entity: Entity,
+    handle: SourceHandle,
+    clip: Arc<[f32]>,
+    looping: bool,
+    /// next sample of the clip
+    cursor: usize,
+    block: Vec<f32>,
+}
+
+impl Emitter {
+    /// the next block of the clip, silence once it's over
+    fn fill(&mut self) {
+        for sample in &mut self.block {
+            if self.cursor >= self.clip.len() && self.looping {
+                self.cursor = 0;
+            }
+
+            *sample = self.clip.get(self.cursor).copied().unwrap_or(0.0);
+            self.cursor = (self.cursor + 1).min(self.clip.len());
+        }
+    }
+}
+
+/// The [`BinauralScene`] on the audio thread, rendering every emitter into interleaved stereo
+pub(crate) struct BinauralStream {
+    scene: BinauralScene,
+    commands: Consumer<Command>,
+    emitters: Vec<Emitter>,
+    left: Vec<f32>,
+    right: Vec<f32>,
+    /// next sample of the rendered block, interleaved
+    position: usize,
+}
+
+impl BinauralStream {
+    pub(crate) fn new(scene: BinauralScene, commands: Consumer<Command>) -> Self {
+        BinauralStream {
+            scene,
+            commands,
+            emitters: vec![],
+            left: vec![0f32; BLOCK_SIZE],
+            right: vec![0f32; BLOCK_SIZE],
+            position: BLOCK_SIZE * 2,
+        }
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if let Some(index) = self.emitters.iter().position(|x| x.entity == entity) {
+            let emitter = self.emitters.swap_remove(index);
+            let _ = self.scene.remove_source(emitter.handle);
+        }
+    }
+
+    /// Apply the queued commands. Adding an emitter builds a source of the scene, which
+    /// allocates, but so does the mixer of `bevy_audio` on this thread.
+    fn apply_commands(&mut self) {
+        while let Some(command) = self.commands.pop() {
+            match command {
+                Command::Add {
+                    entity,
+                    clip,
+                    looping,
+                    position,
+                } => {
+                    self.remove(entity);
+                    if let Ok(handle) = self.scene.add_source(position) {
+                        self.emitters.push(Emitter {
+                            entity,
+                            handle,
+                            clip,
+                            looping,
+                            cursor: 0,
+                            block: vec![0f32; BLOCK_SIZE],
+                        });
+                    }
+                }
+                Command::Move(entity, position) => {
+                    if let Some(emitter) = self.emitters.iter().find(|x| x.entity == entity) {
+                        // a position the scene rejects leaves the emitter where it was
+                        let _ = self.scene.set_position(
+                            emitter.handle,
+                            position.azimuth,
+                            position.elevation,
+                            position.distance,
+                        );
+                    }
+                }
+                Command::Remove(entity) => self.remove(entity),
+            }
+        }
+    }
+
+    fn render(&mut self) {
+        self.apply_commands();
+        for emitter in &mut self.emitters {
+            emitter.fill();
+        }
+
+        let emitters = &self.emitters;
+        let input = |handle| {
+            emitters
+                .iter()
+                .find(|x: &&Emitter| x.handle == handle)
+                .map(|x| &x.block[..])
+        };
+        if self
+            .scene
+            .render_with(input, &mut self.left, &mut self.right)
+            .is_err()
+        {
+            self.left.fill(0f32);
+            self.right.fill(0f32);
+        }
+    }
+}
+
+impl Iterator for BinauralStream {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position == BLOCK_SIZE * 2 {
+            self.render();
+            self.position = 0;
+        }
+
+        let sample = [&self.left, &self.right][self.position % 2][self.position / 2];
+        self.position += 1;
+
+        Some(sample)
+    }
+}
+
+/// The output of the [`VirtualSurroundPlugin`](crate::VirtualSurroundPlugin), played by an
+/// `AudioPlayer` it spawns. It renders once, playing it again stays silent.
+#[derive(Asset, TypePath)]
+pub struct BinauralOutput {
+    stream: Mutex<Option<BinauralStream>>,
+}
+
+impl BinauralOutput {
+    pub(crate) fn new(stream: BinauralStream) -> Self {
+        BinauralOutput {
+            stream: Mutex::new(Some(stream)),
+        }
+    }
+}
+
+/// Stream of a [`BinauralOutput`] for `bevy_audio`, or nothing once it was played
+pub struct BinauralDecoder(Option<BinauralStream>);
+
+impl Iterator for BinauralDecoder {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.0.as_mut()?.next()
+    }
+}
+
+impl Source for BinauralDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.0
+            .as_ref()
+            .map_or(48000, |x| x.scene.sample_rate() as u32)
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Decodable for BinauralOutput {
+    type DecoderItem = f32;
+    type Decoder = BinauralDecoder;
+
+    fn decoder(&self) -> BinauralDecoder {
+        BinauralDecoder(self.stream.lock().unwrap().take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BinauralStream, Command};
+    use bevy::ecs::entity::Entity;
+    use ringbuf::RingBuffer;
+    use virtual_surround::hrtf::SphericalHead;
+    use virtual_surround::{BinauralScene, ConvolutionStrategy, SourcePosition, BLOCK_SIZE};
+
+    #[test]
+    fn renders_emitters_until_removed() {
+        let scene = BinauralScene::new(
+            SphericalHead::new(8.75, 48000),
+            ConvolutionStrategy::default().factory(),
+        )
+        .unwrap();
+        let (mut commands, consumer) = RingBuffer::new(8).split();
+        let mut stream = BinauralStream::new(scene, consumer);
+        let block = |stream: &mut BinauralStream| {
+            (0..BLOCK_SIZE * 2)
+                .map(|_| stream.next().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let energy = |block: &[f32], ear: usize| {
+            block
+                .iter()
+                .skip(ear)
+                .step_by(2)
+                .map(|x| x * x)
+                .sum::<f32>()
+        };
+
+        assert!(block(&mut stream).iter().all(|x| *x == 0.0));
+
+        // a click on the left, looping every block
+        let entity = Entity::from_raw(1);
+        let mut clip = vec![0f32; BLOCK_SIZE];
+        clip[0] = 1.0;
+        let _ = commands.push(Command::Add {
+            entity,
+            clip: clip.into(),
+            looping: true,
+            position: SourcePosition::new(90.0, 0.0, 1.0),
+        });
+        for _ in 0..4 {
+            let output = block(&mut stream);
+            assert!(energy(&output, 0) > energy(&output, 1) * 2.0);
+        }
+
+        // moved to the right and then removed
+        let _ = commands.push(Command::Move(entity, SourcePosition::new(-90.0, 0.0, 1.0)));
+        block(&mut stream);
+        let output = block(&mut stream);
+        assert!(energy(&output, 1) > energy(&output, 0) * 2.0);
+
+        let _ = commands.push(Command::Remove(entity));
+        assert!(block(&mut stream).iter().all(|x| *x == 0.0));
+    }
+}
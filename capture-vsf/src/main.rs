@@ -0,0 +1,504 @@
+use anyhow::Context;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, Host, Sample, SampleFormat, SampleRate, Stream, SupportedStreamConfig};
+use ringbuf::{Consumer, Producer, RingBuffer};
+use std::env::args;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use virtual_surround::hrir::Hrir;
+use virtual_surround::{
+    capabilities, channel_from_name, get_channel_name, standard_layout, BlockAdapter,
+    DriftCompensator, Engine, FilterBuilder, MixingMatrix, RawStreamReader, Speaker,
+    VirtualSurroundFilter,
+};
+
+/// frames moved from the capture queue to the compensator at once
+const TRANSFER_FRAMES: usize = 1024;
+
+/// shared with the main thread, so it can report how the capture is doing
+#[derive(Default)]
+struct Status {
+    /// drift estimate of the compensator, as f64 bits
+    drift: AtomicU64,
+    underruns: AtomicUsize,
+    overruns: AtomicUsize,
+    /// captured buffers dropped because the queue to the playback thread was full
+    dropped: AtomicUsize,
+}
+
+/// where the audio to virtualize comes from
+enum Source {
+    Device(Device, SupportedStreamConfig),
+    /// a FIFO written by some other program, see `RawStreamHeader`
+    Fifo(PathBuf, RawStreamReader<File>),
+}
+
+struct Render {
+    consumer: Consumer<f32>,
+    /// the source fills the queue as fast as it's drained instead of running on its own clock
+    paced: bool,
+    compensator: DriftCompensator,
+    vsf: VirtualSurroundFilter,
+    adapter: BlockAdapter,
+    transfer: Vec<f32>,
+    capture: Vec<f32>,
+    stereo: Vec<f32>,
+    status: Arc<Status>,
+}
+
+/// channel order of a capture device with `channels` channels, like WAVEFORMATEXTENSIBLE orders
+/// them
+fn default_map(channels: usize) -> anyhow::Result<Vec<Speaker>> {
+    standard_layout(channels)
+        .map(<[Speaker]>::to_vec)
+        .with_context(|| {
+            format!(
+                "No default channel map for {} channels, pass one with --map",
+                channels
+            )
+        })
+}
+
+/// `FL,FR,FC,...` in device order, channels after the listed ones are ignored
+fn parse_map(map: &str, channels: usize) -> anyhow::Result<Vec<Speaker>> {
+    let mut masks = map
+        .split(',')
+        .map(|name| {
+            channel_from_name(name.trim()).with_context(|| format!("Unknown channel {}", name))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if masks.len() > channels {
+        anyhow::bail!(
+            "Channel map lists {} channels, the capture device has {}",
+            masks.len(),
+            channels
+        );
+    }
+
+    masks.resize(channels, Speaker::DirectOut);
+    Ok(masks)
+}
+
+fn find_device<I: Iterator<Item = Device>>(
+    devices: I,
+    name: Option<&str>,
+    default: Option<Device>,
+) -> anyhow::Result<Device> {
+    match name {
+        Some(name) => {
+            for device in devices {
+                if device.name().is_ok_and(|x| x.contains(name)) {
+                    return Ok(device);
+                }
+            }
+
+            anyhow::bail!("No audio device matching {}", name)
+        }
+        None => default.context("No default audio device"),
+    }
+}
+
+/// the capture config with `channels`, or the most channels, preferably at `rate` so the
+/// compensator only has to handle the drift
+fn capture_config(
+    device: &Device,
+    channels: Option<u16>,
+    rate: u32,
+) -> anyhow::Result<SupportedStreamConfig> {
+    let mut configs = device
+        .supported_input_configs()?
+        .filter(|x| channels.is_none_or(|channels| x.channels() == channels))
+        .collect::<Vec<_>>();
+    configs.sort_by_key(|x| std::cmp::Reverse(x.channels()));
+
+    let at_rate = configs
+        .iter()
+        .find(|x| x.min_sample_rate().0 <= rate && rate <= x.max_sample_rate().0);
+
+    match (at_rate, configs.first()) {
+        (Some(config), _) => Ok(config.clone().with_sample_rate(SampleRate(rate))),
+        (None, Some(config)) => Ok(config.clone().with_max_sample_rate()),
+        (None, None) => anyhow::bail!("Capture device has no config with {:?} channels", channels),
+    }
+}
+
+fn list_devices(host: &Host) -> anyhow::Result<()> {
+    for (kind, devices) in [
+        ("input", host.input_devices()?.collect::<Vec<_>>()),
+        ("output", host.output_devices()?.collect::<Vec<_>>()),
+    ] {
+        for device in devices {
+            let configs = match kind {
+                "input" => device
+                    .supported_input_configs()
+                    .map(|x| x.collect::<Vec<_>>()),
+                _ => device
+                    .supported_output_configs()
+                    .map(|x| x.collect::<Vec<_>>()),
+            };
+
+            println!("{} {}", kind, device.name()?);
+            for config in configs.unwrap_or_default() {
+                println!(
+                    "  {} channels {}-{}hz {:?}",
+                    config.channels(),
+                    config.min_sample_rate().0,
+                    config.max_sample_rate().0,
+                    config.sample_format()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = args();
+    let program = args.next().unwrap_or_else(|| "capture-vsf".to_string());
+
+    let mut engine = None;
+    let mut input = None;
+    let mut output = None;
+    let mut channels = None;
+    let mut map = None;
+    let mut fifo = None;
+    let mut latency_ms = 20.0f32;
+    let mut list = false;
+    let mut positional = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--engine" => engine = Some(args.next().context("--engine needs a value")?),
+            "--version" => {
+                println!("{}", capabilities());
+                return Ok(());
+            }
+            "--input" => input = Some(args.next().context("--input needs a value")?),
+            "--output" => output = Some(args.next().context("--output needs a value")?),
+            "--channels" => {
+                channels = Some(args.next().context("--channels needs a value")?.parse()?)
+            }
+            "--map" => map = Some(args.next().context("--map needs a value")?),
+            "--fifo" => fifo = Some(PathBuf::from(args.next().context("--fifo needs a value")?)),
+            "--latency" => latency_ms = args.next().context("--latency needs a value")?.parse()?,
+            "--list" => list = true,
+            _ => positional.push(arg),
+        }
+    }
+
+    let host = cpal::default_host();
+
+    if list {
+        return list_devices(&host);
+    }
+
+    if positional.is_empty() {
+        println!(
+            "usage: {} [--version] [--list] [--input <device>] [--output <device>] [--channels <n>] [--map FL,FR,..] [--latency <ms>] [--fifo <path>] [--engine <name>] <hrir file>",
+            program
+        );
+        return Ok(());
+    }
+
+    let output_device = find_device(
+        host.output_devices()?,
+        output.as_deref(),
+        host.default_output_device(),
+    )?;
+    let output_config = output_device.default_output_config()?;
+    let rate = output_config.sample_rate().0;
+
+    let source = match fifo {
+        Some(path) => {
+            println!("waiting for a writer on {}", path.display());
+            let reader = RawStreamReader::new(File::open(&path)?)?;
+            Source::Fifo(path, reader)
+        }
+        None => {
+            let device = find_device(
+                host.input_devices()?,
+                input.as_deref(),
+                host.default_input_device(),
+            )?;
+            let config = capture_config(&device, channels, rate)?;
+            Source::Device(device, config)
+        }
+    };
+
+    let (capture_channels, capture_rate, layout, name) = match &source {
+        Source::Device(device, config) => {
+            let channels = config.channels() as usize;
+            let layout = match &map {
+                Some(map) => parse_map(map, channels)?,
+                None => default_map(channels)?,
+            };
+
+            (channels, config.sample_rate().0, layout, device.name()?)
+        }
+        Source::Fifo(path, reader) => (
+            reader.channels(),
+            reader.header().sample_rate,
+            reader.header().layout.clone(),
+            path.display().to_string(),
+        ),
+    };
+
+    let mut builder = FilterBuilder::new()
+        .sample_rate(rate)
+        .diagnostics(|x| println!("warning: {}", x));
+    if let Some(engine) = engine {
+        builder = builder.engine(Engine::by_name(&engine)?.factory());
+    }
+
+    let hrir = Path::new(&positional[0]);
+    let mut vsf = if hrir.is_dir() {
+        builder.build_from_hrir(Hrir::from_dir(hrir)?)?
+    } else {
+        builder.build(File::open(hrir)?)?
+    };
+    let positions = vsf.positions().collect::<Vec<_>>();
+    let matrix = MixingMatrix::automatic(&layout, &positions);
+
+    println!(
+        "capturing {} channels at {}hz from {} ({})",
+        capture_channels,
+        capture_rate,
+        name,
+        layout
+            .iter()
+            .map(|x| get_channel_name(*x))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    if !matrix.dropped().is_empty() {
+        println!("ignoring input channels {:?}", matrix.dropped());
+    }
+
+    vsf.set_mixing_matrix(Some(matrix))?;
+
+    let latency = (latency_ms / 1000.0 * capture_rate as f32) as usize;
+    let queue = (latency * 4).max(TRANSFER_FRAMES) * capture_channels;
+    let (producer, consumer) = RingBuffer::<f32>::new(queue).split();
+    let status = Arc::new(Status::default());
+
+    let block_size = vsf.block_size();
+    let render = Render {
+        consumer,
+        paced: matches!(source, Source::Fifo(..)),
+        compensator: DriftCompensator::new(
+            capture_channels,
+            capture_rate as usize,
+            rate as usize,
+            latency,
+            TRANSFER_FRAMES,
+        )?,
+        adapter: BlockAdapter::new(vsf.input_channels(), block_size, block_size)?,
+        vsf,
+        transfer: vec![0f32; TRANSFER_FRAMES * capture_channels],
+        capture: vec![0f32; block_size * capture_channels],
+        stereo: vec![0f32; block_size * 2],
+        status: status.clone(),
+    };
+
+    println!(
+        "playing on {} at {}hz, {} ms of capture queue",
+        output_device.name()?,
+        rate,
+        latency_ms
+    );
+
+    let input_stream = match source {
+        Source::Device(device, config) => Some(match config.sample_format() {
+            SampleFormat::F32 => capture::<f32>(&device, &config, producer, &status)?,
+            SampleFormat::I16 => capture::<i16>(&device, &config, producer, &status)?,
+            SampleFormat::U16 => capture::<u16>(&device, &config, producer, &status)?,
+        }),
+        Source::Fifo(path, reader) => {
+            std::thread::spawn(move || {
+                if let Err(err) = read_fifo(path, reader, producer) {
+                    eprintln!("fifo error: {}", err);
+                }
+            });
+
+            None
+        }
+    };
+
+    let output_stream = match output_config.sample_format() {
+        SampleFormat::F32 => play::<f32>(&output_device, &output_config, render)?,
+        SampleFormat::I16 => play::<i16>(&output_device, &output_config, render)?,
+        SampleFormat::U16 => play::<u16>(&output_device, &output_config, render)?,
+    };
+
+    if let Some(stream) = &input_stream {
+        stream.play()?;
+    }
+    output_stream.play()?;
+
+    println!("press enter for drift statistics, q to quit");
+    let mut line = String::new();
+    while std::io::stdin().read_line(&mut line)? > 0 && line.trim() != "q" {
+        println!(
+            "drift {:.1} ppm, {} underruns, {} overruns, {} dropped capture buffers",
+            (f64::from_bits(status.drift.load(Ordering::Relaxed)) - 1.0) * 1e6,
+            status.underruns.load(Ordering::Relaxed),
+            status.overruns.load(Ordering::Relaxed),
+            status.dropped.load(Ordering::Relaxed)
+        );
+        line.clear();
+    }
+
+    Ok(())
+}
+
+fn capture<T: Sample>(
+    device: &Device,
+    config: &SupportedStreamConfig,
+    mut producer: Producer<f32>,
+    status: &Arc<Status>,
+) -> anyhow::Result<Stream> {
+    let status = status.clone();
+
+    Ok(device.build_input_stream(
+        &config.config(),
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            // only whole buffers, so the queue never holds part of a frame
+            if producer.remaining() < data.len() {
+                status.dropped.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+
+            producer.push_iter(&mut data.iter().map(|x| x.to_f32()));
+        },
+        |err| eprintln!("capture error: {}", err),
+    )?)
+}
+
+/// queue the frames of `reader`, and of every writer opening the FIFO after it with the same
+/// header
+fn read_fifo(
+    path: PathBuf,
+    mut reader: RawStreamReader<File>,
+    mut producer: Producer<f32>,
+) -> anyhow::Result<()> {
+    let header = reader.header().clone();
+    let mut frames = vec![0f32; TRANSFER_FRAMES * reader.channels()];
+
+    loop {
+        let read = reader.read_frames(&mut frames)? * reader.channels();
+
+        if read == 0 {
+            println!("stream ended, waiting for the next writer");
+
+            let next = RawStreamReader::new(File::open(&path)?);
+            match next {
+                Ok(next) if next.header() == &header => reader = next,
+                Ok(next) => eprintln!(
+                    "ignoring stream of {}hz {:?}, the filter is set up for {}hz {:?}",
+                    next.header().sample_rate,
+                    next.header().layout,
+                    header.sample_rate,
+                    header.layout
+                ),
+                Err(err) => eprintln!("ignoring stream: {}", err),
+            }
+
+            continue;
+        }
+
+        // only whole reads, so the queue never holds part of a frame
+        while producer.remaining() < read {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        producer.push_slice(&frames[..read]);
+    }
+}
+
+fn play<T: Sample>(
+    device: &Device,
+    config: &SupportedStreamConfig,
+    mut render: Render,
+) -> anyhow::Result<Stream> {
+    let channels = config.channels() as usize;
+
+    Ok(device.build_output_stream(
+        &config.config(),
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let frames = data.len() / channels;
+
+            if let Err(err) = render.process(frames) {
+                eprintln!("{}", err);
+                data.iter_mut().for_each(|x| *x = T::from(&0f32));
+                return;
+            }
+
+            // the binaural rendering goes to the first two channels of the output
+            for (frame, stereo) in data.chunks_exact_mut(channels).zip(render.stereo.chunks(2)) {
+                for (c, sample) in frame.iter_mut().enumerate() {
+                    *sample = T::from(stereo.get(c).unwrap_or(&0f32));
+                }
+            }
+        },
+        |err| eprintln!("playback error: {}", err),
+    )?)
+}
+
+impl Render {
+    /// render `frames` of stereo into `stereo`
+    fn process(&mut self, frames: usize) -> anyhow::Result<()> {
+        let channels = self.compensator.channels();
+
+        // a paced source only gets to fill the compensator up to its latency
+        let mut room = if self.paced {
+            self.compensator
+                .latency()
+                .saturating_sub(self.compensator.queued())
+                * channels
+        } else {
+            usize::MAX
+        };
+
+        while room > 0 {
+            let transfer = self.transfer.len().min(room);
+            let popped = self.consumer.pop_slice(&mut self.transfer[..transfer]);
+            if popped == 0 {
+                break;
+            }
+
+            self.compensator.push(&self.transfer[..popped]);
+            room -= popped.min(room);
+        }
+
+        if frames != self.adapter.buffer_size() {
+            self.adapter.set_buffer_size(frames)?;
+            self.capture.resize(frames * channels, 0f32);
+            self.stereo.resize(frames * 2, 0f32);
+        }
+
+        self.compensator.pull(&mut self.capture);
+
+        let vsf = &mut self.vsf;
+        self.adapter
+            .process(&self.capture, &mut self.stereo, |input, output| {
+                vsf.transform(input, output)
+            })?;
+
+        self.status
+            .drift
+            .store(self.compensator.drift().to_bits(), Ordering::Relaxed);
+        self.status
+            .underruns
+            .store(self.compensator.underruns(), Ordering::Relaxed);
+        self.status
+            .overruns
+            .store(self.compensator.overruns(), Ordering::Relaxed);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::Context;
+use std::env::args;
+use std::fs::File;
+use std::io::BufWriter;
+use virtual_surround::hrir::{EarLayout, Hrir, Normalization};
+use virtual_surround::hrtf::{HrtfSource, SofaHrtf, SpeakerDirection, SphericalHead};
+use virtual_surround::{channel_from_name, get_channel_name, standard_layout, Speaker};
+
+fn usage(name: &str) -> ! {
+    println!(
+        "usage: {} [--layout 5.1|7.1|FL,FR,..] [--speaker <name>:<azimuth>[:<elevation>]]... [--rate <hz>] [--normalize sum-peak[:<factor>]|peak|rms[:<dB>]|none] [--stereo-pairs] <sofa file>|--spherical-head <cm> <output wav>",
+        name
+    );
+    std::process::exit(1)
+}
+
+/// `5.1`, `7.1` or a list of speaker names
+fn parse_layout(layout: &str) -> anyhow::Result<Vec<Speaker>> {
+    let preset = match layout {
+        "2.0" => Some(2),
+        "5.1" => Some(6),
+        "7.1" => Some(8),
+        _ => None,
+    };
+    if let Some(speakers) = preset.and_then(standard_layout) {
+        return Ok(speakers.to_vec());
+    }
+
+    layout
+        .split(',')
+        .map(|name| {
+            channel_from_name(name.trim())
+                .filter(|x| *x != Speaker::DirectOut)
+                .with_context(|| format!("Unknown speaker {}", name))
+        })
+        .collect()
+}
+
+/// `FL:35` or `TFL:45:30`, azimuth and elevation in degrees
+fn parse_speaker(speaker: &str) -> anyhow::Result<SpeakerDirection> {
+    let mut parts = speaker.split(':');
+    let name = parts.next().unwrap_or_default();
+    let position = channel_from_name(name)
+        .filter(|x| *x != Speaker::DirectOut)
+        .with_context(|| format!("Unknown speaker {}", name))?;
+    let azimuth = parts
+        .next()
+        .with_context(|| format!("{} needs an azimuth", name))?
+        .parse()?;
+    let elevation = parts.next().map_or(Ok(0.0), str::parse)?;
+
+    Ok(SpeakerDirection::new(position, azimuth, elevation))
+}
+
+fn parse_normalization(normalization: &str) -> anyhow::Result<Normalization> {
+    let (kind, value) = match normalization.split_once(':') {
+        Some((kind, value)) => (kind, Some(value.parse::<f32>()?)),
+        None => (normalization, None),
+    };
+
+    Ok(match kind {
+        "sum-peak" => Normalization::SumPeak {
+            factor: value.unwrap_or(2.5),
+        },
+        "peak" => Normalization::PerChannelPeak,
+        "rms" => Normalization::Rms {
+            target_db: value.unwrap_or(-20.0),
+        },
+        "none" => Normalization::None,
+        _ => anyhow::bail!("Unknown normalization {}", normalization),
+    })
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut args = args();
+    let program = args.next().unwrap_or_else(|| "hrir-convert".to_string());
+
+    let mut layout = standard_layout(6).unwrap_or_default().to_vec();
+    let mut custom = vec![];
+    let mut sample_rate = 48000;
+    let mut normalization = Normalization::None;
+    let mut ears = EarLayout::Mirrored;
+    let mut head_radius = None;
+    let mut positional = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--layout" => layout = parse_layout(&args.next().context("--layout needs a value")?)?,
+            "--speaker" => custom.push(parse_speaker(
+                &args.next().context("--speaker needs a value")?,
+            )?),
+            "--rate" => sample_rate = args.next().context("--rate needs a value")?.parse()?,
+            "--normalize" => {
+                normalization =
+                    parse_normalization(&args.next().context("--normalize needs a value")?)?
+            }
+            "--stereo-pairs" => ears = EarLayout::StereoPairs,
+            "--spherical-head" => {
+                head_radius = Some(
+                    args.next()
+                        .context("--spherical-head needs a radius")?
+                        .parse::<f32>()?,
+                )
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let source: Box<dyn HrtfSource> = match (head_radius, positional.as_slice()) {
+        (Some(radius), [_]) => Box::new(SphericalHead::new(radius, sample_rate)),
+        (None, [input, _]) => Box::new(SofaHrtf::open(input, sample_rate)?),
+        _ => usage(&program),
+    };
+    let output = &positional[positional.len() - 1];
+
+    // speakers of the layout at their standard angles unless placed by --speaker, which can
+    // also add speakers
+    let mut speakers = layout
+        .iter()
+        .filter(|x| !custom.iter().any(|y| y.position == **x))
+        .filter_map(|x| SpeakerDirection::standard(*x))
+        .collect::<Vec<_>>();
+    speakers.extend(custom);
+
+    let mut hrir = Hrir::from_hrtf(&*source, &speakers)?;
+    hrir.normalize_with(normalization);
+
+    for speaker in &speakers {
+        println!(
+            "{} at {}° azimuth, {}° elevation",
+            get_channel_name(speaker.position),
+            speaker.azimuth,
+            speaker.elevation
+        );
+    }
+
+    let file = File::create(output).with_context(|| format!("Failed to create {}", output))?;
+    hrir.write_wav(BufWriter::new(file), ears)
+        .context("Failed to write the HRIR, --stereo-pairs keeps asymmetric layouts")?;
+    println!(
+        "wrote {} speakers of {} samples at {}Hz to {}",
+        hrir.speakers.len(),
+        hrir.ir_length(),
+        hrir.sample_rate,
+        output
+    );
+
+    Ok(())
+}
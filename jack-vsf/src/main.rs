@@ -1,37 +1,154 @@
+use anyhow::Context;
 use jack::{
     AudioIn, AudioOut, Client, ClientOptions, Control, Frames, Port, ProcessHandler, ProcessScope,
 };
-use std::env::args;
-use std::fs::File;
-use virtual_surround::{get_channel_name, RawVirtualSurroundFilter};
+use std::env::{args, var_os};
+use std::path::PathBuf;
+use std::time::Duration;
+use virtual_surround::{
+    capabilities, get_channel_name, BlockAdapter, Engine, FilterBuilder, FilterConfig, Level,
+    LoadSnapshot, MeterSnapshot, Speaker, VirtualSurroundFilter,
+};
+
+fn engine_cache() -> Option<PathBuf> {
+    let cache = var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+
+    Some(cache.join("virtual-surround").join("engines"))
+}
+
+fn format_level(name: &str, level: &Level) -> String {
+    let flag = if level.is_clipping() {
+        " CLIP"
+    } else if level.is_silent() {
+        " silent"
+    } else {
+        ""
+    };
+
+    format!(
+        "{} {:.1}/{:.1}dB{}",
+        name,
+        level.peak_db(),
+        level.rms_db(),
+        flag
+    )
+}
+
+fn print_levels(layout: &[Speaker], snapshot: &MeterSnapshot) {
+    let levels = layout
+        .iter()
+        .zip(&snapshot.inputs)
+        .map(|(speaker, level)| format_level(get_channel_name(*speaker), level))
+        .chain([
+            format_level("out L", &snapshot.left),
+            format_level("out R", &snapshot.right),
+        ])
+        .collect::<Vec<_>>();
+
+    println!("{}", levels.join("  "));
+}
+
+fn print_load(snapshot: &LoadSnapshot) {
+    if snapshot.blocks == 0 {
+        return;
+    }
+
+    println!(
+        "dsp load {:.0}% p99 {:.0}% max {:.0}%{}",
+        snapshot.load() * 100.0,
+        snapshot.p99_load() * 100.0,
+        snapshot.max.as_secs_f32() / snapshot.budget.as_secs_f32() * 100.0,
+        if snapshot.is_overloaded() {
+            "  warning: blocks take longer to render than they last"
+        } else {
+            ""
+        }
+    );
+}
 
 struct Filter {
-    vsf: RawVirtualSurroundFilter,
+    vsf: VirtualSurroundFilter,
     input_ports: Vec<Port<AudioIn>>,
-    input_space: Vec<Vec<f32>>,
-    input_offset: usize,
-    buffer_size: usize,
     output_ports: Vec<Port<AudioOut>>,
-    output_buffer: usize,
-    output_space: Vec<Vec<f32>>,
-    has_buffer: bool,
+    adapter: BlockAdapter,
 }
 
 fn main() -> anyhow::Result<()> {
-    let args = args().collect::<Vec<String>>();
-    if args.len() < 2 {
-        println!("usage: {} <hrir file>", &args[0]);
+    #[cfg(feature = "tracing")]
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let mut args = args();
+    let program = args.next().unwrap_or_else(|| "jack-vsf".to_string());
+
+    let mut engine = None;
+    let mut config = None;
+    let mut prepared = None;
+    let mut show_meters = false;
+    let mut show_load = false;
+    let mut positional = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--engine" => engine = Some(args.next().context("--engine needs a value")?),
+            "--config" => config = Some(args.next().context("--config needs a file")?),
+            "--prepared" => prepared = Some(args.next().context("--prepared needs a file")?),
+            "--meters" => show_meters = true,
+            "--load" => show_load = true,
+            "--version" => {
+                println!("{}", capabilities());
+                return Ok(());
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    if engine.as_deref() == Some("list") {
+        for engine in Engine::available() {
+            println!("{} {:?}", engine.name(), engine.capabilities());
+        }
+
         return Ok(());
     }
 
-    let file = File::open(&args[1])?;
+    let mut config = match config {
+        Some(path) => FilterConfig::load(path)?,
+        None => FilterConfig::default(),
+    };
+    if let Some(hrir) = positional.first() {
+        config.hrir = Some(hrir.into());
+    }
+
+    if config.hrir.is_none() {
+        println!(
+            "usage: {} [--version] [--meters] [--load] [--config <file>] [--prepared <file>] [--engine <name>|fastest|list] <hrir file>",
+            program
+        );
+        return Ok(());
+    }
 
     let (client, _) = Client::new(
         "Virtual Surround",
         ClientOptions::USE_EXACT_NAME | ClientOptions::NO_START_SERVER,
     )?;
 
-    let vsf = RawVirtualSurroundFilter::new(file, Some(client.sample_rate() as u32))?;
+    config.sample_rate = Some(client.sample_rate() as u32);
+    let mut builder =
+        FilterBuilder::from_config(&config)?.diagnostics(|x| println!("warning: {}", x));
+    match engine.as_deref() {
+        Some("fastest") => builder = builder.fastest_engine(engine_cache()),
+        Some(engine) => builder = builder.engine(Engine::by_name(engine)?.factory()),
+        None => {}
+    }
+
+    let mut vsf = match prepared {
+        Some(path) => config.build_with_cache(builder, path)?,
+        None => config.build_with(builder)?,
+    };
 
     println!(
         "forced latency of {} samples / {} ms",
@@ -41,33 +158,44 @@ fn main() -> anyhow::Result<()> {
 
     let mut input_ports = vec![];
 
-    let mut input_space = vec![];
-
-    for chan in vsf.positions() {
+    for chan in vsf.input_layout() {
         let port = client.register_port(&format!("input_{}", get_channel_name(chan)), AudioIn)?;
         input_ports.push(port);
-        input_space.push(vec![0f32; vsf.samples_required()]);
     }
 
     let mut output_ports = vec![];
     output_ports.push(client.register_port("output_FL", AudioOut)?);
     output_ports.push(client.register_port("output_FR", AudioOut)?);
 
+    if show_meters {
+        // peak and rms of the last second for every speaker and the output
+        let meters = vsf.meters();
+        let layout = vsf.positions().collect::<Vec<_>>();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(1));
+            print_levels(&layout, &meters.snapshot());
+        });
+    }
+
+    if show_load {
+        // time taken to render a block against the time it lasts
+        let load = vsf.dsp_load();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(1));
+            print_load(&load.snapshot());
+        });
+    }
+
     let block_size = vsf.block_size();
     client.set_buffer_size(block_size as u32)?;
 
     let client = client.activate_async(
         (),
         Filter {
+            adapter: BlockAdapter::new(vsf.input_channels(), block_size, block_size)?,
             vsf,
             input_ports,
-            input_space,
-            input_offset: 0,
-            buffer_size: block_size,
-            output_buffer: 0,
             output_ports,
-            output_space: vec![vec![0f32; block_size], vec![0f32; block_size]],
-            has_buffer: false,
         },
     )?;
 
@@ -80,104 +208,54 @@ fn main() -> anyhow::Result<()> {
 
 impl ProcessHandler for Filter {
     fn process(&mut self, client: &Client, process_scope: &ProcessScope) -> Control {
-        if process_scope.n_frames() as usize != self.buffer_size {
-            if self.buffer_size(client, process_scope.n_frames()) == Control::Quit {
-                return Control::Quit;
-            }
+        if process_scope.n_frames() as usize != self.adapter.buffer_size()
+            && self.buffer_size(client, process_scope.n_frames()) == Control::Quit
+        {
+            return Control::Quit;
         }
 
-        for (c, port) in self.input_ports.iter().enumerate() {
-            self.input_space[c][self.input_offset..self.input_offset + self.buffer_size]
-                .copy_from_slice(port.as_slice(process_scope));
-        }
+        let input = self
+            .input_ports
+            .iter()
+            .map(|x| x.as_slice(process_scope))
+            .collect::<Vec<_>>();
 
-        if self.input_offset < (self.vsf.samples_required() - self.buffer_size) {
-            self.input_offset += self.buffer_size;
-            if self.has_buffer && self.output_buffer < self.vsf.block_size() {
-                self.output_ports[0]
-                    .as_mut_slice(process_scope)
-                    .copy_from_slice(
-                        &self.output_space[0]
-                            [self.output_buffer..self.output_buffer + self.buffer_size],
-                    );
-                self.output_ports[1]
-                    .as_mut_slice(process_scope)
-                    .copy_from_slice(
-                        &self.output_space[1]
-                            [self.output_buffer..self.output_buffer + self.buffer_size],
-                    );
-                self.output_buffer += self.buffer_size;
-
-                if self.output_buffer >= self.vsf.block_size() {
-                    self.has_buffer = false;
-                }
-            }
+        let (left, right) = self.output_ports.split_at_mut(1);
+        let left = left[0].as_mut_slice(process_scope);
+        let right = right[0].as_mut_slice(process_scope);
+        let vsf = &mut self.vsf;
 
-            return Control::Continue;
-        }
-
-        let mut output_buffers = if self.buffer_size == self.vsf.block_size() {
-            self.output_ports
-                .iter_mut()
-                .map(|x| x.as_mut_slice(process_scope))
-                .collect::<Vec<_>>()
+        // what errors?
+        let _ = if self.adapter.buffer_size() == vsf.block_size() {
+            // no rebuffering needed, skip the interleaving too
+            vsf.transform_planar(&input, left, right).map(|_| ())
         } else {
-            self.output_space
-                .iter_mut()
-                .map(|x| x.as_mut_slice())
-                .collect::<Vec<_>>()
+            self.adapter
+                .process_planar(&input, left, right, |input, output| {
+                    vsf.transform(input, output)
+                })
         };
 
-        let left = output_buffers.remove(0);
-        let right = output_buffers.remove(0);
-
-        left.fill(0.0);
-        right.fill(0.0);
-
-        // what errors?
-        let _ = self.vsf.transform(
-            &mut self
-                .input_space
-                .iter_mut()
-                .map(|x| x.as_mut_slice())
-                .collect::<Vec<_>>(),
-            (left, right),
-        );
-
-        if self.buffer_size != self.vsf.block_size() {
-            self.output_ports[0]
-                .as_mut_slice(process_scope)
-                .copy_from_slice(&self.output_space[0][..self.buffer_size]);
-            self.output_ports[1]
-                .as_mut_slice(process_scope)
-                .copy_from_slice(&self.output_space[1][..self.buffer_size]);
-            self.output_buffer = self.buffer_size;
-            self.has_buffer = true;
-        }
-
-        for space in &mut self.input_space {
-            space.copy_within(self.vsf.block_size().., 0);
-        }
-
-        self.input_offset = self.vsf.samples_required() - self.vsf.block_size();
-
         Control::Continue
     }
 
     fn buffer_size(&mut self, _: &Client, size: Frames) -> Control {
-        if size as usize == self.buffer_size {
+        if size as usize == self.adapter.buffer_size() {
             return Control::Continue;
         }
 
-        if self.vsf.block_size() % size as usize != 0 || size as usize > self.vsf.block_size() {
-            println!("JACK buffer size needs to be equal or smaller and (buffer_size % block_size) === 0, requested buffer size is {}, block size is {}", size, self.vsf.block_size());
+        let old_size = self.adapter.buffer_size();
+        if let Err(err) = self.adapter.set_buffer_size(size as usize) {
+            println!("{}", err);
             return Control::Quit;
         }
 
-        println!("Buffer size changed from {} to {}", self.buffer_size, size);
-        self.buffer_size = size as usize;
-        self.input_offset = 0;
-        self.has_buffer = false;
+        println!(
+            "Buffer size changed from {} to {}, rebuffering adds {} samples of latency",
+            old_size,
+            size,
+            self.adapter.latency()
+        );
         Control::Continue
     }
 }
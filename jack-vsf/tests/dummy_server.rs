@@ -0,0 +1,213 @@
+use jack::{
+    AudioIn, AudioOut, Client, ClientOptions, ClosureProcessHandler, Control, PortFlags,
+    ProcessScope,
+};
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const HRIR: &str = "../resources/hrir_kemar/hrir-kemar.wav";
+/// the HRIR's own rate, so nothing is resampled
+const SAMPLE_RATE: usize = 44100;
+const FILTER: &str = "Virtual Surround";
+
+/// child process killed when the test ends, however it ends
+struct Process(Child);
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// wait up to 10 seconds for `condition`
+fn wait_for<F: FnMut() -> bool>(mut condition: F) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(10) {
+        if condition() {
+            return true;
+        }
+
+        sleep(Duration::from_millis(100));
+    }
+
+    false
+}
+
+/// a jackd with the dummy driver, `None` when jackd isn't installed
+fn start_server(name: &str, period: usize) -> Option<Process> {
+    let child = Command::new("jackd")
+        .args(["--no-realtime", "-n", name, "-d", "dummy"])
+        .args(["-r", &SAMPLE_RATE.to_string(), "-p", &period.to_string()])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+
+    match child {
+        Ok(child) => Some(Process(child)),
+        Err(err) => {
+            eprintln!("skipping, failed to start jackd: {}", err);
+            None
+        }
+    }
+}
+
+/// left and right output of jack-vsf while a 1khz sine plays on its `input_FL` port, every other
+/// input is silent
+fn capture(server: &str, seconds: f32, buffer_size: Option<u32>) -> (Vec<f32>, Vec<f32>) {
+    let mut client = None;
+    assert!(
+        wait_for(|| {
+            client = Client::new("vsf-test", ClientOptions::NO_START_SERVER).ok();
+            client.is_some()
+        }),
+        "jackd didn't come up"
+    );
+    let (client, _) = client.unwrap();
+
+    let mut filter = Process(
+        Command::new(env!("CARGO_BIN_EXE_jack-vsf"))
+            .arg(HRIR)
+            .env("JACK_DEFAULT_SERVER", server)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("Failed to start jack-vsf"),
+    );
+
+    let output_ports = [
+        format!("{}:output_FL", FILTER),
+        format!("{}:output_FR", FILTER),
+    ];
+    assert!(
+        wait_for(|| output_ports
+            .iter()
+            .all(|x| client.port_by_name(x).is_some())),
+        "jack-vsf didn't register its ports"
+    );
+
+    let inputs = client.ports(
+        Some(&format!("{}:input_.*", FILTER)),
+        None,
+        PortFlags::IS_INPUT,
+    );
+    assert!(
+        inputs.contains(&format!("{}:input_FL", FILTER)),
+        "{:?}",
+        inputs
+    );
+
+    let mut feeds = vec![];
+    for input in &inputs {
+        let name = input.rsplit(':').next().unwrap();
+        feeds.push((
+            name == "input_FL",
+            client
+                .register_port(&format!("feed_{}", name), AudioOut)
+                .unwrap(),
+        ));
+    }
+    let left = client.register_port("capture_FL", AudioIn).unwrap();
+    let right = client.register_port("capture_FR", AudioIn).unwrap();
+
+    let frames = (seconds * SAMPLE_RATE as f32) as usize;
+    let captured = Arc::new(Mutex::new((
+        Vec::with_capacity(frames),
+        Vec::with_capacity(frames),
+    )));
+    let shared = captured.clone();
+    let mut phase = 0usize;
+
+    let process = ClosureProcessHandler::new(move |_: &Client, scope: &ProcessScope| {
+        for (sine, port) in &mut feeds {
+            let buffer = port.as_mut_slice(scope);
+            for (s, sample) in buffer.iter_mut().enumerate() {
+                *sample = if *sine {
+                    let t = (phase + s) as f32 / SAMPLE_RATE as f32;
+                    (t * 1000.0 * std::f32::consts::TAU).sin() * 0.25
+                } else {
+                    0.0
+                };
+            }
+        }
+        phase += scope.n_frames() as usize;
+
+        let mut captured = shared.lock().unwrap();
+        let room = frames - captured.0.len();
+        let take = room.min(scope.n_frames() as usize);
+        captured.0.extend_from_slice(&left.as_slice(scope)[..take]);
+        captured.1.extend_from_slice(&right.as_slice(scope)[..take]);
+
+        Control::Continue
+    });
+
+    let active = client.activate_async((), process).unwrap();
+    let client = active.as_client();
+
+    for input in &inputs {
+        let name = input.rsplit(':').next().unwrap();
+        client
+            .connect_ports_by_name(&format!("vsf-test:feed_{}", name), input)
+            .unwrap();
+    }
+    for (output, capture) in output_ports.iter().zip(["capture_FL", "capture_FR"]) {
+        client
+            .connect_ports_by_name(output, &format!("vsf-test:{}", capture))
+            .unwrap();
+    }
+
+    // jack-vsf asks for its own block size on startup, changing it afterwards makes it rebuffer
+    if let Some(size) = buffer_size {
+        client.set_buffer_size(size).unwrap();
+    }
+
+    assert!(
+        wait_for(|| captured.lock().unwrap().0.len() == frames),
+        "captured too little output"
+    );
+    active.deactivate().unwrap();
+
+    // jack-vsf quits on a line on stdin
+    let stdin = filter.0.stdin.as_mut().unwrap();
+    let _ = stdin.write_all(b"\n");
+    assert!(wait_for(|| matches!(filter.0.try_wait(), Ok(Some(_)))));
+    assert!(filter.0.try_wait().unwrap().unwrap().success());
+
+    let captured = captured.lock().unwrap();
+    (captured.0.clone(), captured.1.clone())
+}
+
+fn rms(samples: &[f32]) -> f32 {
+    (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// the front left speaker is louder in the left ear, once the filter's latency has passed
+fn check(left: &[f32], right: &[f32]) {
+    assert!(left.iter().chain(right).all(|x| x.is_finite()));
+    assert!(left.iter().chain(right).all(|x| x.abs() <= 1.0));
+
+    let settled = left.len() / 2;
+    let (left, right) = (rms(&left[settled..]), rms(&right[settled..]));
+    assert!(left > 0.01, "no output: {} {}", left, right);
+    assert!(left > right * 1.2, "{} {}", left, right);
+}
+
+#[test]
+fn renders_through_a_dummy_server() {
+    let server = format!("vsf-test-{}", std::process::id());
+    let _jackd = match start_server(&server, 512) {
+        Some(jackd) => jackd,
+        None => return,
+    };
+    std::env::set_var("JACK_DEFAULT_SERVER", &server);
+
+    let (left, right) = capture(&server, 1.0, None);
+    check(&left, &right);
+
+    // periods not matching the block go through the block adapter
+    let (left, right) = capture(&server, 1.0, Some(128));
+    check(&left, &right);
+}
@@ -0,0 +1,269 @@
+//! The parameters of a [`VsfController`] and how control surfaces address them. This only maps
+//! addresses and values, the OSC, D-Bus, HTTP and MIDI servers themselves are up to front-ends,
+//! which hand what they receive to [`Control::set_address`].
+
+use virtual_surround::VsfController;
+
+mod surface;
+
+pub use crate::surface::Surface;
+
+/// What a parameter takes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Range {
+    Float {
+        min: f32,
+        max: f32,
+    },
+    Toggle,
+    /// a position in a list which only the filter knows, from 0 up to 127 so it fits MIDI
+    Index,
+    /// an action like a toggle which fires when switched on, it always reads off
+    Trigger,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Value {
+    Float(f32),
+    Toggle(bool),
+}
+
+impl Value {
+    /// `on`/`off`, `true`/`false` or a number, as sent by text based protocols
+    pub fn parse(text: &str) -> anyhow::Result<Value> {
+        match text.trim() {
+            "on" | "true" => Ok(Value::Toggle(true)),
+            "off" | "false" => Ok(Value::Toggle(false)),
+            x => x
+                .parse::<f32>()
+                .ok()
+                .filter(|x| x.is_finite())
+                .map(Value::Float)
+                .ok_or_else(|| anyhow::anyhow!("Invalid value: {}", text)),
+        }
+    }
+
+    fn float(self) -> f32 {
+        match self {
+            Value::Float(x) => x,
+            Value::Toggle(x) => x as u8 as f32,
+        }
+    }
+
+    fn toggle(self) -> bool {
+        match self {
+            Value::Float(x) => x >= 0.5,
+            Value::Toggle(x) => x,
+        }
+    }
+}
+
+/// A setting of the filter every control surface exposes, looked up with [`Parameter::find`]
+/// or through a [`Surface`]
+#[derive(Debug)]
+pub struct Parameter {
+    /// lowercase and with underscores, the surfaces derive their addresses from it
+    pub name: &'static str,
+    pub description: &'static str,
+    pub range: Range,
+    pub default: Value,
+    apply: fn(&mut VsfController, Value) -> anyhow::Result<()>,
+}
+
+/// Every parameter, adding one here exposes it on every surface. New parameters go at the end,
+/// their index is their MIDI controller.
+pub const PARAMETERS: &[Parameter] = &[
+    Parameter {
+        name: "mix",
+        description: "blend between the plain stereo downmix (0) and the virtualized output (1)",
+        range: Range::Float { min: 0.0, max: 1.0 },
+        default: Value::Float(1.0),
+        apply: |controller, value| controller.set_mix(value.float()),
+    },
+    Parameter {
+        name: "bypass",
+        description: "output only the plain stereo downmix",
+        range: Range::Toggle,
+        default: Value::Toggle(false),
+        apply: |controller, value| controller.set_bypass(value.toggle()),
+    },
+    Parameter {
+        name: "gain",
+        description: "output gain in dB",
+        range: Range::Float {
+            min: -60.0,
+            max: 12.0,
+        },
+        default: Value::Float(0.0),
+        apply: |controller, value| controller.set_gain(value.float()),
+    },
+    Parameter {
+        name: "recenter",
+        description: "make where the head points now the front",
+        range: Range::Trigger,
+        default: Value::Toggle(false),
+        apply: |controller, value| {
+            if value.toggle() {
+                controller.recenter()
+            } else {
+                Ok(())
+            }
+        },
+    },
+    Parameter {
+        name: "profile",
+        description: "index of the HRIR profile in the filter's profiles",
+        range: Range::Index,
+        default: Value::Float(0.0),
+        apply: |controller, value| {
+            let index = value.float() as usize;
+            let name = controller
+                .profiles()
+                .nth(index)
+                .map(str::to_string)
+                .ok_or_else(|| anyhow::anyhow!("Filter has no profile {}", index))?;
+            controller.set_profile(&name)
+        },
+    },
+];
+
+impl Parameter {
+    pub fn find(name: &str) -> Option<&'static Parameter> {
+        PARAMETERS.iter().find(|x| x.name == name)
+    }
+
+    pub(crate) fn index(&self) -> usize {
+        PARAMETERS.iter().position(|x| x.name == self.name).unwrap()
+    }
+
+    /// `value` converted to the parameter's range and clamped into it, a toggle is on from 0.5
+    pub fn value(&self, value: Value) -> Value {
+        match self.range {
+            Range::Float { min, max } => Value::Float(value.float().clamp(min, max)),
+            Range::Index => Value::Float(value.float().round().clamp(0.0, 127.0)),
+            Range::Toggle | Range::Trigger => Value::Toggle(value.toggle()),
+        }
+    }
+
+    /// `value` scaled to 0.0 to 1.0, for protocols without units like MIDI
+    pub fn normalize(&self, value: Value) -> f32 {
+        match (self.range, self.value(value)) {
+            (Range::Float { min, max }, Value::Float(x)) => (x - min) / (max - min),
+            (Range::Index, Value::Float(x)) => x / 127.0,
+            (_, value) => value.float(),
+        }
+    }
+
+    pub fn denormalize(&self, normalized: f32) -> Value {
+        let normalized = normalized.clamp(0.0, 1.0);
+        match self.range {
+            Range::Float { min, max } => Value::Float(min + normalized * (max - min)),
+            Range::Index => Value::Float((normalized * 127.0).round()),
+            Range::Toggle | Range::Trigger => Value::Toggle(normalized >= 0.5),
+        }
+    }
+}
+
+/// Applies parameters to a [`VsfController`] and remembers what they were set to, so surfaces
+/// can report the current values
+pub struct Control {
+    controller: VsfController,
+    values: Vec<Value>,
+}
+
+impl Control {
+    pub fn new(controller: VsfController) -> Self {
+        Control {
+            controller,
+            values: PARAMETERS.iter().map(|x| x.default).collect(),
+        }
+    }
+
+    pub fn get(&self, parameter: &Parameter) -> Value {
+        self.values[parameter.index()]
+    }
+
+    /// set `parameter`, returns the value it was clamped to
+    pub fn set(&mut self, parameter: &Parameter, value: Value) -> anyhow::Result<Value> {
+        let value = parameter.value(value);
+        (parameter.apply)(&mut self.controller, value)?;
+        if parameter.range != Range::Trigger {
+            self.values[parameter.index()] = value;
+        }
+
+        Ok(value)
+    }
+
+    /// set the parameter at `address` on `surface`
+    pub fn set_address(
+        &mut self,
+        surface: Surface,
+        address: &str,
+        value: Value,
+    ) -> anyhow::Result<Value> {
+        let parameter = surface
+            .parameter(address)
+            .ok_or_else(|| anyhow::anyhow!("No parameter at {} on {:?}", address, surface))?;
+
+        self.set(parameter, value)
+    }
+
+    /// the controller, e.g. to swap in another filter
+    pub fn controller(&mut self) -> &mut VsfController {
+        &mut self.controller
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Control, Parameter, Value};
+    use std::fs::File;
+    use virtual_surround::{FilterBuilder, VsfProcessor};
+
+    #[test]
+    fn remembers_values_but_not_triggers() {
+        let filter = FilterBuilder::new()
+            .build(File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap())
+            .unwrap();
+        let (_processor, controller) = VsfProcessor::new(filter);
+        let mut control = Control::new(controller);
+
+        let gain = Parameter::find("gain").unwrap();
+        assert_eq!(
+            control.set(gain, Value::Float(20.0)).unwrap(),
+            Value::Float(12.0)
+        );
+        assert_eq!(control.get(gain), Value::Float(12.0));
+
+        let recenter = Parameter::find("recenter").unwrap();
+        assert_eq!(
+            control.set(recenter, Value::parse("on").unwrap()).unwrap(),
+            Value::Toggle(true)
+        );
+        assert_eq!(control.get(recenter), Value::Toggle(false));
+    }
+
+    #[test]
+    fn switches_profiles_by_index() {
+        let mut filter = FilterBuilder::new()
+            .build(File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap())
+            .unwrap();
+        filter
+            .add_profile(
+                "kemar",
+                File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap(),
+            )
+            .unwrap();
+        let (_processor, controller) = VsfProcessor::new(filter);
+        let mut control = Control::new(controller);
+
+        let profile = Parameter::find("profile").unwrap();
+        assert_eq!(
+            control.set(profile, Value::Float(0.8)).unwrap(),
+            Value::Float(1.0)
+        );
+        assert_eq!(control.get(profile), Value::Float(1.0));
+        assert!(control.set(profile, Value::Float(2.0)).is_err());
+        assert_eq!(control.get(profile), Value::Float(1.0));
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{Parameter, Value, PARAMETERS};
+
+/// first MIDI controller used, 20 to 31 are undefined in the MIDI spec
+const FIRST_CONTROLLER: usize = 20;
+
+/// A protocol the parameters are exposed on, each names them its own way
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Surface {
+    /// `/vsf/<name>` with a float or bool argument
+    Osc,
+    /// a property in CamelCase on the filter's object
+    DBus,
+    /// `/parameters/<name>`, the value as text in the body
+    Http,
+    /// a control change number, the value scaled from 0 to 127
+    Midi,
+}
+
+impl Surface {
+    pub fn address(self, parameter: &Parameter) -> String {
+        match self {
+            Surface::Osc => format!("/vsf/{}", parameter.name),
+            Surface::DBus => parameter
+                .name
+                .split('_')
+                .map(|x| x[..1].to_uppercase() + &x[1..])
+                .collect(),
+            Surface::Http => format!("/parameters/{}", parameter.name),
+            Surface::Midi => (FIRST_CONTROLLER + parameter.index()).to_string(),
+        }
+    }
+
+    pub fn parameter(self, address: &str) -> Option<&'static Parameter> {
+        PARAMETERS.iter().find(|x| self.address(x) == address)
+    }
+
+    /// parameter on a MIDI controller
+    pub fn midi_parameter(controller: u8) -> Option<&'static Parameter> {
+        PARAMETERS.get((controller as usize).checked_sub(FIRST_CONTROLLER)?)
+    }
+
+    pub fn from_midi(parameter: &Parameter, value: u8) -> Value {
+        parameter.denormalize(value.min(127) as f32 / 127.0)
+    }
+
+    pub fn to_midi(parameter: &Parameter, value: Value) -> u8 {
+        (parameter.normalize(value) * 127.0).round() as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Surface;
+    use crate::{Parameter, Value, PARAMETERS};
+
+    #[test]
+    fn every_parameter_has_an_address_everywhere() {
+        for surface in [Surface::Osc, Surface::DBus, Surface::Http, Surface::Midi] {
+            for parameter in PARAMETERS {
+                let address = surface.address(parameter);
+                assert_eq!(surface.parameter(&address).unwrap().name, parameter.name);
+            }
+        }
+
+        assert_eq!(
+            Surface::DBus.address(Parameter::find("mix").unwrap()),
+            "Mix"
+        );
+        assert_eq!(Surface::Osc.parameter("/vsf/nothing").map(|x| x.name), None);
+    }
+
+    #[test]
+    fn scales_midi_to_the_range() {
+        let gain = Parameter::find("gain").unwrap();
+        let controller = Surface::Midi.address(gain).parse::<u8>().unwrap();
+        assert_eq!(Surface::midi_parameter(controller).unwrap().name, "gain");
+
+        assert_eq!(Surface::from_midi(gain, 0), Value::Float(-60.0));
+        assert_eq!(Surface::from_midi(gain, 127), Value::Float(12.0));
+        assert_eq!(Surface::to_midi(gain, Value::Float(100.0)), 127);
+        assert_eq!(Surface::to_midi(gain, Surface::from_midi(gain, 64)), 64);
+
+        let bypass = Parameter::find("bypass").unwrap();
+        assert_eq!(Surface::from_midi(bypass, 127), Value::Toggle(true));
+        assert_eq!(Surface::to_midi(bypass, Value::parse("off").unwrap()), 0);
+
+        // an index is the MIDI value itself
+        let profile = Parameter::find("profile").unwrap();
+        assert_eq!(Surface::from_midi(profile, 3), Value::Float(3.0));
+        assert_eq!(Surface::to_midi(profile, Value::Float(3.0)), 3);
+    }
+}
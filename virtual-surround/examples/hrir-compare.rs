@@ -0,0 +1,217 @@
+use hound::{SampleFormat, WavSpec};
+use std::env::args;
+use std::fs::File;
+use std::path::Path;
+use virtual_surround::{
+    FilterBuilder, LoudnessMeter, MixingMatrix, Speaker, VirtualSurroundFilter,
+};
+
+/// crossfade between the renderings of the A/B file
+const CROSSFADE_MS: f32 = 10.0;
+
+fn usage(name: &str) -> ! {
+    println!(
+        "{} [--start <seconds>] [--length <seconds>] [--switch <seconds>] <input> <output dir> <hrir>...",
+        name
+    );
+    std::process::exit(1)
+}
+
+/// interleaved stereo of `input` through `hrir` with the HRIR onset taken out, `start` and
+/// `frames` select the excerpt
+fn render(
+    hrir: &str,
+    input: &[f32],
+    channels: usize,
+    layout: &[virtual_surround::Speaker],
+    sample_rate: u32,
+    start: usize,
+    frames: usize,
+) -> Vec<f32> {
+    let mut vs = FilterBuilder::new()
+        .sample_rate(sample_rate)
+        .build(File::open(hrir).expect("Failed to open hrir"))
+        .expect("Failed to create filter");
+
+    if channels > 2 {
+        let positions = vs.positions().collect::<Vec<_>>();
+        vs.set_mixing_matrix(Some(MixingMatrix::automatic(layout, &positions)))
+            .expect("Failed to set mixing matrix");
+    }
+
+    // the window is filled with what came before the excerpt, the rendering runs past its end by
+    // the onset of the HRIR which is cut off the front
+    let block = vs.block_size();
+    let preroll = vs.samples_required();
+    let delay = vs.ir_delay();
+    let blocks = (preroll + frames + delay).div_ceil(block);
+
+    let frame = |index: usize| -> &[f32] {
+        match (start + index).checked_sub(preroll) {
+            Some(at) if at < input.len() / channels => &input[at * channels..(at + 1) * channels],
+            _ => &[],
+        }
+    };
+
+    let mut output = vec![];
+    let mut chunk = vec![0f32; block * channels];
+    let mut out = vec![0f32; block * 2];
+
+    for index in 0..blocks {
+        chunk.fill(0f32);
+        for s in 0..block {
+            let samples = frame(index * block + s);
+            chunk[s * channels..s * channels + samples.len()].copy_from_slice(samples);
+        }
+
+        out.fill(0f32);
+        transform(&mut vs, channels, &chunk, &mut out);
+        output.extend_from_slice(&out);
+    }
+
+    output[(preroll + delay) * 2..(preroll + delay + frames) * 2].to_vec()
+}
+
+fn transform(vs: &mut VirtualSurroundFilter, channels: usize, input: &[f32], output: &mut [f32]) {
+    match channels {
+        1 => vs.transform_mono(input, output),
+        2 => vs.transform_stereo(input, output),
+        _ => vs.transform(input, output),
+    }
+    .expect("Failed to transform");
+}
+
+fn write(path: &Path, sample_rate: u32, samples: &[f32]) {
+    let spec = WavSpec {
+        channels: 2,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+
+    let mut w = hound::WavWriter::create(path, spec).expect("Failed to create wav writer");
+    for sample in samples {
+        w.write_sample(*sample).expect("Failed to write sample");
+    }
+    w.finalize().expect("Failed to finalize");
+}
+
+/// Renders an excerpt through every HRIR, level matched to the quietest and aligned, into a file
+/// per HRIR and an `ab.wav` which cycles through them every `--switch` seconds
+pub fn main() {
+    let mut start = 0.0f32;
+    let mut length = 30.0f32;
+    let mut switch = 2.0f32;
+    let mut arg = vec![];
+
+    let mut args = args();
+    let name = args.next().unwrap_or_default();
+    while let Some(value) = args.next() {
+        let mut seconds = || -> f32 {
+            args.next()
+                .and_then(|x| x.parse().ok())
+                .unwrap_or_else(|| usage(&name))
+        };
+
+        match value.as_str() {
+            "--start" => start = seconds(),
+            "--length" => length = seconds(),
+            "--switch" => switch = seconds(),
+            _ => arg.push(value),
+        }
+    }
+
+    if arg.len() < 3 {
+        usage(&name);
+    }
+
+    let mut r = bwavfile::WaveReader::open(&arg[0]).expect("Failed to open input wav");
+    let sample_rate = r.format().expect("Failed to read input format").sample_rate;
+    let layout = r
+        .channels()
+        .expect("Failed to read input format")
+        .iter()
+        .map(|x| Speaker::from(x.speaker))
+        .collect::<Vec<_>>();
+    let channels = layout.len();
+
+    let mut input = vec![];
+    let mut samples = vec![0f32; channels];
+    let mut fr = r.audio_frame_reader().unwrap();
+    while let Ok(1) = fr.read_float_frame(&mut samples) {
+        input.extend_from_slice(&samples);
+    }
+
+    let rate = sample_rate as f32;
+    let start = ((start * rate) as usize).min(input.len() / channels);
+    let frames = ((length * rate) as usize).min(input.len() / channels - start);
+    let dir = Path::new(&arg[1]);
+    std::fs::create_dir_all(dir).expect("Failed to create output dir");
+
+    let hrirs = &arg[2..];
+    let mut renderings = hrirs
+        .iter()
+        .map(|hrir| render(hrir, &input, channels, &layout, sample_rate, start, frames))
+        .collect::<Vec<_>>();
+
+    let loudness = renderings
+        .iter()
+        .map(|x| {
+            let mut meter = LoudnessMeter::new(sample_rate as usize);
+            let left = x.iter().step_by(2).copied().collect::<Vec<_>>();
+            let right = x.iter().skip(1).step_by(2).copied().collect::<Vec<_>>();
+            meter.process(&left, &right);
+            meter.integrated()
+        })
+        .collect::<Vec<_>>();
+    let quietest = loudness
+        .iter()
+        .copied()
+        .filter(|x| x.is_finite())
+        .fold(f32::INFINITY, f32::min);
+
+    for ((rendering, lufs), hrir) in renderings.iter_mut().zip(&loudness).zip(hrirs) {
+        let gain_db = if lufs.is_finite() {
+            quietest - lufs
+        } else {
+            0.0
+        };
+        let gain = 10f32.powf(gain_db / 20.0);
+        rendering.iter_mut().for_each(|x| *x *= gain);
+
+        let stem = Path::new(hrir).file_stem().unwrap_or_default();
+        let path = dir.join(stem).with_extension("wav");
+        println!("{:.1} LUFS, {:+.1} dB: {}", lufs, gain_db, path.display());
+        write(&path, sample_rate, rendering);
+    }
+
+    // cycle through the renderings, crossfading at every switch
+    let segment = ((switch * rate) as usize).max(1);
+    let fade = ((CROSSFADE_MS / 1000.0 * rate) as usize).clamp(1, segment);
+    let mut ab = vec![0f32; frames * 2];
+
+    for s in 0..frames {
+        let index = s / segment;
+        let current = index % renderings.len();
+
+        if s % segment == 0 {
+            println!("{:.3}s {}", s as f32 / rate, hrirs[current]);
+        }
+
+        let (previous, mix) = match (index, s % segment) {
+            (0, _) => (current, 1.0),
+            (_, offset) if offset < fade => (
+                (index - 1) % renderings.len(),
+                (offset + 1) as f32 / (fade + 1) as f32,
+            ),
+            _ => (current, 1.0),
+        };
+
+        for c in 0..2 {
+            ab[s * 2 + c] = renderings[current][s * 2 + c] * mix
+                + renderings[previous][s * 2 + c] * (1.0 - mix);
+        }
+    }
+
+    write(&dir.join("ab.wav"), sample_rate, &ab);
+}
@@ -1,53 +1,213 @@
-use hound::{SampleFormat, WavSpec};
+use hound::{SampleFormat, WavSpec, WavWriter};
 use std::env::args;
 use std::fs::File;
-use virtual_surround::VirtualSurroundFilter;
+use std::io::{BufWriter, ErrorKind};
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use virtual_surround::{
+    layout_from_mask, BlendPreset, Engine, FilterBuilder, MixingMatrix, Upmix,
+    VirtualSurroundFilter,
+};
 
 pub fn main() {
-    let arg = args().collect::<Vec<String>>();
+    let mut engine = None;
+    let mut preset = None;
+    let mut upmix = None;
+    let mut coloration = false;
+    let mut arg = vec![];
+
+    let mut args = args();
+    while let Some(value) = args.next() {
+        if value == "--engine" {
+            engine = Some(Engine::by_name(&args.next().expect("--engine needs a value")).unwrap());
+        } else if value == "--preset" {
+            let file = File::open(args.next().expect("--preset needs a value"))
+                .expect("Failed to open preset");
+            preset = Some(BlendPreset::from_reader(file).expect("Failed to read preset"));
+        } else if value == "--upmix" {
+            upmix = Some(Upmix::default());
+        } else if value == "--coloration" {
+            coloration = true;
+        } else {
+            arg.push(value);
+        }
+    }
+
     if arg.len() < 3 {
-        println!("{} <input> <output>", arg[0]);
+        println!(
+            "{} [--engine <name>] [--preset <file>] [--upmix] [--coloration] <input> <output>",
+            arg[0]
+        );
     }
 
-    let r = bwavfile::WaveReader::open(&arg[1]).expect("Failed to open input wav");
+    // anything symphonia can decode: FLAC, Ogg Vorbis, MP3, WAV, ...
+    let file = File::open(&arg[1]).expect("Failed to open input");
+    let mut hint = Hint::new();
+    if let Some(extension) = Path::new(&arg[1]).extension().and_then(|x| x.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            MediaSourceStream::new(Box::new(file), Default::default()),
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .expect("Unsupported input format")
+        .format;
+    let track = format.default_track().expect("Input has no audio track");
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&params, &DecoderOptions::default())
+        .expect("Unsupported input codec");
+
+    let sample_rate = params.sample_rate.expect("Input has no sample rate");
+    let input_channels = params.channels.expect("Input has no channel layout");
+    let channels = input_channels.count();
+    let layout = layout_from_mask(input_channels.bits(), channels);
+    println!(
+        "{} channels at {}hz, {:?}",
+        channels,
+        sample_rate,
+        layout.as_deref().unwrap_or_default()
+    );
+
     let spec = WavSpec {
         channels: 2,
-        sample_rate: 44100,
+        sample_rate,
         bits_per_sample: 32,
         sample_format: SampleFormat::Float,
     };
 
-    let mut w = hound::WavWriter::create(&arg[2], spec).expect("Failed to create wav writer");
+    let mut w = WavWriter::create(&arg[2], spec).expect("Failed to create wav writer");
 
-    let mut vs = VirtualSurroundFilter::new_from_hrir(
-        File::open("resources/hrir_kemar/hrir-kemar.wav").expect("Failed to open hrir"),
-    )
-    .expect("Failed to create filter");
-    let mut block: Vec<f32> = vec![0f32; vs.block_size() * 6];
+    let mut builder = FilterBuilder::new().sample_rate(sample_rate);
+    if let Some(engine) = engine {
+        builder = builder.engine(engine.factory());
+    }
+
+    let mut vs = builder
+        .build(File::open("resources/hrir_kemar/hrir-kemar.wav").expect("Failed to open hrir"))
+        .expect("Failed to create filter");
+    vs.set_blend_preset(preset).expect("Invalid preset");
+    vs.set_upmix(upmix);
+    vs.set_coloration_analysis(coloration);
+    // mono input is played from the center, stereo from the front pair or upmixed, anything else
+    // is mixed to the speakers of the hrir
+    if channels > 2 {
+        let positions = vs.positions().collect::<Vec<_>>();
+        let layout = layout.expect("No layout for that many channels");
+        let matrix = MixingMatrix::automatic(&layout, &positions);
+        if !matrix.dropped().is_empty() {
+            println!("dropping input channels {:?}", matrix.dropped());
+        }
+
+        vs.set_mixing_matrix(Some(matrix))
+            .expect("Failed to set mixing matrix");
+    }
+
+    let mut block: Vec<f32> = vec![0f32; vs.block_size() * channels];
     let mut offset = 0;
 
-    let mut samples = vec![0f32; 6];
+    let mut samples: Option<SampleBuffer<f32>> = None;
 
-    let mut fr = r.audio_frame_reader().unwrap();
+    let mut output: Vec<f32> = vec![0f32; vs.block_size() * 2];
 
-    while let Ok(1) = fr.read_float_frame(&mut samples) {
-        block[offset..offset + samples.len()].copy_from_slice(&samples);
-        offset += samples.len();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => panic!("Failed to read input: {}", e),
+        };
 
-        if offset >= block.len() {
-            println!("got full block");
-            let mut output: Vec<f32> = vec![0f32; vs.block_size() * 2];
-            vs.transform(&block, &mut output)
-                .expect("Failed to transform");
+        if packet.track_id() != track_id {
+            continue;
+        }
 
-            for sample in output {
-                w.write_sample(sample).expect("Failed to write sample");
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // a corrupt packet is skipped like players do
+            Err(Error::DecodeError(e)) => {
+                println!("skipping packet: {}", e);
+                continue;
             }
+            Err(e) => panic!("Failed to decode input: {}", e),
+        };
 
-            offset = 0;
+        if samples
+            .as_ref()
+            .is_none_or(|x| x.capacity() < decoded.capacity() * channels)
+        {
+            samples = Some(SampleBuffer::new(
+                decoded.capacity() as u64,
+                *decoded.spec(),
+            ));
+        }
+
+        let samples = samples.as_mut().unwrap();
+        samples.copy_interleaved_ref(decoded);
+        for sample in samples.samples() {
+            block[offset] = *sample;
+            offset += 1;
+
+            if offset == block.len() {
+                render(&mut vs, channels, &block, &mut output, &mut w);
+                offset = 0;
+            }
+        }
+    }
+
+    if offset > 0 {
+        // the last partial block, padded with silence
+        block[offset..].fill(0f32);
+        render(&mut vs, channels, &block, &mut output, &mut w);
+    }
+
+    // the tail of the HRIR after the input ended
+    loop {
+        let frames = vs.drain(&mut output).expect("Failed to drain");
+        if frames == 0 {
+            break;
+        }
+
+        for sample in &output[..frames * 2] {
+            w.write_sample(*sample).expect("Failed to write sample");
         }
     }
 
     w.flush().expect("Failed to flush");
     w.finalize().expect("Failed to finalize");
+
+    if let Some(report) = vs.coloration() {
+        print!("{}", report);
+    }
+}
+
+/// virtualize a block of input and write it out
+fn render(
+    vs: &mut VirtualSurroundFilter,
+    channels: usize,
+    block: &[f32],
+    output: &mut [f32],
+    w: &mut WavWriter<BufWriter<File>>,
+) {
+    println!("got full block");
+    match channels {
+        1 => vs.transform_mono(block, output),
+        2 => vs.transform_stereo(block, output),
+        _ => vs.transform(block, output),
+    }
+    .expect("Failed to transform");
+
+    for sample in output.iter() {
+        w.write_sample(*sample).expect("Failed to write sample");
+    }
 }
@@ -0,0 +1,211 @@
+/// Bridges host buffers of any size to a processor which only takes fixed blocks, like
+/// [`VirtualSurroundFilter::transform`](crate::VirtualSurroundFilter::transform).
+///
+/// Input is collected into interleaved blocks of `block_size` frames, each full block is handed to
+/// the render callback, and its stereo output is queued and played back over the following host
+/// buffers. The output is delayed by a constant [`latency`](Self::latency) of
+/// `block_size - gcd(buffer_size, block_size)` frames, so buffers dividing the block cost
+/// `block_size - buffer_size` and buffers which are a multiple of it cost nothing.
+///
+/// Nothing is allocated while processing, only [`set_buffer_size`](Self::set_buffer_size)
+/// allocates.
+#[derive(Debug, Clone)]
+pub struct BlockAdapter {
+    channels: usize,
+    block_size: usize,
+    buffer_size: usize,
+    input: Vec<f32>,
+    input_fill: usize,
+    rendered: Vec<f32>,
+    output: Vec<f32>,
+    output_read: usize,
+    output_available: usize,
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl BlockAdapter {
+    pub fn new(channels: usize, block_size: usize, buffer_size: usize) -> anyhow::Result<Self> {
+        if channels == 0 || block_size == 0 {
+            anyhow::bail!("BlockAdapter needs at least one channel and a non empty block");
+        }
+
+        let mut adapter = BlockAdapter {
+            channels,
+            block_size,
+            buffer_size: 0,
+            input: vec![0f32; channels * block_size],
+            input_fill: 0,
+            rendered: vec![0f32; block_size * 2],
+            output: vec![],
+            output_read: 0,
+            output_available: 0,
+        };
+
+        adapter.set_buffer_size(buffer_size)?;
+        Ok(adapter)
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// frames of delay added on top of the latency of the processor itself
+    pub fn latency(&self) -> usize {
+        self.block_size - gcd(self.buffer_size, self.block_size)
+    }
+
+    /// switch to host buffers of `size` frames, this drops anything still queued
+    pub fn set_buffer_size(&mut self, size: usize) -> anyhow::Result<()> {
+        if size == 0 {
+            anyhow::bail!("Buffer size needs to be at least 1 frame");
+        }
+
+        self.buffer_size = size;
+
+        // worst case the queue holds the latency, a buffer and the block rendered within it
+        self.output = vec![0f32; (self.latency() + size + self.block_size) * 2];
+        self.reset();
+
+        Ok(())
+    }
+
+    /// forget collected input and queued output, the output starts over with
+    /// [`latency`](Self::latency) frames of silence
+    pub fn reset(&mut self) {
+        self.input_fill = 0;
+        self.output.fill(0f32);
+        self.output_read = 0;
+        self.output_available = self.latency();
+    }
+
+    /// interleaved `input` of [`buffer_size`](Self::buffer_size) frames in, as many interleaved
+    /// stereo frames out. `render` gets an interleaved block of input and fills a block of
+    /// interleaved stereo output, anything it returns besides an error is ignored.
+    pub fn process<F, R>(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+        mut render: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(&[f32], &mut [f32]) -> anyhow::Result<R>,
+    {
+        if input.len() != self.buffer_size * self.channels || output.len() < self.buffer_size * 2 {
+            anyhow::bail!(
+                "BlockAdapter expected buffers of {} frames, got {} input and {} output frames",
+                self.buffer_size,
+                input.len() / self.channels,
+                output.len() / 2
+            );
+        }
+
+        for frame in input.chunks_exact(self.channels) {
+            let start = self.input_fill * self.channels;
+            self.input[start..start + self.channels].copy_from_slice(frame);
+            self.push_frame(&mut render)?;
+        }
+
+        for frame in output[..self.buffer_size * 2].chunks_exact_mut(2) {
+            let (left, right) = self.pop_frame();
+            frame[0] = left;
+            frame[1] = right;
+        }
+
+        Ok(())
+    }
+
+    /// like [`process`](Self::process) with one buffer per channel in and separate left and right
+    /// buffers out, the way JACK and most plugin hosts hand them over
+    pub fn process_planar<F, R>(
+        &mut self,
+        input: &[&[f32]],
+        left: &mut [f32],
+        right: &mut [f32],
+        mut render: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(&[f32], &mut [f32]) -> anyhow::Result<R>,
+    {
+        if input.len() != self.channels
+            || input.iter().any(|x| x.len() != self.buffer_size)
+            || left.len() < self.buffer_size
+            || right.len() < self.buffer_size
+        {
+            anyhow::bail!(
+                "BlockAdapter expected {} buffers of {} frames",
+                self.channels,
+                self.buffer_size
+            );
+        }
+
+        for s in 0..self.buffer_size {
+            let start = self.input_fill * self.channels;
+            for (sample, channel) in self.input[start..start + self.channels]
+                .iter_mut()
+                .zip(input)
+            {
+                *sample = channel[s];
+            }
+
+            self.push_frame(&mut render)?;
+        }
+
+        for (left, right) in left.iter_mut().zip(right.iter_mut()).take(self.buffer_size) {
+            let frame = self.pop_frame();
+            *left = frame.0;
+            *right = frame.1;
+        }
+
+        Ok(())
+    }
+
+    fn push_frame<F, R>(&mut self, render: &mut F) -> anyhow::Result<()>
+    where
+        F: FnMut(&[f32], &mut [f32]) -> anyhow::Result<R>,
+    {
+        self.input_fill += 1;
+        if self.input_fill < self.block_size {
+            return Ok(());
+        }
+
+        // processors may leave the output alone while they're still filling their window
+        self.input_fill = 0;
+        self.rendered.fill(0f32);
+        render(&self.input, &mut self.rendered)?;
+
+        let capacity = self.output.len() / 2;
+        for frame in self.rendered.chunks_exact(2) {
+            let index = (self.output_read + self.output_available) % capacity;
+            self.output[index * 2..index * 2 + 2].copy_from_slice(frame);
+            self.output_available += 1;
+        }
+
+        Ok(())
+    }
+
+    fn pop_frame(&mut self) -> (f32, f32) {
+        // the latency guarantees a block is rendered before the queue runs dry
+        debug_assert!(self.output_available > 0);
+
+        let index = self.output_read;
+        self.output_read = (self.output_read + 1) % (self.output.len() / 2);
+        self.output_available -= 1;
+
+        (self.output[index * 2], self.output[index * 2 + 1])
+    }
+}
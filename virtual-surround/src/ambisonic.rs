@@ -0,0 +1,562 @@
+use crate::dsp::invert;
+use crate::hrir::{Hrir, SpeakerIr};
+use crate::hrtf::{HrtfSource, SpeakerDirection};
+use crate::protection::OutputProtector;
+use crate::smooth::ramp_samples;
+use crate::Speaker;
+use crate::{
+    fft_len_for, ConvolutionEngine, EngineFactory, Orientation, OutputProtection, Smoothed,
+    BLOCK_SIZE,
+};
+use std::f64::consts::PI;
+
+/// third order, 16 channels
+pub const MAX_AMBISONIC_ORDER: usize = 3;
+
+/// directions used to fit the per order rotation matrices
+const ROTATION_POINTS: usize = 32;
+
+/// Channel order and normalization of ambisonic input
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum AmbisonicFormat {
+    /// ACN order (W, Y, Z, X, ...) with SN3D normalization
+    #[default]
+    AmbiX,
+    /// Furse-Malham, W, X, Y, Z, R, S, ... with maxN normalization and W at -3 dB
+    FuMa,
+}
+
+/// ACN index and SN3D gain of every FuMa channel
+const FUMA: [(usize, f32); 16] = [
+    (0, std::f32::consts::SQRT_2),
+    (3, 1.0),
+    (1, 1.0),
+    (2, 1.0),
+    (6, 1.0),
+    (7, 1.154_700_5),
+    (5, 1.154_700_5),
+    (8, 1.154_700_5),
+    (4, 1.154_700_5),
+    (12, 1.0),
+    (13, 1.185_854_1),
+    (11, 1.185_854_1),
+    (14, 1.341_640_8),
+    (10, 1.341_640_8),
+    (15, 1.264_911),
+    (9, 1.264_911),
+];
+
+/// channels of a full sphere signal of `order`
+pub fn ambisonic_channels(order: usize) -> usize {
+    (order + 1) * (order + 1)
+}
+
+/// real spherical harmonics in ACN order with SN3D normalization for a unit vector with x to the
+/// front, y to the left and z up
+fn spherical_harmonics(order: usize, [x, y, z]: [f64; 3]) -> Vec<f64> {
+    let sqrt3 = 3f64.sqrt();
+    let sqrt15 = 15f64.sqrt();
+    let sqrt3_8 = (3f64 / 8.0).sqrt();
+    let sqrt5_8 = (5f64 / 8.0).sqrt();
+
+    let all = [
+        1.0,
+        y,
+        z,
+        x,
+        sqrt3 * x * y,
+        sqrt3 * y * z,
+        (3.0 * z * z - 1.0) / 2.0,
+        sqrt3 * x * z,
+        sqrt3 / 2.0 * (x * x - y * y),
+        sqrt5_8 * y * (3.0 * x * x - y * y),
+        sqrt15 * x * y * z,
+        sqrt3_8 * y * (5.0 * z * z - 1.0),
+        z * (5.0 * z * z - 3.0) / 2.0,
+        sqrt3_8 * x * (5.0 * z * z - 1.0),
+        sqrt15 / 2.0 * z * (x * x - y * y),
+        sqrt5_8 * x * (x * x - 3.0 * y * y),
+    ];
+
+    all[..ambisonic_channels(order)].to_vec()
+}
+
+/// roughly uniform spiral of `points` unit vectors over the sphere
+pub(crate) fn fibonacci_grid(points: usize) -> Vec<[f64; 3]> {
+    let angle = PI * (3.0 - 5f64.sqrt());
+
+    (0..points)
+        .map(|i| {
+            let z = 1.0 - (2 * i + 1) as f64 / points as f64;
+            let radius = (1.0 - z * z).sqrt();
+            let (sin, cos) = (angle * i as f64).sin_cos();
+            [radius * cos, radius * sin, z]
+        })
+        .collect()
+}
+
+/// `a * b^T`
+fn multiply_transposed(a: &[Vec<f64>], b: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    a.iter()
+        .map(|row| {
+            b.iter()
+                .map(|other| row.iter().zip(other).map(|(x, y)| x * y).sum())
+                .collect()
+        })
+        .collect()
+}
+
+pub(crate) type Matrix = [[f64; 3]; 3];
+
+/// rotation of the sound field in degrees, yaw turns it to the left, pitch tilts the front up and
+/// roll lifts the right side
+pub(crate) fn rotation_matrix(yaw: f32, pitch: f32, roll: f32) -> Matrix {
+    let (sy, cy) = (yaw as f64).to_radians().sin_cos();
+    let (sp, cp) = (-pitch as f64).to_radians().sin_cos();
+    let (sr, cr) = (roll as f64).to_radians().sin_cos();
+
+    // z * y * x with x to the front, y to the left and z up
+    [
+        [cy * cp, cy * sp * sr - sy * cr, cy * sp * cr + sy * sr],
+        [sy * cp, sy * sp * sr + cy * cr, sy * sp * cr - cy * sr],
+        [-sp, cp * sr, cp * cr],
+    ]
+}
+
+/// block diagonal matrix turning the harmonics of a direction into the harmonics of the rotated
+/// direction, fitted per order by least squares over a grid of directions
+fn harmonic_rotation(order: usize, rotation: &Matrix) -> Vec<Vec<f32>> {
+    let channels = ambisonic_channels(order);
+    let mut output = vec![vec![0f32; channels]; channels];
+    output[0][0] = 1.0;
+
+    let points = fibonacci_grid(ROTATION_POINTS);
+    let rotated = points
+        .iter()
+        .map(|p| [0, 1, 2].map(|r| (0..3).map(|c| rotation[r][c] * p[c]).sum()))
+        .collect::<Vec<_>>();
+
+    let harmonics = |points: &[[f64; 3]], range: std::ops::Range<usize>| {
+        range
+            .map(|channel| {
+                points
+                    .iter()
+                    .map(|p| spherical_harmonics(order, *p)[channel])
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    for degree in 1..=order {
+        let range = degree * degree..(degree + 1) * (degree + 1);
+        let a = harmonics(&points, range.clone());
+        let b = harmonics(&rotated, range.clone());
+
+        // M = B A^T (A A^T)^-1
+        let inverse = invert(&multiply_transposed(&a, &a)).expect("rotation grid is degenerate");
+        let ba = multiply_transposed(&b, &a);
+
+        for (i, row) in ba.iter().enumerate() {
+            for j in 0..range.len() {
+                output[range.start + i][range.start + j] = row
+                    .iter()
+                    .zip(&inverse)
+                    .map(|(x, inv)| x * inv[j])
+                    .sum::<f64>() as f32;
+            }
+        }
+    }
+
+    output
+}
+
+/// Binauralizes ambisonics up to third order by decoding to virtual speakers and folding their
+/// impulse responses into one left/right pair per ambisonic channel, so the cost doesn't depend on
+/// the number of virtual speakers
+#[derive(Debug)]
+pub struct AmbisonicVirtualizer {
+    order: usize,
+    format: AmbisonicFormat,
+    sample_rate: usize,
+    engine: Box<dyn ConvolutionEngine>,
+    fft_len: usize,
+    available_data: usize,
+    in_space: Vec<Vec<f32>>,
+    frame: Vec<f32>,
+    left_out_space: Vec<f32>,
+    right_out_space: Vec<f32>,
+    rotation: Vec<Vec<f32>>,
+    target_rotation: Vec<Vec<f32>>,
+    /// from `rotation` (0.0) to `target_rotation` (1.0)
+    progress: Smoothed,
+    protection: OutputProtector,
+}
+
+impl AmbisonicVirtualizer {
+    /// decode to the speakers of `hrir`, assuming they're placed at their
+    /// [standard directions](SpeakerDirection::standard), the LFE is skipped. A surround layout
+    /// can't resolve much more than first order.
+    pub fn from_hrir(hrir: &Hrir, order: usize, engine: EngineFactory) -> anyhow::Result<Self> {
+        let speakers = hrir
+            .speakers
+            .iter()
+            .filter(|x| x.position != Speaker::LowFrequency)
+            .filter_map(|x| {
+                SpeakerDirection::standard(x.position)
+                    .map(|direction| (direction.cartesian(), x.left.clone(), x.right.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        Self::from_speakers(speakers, order, hrir.sample_rate as usize, engine)
+    }
+
+    /// decode to a dense grid of directions sampled from `source`
+    pub fn from_hrtf<S: HrtfSource + ?Sized>(
+        source: &S,
+        order: usize,
+        engine: EngineFactory,
+    ) -> anyhow::Result<Self> {
+        let directions = fibonacci_grid(ambisonic_channels(order) * 4)
+            .iter()
+            .map(|[x, y, z]| {
+                SpeakerDirection::new(
+                    Speaker::DirectOut,
+                    y.atan2(*x).to_degrees() as f32,
+                    z.asin().to_degrees() as f32,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut hrir = Hrir {
+            sample_rate: source.sample_rate(),
+            speakers: vec![],
+        };
+
+        for direction in &directions {
+            let (left, right) = source.impulse(direction)?;
+            hrir.speakers.push(SpeakerIr {
+                position: Speaker::DirectOut,
+                left,
+                right,
+            });
+        }
+
+        // normalize expects equally long responses
+        let length = hrir
+            .speakers
+            .iter()
+            .map(|x| x.left.len().max(x.right.len()))
+            .max()
+            .unwrap_or(0);
+        for speaker in &mut hrir.speakers {
+            speaker.left.resize(length, 0f32);
+            speaker.right.resize(length, 0f32);
+        }
+
+        // normalized as a set, then brought back up so a full scale diffuse field, which the
+        // decoder spreads evenly over the grid, lands where normalize puts the sum of all speakers
+        hrir.normalize();
+        let count = directions.len() as f32;
+
+        let speakers = directions
+            .iter()
+            .zip(hrir.speakers)
+            .map(|(direction, ir)| {
+                let scale = |x: Vec<f32>| x.into_iter().map(|x| x * count).collect();
+                (direction.cartesian(), scale(ir.left), scale(ir.right))
+            })
+            .collect();
+
+        Self::from_speakers(speakers, order, source.sample_rate() as usize, engine)
+    }
+
+    fn from_speakers(
+        speakers: Vec<([f32; 3], Vec<f32>, Vec<f32>)>,
+        order: usize,
+        sample_rate: usize,
+        engine: EngineFactory,
+    ) -> anyhow::Result<Self> {
+        if order == 0 || order > MAX_AMBISONIC_ORDER {
+            anyhow::bail!(
+                "Ambisonic order {} isn't supported, only 1 up to {}",
+                order,
+                MAX_AMBISONIC_ORDER
+            );
+        }
+
+        if speakers.is_empty() {
+            anyhow::bail!("Ambisonic decoding needs at least one virtual speaker");
+        }
+
+        let channels = ambisonic_channels(order);
+        let length = speakers
+            .iter()
+            .map(|x| x.1.len().max(x.2.len()))
+            .max()
+            .unwrap_or(0);
+        let fft_len = fft_len_for(length);
+
+        // mode matching decoder, D = Y^T (Y Y^T + λI)^-1, regularized so directions the speakers
+        // don't cover (e.g. height on a surround layout) are left out instead of blowing up
+        let harmonics = (0..channels)
+            .map(|channel| {
+                speakers
+                    .iter()
+                    .map(|([x, y, z], _, _)| {
+                        spherical_harmonics(order, [*x as f64, *y as f64, *z as f64])[channel]
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let mut gram = multiply_transposed(&harmonics, &harmonics);
+        let trace = (0..channels).map(|i| gram[i][i]).sum::<f64>();
+        for (i, row) in gram.iter_mut().enumerate() {
+            row[i] += 0.01 * trace / channels as f64;
+        }
+
+        let inverse =
+            invert(&gram).ok_or_else(|| anyhow::anyhow!("Ambisonic decoder is singular"))?;
+
+        let mut irs = vec![vec![0f32; fft_len]; channels * 2];
+        for (s, (_, left, right)) in speakers.iter().enumerate() {
+            for channel in 0..channels {
+                let gain = (0..channels)
+                    .map(|j| harmonics[j][s] * inverse[j][channel])
+                    .sum::<f64>() as f32;
+
+                for (ear, ir) in [left, right].iter().enumerate() {
+                    for (output, sample) in irs[channel * 2 + ear].iter_mut().zip(ir.iter()) {
+                        *output += sample * gain;
+                    }
+                }
+            }
+        }
+
+        let mut engine = engine(channels, fft_len)?;
+        engine.init_irs(&irs.into_iter().enumerate().collect::<Vec<_>>())?;
+
+        let identity = harmonic_rotation(order, &rotation_matrix(0.0, 0.0, 0.0));
+
+        Ok(AmbisonicVirtualizer {
+            order,
+            format: AmbisonicFormat::default(),
+            sample_rate,
+            engine,
+            fft_len,
+            available_data: 0,
+            in_space: vec![vec![0f32; fft_len]; channels],
+            frame: vec![0f32; channels],
+            left_out_space: vec![0f32; BLOCK_SIZE],
+            right_out_space: vec![0f32; BLOCK_SIZE],
+            rotation: identity.clone(),
+            target_rotation: identity,
+            progress: Smoothed::new(1.0, BLOCK_SIZE),
+            protection: OutputProtector::new(OutputProtection::default(), sample_rate),
+        })
+    }
+
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    /// input channels per frame, `(order + 1)²`
+    pub fn channels(&self) -> usize {
+        ambisonic_channels(self.order)
+    }
+
+    pub fn set_format(&mut self, format: AmbisonicFormat) {
+        self.format = format;
+    }
+
+    pub fn format(&self) -> AmbisonicFormat {
+        self.format
+    }
+
+    /// time in milliseconds rotations ramp over, a block by default, see
+    /// [`FilterBuilder::ramp_time`](crate::FilterBuilder::ramp_time)
+    pub fn set_ramp_time(&mut self, ms: f32) {
+        self.progress
+            .set_ramp(ramp_samples(ms, self.sample_rate).max(1));
+    }
+
+    pub fn ramp_time(&self) -> f32 {
+        self.progress.ramp() as f32 * 1000.0 / self.sample_rate as f32
+    }
+
+    /// ramp to `rotation` from wherever the rotation got to
+    fn rotate_to(&mut self, rotation: Vec<Vec<f32>>) {
+        let t = self.progress.value();
+        for (from, to) in self.rotation.iter_mut().zip(&self.target_rotation) {
+            for (from, to) in from.iter_mut().zip(to) {
+                *from += (to - *from) * t;
+            }
+        }
+
+        self.target_rotation = rotation;
+        self.progress.set_immediately(0.0);
+        self.progress.set(1.0);
+    }
+
+    /// rotate the sound field in degrees, pass the inverse of the head orientation to keep the
+    /// scene in place while the head turns. The change is spread over the
+    /// [`ramp_time`](Self::set_ramp_time).
+    pub fn set_rotation(&mut self, yaw: f32, pitch: f32, roll: f32) {
+        self.rotate_to(harmonic_rotation(
+            self.order,
+            &rotation_matrix(yaw, pitch, roll),
+        ));
+    }
+
+    /// rotate the sound field against `orientation`, e.g. from a
+    /// [`HeadTracker`](crate::HeadTracker), so the scene stays in place while the head turns
+    pub fn set_head_orientation(&mut self, orientation: Orientation) {
+        let rotation = rotation_matrix(orientation.yaw, orientation.pitch, orientation.roll);
+        let inverse = [0, 1, 2].map(|r| [0, 1, 2].map(|c| rotation[c][r]));
+        self.rotate_to(harmonic_rotation(self.order, &inverse));
+    }
+
+    pub fn set_output_protection(&mut self, protection: OutputProtection) {
+        self.protection = OutputProtector::new(protection, self.sample_rate);
+    }
+
+    pub fn samples_required(&self) -> usize {
+        self.fft_len
+    }
+
+    pub fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    pub fn sample_latency(&self) -> usize {
+        self.engine.latency() + self.protection.latency()
+    }
+
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    /// like [`VirtualSurroundFilter::transform`](crate::VirtualSurroundFilter::transform) with
+    /// [`channels`](Self::channels) interleaved ambisonic channels as input
+    pub fn transform(&mut self, input: &[f32], output: &mut [f32]) -> anyhow::Result<()> {
+        let channels = self.channels();
+
+        if input.len() != BLOCK_SIZE * channels || output.len() < BLOCK_SIZE * 2 {
+            anyhow::bail!(
+                "transform takes blocks of {} frames, got {} input and {} output frames, use a BlockAdapter for other buffer sizes",
+                BLOCK_SIZE,
+                input.len() / channels,
+                output.len() / 2
+            );
+        }
+
+        if self.available_data == self.fft_len {
+            for space in &mut self.in_space {
+                space.copy_within(BLOCK_SIZE.., 0);
+            }
+
+            self.available_data -= BLOCK_SIZE;
+        }
+
+        let offset = self.available_data;
+
+        for (s, frame) in input.chunks_exact(channels).enumerate() {
+            match self.format {
+                AmbisonicFormat::AmbiX => self.frame.copy_from_slice(frame),
+                AmbisonicFormat::FuMa => {
+                    for (sample, (acn, gain)) in frame.iter().zip(FUMA.iter()) {
+                        self.frame[*acn] = sample * gain;
+                    }
+                }
+            }
+
+            let t = self.progress.next_value();
+
+            for degree in 0..=self.order {
+                let range = degree * degree..(degree + 1) * (degree + 1);
+
+                for row in range.clone() {
+                    let (from, to) = (&self.rotation[row], &self.target_rotation[row]);
+                    self.in_space[row][offset + s] = range
+                        .clone()
+                        .map(|c| (from[c] + (to[c] - from[c]) * t) * self.frame[c])
+                        .sum();
+                }
+            }
+        }
+
+        if !self.progress.is_ramping() {
+            self.rotation.clone_from(&self.target_rotation);
+        }
+        self.available_data += BLOCK_SIZE;
+
+        if self.available_data < self.fft_len {
+            return Ok(());
+        }
+
+        self.left_out_space.fill(0f32);
+        self.right_out_space.fill(0f32);
+
+        for (channel, window) in self.in_space.iter().enumerate() {
+            self.engine.process(
+                channel,
+                window,
+                &mut self.left_out_space,
+                &mut self.right_out_space,
+            )?;
+        }
+
+        self.protection.process(
+            &self.left_out_space,
+            &self.right_out_space,
+            &mut output[..BLOCK_SIZE * 2],
+        );
+
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        self.engine.reset();
+        self.available_data = 0;
+        for space in &mut self.in_space {
+            space.fill(0f32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{harmonic_rotation, rotation_matrix, spherical_harmonics, MAX_AMBISONIC_ORDER};
+
+    #[test]
+    fn yaw_turns_front_to_the_left() {
+        let m = rotation_matrix(90.0, 0.0, 0.0);
+        assert!((m[1][0] - 1.0).abs() < 1e-9);
+
+        // pitching up lifts the front
+        let m = rotation_matrix(0.0, 90.0, 0.0);
+        assert!((m[2][0] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rotated_harmonics_match_rotated_direction() {
+        let order = MAX_AMBISONIC_ORDER;
+        let rotation = rotation_matrix(40.0, -25.0, 70.0);
+        let harmonic = harmonic_rotation(order, &rotation);
+
+        let direction = [0.48f64, -0.6, 0.64];
+        let rotated = [0, 1, 2].map(|r| (0..3).map(|c| rotation[r][c] * direction[c]).sum());
+
+        let input = spherical_harmonics(order, direction);
+        let expected = spherical_harmonics(order, rotated);
+
+        for (row, expected) in harmonic.iter().zip(expected) {
+            let value = row
+                .iter()
+                .zip(&input)
+                .map(|(m, x)| *m as f64 * x)
+                .sum::<f64>();
+            assert!((value - expected).abs() < 1e-4, "{} != {}", value, expected);
+        }
+    }
+}
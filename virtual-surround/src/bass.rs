@@ -0,0 +1,147 @@
+use crate::biquad::LinkwitzRiley;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BassManagement {
+    /// crossover frequency in hz
+    pub crossover: f32,
+    /// gain applied to the summed bass before it's fed to both ears
+    pub gain_db: f32,
+    /// also move the content below the crossover out of the other channels into the bass sum
+    pub redirect_satellites: bool,
+}
+
+impl Default for BassManagement {
+    fn default() -> Self {
+        BassManagement {
+            crossover: 80.0,
+            gain_db: 0.0,
+            redirect_satellites: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BassManager {
+    lfe: Option<usize>,
+    gain: f32,
+    redirect_satellites: bool,
+    low_pass: Vec<LinkwitzRiley>,
+    high_pass: Vec<LinkwitzRiley>,
+    space: Vec<f32>,
+}
+
+impl BassManager {
+    pub fn new(
+        config: BassManagement,
+        sample_rate: usize,
+        channels: usize,
+        lfe: Option<usize>,
+        window: usize,
+    ) -> Self {
+        BassManager {
+            lfe,
+            gain: 10f32.powf(config.gain_db / 20.0),
+            redirect_satellites: config.redirect_satellites,
+            low_pass: vec![LinkwitzRiley::low_pass(sample_rate, config.crossover); channels],
+            high_pass: vec![LinkwitzRiley::high_pass(sample_rate, config.crossover); channels],
+            space: vec![0f32; window],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for filter in self.low_pass.iter_mut().chain(&mut self.high_pass) {
+            filter.reset();
+        }
+
+        self.space.fill(0f32);
+    }
+
+    pub fn shift(&mut self, amount: usize) {
+        self.space.copy_within(amount.., 0);
+    }
+
+    /// takes the bass out of `input[..][offset..offset + len]`, LFE is removed entirely, satellites
+    /// only when redirected
+    pub fn process(&mut self, input: &mut [Vec<f32>], offset: usize, len: usize) {
+        self.space[offset..offset + len].fill(0f32);
+
+        for (c, channel) in input.iter_mut().enumerate() {
+            let is_lfe = Some(c) == self.lfe;
+            if !is_lfe && !self.redirect_satellites {
+                continue;
+            }
+
+            let bass = &mut self.space[offset..offset + len];
+
+            for (sample, bass) in channel[offset..offset + len].iter_mut().zip(bass) {
+                *bass += self.low_pass[c].process(*sample) * self.gain;
+
+                *sample = if is_lfe {
+                    0f32
+                } else {
+                    self.high_pass[c].process(*sample)
+                };
+            }
+        }
+    }
+
+    /// bass for the window range ending at `end`, added to both ears
+    pub fn mix_into(&self, end: usize, left: &mut [f32], right: &mut [f32]) {
+        let bass = &self.space[end - left.len()..end];
+
+        for (s, sample) in bass.iter().enumerate() {
+            left[s] += sample;
+            right[s] += sample;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BassManagement, BassManager};
+    use std::f32::consts::TAU;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    /// rms of a sine at `frequency` on the satellite and the LFE after processing, and of the bass
+    /// mixed into an ear, once the filters settled
+    fn split(frequency: f32) -> (f32, f32, f32) {
+        let config = BassManagement {
+            redirect_satellites: true,
+            ..BassManagement::default()
+        };
+        let mut manager = BassManager::new(config, 48000, 2, Some(1), 48000);
+
+        let sine = (0..48000)
+            .map(|x| (x as f32 * frequency / 48000.0 * TAU).sin())
+            .collect::<Vec<_>>();
+        let mut input = vec![sine.clone(), sine];
+        manager.process(&mut input, 0, 48000);
+
+        let mut left = vec![0f32; 24000];
+        let mut right = vec![0f32; 24000];
+        manager.mix_into(48000, &mut left, &mut right);
+        assert_eq!(left, right);
+
+        (rms(&input[0][24000..]), rms(&input[1][24000..]), rms(&left))
+    }
+
+    #[test]
+    fn moves_low_frequencies_to_the_bass() {
+        let sine = std::f32::consts::FRAC_1_SQRT_2;
+
+        // the LFE is removed and summed with the satellite's low end
+        let (satellite, lfe, bass) = split(30.0);
+        assert_eq!(lfe, 0.0);
+        assert!(satellite < sine * 0.05, "{}", satellite);
+        assert!((bass - sine * 2.0).abs() < sine * 0.1, "{}", bass);
+
+        // above the crossover the satellite keeps it and next to nothing reaches the bass
+        let (satellite, lfe, bass) = split(2000.0);
+        assert_eq!(lfe, 0.0);
+        assert!((satellite - sine).abs() < sine * 0.01, "{}", satellite);
+        assert!(bass < 0.001, "{}", bass);
+    }
+}
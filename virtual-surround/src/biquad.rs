@@ -0,0 +1,183 @@
+use std::f32::consts::PI;
+
+pub const BUTTERWORTH_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Biquad filter using the coefficients from the RBJ audio EQ cookbook, run as transposed direct
+/// form II
+#[derive(Debug, Copy, Clone)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    fn from_coefficients(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    fn omega(sample_rate: usize, frequency: f32) -> (f32, f32) {
+        let w0 = 2.0 * PI * frequency / sample_rate as f32;
+        (w0.cos(), w0.sin())
+    }
+
+    pub fn low_pass(sample_rate: usize, frequency: f32, q: f32) -> Self {
+        let (cos, sin) = Self::omega(sample_rate, frequency);
+        let alpha = sin / (2.0 * q);
+
+        Self::from_coefficients(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn high_pass(sample_rate: usize, frequency: f32, q: f32) -> Self {
+        let (cos, sin) = Self::omega(sample_rate, frequency);
+        let alpha = sin / (2.0 * q);
+
+        Self::from_coefficients(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn peaking(sample_rate: usize, frequency: f32, q: f32, gain_db: f32) -> Self {
+        let (cos, sin) = Self::omega(sample_rate, frequency);
+        let alpha = sin / (2.0 * q);
+        let a = 10f32.powf(gain_db / 40.0);
+
+        Self::from_coefficients(
+            1.0 + alpha * a,
+            -2.0 * cos,
+            1.0 - alpha * a,
+            1.0 + alpha / a,
+            -2.0 * cos,
+            1.0 - alpha / a,
+        )
+    }
+
+    pub fn low_shelf(sample_rate: usize, frequency: f32, q: f32, gain_db: f32) -> Self {
+        let (cos, sin) = Self::omega(sample_rate, frequency);
+        let alpha = sin / (2.0 * q);
+        let a = 10f32.powf(gain_db / 40.0);
+        let sqrt = 2.0 * a.sqrt() * alpha;
+
+        Self::from_coefficients(
+            a * ((a + 1.0) - (a - 1.0) * cos + sqrt),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - sqrt),
+            (a + 1.0) + (a - 1.0) * cos + sqrt,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - sqrt,
+        )
+    }
+
+    pub fn high_shelf(sample_rate: usize, frequency: f32, q: f32, gain_db: f32) -> Self {
+        let (cos, sin) = Self::omega(sample_rate, frequency);
+        let alpha = sin / (2.0 * q);
+        let a = 10f32.powf(gain_db / 40.0);
+        let sqrt = 2.0 * a.sqrt() * alpha;
+
+        Self::from_coefficients(
+            a * ((a + 1.0) + (a - 1.0) * cos + sqrt),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - sqrt),
+            (a + 1.0) - (a - 1.0) * cos + sqrt,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - sqrt,
+        )
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+}
+
+/// 4th order Linkwitz-Riley crossover half, two cascaded butterworth sections
+#[derive(Debug, Copy, Clone)]
+pub struct LinkwitzRiley([Biquad; 2]);
+
+impl LinkwitzRiley {
+    pub fn low_pass(sample_rate: usize, frequency: f32) -> Self {
+        let section = Biquad::low_pass(sample_rate, frequency, BUTTERWORTH_Q);
+        LinkwitzRiley([section, section])
+    }
+
+    pub fn high_pass(sample_rate: usize, frequency: f32) -> Self {
+        let section = Biquad::high_pass(sample_rate, frequency, BUTTERWORTH_Q);
+        LinkwitzRiley([section, section])
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let first = self.0[0].process(input);
+        self.0[1].process(first)
+    }
+
+    pub fn reset(&mut self) {
+        self.0[0].reset();
+        self.0[1].reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LinkwitzRiley;
+    use std::f32::consts::TAU;
+
+    /// gain of `filter` on a sine at `frequency` once it settled
+    fn gain<F: FnMut(f32) -> f32>(mut filter: F, frequency: f32) -> f32 {
+        let output = (0..48000)
+            .map(|x| filter((x as f32 * frequency / 48000.0 * TAU).sin()))
+            .skip(24000)
+            .map(|x| x * x)
+            .sum::<f32>();
+
+        (output / 12000.0).sqrt()
+    }
+
+    #[test]
+    fn crossover_halves_sum_flat() {
+        for frequency in [20.0, 80.0, 250.0, 1000.0, 10000.0] {
+            let mut low = LinkwitzRiley::low_pass(48000, 80.0);
+            let mut high = LinkwitzRiley::high_pass(48000, 80.0);
+            let sum = gain(|x| low.process(x) + high.process(x), frequency);
+            assert!((sum - 1.0).abs() < 0.01, "{} at {}hz", sum, frequency);
+        }
+
+        // each half is 6 dB down at the crossover and keeps out of the other's band
+        let mut low = LinkwitzRiley::low_pass(48000, 80.0);
+        assert!((gain(|x| low.process(x), 80.0) - 0.5).abs() < 0.01);
+        let mut low = LinkwitzRiley::low_pass(48000, 80.0);
+        assert!(gain(|x| low.process(x), 2000.0) < 0.001);
+        let mut high = LinkwitzRiley::high_pass(48000, 80.0);
+        assert!(gain(|x| high.process(x), 20.0) < 0.01);
+    }
+}
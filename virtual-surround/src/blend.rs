@@ -0,0 +1,257 @@
+use crate::biquad::LinkwitzRiley;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+
+/// interaural delay of the crossfed signal
+const CROSSFEED_DELAY_MS: f32 = 0.3;
+
+/// One frequency band of a [`BlendPreset`]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BlendBand {
+    /// upper edge of the band in hz, `None` for the last band
+    pub up_to: Option<f32>,
+    /// share of the HRTF rendering in this band, the rest is crossfed stereo downmix
+    pub hrtf: f32,
+    /// level of the opposite channel in the crossfed signal
+    pub crossfeed_db: f32,
+}
+
+/// Per band blend between the HRTF rendering and a plain crossfeed of the stereo downmix, usually
+/// tuned for a headphone model.
+///
+/// The text format has one `key = value` per line, `#` starts a comment:
+///
+/// ```text
+/// name = open back, music
+/// # band = <upper edge in hz or max> <hrtf percentage> <crossfeed db>
+/// band = 700 40 -4.5
+/// band = max 80 -inf
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlendPreset {
+    pub name: String,
+    pub bands: Vec<BlendBand>,
+}
+
+impl BlendPreset {
+    pub fn from_reader<R: Read>(mut reader: R) -> anyhow::Result<Self> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut name = String::new();
+        let mut bands = vec![];
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => anyhow::bail!("Preset line without a value: {}", line),
+            };
+
+            match key {
+                "name" => name = value.to_string(),
+                "band" => {
+                    let fields = value.split_whitespace().collect::<Vec<_>>();
+                    if fields.len() != 3 {
+                        anyhow::bail!(
+                            "Preset band needs an edge, hrtf % and crossfeed db: {}",
+                            line
+                        );
+                    }
+
+                    bands.push(BlendBand {
+                        up_to: match fields[0] {
+                            "max" => None,
+                            edge => Some(edge.parse()?),
+                        },
+                        hrtf: fields[1].parse::<f32>()? / 100.0,
+                        crossfeed_db: fields[2].parse()?,
+                    });
+                }
+                key => anyhow::bail!("Unknown preset key {}", key),
+            }
+        }
+
+        let preset = BlendPreset { name, bands };
+        preset.validate()?;
+        Ok(preset)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let (last, rest) = match self.bands.split_last() {
+            Some(bands) => bands,
+            None => anyhow::bail!("Preset {} has no bands", self.name),
+        };
+
+        if last.up_to.is_some() || rest.iter().any(|x| x.up_to.is_none()) {
+            anyhow::bail!(
+                "Only the last band of preset {} may go up to max",
+                self.name
+            );
+        }
+
+        let edges = rest.iter().filter_map(|x| x.up_to).collect::<Vec<_>>();
+        if edges.windows(2).any(|x| x[0] >= x[1]) || edges.first().is_some_and(|x| *x <= 0.0) {
+            anyhow::bail!("Band edges of preset {} have to be increasing", self.name);
+        }
+
+        if self.bands.iter().any(|x| !(0.0..=1.0).contains(&x.hrtf)) {
+            anyhow::bail!("HRTF share of preset {} has to be within 0-100%", self.name);
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for BlendPreset {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "name = {}", self.name)?;
+
+        for band in &self.bands {
+            match band.up_to {
+                Some(edge) => write!(f, "band = {}", edge)?,
+                None => write!(f, "band = max")?,
+            }
+
+            writeln!(f, " {} {}", band.hrtf * 100.0, band.crossfeed_db)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BandState {
+    hrtf: f32,
+    crossfeed: f32,
+    filters: [Vec<LinkwitzRiley>; 2],
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BlendProcessor {
+    bands: Vec<BandState>,
+    delay: [Vec<f32>; 2],
+    position: usize,
+}
+
+impl BlendProcessor {
+    pub fn new(preset: &BlendPreset, sample_rate: usize) -> Self {
+        let mut lower = None;
+        let bands = preset
+            .bands
+            .iter()
+            .map(|band| {
+                let mut chain = vec![];
+                if let Some(lower) = lower {
+                    chain.push(LinkwitzRiley::high_pass(sample_rate, lower));
+                }
+
+                if let Some(upper) = band.up_to {
+                    chain.push(LinkwitzRiley::low_pass(sample_rate, upper));
+                }
+
+                lower = band.up_to;
+
+                BandState {
+                    hrtf: band.hrtf,
+                    crossfeed: 10f32.powf(band.crossfeed_db / 20.0),
+                    filters: [chain.clone(), chain],
+                }
+            })
+            .collect();
+
+        let delay = ((CROSSFEED_DELAY_MS / 1000.0) * sample_rate as f32)
+            .round()
+            .max(1.0) as usize;
+
+        BlendProcessor {
+            bands,
+            delay: [vec![0f32; delay], vec![0f32; delay]],
+            position: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for band in &mut self.bands {
+            for filter in band.filters.iter_mut().flatten() {
+                filter.reset();
+            }
+        }
+
+        for delay in &mut self.delay {
+            delay.fill(0f32);
+        }
+    }
+
+    /// blend the HRTF rendering in `left` and `right` with a crossfeed of the `dry` downmix
+    pub fn process(&mut self, dry: (&[f32], &[f32]), left: &mut [f32], right: &mut [f32]) {
+        for s in 0..left.len() {
+            let dry_left = dry.0[s];
+            let dry_right = dry.1[s];
+
+            let slot = self.position;
+            let delayed_left = std::mem::replace(&mut self.delay[0][slot], dry_left);
+            let delayed_right = std::mem::replace(&mut self.delay[1][slot], dry_right);
+            self.position = (self.position + 1) % self.delay[0].len();
+
+            let mut output = [0f32; 2];
+
+            for band in &mut self.bands {
+                let normalize = 1.0 / (1.0 + band.crossfeed);
+                let crossfed = [
+                    (dry_left + delayed_right * band.crossfeed) * normalize,
+                    (dry_right + delayed_left * band.crossfeed) * normalize,
+                ];
+
+                let wet = [left[s], right[s]];
+
+                for ear in 0..2 {
+                    let mixed = wet[ear] * band.hrtf + crossfed[ear] * (1.0 - band.hrtf);
+                    output[ear] += band.filters[ear]
+                        .iter_mut()
+                        .fold(mixed, |x, filter| filter.process(x));
+                }
+            }
+
+            left[s] = output[0];
+            right[s] = output[1];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlendBand, BlendPreset};
+
+    #[test]
+    fn preset_round_trip() {
+        let preset =
+            BlendPreset::parse("name = test\nband = 700 40 -4.5 # bass\nband = max 100 -inf\n")
+                .unwrap();
+
+        assert_eq!(
+            preset.bands,
+            vec![
+                BlendBand {
+                    up_to: Some(700.0),
+                    hrtf: 0.4,
+                    crossfeed_db: -4.5
+                },
+                BlendBand {
+                    up_to: None,
+                    hrtf: 1.0,
+                    crossfeed_db: f32::NEG_INFINITY
+                },
+            ]
+        );
+
+        assert_eq!(BlendPreset::parse(&preset.to_string()).unwrap(), preset);
+    }
+}
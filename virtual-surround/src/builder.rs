@@ -0,0 +1,435 @@
+use crate::config::parse_layout;
+use crate::diagnostics::{diagnose, Diagnostic, DiagnosticsSink};
+use crate::hrir::{
+    EarLayout, FadeWindow, Hrir, Normalization, OnsetAlignment, KEMAR_HEAD_RADIUS_CM,
+};
+use crate::prepared::Prepared;
+use crate::{
+    ambisonic_channels, fft_len_for, AmbisonicVirtualizer, ConvolutionStrategy, Diagnostics,
+    Engine, EngineFactory, FilterConfig, RawVirtualSurroundFilter, ResampleQuality, RoomModel,
+    Speaker, SpectrumPrecision, VirtualSurroundFilter, BLOCK_SIZE,
+};
+use std::io::{Read, Seek};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default)]
+enum EngineSelection {
+    #[default]
+    Default,
+    Factory(EngineFactory),
+    Fastest(Option<PathBuf>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FilterBuilder {
+    sample_rate: Option<u32>,
+    ear_layout: EarLayout,
+    engine: EngineSelection,
+    strategy: ConvolutionStrategy,
+    precision: SpectrumPrecision,
+    onset_alignment: Option<OnsetAlignment>,
+    trim_silence: bool,
+    threads: Option<usize>,
+    normalization: Normalization,
+    layout: Option<Vec<Speaker>>,
+    truncation: Option<(f32, FadeWindow)>,
+    minimum_phase: bool,
+    diffuse_field_eq: bool,
+    itd_scale: Option<f32>,
+    room: Option<RoomModel>,
+    ramp_ms: Option<f32>,
+    skip_unusable: bool,
+    resample_quality: ResampleQuality,
+    diagnostics: Option<DiagnosticsSink>,
+}
+
+impl FilterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// resample the HRIR to the given sample rate, requires the `resample` feature
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    /// how carefully the HRIR is resampled, see [`ResampleQuality`]
+    pub fn resample_quality(mut self, quality: ResampleQuality) -> Self {
+        self.resample_quality = quality;
+        self
+    }
+
+    /// builder set up for the HRIR settings of `config`, see [`FilterConfig::build`]
+    pub fn from_config(config: &FilterConfig) -> anyhow::Result<Self> {
+        if config.block_size != BLOCK_SIZE {
+            anyhow::bail!(
+                "Config asks for blocks of {} frames, only {} is supported",
+                config.block_size,
+                BLOCK_SIZE
+            );
+        }
+
+        let mut builder = FilterBuilder::new()
+            .ear_layout(config.ear_layout)
+            .normalization(config.normalization)
+            .minimum_phase(config.minimum_phase)
+            .diffuse_field_eq(config.diffuse_field_eq)
+            .trim_leading_silence(config.trim_leading_silence)
+            .resample_quality(config.resample_quality)
+            .spectrum_precision(config.spectrum_precision)
+            .skip_unusable_channels(config.skip_unusable_channels);
+        if let Some(sample_rate) = config.sample_rate {
+            builder = builder.sample_rate(sample_rate);
+        }
+        if let Some(cm) = config.head_radius_cm {
+            builder = builder.head_radius(cm);
+        }
+        if let Some(room) = config.room {
+            builder = builder.room(room);
+        }
+        if let Some(ms) = config.truncate_ms {
+            builder = builder.truncate(ms, config.truncate_window);
+        }
+        if let Some(layout) = &config.hrir_layout {
+            builder = builder.with_layout(&parse_layout(layout)?);
+        }
+        if !config.engines.is_empty() {
+            let names = config
+                .engines
+                .iter()
+                .map(|x| x.as_str())
+                .collect::<Vec<_>>();
+            builder = builder.engine(Engine::preferred(&names)?.factory());
+        }
+
+        Ok(builder)
+    }
+
+    pub fn ear_layout(mut self, ear_layout: EarLayout) -> Self {
+        self.ear_layout = ear_layout;
+        self
+    }
+
+    /// speakers of the HRIR wav in order, for files without a channel mask or with a wrong one.
+    /// Files without one otherwise get the [`standard_layout`](crate::standard_layout) of their
+    /// speaker count.
+    pub fn with_layout(mut self, layout: &[Speaker]) -> Self {
+        self.layout = Some(layout.to_vec());
+        self
+    }
+
+    /// time align the speakers of the HRIR, see [`Hrir::align_onsets`]
+    pub fn align_onsets(mut self, alignment: OnsetAlignment) -> Self {
+        self.onset_alignment = Some(alignment);
+        self
+    }
+
+    /// cut the leading silence all impulse responses share, see
+    /// [`Hrir::trim_leading_silence`]. The cut is reported to the
+    /// [`diagnostics`](FilterBuilder::diagnostics).
+    pub fn trim_leading_silence(mut self, trim: bool) -> Self {
+        self.trim_silence = trim;
+        self
+    }
+
+    /// scale the ITD of the HRIR by `factor`, see [`Hrir::scale_itd`]
+    pub fn itd_scale(mut self, factor: f32) -> Self {
+        self.itd_scale = Some(factor);
+        self
+    }
+
+    /// scale the ITD for a listener with a head radius of `cm` instead of the
+    /// [`KEMAR_HEAD_RADIUS_CM`] of the mannequin generic HRIRs are measured on
+    pub fn head_radius(self, cm: f32) -> Self {
+        self.itd_scale(cm / KEMAR_HEAD_RADIUS_CM)
+    }
+
+    /// convert the HRIR to minimum phase plus the delay to each ear, see
+    /// [`Hrir::to_minimum_phase`]. Shorter impulse responses and less pre-delay, for games and
+    /// other latency-sensitive uses.
+    pub fn minimum_phase(mut self, minimum_phase: bool) -> Self {
+        self.minimum_phase = minimum_phase;
+        self
+    }
+
+    /// equalize out what all directions of the HRIR share, like the ear canal resonances of
+    /// some measurements, see [`Hrir::diffuse_field_equalize`]
+    pub fn diffuse_field_eq(mut self, equalize: bool) -> Self {
+        self.diffuse_field_eq = equalize;
+        self
+    }
+
+    /// add the early reflections of `room` to the HRIR, see [`Hrir::add_early_reflections`]
+    pub fn room(mut self, room: RoomModel) -> Self {
+        self.room = Some(room);
+        self
+    }
+
+    /// cut the HRIR down to `ms` milliseconds with `window` fading out its end, trading the
+    /// tail for latency and CPU, see [`Hrir::truncate`]
+    pub fn truncate(mut self, ms: f32, window: FadeWindow) -> Self {
+        self.truncation = Some((ms, window));
+        self
+    }
+
+    /// how the HRIR is scaled, PulseAudio's [`Normalization::SumPeak`] unless changed
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// how the default engine convolves, [`ConvolutionStrategy::OverlapSave`] unless changed.
+    /// Ignored when an engine is picked with [`engine`](Self::engine) or
+    /// [`fastest_engine`](Self::fastest_engine).
+    pub fn strategy(mut self, strategy: ConvolutionStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// precision the default engine keeps the spectra of the impulse responses in,
+    /// [`SpectrumPrecision::Half`] halves the memory they take with either overlap strategy.
+    /// Ignored like the [`strategy`](Self::strategy).
+    pub fn spectrum_precision(mut self, precision: SpectrumPrecision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// use a different convolution engine than the one picked by [`strategy`](Self::strategy)
+    pub fn engine(mut self, engine: EngineFactory) -> Self {
+        self.engine = EngineSelection::Factory(engine);
+        self
+    }
+
+    /// benchmark the available engines on the loaded HRIR's size and use the fastest, see
+    /// [`Engine::fastest`]
+    pub fn fastest_engine(mut self, cache: Option<PathBuf>) -> Self {
+        self.engine = EngineSelection::Fastest(cache);
+        self
+    }
+
+    /// time in milliseconds runtime parameter changes ramp over, see
+    /// [`VirtualSurroundFilter::set_ramp_time`] and [`AmbisonicVirtualizer::set_ramp_time`]
+    pub fn ramp_time(mut self, ms: f32) -> Self {
+        self.ramp_ms = Some(ms);
+        self
+    }
+
+    /// convolve the channels on up to `threads` threads, see
+    /// [`RawVirtualSurroundFilter::from_hrir_with_threads`]
+    #[cfg(feature = "parallel")]
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Report what looks off about the HRIR while loading it, e.g. a DC offset or clipping,
+    /// instead of letting it pass silently. An HRIR with one ear per speaker missing the mirrored
+    /// side of a speaker is reported rather than failing to load.
+    pub fn diagnostics<D: Diagnostics + 'static>(mut self, sink: D) -> Self {
+        self.diagnostics = Some(DiagnosticsSink(Arc::new(sink)));
+        self
+    }
+
+    /// Leave out the speakers of an HRIR wav which can't be placed, channels without a position in
+    /// the channel mask and, with one ear per speaker, ones without a mirrored side, instead of
+    /// failing to load it. Their input is dropped, they're reported to the
+    /// [`diagnostics`](FilterBuilder::diagnostics).
+    pub fn skip_unusable_channels(mut self, skip: bool) -> Self {
+        self.skip_unusable = skip;
+        self
+    }
+
+    pub fn load_hrir<R: Read + Seek>(&self, reader: R) -> anyhow::Result<Hrir> {
+        self.prepare_hrir(self.read_hrir(reader)?)
+    }
+
+    fn read_hrir<R: Read + Seek>(&self, reader: R) -> anyhow::Result<Hrir> {
+        Hrir::read_wav(
+            reader,
+            self.ear_layout,
+            self.layout.as_deref(),
+            self.skip_unusable,
+            self.diagnostics.as_ref().map(|x| x.0.as_ref()),
+        )
+    }
+
+    /// resample, align, scale the ITD of, add the room to, truncate and normalize an HRIR the
+    /// same way a loaded one is
+    pub fn prepare_hrir(&self, hrir: Hrir) -> anyhow::Result<Hrir> {
+        if !cfg!(any(feature = "resample", feature = "resample-rubato"))
+            && self.sample_rate.is_some()
+        {
+            anyhow::bail!("virtual-surround is compiled without resampling support, cannot request resampling");
+        }
+
+        trace_span!(INFO, "prepare_hrir", speakers = hrir.speakers.len());
+
+        #[allow(unused_mut)]
+        let mut hrir = hrir;
+
+        if let Some(diagnostics) = &self.diagnostics {
+            diagnose(&hrir, self.sample_rate, diagnostics.0.as_ref());
+        }
+
+        #[cfg(any(feature = "resample", feature = "resample-rubato"))]
+        {
+            if let Some(sample_rate) = self.sample_rate {
+                hrir.resample_with(sample_rate, self.resample_quality)?;
+            }
+        }
+
+        if self.trim_silence {
+            let trimmed = hrir.trim_leading_silence();
+            trace_event!(DEBUG, trimmed, "trimmed leading silence");
+            if let (Some(diagnostics), true) = (&self.diagnostics, trimmed > 0) {
+                diagnostics.0.report(Diagnostic::TrimmedSilence {
+                    ms: trimmed as f32 * 1000.0 / hrir.sample_rate as f32,
+                });
+            }
+        }
+
+        if let Some(alignment) = self.onset_alignment {
+            hrir.align_onsets(alignment);
+        }
+
+        if let Some(factor) = self.itd_scale {
+            hrir.scale_itd(factor);
+        }
+
+        if self.diffuse_field_eq {
+            hrir.diffuse_field_equalize();
+        }
+
+        if self.minimum_phase {
+            hrir.to_minimum_phase();
+        }
+
+        if let Some(room) = &self.room {
+            hrir.add_early_reflections(room);
+        }
+
+        if let Some((ms, window)) = self.truncation {
+            let length = (ms.max(0.0) / 1000.0 * hrir.sample_rate as f32).round() as usize;
+            hrir.truncate(length.max(1), window);
+        }
+
+        hrir.normalize_with(self.normalization);
+
+        Ok(hrir)
+    }
+
+    pub fn build_raw<R: Read + Seek>(&self, reader: R) -> anyhow::Result<RawVirtualSurroundFilter> {
+        self.build_raw_from_hrir(self.read_hrir(reader)?)
+    }
+
+    /// build from an HRIR which didn't come from a wav, e.g. one made by
+    /// [`Hrir::from_hrtf`](crate::hrir::Hrir::from_hrtf)
+    pub fn build_raw_from_hrir(&self, hrir: Hrir) -> anyhow::Result<RawVirtualSurroundFilter> {
+        trace_span!(INFO, "build_filter");
+        let hrir = self.prepare_hrir(hrir)?;
+        let engine = self.engine_factory(hrir.speakers.len(), fft_len_for(hrir.ir_length()))?;
+        trace_event!(
+            INFO,
+            sample_rate = hrir.sample_rate,
+            speakers = hrir.speakers.len(),
+            length = hrir.ir_length(),
+            "HRIR prepared"
+        );
+
+        RawVirtualSurroundFilter::from_hrir_with_threads(&hrir, engine, self.threads.unwrap_or(1))
+    }
+
+    /// Filter from a file [`RawVirtualSurroundFilter::save_prepared`] wrote under the same `key`,
+    /// convolving with the engine and threads of this builder. The HRIR settings of the builder
+    /// aren't applied again, a file prepared for another sample rate than the one asked for is
+    /// rejected.
+    pub fn build_prepared<R: Read>(
+        &self,
+        reader: R,
+        key: &str,
+    ) -> anyhow::Result<VirtualSurroundFilter> {
+        trace_span!(INFO, "build_prepared");
+        let prepared = Prepared::read(reader, key)?;
+        if let Some(sample_rate) = self.sample_rate.filter(|x| *x != prepared.sample_rate) {
+            anyhow::bail!(
+                "Filter was prepared at {}Hz, not {}Hz",
+                prepared.sample_rate,
+                sample_rate
+            );
+        }
+
+        let engine = self.engine_factory(
+            prepared.positions.len(),
+            fft_len_for(prepared.irs[0][0].len()),
+        )?;
+        self.finish(prepared.into_filter(engine, self.threads.unwrap_or(1))?)
+    }
+
+    fn engine_factory(&self, channels: usize, length: usize) -> anyhow::Result<EngineFactory> {
+        Ok(match &self.engine {
+            EngineSelection::Default => self.strategy.factory_with_precision(self.precision)?,
+            EngineSelection::Factory(engine) => *engine,
+            EngineSelection::Fastest(cache) => {
+                Engine::fastest(channels, length, cache.as_deref())?.factory()
+            }
+        })
+    }
+
+    pub fn build<R: Read + Seek>(&self, reader: R) -> anyhow::Result<VirtualSurroundFilter> {
+        self.finish(self.build_raw(reader)?)
+    }
+
+    pub fn build_from_hrir(&self, hrir: Hrir) -> anyhow::Result<VirtualSurroundFilter> {
+        self.finish(self.build_raw_from_hrir(hrir)?)
+    }
+
+    fn finish(&self, raw: RawVirtualSurroundFilter) -> anyhow::Result<VirtualSurroundFilter> {
+        let mut filter = VirtualSurroundFilter::from_raw(raw)?;
+        if let Some(ms) = self.ramp_ms {
+            filter.set_ramp_time(ms);
+        }
+
+        Ok(filter)
+    }
+
+    /// ambisonics renderer of `order` decoding to the speakers of the HRIR
+    pub fn build_ambisonic<R: Read + Seek>(
+        &self,
+        reader: R,
+        order: usize,
+    ) -> anyhow::Result<AmbisonicVirtualizer> {
+        self.build_ambisonic_from_hrir(self.read_hrir(reader)?, order)
+    }
+
+    pub fn build_ambisonic_from_hrir(
+        &self,
+        hrir: Hrir,
+        order: usize,
+    ) -> anyhow::Result<AmbisonicVirtualizer> {
+        let hrir = self.prepare_hrir(hrir)?;
+        let engine =
+            self.engine_factory(ambisonic_channels(order), fft_len_for(hrir.ir_length()))?;
+
+        let mut virtualizer = AmbisonicVirtualizer::from_hrir(&hrir, order, engine)?;
+        if let Some(ms) = self.ramp_ms {
+            virtualizer.set_ramp_time(ms);
+        }
+
+        Ok(virtualizer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(not(any(feature = "resample", feature = "resample-rubato")))]
+    #[test]
+    fn sample_rate_without_resampling_is_an_error() {
+        let error = super::FilterBuilder::new()
+            .sample_rate(44100)
+            .load_hrir(std::fs::File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap())
+            .unwrap_err();
+        assert!(error.to_string().contains("resampling"), "{}", error);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::ambisonic::{rotation_matrix, Matrix};
+use crate::{HeadTracker, Orientation};
+use anyhow::Context;
+use std::fs;
+use std::path::Path;
+
+/// least the head has to turn between the poses for the turn axis to be trusted
+const MIN_TURN: f64 = 30.0;
+
+fn matrix(orientation: Orientation) -> Matrix {
+    rotation_matrix(orientation.yaw, orientation.pitch, orientation.roll)
+}
+
+fn orientation(m: &Matrix) -> Orientation {
+    // inverse of rotation_matrix, which is z * y * x with the pitch negated
+    Orientation {
+        yaw: m[1][0].atan2(m[0][0]).to_degrees() as f32,
+        pitch: m[2][0].clamp(-1.0, 1.0).asin().to_degrees() as f32,
+        roll: m[2][1].atan2(m[2][2]).to_degrees() as f32,
+    }
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    [0, 1, 2].map(|r| [0, 1, 2].map(|c| (0..3).map(|k| a[r][k] * b[k][c]).sum()))
+}
+
+fn transpose(m: &Matrix) -> Matrix {
+    [0, 1, 2].map(|r| [0, 1, 2].map(|c| m[c][r]))
+}
+
+/// rotation by `angle` radians around the unit vector `axis`
+fn axis_rotation(axis: [f64; 3], angle: f64) -> Matrix {
+    let (s, c) = angle.sin_cos();
+    let [x, y, z] = axis;
+    let t = 1.0 - c;
+
+    [
+        [t * x * x + c, t * x * y - s * z, t * x * z + s * y],
+        [t * x * y + s * z, t * y * y + c, t * y * z - s * x],
+        [t * x * z - s * y, t * y * z + s * x, t * z * z + c],
+    ]
+}
+
+/// Rotation offset between a tracker's axes and the speaker layout, measured with a
+/// [`Calibrator`]. Without it a tracker mounted at an angle turns the scene around a tilted axis
+/// when the head only turns left or right. The tracker is assumed to be tilted, a tracker also
+/// twisted around its vertical axis keeps mixing up a little pitch and roll.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrackerCalibration {
+    /// tracker orientation while looking at the front speaker
+    forward: Matrix,
+    /// rotation from the tracker's axes to the layout's
+    axes: Matrix,
+}
+
+impl Default for TrackerCalibration {
+    fn default() -> Self {
+        let identity = rotation_matrix(0.0, 0.0, 0.0);
+
+        TrackerCalibration {
+            forward: identity,
+            axes: identity,
+        }
+    }
+}
+
+impl TrackerCalibration {
+    /// from the tracker's orientation looking at the front speaker and looking to the left
+    pub fn from_poses(forward: Orientation, left: Orientation) -> anyhow::Result<Self> {
+        let forward = matrix(forward);
+        let turn = multiply(&transpose(&forward), &matrix(left));
+
+        // the axis the head turned around, in the tracker's axes
+        let trace = turn[0][0] + turn[1][1] + turn[2][2];
+        let angle = ((trace - 1.0) / 2.0).clamp(-1.0, 1.0).acos();
+        if angle.to_degrees() < MIN_TURN {
+            anyhow::bail!(
+                "Head turned {:.0} degrees between the poses, look further to the left",
+                angle.to_degrees()
+            );
+        }
+
+        let axis = [
+            turn[2][1] - turn[1][2],
+            turn[0][2] - turn[2][0],
+            turn[1][0] - turn[0][1],
+        ];
+        let norm = axis.iter().map(|x| x * x).sum::<f64>().sqrt();
+        if norm < 1e-6 {
+            anyhow::bail!("Head turned around too far between the poses, look less to the left");
+        }
+        let axis = axis.map(|x| x / norm);
+
+        // the shortest rotation turning that axis straight up, whichever way the tracker is
+        // mounted looking left becomes a positive yaw
+        let cross = [axis[1], -axis[0], 0.0];
+        let sin = cross.iter().map(|x| x * x).sum::<f64>().sqrt();
+        let axes = if sin < 1e-9 {
+            if axis[2] > 0.0 {
+                rotation_matrix(0.0, 0.0, 0.0)
+            } else {
+                rotation_matrix(0.0, 0.0, 180.0)
+            }
+        } else {
+            axis_rotation(cross.map(|x| x / sin), sin.atan2(axis[2]))
+        };
+
+        Ok(TrackerCalibration { forward, axes })
+    }
+
+    /// head orientation relative to the layout for an orientation the tracker reported
+    pub fn apply(&self, orientation: Orientation) -> Orientation {
+        let relative = multiply(&transpose(&self.forward), &matrix(orientation));
+        let turned = multiply(&multiply(&self.axes, &relative), &transpose(&self.axes));
+        self::orientation(&turned)
+    }
+
+    /// Save to a plain text file, a `forward` and an `axes` line each with yaw, pitch and roll in
+    /// degrees
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let contents = [("forward", &self.forward), ("axes", &self.axes)]
+            .iter()
+            .map(|(name, m)| {
+                let o = orientation(m);
+                format!("{} {} {} {}\n", name, o.yaw, o.pitch, o.roll)
+            })
+            .collect::<String>();
+
+        fs::write(path.as_ref(), contents)
+            .with_context(|| format!("Failed to write calibration to {}", path.as_ref().display()))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path.as_ref()).with_context(|| {
+            format!(
+                "Failed to read calibration from {}",
+                path.as_ref().display()
+            )
+        })?;
+
+        let mut calibration = TrackerCalibration::default();
+        for line in contents.lines().filter(|x| !x.trim().is_empty()) {
+            let (name, values) = line.trim().split_once(' ').unwrap_or((line, ""));
+            let value = Orientation::parse(values)
+                .with_context(|| format!("Invalid calibration line: {}", line))?;
+
+            match name {
+                "forward" => calibration.forward = matrix(value),
+                "axes" => calibration.axes = matrix(value),
+                _ => anyhow::bail!("Unknown calibration line: {}", line),
+            }
+        }
+
+        Ok(calibration)
+    }
+}
+
+/// What a [`Calibrator`] asks the listener to do next
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CalibrationStep {
+    /// look straight at the front speaker
+    LookForward,
+    /// turn the head to the left, ideally by 90 degrees, without tilting it
+    LookLeft,
+}
+
+/// Guided calibration: prompt the listener with [`step`](Self::step), and once they hold still
+/// [`capture`](Self::capture) the tracker's orientation, until there are no steps left.
+#[derive(Debug, Clone, Default)]
+pub struct Calibrator {
+    forward: Option<Orientation>,
+    left: Option<Orientation>,
+}
+
+impl Calibrator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// pose to capture next, `None` once [`finish`](Self::finish) can be called
+    pub fn step(&self) -> Option<CalibrationStep> {
+        if self.forward.is_none() {
+            Some(CalibrationStep::LookForward)
+        } else if self.left.is_none() {
+            Some(CalibrationStep::LookLeft)
+        } else {
+            None
+        }
+    }
+
+    pub fn capture(&mut self, orientation: Orientation) {
+        match self.step() {
+            Some(CalibrationStep::LookForward) => self.forward = Some(orientation),
+            Some(CalibrationStep::LookLeft) => self.left = Some(orientation),
+            None => {}
+        }
+    }
+
+    pub fn finish(&self) -> anyhow::Result<TrackerCalibration> {
+        match (self.forward, self.left) {
+            (Some(forward), Some(left)) => TrackerCalibration::from_poses(forward, left),
+            _ => anyhow::bail!("Calibration isn't done, {:?} is next", self.step().unwrap()),
+        }
+    }
+}
+
+/// A tracker reporting orientations relative to the speaker layout
+pub struct CalibratedTracker<T: HeadTracker> {
+    tracker: T,
+    calibration: TrackerCalibration,
+}
+
+impl<T: HeadTracker> CalibratedTracker<T> {
+    pub fn new(tracker: T, calibration: TrackerCalibration) -> Self {
+        CalibratedTracker {
+            tracker,
+            calibration,
+        }
+    }
+
+    pub fn set_calibration(&mut self, calibration: TrackerCalibration) {
+        self.calibration = calibration;
+    }
+
+    pub fn into_inner(self) -> T {
+        self.tracker
+    }
+}
+
+impl<T: HeadTracker> HeadTracker for CalibratedTracker<T> {
+    fn poll(&mut self) -> anyhow::Result<Option<Orientation>> {
+        Ok(self.tracker.poll()?.map(|x| self.calibration.apply(x)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        axis_rotation, matrix, multiply, orientation, transpose, Calibrator, TrackerCalibration,
+    };
+    use crate::Orientation;
+
+    fn assert_close(a: Orientation, b: Orientation) {
+        assert!(
+            (a.yaw - b.yaw).abs() < 1e-2
+                && (a.pitch - b.pitch).abs() < 1e-2
+                && (a.roll - b.roll).abs() < 1e-2,
+            "{:?} {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn undoes_a_tilted_mount() {
+        // tracker tilted on the headphones and turned away from the front speaker
+        let tilt = axis_rotation([0.6, 0.8, 0.0], 25f64.to_radians());
+        let offset = matrix(Orientation::new(70.0, 5.0, 0.0));
+        let read = |head: Orientation| {
+            let turned = multiply(&multiply(&transpose(&tilt), &matrix(head)), &tilt);
+            orientation(&multiply(&offset, &turned))
+        };
+
+        let mut calibrator = Calibrator::new();
+        assert!(calibrator.finish().is_err());
+        calibrator.capture(read(Orientation::default()));
+        calibrator.capture(read(Orientation::new(80.0, 0.0, 0.0)));
+        assert_eq!(calibrator.step(), None);
+        let calibration = calibrator.finish().unwrap();
+
+        for head in [
+            Orientation::new(-45.0, 0.0, 0.0),
+            Orientation::new(30.0, 10.0, -5.0),
+        ] {
+            assert_close(calibration.apply(read(head)), head);
+        }
+
+        let path = std::env::temp_dir().join(format!("vsf-calibration-{}", std::process::id()));
+        calibration.save(&path).unwrap();
+        let loaded = TrackerCalibration::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let head = Orientation::new(12.0, -8.0, 3.0);
+        assert_close(loaded.apply(read(head)), head);
+    }
+
+    #[test]
+    fn rejects_small_turns() {
+        assert!(TrackerCalibration::from_poses(
+            Orientation::new(10.0, 0.0, 0.0),
+            Orientation::new(20.0, 0.0, 0.0)
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{Engine, EngineCapabilities, BLOCK_SIZE, MAX_AMBISONIC_ORDER, MAX_CHANNELS};
+use std::fmt::{Display, Formatter};
+
+/// What this build of the library supports, for frontends to adapt to and bug reports to state
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    pub version: &'static str,
+    /// sample rate converter used when the HRIR rate differs from the target rate, `None` when
+    /// compiled without the `resample` feature
+    pub resampler: Option<&'static str>,
+    /// engines compiled in, in order of preference
+    pub engines: Vec<(&'static str, EngineCapabilities)>,
+    /// HRIR formats which can be loaded
+    pub loaders: Vec<&'static str>,
+    pub max_channels: usize,
+    pub block_size: usize,
+    pub max_ambisonic_order: usize,
+}
+
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        resampler: if cfg!(feature = "resample-rubato") {
+            Some("rubato")
+        } else if cfg!(feature = "resample") {
+            Some("libsamplerate")
+        } else {
+            None
+        },
+        engines: Engine::available()
+            .iter()
+            .map(|x| (x.name(), x.capabilities()))
+            .collect(),
+        loaders: vec![
+            "wav",
+            #[cfg(feature = "sofa")]
+            "sofa",
+        ],
+        max_channels: MAX_CHANNELS,
+        block_size: BLOCK_SIZE,
+        max_ambisonic_order: MAX_AMBISONIC_ORDER,
+    }
+}
+
+impl Display for Capabilities {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "virtual-surround {}", self.version)?;
+        writeln!(f, "resampler: {}", self.resampler.unwrap_or("none"))?;
+
+        let engines = self
+            .engines
+            .iter()
+            .map(|(name, capabilities)| {
+                match (capabilities.double_precision, capabilities.fixed_point) {
+                    (true, _) => format!("{} ({:?}, f64)", name, capabilities.simd),
+                    (_, true) => format!("{} ({:?}, q31)", name, capabilities.simd),
+                    _ => format!("{} ({:?})", name, capabilities.simd),
+                }
+            })
+            .collect::<Vec<_>>();
+        writeln!(f, "engines: {}", engines.join(", "))?;
+        writeln!(f, "loaders: {}", self.loaders.join(", "))?;
+        writeln!(f, "max channels: {}", self.max_channels)?;
+        writeln!(f, "block size: {}", self.block_size)?;
+        write!(f, "max ambisonic order: {}", self.max_ambisonic_order)
+    }
+}
@@ -0,0 +1,267 @@
+use crate::dsp::fft;
+use crate::{EqBand, HeadphoneEq};
+use std::f64::consts::PI;
+use std::fmt::{Display, Formatter};
+
+/// frames per analyzed spectrum, fine enough to resolve the lowest third octaves
+const FFT_LEN: usize = 16384;
+/// bands are centered on 1 khz * 2^(n / 3), 20 hz up to 20 khz
+const LOWEST_BAND: i32 = -17;
+const HIGHEST_BAND: i32 = 13;
+/// most a band of the compensation boosts or cuts
+const MAX_COMPENSATION_DB: f32 = 12.0;
+
+/// Long-term spectra of a reference (usually the plain stereo downmix) and the binaural output,
+/// compared per third octave to show how the HRIR colors the sound
+#[derive(Debug, Clone)]
+pub struct ColorationAnalyzer {
+    sample_rate: usize,
+    window: Vec<f64>,
+    /// reference left and right, output left and right
+    frames: [Vec<f32>; 4],
+    fill: usize,
+    /// power per bin summed over both ears and every frame, reference then output
+    power: [Vec<f64>; 2],
+    spectra: usize,
+    re: Vec<f64>,
+    im: Vec<f64>,
+}
+
+/// One third octave of a [`ColorationReport`]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ColorationBand {
+    pub frequency: f32,
+    /// output level relative to the reference
+    pub difference_db: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorationReport {
+    /// average difference over all bands, the part of the coloration which is just a level change
+    pub level_db: f32,
+    /// bands without energy in the reference are left out
+    pub bands: Vec<ColorationBand>,
+}
+
+impl ColorationReport {
+    /// peaking bands undoing the coloration besides its level, with a preamp keeping the boosts
+    /// from clipping
+    pub fn compensation(&self) -> HeadphoneEq {
+        // bandwidth of a third octave
+        let q = 2f32.powf(1.0 / 6.0) / (2f32.powf(1.0 / 3.0) - 1.0);
+
+        let bands = self
+            .bands
+            .iter()
+            .map(|band| EqBand::Peaking {
+                frequency: band.frequency,
+                q,
+                gain_db: (self.level_db - band.difference_db)
+                    .clamp(-MAX_COMPENSATION_DB, MAX_COMPENSATION_DB),
+            })
+            .collect::<Vec<_>>();
+
+        let boost = bands
+            .iter()
+            .map(|x| match x {
+                EqBand::Peaking { gain_db, .. } => *gain_db,
+                _ => 0.0,
+            })
+            .fold(0f32, f32::max);
+
+        HeadphoneEq::parametric(-boost, bands)
+    }
+}
+
+impl Display for ColorationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "level: {:+.1} dB", self.level_db)?;
+
+        for band in &self.bands {
+            writeln!(
+                f,
+                "{:>7.0} Hz {:+5.1} dB",
+                band.frequency,
+                band.difference_db - self.level_db
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ColorationAnalyzer {
+    pub fn new(sample_rate: usize) -> Self {
+        ColorationAnalyzer {
+            sample_rate,
+            window: (0..FFT_LEN)
+                .map(|x| 0.5 - 0.5 * (2.0 * PI * x as f64 / FFT_LEN as f64).cos())
+                .collect(),
+            frames: [
+                vec![0f32; FFT_LEN],
+                vec![0f32; FFT_LEN],
+                vec![0f32; FFT_LEN],
+                vec![0f32; FFT_LEN],
+            ],
+            fill: 0,
+            power: [vec![0f64; FFT_LEN / 2 + 1], vec![0f64; FFT_LEN / 2 + 1]],
+            spectra: 0,
+            re: vec![0f64; FFT_LEN],
+            im: vec![0f64; FFT_LEN],
+        }
+    }
+
+    /// add a stretch of the reference and the output, both of the same length
+    pub fn process(&mut self, reference: (&[f32], &[f32]), output: (&[f32], &[f32])) {
+        let len = reference.0.len();
+        let mut offset = 0;
+
+        while offset < len {
+            let take = (FFT_LEN - self.fill).min(len - offset);
+
+            for (frame, input) in
+                self.frames
+                    .iter_mut()
+                    .zip([reference.0, reference.1, output.0, output.1])
+            {
+                frame[self.fill..self.fill + take].copy_from_slice(&input[offset..offset + take]);
+            }
+
+            self.fill += take;
+            offset += take;
+
+            if self.fill == FFT_LEN {
+                self.analyze();
+                self.fill = 0;
+            }
+        }
+    }
+
+    fn analyze(&mut self) {
+        for (index, frame) in self.frames.iter().enumerate() {
+            for ((re, im), (sample, window)) in self
+                .re
+                .iter_mut()
+                .zip(self.im.iter_mut())
+                .zip(frame.iter().zip(&self.window))
+            {
+                *re = *sample as f64 * window;
+                *im = 0.0;
+            }
+
+            fft(&mut self.re, &mut self.im, false);
+
+            for (bin, power) in self.power[index / 2].iter_mut().enumerate() {
+                *power += self.re[bin] * self.re[bin] + self.im[bin] * self.im[bin];
+            }
+        }
+
+        self.spectra += 1;
+    }
+
+    /// forget everything analyzed so far
+    pub fn reset(&mut self) {
+        self.fill = 0;
+        self.spectra = 0;
+        for power in &mut self.power {
+            power.fill(0.0);
+        }
+    }
+
+    /// `None` until a whole spectrum has been analyzed
+    pub fn report(&self) -> Option<ColorationReport> {
+        if self.spectra == 0 {
+            return None;
+        }
+
+        let bin_hz = self.sample_rate as f64 / FFT_LEN as f64;
+        let nyquist = self.sample_rate as f64 / 2.0;
+
+        let bands = (LOWEST_BAND..=HIGHEST_BAND)
+            .filter_map(|n| {
+                let center = 1000.0 * 2f64.powf(n as f64 / 3.0);
+                let (low, high) = (
+                    center * 2f64.powf(-1.0 / 6.0),
+                    center * 2f64.powf(1.0 / 6.0),
+                );
+                if high > nyquist {
+                    return None;
+                }
+
+                let bins = (low / bin_hz).ceil() as usize..(high / bin_hz).ceil() as usize;
+                let reference = self.power[0][bins.clone()].iter().sum::<f64>();
+                let output = self.power[1][bins].iter().sum::<f64>();
+
+                if reference <= 0.0 || output <= 0.0 {
+                    return None;
+                }
+
+                Some(ColorationBand {
+                    frequency: center as f32,
+                    difference_db: (10.0 * (output / reference).log10()) as f32,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let level_db = if bands.is_empty() {
+            0.0
+        } else {
+            bands.iter().map(|x| x.difference_db).sum::<f32>() / bands.len() as f32
+        };
+
+        Some(ColorationReport { level_db, bands })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColorationAnalyzer, FFT_LEN};
+    use crate::biquad::Biquad;
+    use crate::{EqBand, HeadphoneEq};
+
+    #[test]
+    fn finds_a_treble_boost() {
+        let sample_rate = 48000;
+        let mut state = 0x1234_5678u32;
+        let reference = (0..FFT_LEN * 8)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+            })
+            .collect::<Vec<_>>();
+
+        let mut shelf =
+            Biquad::high_shelf(sample_rate, 4000.0, std::f32::consts::FRAC_1_SQRT_2, 6.0);
+        let output = reference
+            .iter()
+            .map(|x| shelf.process(*x))
+            .collect::<Vec<_>>();
+
+        let mut analyzer = ColorationAnalyzer::new(sample_rate);
+        assert!(analyzer.report().is_none());
+        analyzer.process((&reference, &reference), (&output, &output));
+        let report = analyzer.report().unwrap();
+
+        let band = |frequency: f32| {
+            report
+                .bands
+                .iter()
+                .find(|x| (x.frequency - frequency).abs() < frequency * 0.05)
+                .unwrap()
+                .difference_db
+        };
+        assert!(band(100.0).abs() < 0.5);
+        assert!((band(16000.0) - 6.0).abs() < 0.5);
+
+        match report.compensation() {
+            HeadphoneEq::Parametric { preamp_db, bands } => {
+                assert_eq!(bands.len(), report.bands.len());
+                assert!(preamp_db <= 0.0);
+                assert!(
+                    matches!(bands.last(), Some(EqBand::Peaking { gain_db, .. }) if *gain_db < 0.0)
+                );
+            }
+            _ => unreachable!(),
+        }
+    }
+}
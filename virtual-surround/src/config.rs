@@ -0,0 +1,294 @@
+use crate::hrir::{EarLayout, FadeWindow, Hrir, Normalization};
+use crate::{
+    channel_from_name, FilterBuilder, HeadphoneEq, OutputProtection, ResampleQuality, Reverb,
+    RoomModel, Speaker, SpectrumPrecision, Transaural, VirtualSurroundFilter, BLOCK_SIZE,
+};
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Everything needed to build and set up a [`VirtualSurroundFilter`], so front-ends share one
+/// config format. With the `config` feature it's (de)serializable with serde and can be
+/// [`load`](FilterConfig::load)ed from and [`save`](FilterConfig::save)d to TOML files, fields
+/// left out keep their defaults.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct FilterConfig {
+    /// HRIR wav to load, or a directory with a wav per speaker, see [`Hrir::from_dir`]
+    pub hrir: Option<PathBuf>,
+    pub ear_layout: EarLayout,
+    /// speakers of the HRIR wav in order, for files without a channel mask, see
+    /// [`FilterBuilder::with_layout`]
+    pub hrir_layout: Option<Vec<String>>,
+    /// speakers of the input in order, e.g. `["FL", "FR", "FC", "LFE", "BL", "BR"]`, instead of
+    /// the HRIR's order, see [`VirtualSurroundFilter::set_input_layout`]
+    pub layout: Option<Vec<String>>,
+    /// only [`BLOCK_SIZE`] is supported, checked so a config made for another size is rejected
+    pub block_size: usize,
+    /// resample the HRIR to this rate, front-ends usually fill in the rate of the audio server
+    pub sample_rate: Option<u32>,
+    /// see [`FilterBuilder::resample_quality`]
+    pub resample_quality: ResampleQuality,
+    pub normalization: Normalization,
+    /// head radius of the listener, see [`FilterBuilder::head_radius`]
+    pub head_radius_cm: Option<f32>,
+    /// see [`FilterBuilder::minimum_phase`]
+    pub minimum_phase: bool,
+    /// see [`FilterBuilder::diffuse_field_eq`]
+    pub diffuse_field_eq: bool,
+    /// see [`FilterBuilder::trim_leading_silence`]
+    pub trim_leading_silence: bool,
+    /// see [`FilterBuilder::skip_unusable_channels`]
+    pub skip_unusable_channels: bool,
+    /// early reflections added to the HRIR, see [`FilterBuilder::room`]
+    pub room: Option<RoomModel>,
+    /// cut the HRIR down to this many milliseconds, see [`FilterBuilder::truncate`]
+    pub truncate_ms: Option<f32>,
+    pub truncate_window: FadeWindow,
+    /// see [`FilterBuilder::spectrum_precision`]
+    pub spectrum_precision: SpectrumPrecision,
+    /// engines to convolve with in order of preference, the first compiled in is used, see
+    /// [`Engine::preferred`](crate::Engine::preferred). The default engine if left out.
+    pub engines: Vec<String>,
+    /// more HRIRs by profile name to switch to, prepared with the settings above, see
+    /// [`VirtualSurroundFilter::add_profile`]
+    pub profiles: BTreeMap<String, PathBuf>,
+    /// profile to render, [`DEFAULT_PROFILE`](crate::DEFAULT_PROFILE) for the `hrir` if left out
+    pub profile: Option<String>,
+    /// meters from the listener per speaker name, see
+    /// [`VirtualSurroundFilter::set_speaker_distance`]
+    pub speaker_distances: BTreeMap<String, f32>,
+    pub mix: f32,
+    pub bypass: bool,
+    pub loudness_matching: bool,
+    /// see [`VirtualSurroundFilter::set_bypass_level_matching`]
+    pub bypass_level_matching: bool,
+    /// see [`VirtualSurroundFilter::set_reverb`]
+    pub reverb: Option<Reverb>,
+    /// reverb send gain per speaker name, see [`VirtualSurroundFilter::set_reverb_send`]
+    pub reverb_sends: BTreeMap<String, f32>,
+    /// milliseconds parameter changes ramp over, see [`VirtualSurroundFilter::set_ramp_time`],
+    /// a block if left out
+    pub ramp_ms: Option<f32>,
+    /// see [`VirtualSurroundFilter::set_low_latency`], for long room impulse responses
+    pub low_latency: bool,
+    /// cutoff of the DC blocker on the output, see [`VirtualSurroundFilter::set_dc_block`]
+    pub dc_block_hz: Option<f32>,
+    /// crosstalk cancellation for a speaker pair, see [`VirtualSurroundFilter::set_transaural`]
+    pub transaural: Option<Transaural>,
+    pub protection: OutputProtection,
+    pub headphone_eq: Option<HeadphoneEq>,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig {
+            hrir: None,
+            ear_layout: EarLayout::default(),
+            hrir_layout: None,
+            layout: None,
+            block_size: BLOCK_SIZE,
+            sample_rate: None,
+            resample_quality: ResampleQuality::default(),
+            normalization: Normalization::default(),
+            head_radius_cm: None,
+            minimum_phase: false,
+            diffuse_field_eq: false,
+            trim_leading_silence: false,
+            skip_unusable_channels: false,
+            room: None,
+            truncate_ms: None,
+            truncate_window: FadeWindow::default(),
+            spectrum_precision: SpectrumPrecision::default(),
+            engines: vec![],
+            profiles: BTreeMap::new(),
+            profile: None,
+            speaker_distances: BTreeMap::new(),
+            mix: 1.0,
+            bypass: false,
+            loudness_matching: false,
+            bypass_level_matching: false,
+            reverb: None,
+            reverb_sends: BTreeMap::new(),
+            ramp_ms: None,
+            low_latency: false,
+            dc_block_hz: None,
+            transaural: None,
+            protection: OutputProtection::default(),
+            headphone_eq: None,
+        }
+    }
+}
+
+/// speakers from their names, see [`channel_from_name`]
+pub(crate) fn parse_layout(names: &[String]) -> anyhow::Result<Vec<Speaker>> {
+    names
+        .iter()
+        .map(|x| channel_from_name(x).with_context(|| format!("Unknown channel {}", x)))
+        .collect()
+}
+
+impl FilterConfig {
+    #[cfg(feature = "config")]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read config {}", path.as_ref().display()))?;
+
+        toml::from_str(&contents)
+            .with_context(|| format!("Invalid config {}", path.as_ref().display()))
+    }
+
+    #[cfg(feature = "config")]
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> anyhow::Result<()> {
+        std::fs::write(path.as_ref(), toml::to_string(self)?)
+            .with_context(|| format!("Failed to write config {}", path.as_ref().display()))
+    }
+
+    /// load the HRIR and build a filter with every setting applied
+    pub fn build(&self) -> anyhow::Result<VirtualSurroundFilter> {
+        self.build_with(FilterBuilder::from_config(self)?)
+    }
+
+    /// like [`build`](FilterConfig::build) with a builder made by [`FilterBuilder::from_config`]
+    /// and changed further, e.g. to pick an engine from outside this crate
+    pub fn build_with(&self, builder: FilterBuilder) -> anyhow::Result<VirtualSurroundFilter> {
+        let mut filter = self.build_hrir(&builder)?;
+        self.add_profiles(&builder, &mut filter)?;
+        self.apply(&mut filter)?;
+
+        Ok(filter)
+    }
+
+    /// Like [`build_with`](FilterConfig::build_with), loading the filter from the prepared filter
+    /// at `cache` instead when one was saved there with the same HRIR settings from an HRIR which
+    /// hasn't changed since, and saving one there otherwise, see
+    /// [`RawVirtualSurroundFilter::save_prepared`](crate::RawVirtualSurroundFilter::save_prepared).
+    /// Failing to save it isn't an error, the next start just prepares the HRIR again.
+    pub fn build_with_cache<P: AsRef<Path>>(
+        &self,
+        builder: FilterBuilder,
+        cache: P,
+    ) -> anyhow::Result<VirtualSurroundFilter> {
+        let key = self.prepared_key()?;
+        let cached = File::open(cache.as_ref())
+            .map_err(anyhow::Error::from)
+            .and_then(|file| builder.build_prepared(BufReader::new(file), &key));
+
+        let mut filter = match cached {
+            Ok(filter) => filter,
+            Err(_) => {
+                trace_event!(INFO, "no usable prepared filter, preparing the HRIR");
+                let filter = self.build_hrir(&builder)?;
+                if let Ok(file) = File::create(cache.as_ref()) {
+                    let _ = filter.save_prepared(BufWriter::new(file), &key);
+                }
+
+                filter
+            }
+        };
+        self.add_profiles(&builder, &mut filter)?;
+        self.apply(&mut filter)?;
+
+        Ok(filter)
+    }
+
+    /// filter of the `hrir` alone, nothing applied yet
+    fn build_hrir(&self, builder: &FilterBuilder) -> anyhow::Result<VirtualSurroundFilter> {
+        let path = self.hrir.as_ref().context("Config doesn't name an HRIR")?;
+        if path.is_dir() {
+            builder.build_from_hrir(Hrir::from_dir(path)?)
+        } else {
+            let file = File::open(path)
+                .with_context(|| format!("Failed to open HRIR {}", path.display()))?;
+
+            builder.build(file)
+        }
+    }
+
+    fn add_profiles(
+        &self,
+        builder: &FilterBuilder,
+        filter: &mut VirtualSurroundFilter,
+    ) -> anyhow::Result<()> {
+        for (name, path) in &self.profiles {
+            let file = File::open(path)
+                .with_context(|| format!("Failed to open HRIR {}", path.display()))?;
+            filter
+                .add_profile_from_hrir(name, builder.load_hrir(file)?)
+                .with_context(|| format!("Failed to add profile {}", name))?;
+        }
+
+        Ok(())
+    }
+
+    /// the HRIR settings and when the HRIR was last modified, what a prepared filter depends on
+    fn prepared_key(&self) -> anyhow::Result<String> {
+        let path = self.hrir.as_ref().context("Config doesn't name an HRIR")?;
+        let modified = std::fs::metadata(path)
+            .and_then(|x| x.modified())
+            .with_context(|| format!("Failed to open HRIR {}", path.display()))?;
+
+        Ok(format!(
+            "{:?} {:?}",
+            (path, modified, self.ear_layout, &self.hrir_layout),
+            (
+                self.sample_rate,
+                self.resample_quality,
+                self.normalization,
+                self.head_radius_cm,
+                self.minimum_phase,
+                self.diffuse_field_eq,
+                self.trim_leading_silence,
+                self.skip_unusable_channels,
+                self.room,
+                self.truncate_ms,
+                self.truncate_window,
+            )
+        ))
+    }
+
+    /// apply the settings which don't need a rebuild to an existing filter
+    pub fn apply(&self, filter: &mut VirtualSurroundFilter) -> anyhow::Result<()> {
+        if let Some(ms) = self.ramp_ms {
+            filter.set_ramp_time(ms);
+        }
+
+        if let Some(layout) = &self.layout {
+            filter.set_input_layout(&parse_layout(layout)?)?;
+        }
+
+        for (name, meters) in &self.speaker_distances {
+            let speaker =
+                channel_from_name(name).with_context(|| format!("Unknown channel {}", name))?;
+            filter.set_speaker_distance(speaker, *meters)?;
+        }
+
+        for (name, gain) in &self.reverb_sends {
+            let speaker =
+                channel_from_name(name).with_context(|| format!("Unknown channel {}", name))?;
+            filter.set_reverb_send(speaker, *gain)?;
+        }
+
+        filter.set_reverb(self.reverb);
+        filter.set_mix(self.mix);
+        filter.set_bypass(self.bypass);
+        filter.set_loudness_matching(self.loudness_matching);
+        filter.set_bypass_level_matching(self.bypass_level_matching);
+        filter.set_low_latency(self.low_latency);
+        filter.set_dc_block(self.dc_block_hz)?;
+        filter.set_transaural(self.transaural)?;
+        filter.set_output_protection(self.protection);
+        filter.set_headphone_eq(self.headphone_eq.clone())?;
+        if let Some(profile) = &self.profile {
+            filter.set_profile(profile)?;
+        }
+
+        Ok(())
+    }
+}
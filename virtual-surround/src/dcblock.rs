@@ -0,0 +1,70 @@
+use std::f32::consts::PI;
+
+/// cutoff of the DC blocker suggested for [`set_dc_block`](crate::VirtualSurroundFilter::set_dc_block),
+/// low enough to leave the bass of any headphones alone
+pub const DEFAULT_DC_BLOCK_HZ: f32 = 5.0;
+
+/// One pole high pass on both ears, `y[n] = x[n] - x[n - 1] + r * y[n - 1]`, which removes the DC
+/// offset some HRIRs add to everything convolved with them
+#[derive(Debug, Clone)]
+pub(crate) struct DcBlocker {
+    cutoff: f32,
+    r: f32,
+    /// last input and output of the left and right ear
+    state: [(f32, f32); 2],
+}
+
+impl DcBlocker {
+    pub(crate) fn new(sample_rate: usize, cutoff: f32) -> Self {
+        DcBlocker {
+            cutoff,
+            r: (-2.0 * PI * cutoff / sample_rate as f32).exp(),
+            state: [(0.0, 0.0); 2],
+        }
+    }
+
+    pub(crate) fn cutoff(&self) -> f32 {
+        self.cutoff
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.state = [(0.0, 0.0); 2];
+    }
+
+    pub(crate) fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (output, (input, last)) in [left, right].iter_mut().zip(&mut self.state) {
+            for sample in output.iter_mut() {
+                let x = *sample;
+                *sample = x - *input + self.r * *last;
+                *input = x;
+                *last = *sample;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DcBlocker, DEFAULT_DC_BLOCK_HZ};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn removes_dc_and_passes_audio() {
+        let rate = 48000;
+        let mut blocker = DcBlocker::new(rate, DEFAULT_DC_BLOCK_HZ);
+
+        // a second of offset has decayed by far more than 60dB
+        let mut left = vec![0.5f32; rate];
+        let mut right = vec![-0.25f32; rate];
+        blocker.process(&mut left, &mut right);
+        assert!(left[rate - 1].abs() < 1e-4 && right[rate - 1].abs() < 1e-4);
+
+        // 100hz on top of the offset comes out at the same level
+        let tone = |s: usize| (2.0 * PI * 100.0 * s as f32 / rate as f32).sin() * 0.5;
+        let mut left = (rate..rate * 2).map(|s| tone(s) + 0.5).collect::<Vec<_>>();
+        let mut right = left.clone();
+        blocker.process(&mut left, &mut right);
+        let peak = left[rate / 2..].iter().fold(0f32, |x, y| x.max(y.abs()));
+        assert!((peak - 0.5).abs() < 0.005, "{}", peak);
+    }
+}
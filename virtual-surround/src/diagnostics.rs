@@ -0,0 +1,256 @@
+use crate::hrir::{onset, Hrir};
+use crate::{get_channel_name, Speaker};
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+/// sample rates audio interfaces commonly run at
+const COMMON_RATES: [u32; 6] = [44100, 48000, 88200, 96000, 176400, 192000];
+/// a response with a baseline further than this from 0, relative to its peak, has a DC offset
+const DC_OFFSET: f32 = 0.01;
+/// samples in a row at full scale which count as clipped, a single one is just a normalized peak
+const CLIPPED_RUN: usize = 3;
+/// every response starting later than this wastes latency
+const LEADING_SILENCE_MS: f32 = 5.0;
+
+/// A non-fatal finding about an HRIR, reported to the [`Diagnostics`] of a
+/// [`FilterBuilder`](crate::FilterBuilder) while it loads one
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagnostic {
+    /// an HRIR with one ear per speaker has no speaker on the other side of `speaker`, its own
+    /// response is used for both ears, which pulls it towards the center
+    MissingMirror { speaker: Speaker },
+    /// the `channel`th speaker of the HRIR was left out, as it has no position
+    /// ([`Speaker::DirectOut`]) or no mirrored side, see
+    /// [`FilterBuilder::skip_unusable_channels`](crate::FilterBuilder::skip_unusable_channels)
+    SkippedChannel { channel: usize, position: Speaker },
+    /// the response of `speaker` sits on `offset` relative to its peak instead of on 0
+    DcOffset { speaker: Speaker, offset: f32 },
+    /// `runs` of samples stuck at full scale in the response of `speaker`
+    Clipped { speaker: Speaker, runs: usize },
+    /// every response only starts after `ms`, see
+    /// [`FilterBuilder::trim_leading_silence`](crate::FilterBuilder::trim_leading_silence)
+    LeadingSilence { ms: f32 },
+    /// `ms` of leading silence were cut off every response
+    TrimmedSilence { ms: f32 },
+    /// the filter runs at a sample rate audio interfaces don't usually run at
+    UnusualSampleRate { sample_rate: u32 },
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnostic::MissingMirror { speaker } => write!(
+                f,
+                "HRIR has no mirrored side for {}, using its response for both ears",
+                get_channel_name(*speaker)
+            ),
+            Diagnostic::SkippedChannel {
+                channel,
+                position: Speaker::DirectOut,
+            } => write!(
+                f,
+                "Skipped HRIR channel {}, it has no speaker position",
+                channel
+            ),
+            Diagnostic::SkippedChannel { channel, position } => write!(
+                f,
+                "Skipped HRIR channel {} ({}), it has no mirrored side",
+                channel,
+                get_channel_name(*position)
+            ),
+            Diagnostic::DcOffset { speaker, offset } => write!(
+                f,
+                "HRIR of {} has a DC offset of {:.1}% of its peak",
+                get_channel_name(*speaker),
+                offset * 100.0
+            ),
+            Diagnostic::Clipped { speaker, runs } => write!(
+                f,
+                "HRIR of {} clips in {} places",
+                get_channel_name(*speaker),
+                runs
+            ),
+            Diagnostic::LeadingSilence { ms } => {
+                write!(f, "HRIR starts with {:.1}ms of silence", ms)
+            }
+            Diagnostic::TrimmedSilence { ms } => {
+                write!(f, "Trimmed {:.1}ms of leading silence off the HRIR", ms)
+            }
+            Diagnostic::UnusualSampleRate { sample_rate } => {
+                write!(f, "HRIR has an unusual sample rate of {}hz", sample_rate)
+            }
+        }
+    }
+}
+
+/// Receives the [`Diagnostic`]s of a [`FilterBuilder`](crate::FilterBuilder), set with
+/// [`diagnostics`](crate::FilterBuilder::diagnostics). Closures and channel senders are sinks.
+pub trait Diagnostics: Send + Sync {
+    fn report(&self, diagnostic: Diagnostic);
+}
+
+impl<F: Fn(Diagnostic) + Send + Sync> Diagnostics for F {
+    fn report(&self, diagnostic: Diagnostic) {
+        self(diagnostic)
+    }
+}
+
+impl Diagnostics for Sender<Diagnostic> {
+    fn report(&self, diagnostic: Diagnostic) {
+        // nobody listening anymore is fine
+        let _ = self.send(diagnostic);
+    }
+}
+
+/// the sink a builder holds on to, so it can still be cloned and debug printed
+#[derive(Clone)]
+pub(crate) struct DiagnosticsSink(pub Arc<dyn Diagnostics>);
+
+impl Debug for DiagnosticsSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DiagnosticsSink")
+    }
+}
+
+/// report everything off about the responses of `hrir`, and about the rate it's resampled to if
+/// it is
+pub(crate) fn diagnose(hrir: &Hrir, resample_to: Option<u32>, sink: &dyn Diagnostics) {
+    let sample_rate = resample_to.unwrap_or(hrir.sample_rate);
+    if !COMMON_RATES.contains(&sample_rate) {
+        sink.report(Diagnostic::UnusualSampleRate { sample_rate });
+    }
+
+    for speaker in &hrir.speakers {
+        let ears = [&speaker.left, &speaker.right];
+
+        let offset = ears.iter().map(|ir| dc_offset(ir)).fold(0f32, |max, x| {
+            if x.abs() > max.abs() {
+                x
+            } else {
+                max
+            }
+        });
+        if offset.abs() > DC_OFFSET {
+            sink.report(Diagnostic::DcOffset {
+                speaker: speaker.position,
+                offset,
+            });
+        }
+
+        let runs = ears.iter().map(|ir| clipped_runs(ir)).sum::<usize>();
+        if runs > 0 {
+            sink.report(Diagnostic::Clipped {
+                speaker: speaker.position,
+                runs,
+            });
+        }
+    }
+
+    let silent = hrir
+        .speakers
+        .iter()
+        .flat_map(|x| [&x.left, &x.right])
+        .filter(|ir| ir.iter().any(|x| *x != 0.0))
+        .map(|ir| onset(ir))
+        .min();
+    if let Some(silent) = silent {
+        let ms = silent as f32 * 1000.0 / hrir.sample_rate as f32;
+        if ms > LEADING_SILENCE_MS {
+            sink.report(Diagnostic::LeadingSilence { ms });
+        }
+    }
+}
+
+/// mean of the silence before the onset of `ir` relative to its peak, or of the last quarter for
+/// responses starting right away. The tail of a truncated response hasn't always settled yet.
+fn dc_offset(ir: &[f32]) -> f32 {
+    let peak = ir.iter().fold(0f32, |max, x| max.max(x.abs()));
+    let baseline = match onset(ir) {
+        onset if onset >= 4 => &ir[..onset],
+        _ => &ir[ir.len() * 3 / 4..],
+    };
+    if peak == 0.0 || baseline.is_empty() {
+        return 0.0;
+    }
+
+    baseline.iter().sum::<f32>() / baseline.len() as f32 / peak
+}
+
+fn clipped_runs(ir: &[f32]) -> usize {
+    let mut runs = 0;
+    let mut run = 0;
+    for x in ir {
+        if x.abs() >= 0.999 {
+            run += 1;
+            if run == CLIPPED_RUN {
+                runs += 1;
+            }
+        } else {
+            run = 0;
+        }
+    }
+
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diagnose, Diagnostic};
+    use crate::hrir::{Hrir, SpeakerIr};
+    use crate::Speaker;
+    use std::sync::Mutex;
+
+    #[test]
+    fn reports_what_is_off() {
+        let mut clean = vec![0f32; 256];
+        clean[2] = 1.0;
+        clean[3] = -0.5;
+
+        let mut late = vec![0f32; 256];
+        late[48] = 1.0;
+        let mut offset = late.clone();
+        offset[48] -= 0.03125;
+        offset.iter_mut().for_each(|x| *x += 0.03125);
+        let mut clipped = late.clone();
+        clipped[49..53].fill(1.0);
+
+        let findings = |sample_rate, irs: Vec<(Speaker, Vec<f32>)>| {
+            let hrir = Hrir {
+                sample_rate,
+                speakers: irs
+                    .into_iter()
+                    .map(|(position, ir)| SpeakerIr {
+                        position,
+                        left: ir.clone(),
+                        right: ir,
+                    })
+                    .collect(),
+            };
+            let found = Mutex::new(vec![]);
+            diagnose(&hrir, None, &|x| found.lock().unwrap().push(x));
+            found.into_inner().unwrap()
+        };
+
+        assert!(findings(48000, vec![(Speaker::FrontLeft, clean)]).is_empty());
+
+        assert_eq!(
+            findings(
+                8000,
+                vec![(Speaker::FrontLeft, offset), (Speaker::FrontRight, clipped)]
+            ),
+            vec![
+                Diagnostic::UnusualSampleRate { sample_rate: 8000 },
+                Diagnostic::DcOffset {
+                    speaker: Speaker::FrontLeft,
+                    offset: 0.03125
+                },
+                Diagnostic::Clipped {
+                    speaker: Speaker::FrontRight,
+                    runs: 2
+                },
+                Diagnostic::LeadingSilence { ms: 6.0 },
+            ]
+        );
+    }
+}
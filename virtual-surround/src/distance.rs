@@ -0,0 +1,207 @@
+use crate::Smoothed;
+
+/// distance the HRIR is assumed to be measured at, speakers at this distance are left untouched
+pub const REFERENCE_DISTANCE: f32 = 1.0;
+/// meters per second
+pub(crate) const SPEED_OF_SOUND: f32 = 343.0;
+
+/// Delay line with a gain, both ramped, for a speaker at a distance or a moving source, where
+/// the ramping delay is what shifts the pitch like the Doppler effect
+#[derive(Debug, Clone)]
+pub(crate) struct DelayLine {
+    gain: Smoothed,
+    /// in samples, the fraction is interpolated
+    delay: Smoothed,
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl DelayLine {
+    pub(crate) fn new(ramp: usize) -> Self {
+        DelayLine {
+            gain: Smoothed::new(1.0, ramp),
+            delay: Smoothed::new(0.0, ramp),
+            buffer: vec![0f32; 4],
+            position: 0,
+        }
+    }
+
+    /// make room for `delay` samples, keeping what's buffered
+    fn grow(&mut self, delay: usize) {
+        let len = delay + 4;
+        if len <= self.buffer.len() {
+            return;
+        }
+
+        // oldest sample first, so the new room comes before it
+        self.buffer.rotate_left(self.position);
+        let mut grown = vec![0f32; len - self.buffer.len()];
+        grown.append(&mut self.buffer);
+        self.buffer = grown;
+        self.position = 0;
+    }
+
+    pub(crate) fn set_gain(&mut self, gain: f32, ramp: usize) {
+        self.gain.set_ramp(ramp);
+        self.gain.set(gain);
+    }
+
+    /// ramp to `delay` samples over `ramp` samples, or longer for delays changing more than a
+    /// sample per sample so they never reach back past what's buffered. Grows the buffer when
+    /// the delay doesn't fit.
+    pub(crate) fn set_delay(&mut self, delay: f32, ramp: usize) {
+        self.grow(delay.ceil() as usize);
+
+        let change = (delay - self.delay.value()).abs().ceil() as usize;
+        self.delay
+            .set_ramp(if ramp > 0 { ramp.max(change) } else { 0 });
+        self.delay.set(delay);
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.buffer.fill(0f32);
+    }
+
+    pub(crate) fn process(&mut self, samples: &mut [f32]) {
+        let len = self.buffer.len();
+
+        for sample in samples.iter_mut() {
+            self.buffer[self.position] = *sample;
+
+            let delay = self.delay.next_value();
+            let whole = delay as usize;
+
+            // cubic hermite between the two samples around the fractional delay
+            let at = |offset: usize| self.buffer[(self.position + len * 2 - offset) % len];
+            let (y0, y1, y2, y3) = (
+                at(whole.saturating_sub(1)),
+                at(whole),
+                at(whole + 1),
+                at(whole + 2),
+            );
+            let t = delay.fract();
+            let c1 = 0.5 * (y2 - y0);
+            let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+            let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+
+            *sample = (((c3 * t + c2) * t + c1) * t + y1) * self.gain.next_value();
+            self.position = (self.position + 1) % len;
+        }
+    }
+}
+
+/// Per speaker gain and delay simulating their distance to the listener, delays are relative to
+/// the nearest speaker so the closest one isn't delayed at all
+#[derive(Debug, Clone)]
+pub(crate) struct SpeakerDistances {
+    distances: Vec<f32>,
+    speakers: Vec<DelayLine>,
+}
+
+impl SpeakerDistances {
+    pub fn new(channels: usize) -> Self {
+        SpeakerDistances {
+            distances: vec![REFERENCE_DISTANCE; channels],
+            speakers: vec![],
+        }
+    }
+
+    pub fn distance(&self, channel: usize) -> f32 {
+        self.distances[channel]
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.speakers.is_empty()
+    }
+
+    /// gains and delays of every speaker ramp to the new ones over `ramp` samples
+    pub fn set_distance(&mut self, channel: usize, meters: f32, sample_rate: usize, ramp: usize) {
+        self.distances[channel] = meters;
+
+        if self.speakers.is_empty() {
+            if self.distances.iter().all(|x| *x == REFERENCE_DISTANCE) {
+                return;
+            }
+
+            self.speakers = self
+                .distances
+                .iter()
+                .map(|_| DelayLine::new(ramp))
+                .collect();
+        }
+
+        let nearest = self.distances.iter().copied().fold(f32::MAX, f32::min);
+
+        for (speaker, distance) in self.speakers.iter_mut().zip(&self.distances) {
+            let delay = (distance - nearest) / SPEED_OF_SOUND * sample_rate as f32;
+            speaker.set_gain(REFERENCE_DISTANCE / distance, ramp);
+            speaker.set_delay(delay, ramp);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for speaker in &mut self.speakers {
+            speaker.reset();
+        }
+    }
+
+    /// fill the delay lines with the input before `end` in `window`, so speakers moved while
+    /// audio runs don't start out of silence
+    pub fn prime(&mut self, window: &[Vec<f32>], end: usize) {
+        for (speaker, window) in self.speakers.iter_mut().zip(window) {
+            let len = speaker.buffer.len().min(end);
+            let start = speaker.buffer.len() - len;
+            speaker.buffer[start..].copy_from_slice(&window[end - len..end]);
+            speaker.position = 0;
+        }
+    }
+
+    pub fn process(&mut self, channel: usize, samples: &mut [f32]) {
+        if let Some(speaker) = self.speakers.get_mut(channel) {
+            speaker.process(samples);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpeakerDistances;
+
+    #[test]
+    fn farther_speaker_is_delayed_and_quieter() {
+        let mut distances = SpeakerDistances::new(2);
+        // 3.43m farther at 1000hz is 10 samples
+        distances.set_distance(1, 4.43, 1000, 0);
+
+        let mut near = vec![0f32; 32];
+        let mut far = vec![0f32; 32];
+        near[0] = 1.0;
+        far[0] = 1.0;
+
+        distances.process(0, &mut near);
+        distances.process(1, &mut far);
+
+        assert_eq!(near[0], 1.0);
+        assert!((far[10] - 1.0 / 4.43).abs() < 1e-3, "{:?}", far);
+        assert!(far[..10].iter().all(|x| x.abs() < 1e-3));
+    }
+
+    #[test]
+    fn moving_a_speaker_ramps() {
+        let mut distances = SpeakerDistances::new(2);
+        let mut samples = vec![1f32; 2000];
+        distances.set_distance(1, 1.0, 48000, 100);
+        distances.process(1, &mut samples[..1000]);
+        assert!(!distances.is_active());
+
+        // 140 samples further away, the delay takes longer than the gain
+        distances.set_distance(1, 2.0, 48000, 100);
+        distances.prime(&vec![samples[..1000].to_vec(); 2], 1000);
+        distances.process(1, &mut samples[1000..]);
+
+        assert!(samples[..1000].iter().all(|x| *x == 1.0));
+        assert!(samples.windows(2).all(|x| (x[0] - x[1]).abs() < 0.01));
+        assert!((samples[1100] - 0.5).abs() < 1e-3);
+        assert!(samples[1100..].iter().all(|x| (x - 0.5).abs() < 1e-3));
+    }
+}
@@ -0,0 +1,250 @@
+/// most the playback rate is bent to catch up with the capture clock, 0.5%
+const MAX_CORRECTION: f64 = 0.005;
+/// proportional and integral gains of the fill level controller, per pull
+const PROPORTIONAL: f64 = 0.01;
+const INTEGRAL: f64 = 0.000_006;
+/// smoothing of the fill level, so block sized jumps of the fifo don't reach the controller
+const AVERAGE: f64 = 0.02;
+
+/// Resamples between a capture device and a playback device which run on their own clocks, like
+/// a console's HDMI or ADAT output captured by a soundcard.
+///
+/// Captured frames are queued with [`push`](DriftCompensator::push), and
+/// [`pull`](DriftCompensator::pull) plays them back at a rate bent slightly by a controller which
+/// keeps the queue at `latency` frames, so the two clocks drifting apart neither runs the queue dry
+/// nor grows it. Underruns play silence until the queue is refilled, overruns drop the oldest
+/// frames.
+///
+/// Nothing is allocated after construction, the queue holds 4 times `latency` plus `max_push`
+/// frames.
+#[derive(Debug, Clone)]
+pub struct DriftCompensator {
+    channels: usize,
+    /// capture frames per playback frame
+    nominal: f64,
+    latency: usize,
+    fifo: Vec<f32>,
+    start: usize,
+    frames: usize,
+    phase: f64,
+    average: f64,
+    integral: f64,
+    correction: f64,
+    playing: bool,
+    underruns: usize,
+    overruns: usize,
+}
+
+/// 4 point hermite interpolation between `x1` and `x2`
+fn hermite(x0: f32, x1: f32, x2: f32, x3: f32, t: f32) -> f32 {
+    let c1 = 0.5 * (x2 - x0);
+    let c2 = x0 - 2.5 * x1 + 2.0 * x2 - 0.5 * x3;
+    let c3 = 0.5 * (x3 - x0) + 1.5 * (x1 - x2);
+
+    ((c3 * t + c2) * t + c1) * t + x1
+}
+
+impl DriftCompensator {
+    pub fn new(
+        channels: usize,
+        capture_rate: usize,
+        playback_rate: usize,
+        latency: usize,
+        max_push: usize,
+    ) -> anyhow::Result<Self> {
+        if channels == 0 || capture_rate == 0 || playback_rate == 0 {
+            anyhow::bail!("DriftCompensator needs at least one channel and non zero rates");
+        }
+
+        // the interpolation looks a frame back and two ahead
+        let latency = latency.max(4);
+
+        Ok(DriftCompensator {
+            channels,
+            nominal: capture_rate as f64 / playback_rate as f64,
+            latency,
+            fifo: vec![0f32; (latency * 4 + max_push) * channels],
+            start: 0,
+            frames: 0,
+            phase: 0.0,
+            average: latency as f64,
+            integral: 0.0,
+            correction: 1.0,
+            playing: false,
+            underruns: 0,
+            overruns: 0,
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// frames the queue is kept at
+    pub fn latency(&self) -> usize {
+        self.latency
+    }
+
+    /// frames currently queued
+    pub fn queued(&self) -> usize {
+        self.frames
+    }
+
+    /// how much faster the capture clock runs than the playback clock, as estimated by the
+    /// controller, 1.0 when they're in sync
+    pub fn drift(&self) -> f64 {
+        self.correction
+    }
+
+    /// times the queue ran dry
+    pub fn underruns(&self) -> usize {
+        self.underruns
+    }
+
+    /// times captured frames were dropped because the queue was full
+    pub fn overruns(&self) -> usize {
+        self.overruns
+    }
+
+    /// forget everything queued and the drift estimate
+    pub fn reset(&mut self) {
+        self.start = 0;
+        self.frames = 0;
+        self.phase = 0.0;
+        self.average = self.latency as f64;
+        self.integral = 0.0;
+        self.correction = 1.0;
+        self.playing = false;
+    }
+
+    /// queue interleaved captured frames
+    pub fn push(&mut self, input: &[f32]) {
+        let capacity = self.fifo.len() / self.channels;
+        let mut incoming = input.len() / self.channels;
+        let mut input = &input[..incoming * self.channels];
+
+        if incoming > capacity {
+            input = &input[(incoming - capacity) * self.channels..];
+            incoming = capacity;
+        }
+
+        if self.frames + incoming > capacity {
+            let dropped = self.frames + incoming - capacity;
+            self.start += dropped;
+            self.frames -= dropped;
+            self.overruns += 1;
+        }
+
+        if self.start + self.frames + incoming > capacity {
+            let range = self.start * self.channels..(self.start + self.frames) * self.channels;
+            self.fifo.copy_within(range, 0);
+            self.start = 0;
+        }
+
+        let offset = (self.start + self.frames) * self.channels;
+        self.fifo[offset..offset + input.len()].copy_from_slice(input);
+        self.frames += incoming;
+    }
+
+    /// fill `output` with interleaved frames at the playback rate
+    pub fn pull(&mut self, output: &mut [f32]) {
+        if !self.playing {
+            if self.frames < self.latency {
+                output.fill(0f32);
+                return;
+            }
+
+            self.playing = true;
+        }
+
+        self.update_correction();
+        let step = self.nominal * self.correction;
+        let channels = self.channels;
+
+        for (index, frame) in output.chunks_exact_mut(channels).enumerate() {
+            if self.frames < 4 {
+                self.underruns += 1;
+                self.playing = false;
+                output[index * channels..].fill(0f32);
+                return;
+            }
+
+            let base = self.start * channels;
+            let t = self.phase as f32;
+            for (c, sample) in frame.iter_mut().enumerate() {
+                let x = |n: usize| self.fifo[base + n * channels + c];
+                *sample = hermite(x(0), x(1), x(2), x(3), t);
+            }
+
+            self.phase += step;
+            let advance = self.phase.floor();
+            self.phase -= advance;
+            self.start += advance as usize;
+            self.frames -= (advance as usize).min(self.frames);
+        }
+    }
+
+    fn update_correction(&mut self) {
+        let fill = self.frames as f64 - self.phase;
+        self.average += (fill - self.average) * AVERAGE;
+
+        let error = (self.average - self.latency as f64) / self.latency as f64;
+        self.integral = (self.integral + error * INTEGRAL).clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        self.correction = (1.0 + self.integral + error * PROPORTIONAL)
+            .clamp(1.0 - MAX_CORRECTION, 1.0 + MAX_CORRECTION);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DriftCompensator;
+
+    #[test]
+    fn follows_a_faster_capture_clock() {
+        let latency = 2048;
+        let mut compensator = DriftCompensator::new(1, 48000, 48000, latency, 512).unwrap();
+
+        // capture clock 300ppm fast, in periods of 480 frames, playback pulls 512
+        let drift = 1.0003;
+        let mut captured = 0.0f64;
+        let mut phase = 0usize;
+        let mut output = vec![0f32; 512];
+        let mut last = 0.0;
+        let (mut queued, mut estimate) = (0.0, 0.0);
+
+        for pull in 0..20000 {
+            while captured < (pull + 1) as f64 * 512.0 * drift {
+                let period = (0..480)
+                    .map(|s| ((phase + s) as f64 * 0.01).sin() as f32)
+                    .collect::<Vec<_>>();
+                phase += 480;
+                captured += 480.0;
+                compensator.push(&period);
+            }
+
+            if pull >= 10000 {
+                queued += compensator.queued() as f64 / 10000.0;
+            }
+
+            compensator.pull(&mut output);
+
+            if pull >= 10000 {
+                estimate += compensator.drift() / 10000.0;
+            }
+
+            if pull > 10 {
+                for sample in &output {
+                    assert!((sample - last).abs() < 0.011);
+                    last = *sample;
+                }
+            } else {
+                last = output[511];
+            }
+        }
+
+        assert_eq!(compensator.underruns(), 0);
+        assert_eq!(compensator.overruns(), 0);
+        assert!((estimate - drift).abs() < 0.00002);
+        assert!((queued - latency as f64).abs() < 64.0);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::bass::BassManager;
+use crate::{stereo_downmix_gains, Smoothed, Speaker};
+
+/// plain stereo downmix of the input window, used for the wet/dry mix and bypass
+#[derive(Debug, Clone)]
+pub(crate) struct DryPath {
+    gains: Vec<(f32, f32)>,
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+
+impl DryPath {
+    pub fn new<I: Iterator<Item = Speaker>>(positions: I, block_size: usize) -> Self {
+        DryPath {
+            gains: positions.map(stereo_downmix_gains).collect(),
+            left: vec![0f32; block_size],
+            right: vec![0f32; block_size],
+        }
+    }
+
+    /// leave `channel` out of the downmix
+    pub fn exclude(&mut self, channel: usize) {
+        self.gains[channel] = (0.0, 0.0);
+    }
+
+    /// downmix the block of the window ending at `end`
+    pub fn render(&mut self, input: &[Vec<f32>], end: usize, bass: Option<&BassManager>) {
+        let start = end - self.left.len();
+
+        self.left.fill(0f32);
+        self.right.fill(0f32);
+
+        for (channel, (left_gain, right_gain)) in input.iter().zip(&self.gains) {
+            for (s, sample) in channel[start..end].iter().enumerate() {
+                self.left[s] += sample * left_gain;
+                self.right[s] += sample * right_gain;
+            }
+        }
+
+        // bass management took the low end out of the window
+        if let Some(bass) = bass {
+            bass.mix_into(end, &mut self.left, &mut self.right);
+        }
+    }
+
+    pub fn output(&self) -> (&[f32], &[f32]) {
+        (&self.left, &self.right)
+    }
+
+    pub fn output_mut(&mut self) -> (&mut [f32], &mut [f32]) {
+        (&mut self.left, &mut self.right)
+    }
+
+    /// blend `left` and `right` with the downmix, `mix` of them is kept, stepped every sample
+    pub fn blend(&self, mix: &mut Smoothed, left: &mut [f32], right: &mut [f32]) {
+        for (s, (left, right)) in left.iter_mut().zip(right.iter_mut()).enumerate() {
+            let mix = mix.next_value();
+            *left = *left * mix + self.left[s] * (1.0 - mix);
+            *right = *right * mix + self.right[s] * (1.0 - mix);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! small load-time and analysis helpers, none of this is meant for the audio path
+
+use std::f64::consts::PI;
+
+/// in place radix-2 complex fft, `re.len()` has to be a power of two
+pub(crate) fn fft(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let n = re.len();
+    assert!(n.is_power_of_two() && im.len() == n);
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f64).sin_cos();
+                let a = start + k;
+                let b = a + len / 2;
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+
+    if inverse {
+        for i in 0..n {
+            re[i] /= n as f64;
+            im[i] /= n as f64;
+        }
+    }
+}
+
+/// minimum phase impulse response for the magnitude response of the `n / 2 + 1` positive
+/// frequency bins of an `n` point fft, by folding the real cepstrum
+pub(crate) fn minimum_phase(magnitude: &[f64], n: usize) -> Vec<f64> {
+    let mut re = vec![0f64; n];
+    let mut im = vec![0f64; n];
+
+    for (k, re) in re.iter_mut().enumerate() {
+        let bin = if k <= n / 2 { k } else { n - k };
+        *re = magnitude[bin].max(1e-9).ln();
+    }
+
+    fft(&mut re, &mut im, true);
+
+    for k in 1..n / 2 {
+        re[k] *= 2.0;
+        im[k] = 0.0;
+    }
+
+    for k in (n / 2 + 1)..n {
+        re[k] = 0.0;
+        im[k] = 0.0;
+    }
+
+    im[0] = 0.0;
+    im[n / 2] = 0.0;
+
+    fft(&mut re, &mut im, false);
+
+    for k in 0..n {
+        let (sin, cos) = im[k].sin_cos();
+        let magnitude = re[k].exp();
+        re[k] = magnitude * cos;
+        im[k] = magnitude * sin;
+    }
+
+    fft(&mut re, &mut im, true);
+
+    re
+}
+
+/// linear interpolation of a `(frequency, value)` curve on a log frequency axis, clamped at both
+/// ends
+pub(crate) fn interpolate_log(points: &[(f32, f32)], frequency: f32) -> f32 {
+    match points.iter().position(|(f, _)| *f >= frequency) {
+        None => points.last().map_or(0.0, |x| x.1),
+        Some(0) => points[0].1,
+        Some(i) => {
+            let (f0, v0) = points[i - 1];
+            let (f1, v1) = points[i];
+            let t = (frequency.ln() - f0.ln()) / (f1.ln() - f0.ln());
+            v0 + (v1 - v0) * t
+        }
+    }
+}
+
+/// inverse of a square matrix by gauss-jordan elimination, `None` if it's singular
+pub(crate) fn invert(matrix: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+    let n = matrix.len();
+    let mut work = matrix
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let mut row = row.clone();
+            row.extend((0..n).map(|j| if i == j { 1.0 } else { 0.0 }));
+            row
+        })
+        .collect::<Vec<_>>();
+
+    for column in 0..n {
+        let pivot =
+            (column..n).max_by(|a, b| work[*a][column].abs().total_cmp(&work[*b][column].abs()))?;
+
+        if work[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+
+        work.swap(column, pivot);
+        let scale = work[column][column];
+        for value in &mut work[column] {
+            *value /= scale;
+        }
+
+        let pivot_row = work[column].clone();
+        for (row, values) in work.iter_mut().enumerate() {
+            let factor = values[column];
+            if row == column || factor == 0.0 {
+                continue;
+            }
+
+            for (value, pivot) in values.iter_mut().zip(&pivot_row) {
+                *value -= factor * pivot;
+            }
+        }
+    }
+
+    Some(work.into_iter().map(|row| row[n..].to_vec()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fft, invert, minimum_phase};
+
+    #[test]
+    fn fft_round_trip() {
+        let input = (0..64).map(|x| (x as f64 * 0.3).sin()).collect::<Vec<_>>();
+        let mut re = input.clone();
+        let mut im = vec![0f64; 64];
+
+        fft(&mut re, &mut im, false);
+        fft(&mut re, &mut im, true);
+
+        for (a, b) in input.iter().zip(&re) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn flat_minimum_phase_is_impulse() {
+        let ir = minimum_phase(&[1.0; 33], 64);
+
+        assert!((ir[0] - 1.0).abs() < 1e-6);
+        assert!(ir[1..].iter().all(|x| x.abs() < 1e-6));
+    }
+
+    #[test]
+    fn inverse_of_inverse() {
+        let matrix = vec![
+            vec![0.0, 2.0, 1.0],
+            vec![1.0, 1.0, 0.0],
+            vec![3.0, 0.0, 4.0],
+        ];
+
+        let inverse = invert(&matrix).unwrap();
+        let back = invert(&inverse).unwrap();
+
+        for (a, b) in matrix.iter().flatten().zip(back.iter().flatten()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+
+        assert!(invert(&[vec![1.0, 2.0], vec![2.0, 4.0]]).is_none());
+    }
+}
@@ -0,0 +1,631 @@
+use crate::BLOCK_SIZE;
+use std::any::Any;
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// blocks timed per engine when benchmarking, the fastest round counts
+const BENCHMARK_ROUNDS: usize = 16;
+
+/// A convolution engine renders every input channel of the filter through a left and right ear
+/// impulse response and sums the results into a stereo output block.
+///
+/// The filter hands the engine a window of `length` samples per channel (see
+/// [`RawVirtualSurroundFilter::samples_required`](crate::RawVirtualSurroundFilter::samples_required)),
+/// the newest [`BLOCK_SIZE`](crate::BLOCK_SIZE) samples at the end, and expects the output for
+/// those newest samples. The trait is object safe, so engines from other crates can be plugged in
+/// through [`FilterBuilder::engine`](crate::FilterBuilder::engine) without touching the filter.
+pub trait ConvolutionEngine: Debug + Send {
+    /// Load an impulse response, `ir_index` is `channel * 2 + ear` with ear 0 being the left ear.
+    /// `impulse` is zero padded to the window length.
+    fn init_ir(&mut self, impulse: &[f32], ir_index: usize) -> anyhow::Result<()>;
+
+    /// Load every impulse response in `impulses` like [`init_ir`](ConvolutionEngine::init_ir),
+    /// each with its `ir_index`, which is how filters load them when they're built with the
+    /// `parallel` feature. Engines transform them side by side on the rayon pool where they can.
+    fn init_irs(&mut self, impulses: &[(usize, Vec<f32>)]) -> anyhow::Result<()> {
+        for (ir_index, impulse) in impulses {
+            self.init_ir(impulse, *ir_index)?;
+        }
+
+        Ok(())
+    }
+
+    /// Convolve the window `samples` of `channel` with both of its impulse responses, adding
+    /// (not writing) the newest block of output to `left_output` and `right_output`.
+    fn process(
+        &mut self,
+        channel: usize,
+        samples: &[f32],
+        left_output: &mut [f32],
+        right_output: &mut [f32],
+    ) -> anyhow::Result<()>;
+
+    /// Convolve every channel of `channels` like [`process`](ConvolutionEngine::process), `input`
+    /// holding the windows of all channels of the filter. Engines which sum the spectra of the
+    /// channels before transforming back do two inverse ffts a block instead of two per channel.
+    fn process_channels(
+        &mut self,
+        channels: &[usize],
+        input: &[&[f32]],
+        left_output: &mut [f32],
+        right_output: &mut [f32],
+    ) -> anyhow::Result<()> {
+        for channel in channels {
+            self.process(*channel, input[*channel], left_output, right_output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Delay in samples between input entering the window and it appearing in the output.
+    fn latency(&self) -> usize;
+
+    /// Forget any state carried between calls to [`process`](ConvolutionEngine::process), loaded
+    /// impulse responses are kept.
+    fn reset(&mut self);
+
+    /// Snapshot of the state carried between calls to [`process`](ConvolutionEngine::process),
+    /// `None` for engines which don't carry any.
+    fn save_state(&self) -> Option<Box<dyn Any + Send>> {
+        None
+    }
+
+    /// Restore a snapshot [`save_state`](ConvolutionEngine::save_state) took from an engine built
+    /// for the same channels and length.
+    fn load_state(&mut self, state: Option<&(dyn Any + Send)>) -> anyhow::Result<()> {
+        match state {
+            Some(_) => anyhow::bail!("Engine carries no state to restore"),
+            None => Ok(()),
+        }
+    }
+
+    /// Impulse response `ir_index` the way the engine keeps it after
+    /// [`init_ir`](ConvolutionEngine::init_ir), e.g. its spectrum, for
+    /// [`RawVirtualSurroundFilter::save_prepared`](crate::RawVirtualSurroundFilter::save_prepared).
+    /// `None` for engines which can't hand it out.
+    fn export_ir(&self, _ir_index: usize) -> Option<Vec<f32>> {
+        None
+    }
+
+    /// Load an impulse response [`export_ir`](ConvolutionEngine::export_ir) handed out by an
+    /// engine of the same kind built for the same channels and length, skipping the work
+    /// [`init_ir`](ConvolutionEngine::init_ir) does.
+    fn import_ir(&mut self, _prepared: &[f32], _ir_index: usize) -> anyhow::Result<()> {
+        anyhow::bail!("Engine can't load prepared impulse responses")
+    }
+
+    /// An engine with the impulse responses of this one and none of its state, sharing them
+    /// instead of copying where it can, for
+    /// [`VirtualSurroundFilter::clone_fresh`](crate::VirtualSurroundFilter::clone_fresh). `None`
+    /// for engines which can't, the filter then loads a new one from its factory.
+    fn clone_fresh(&self) -> Option<Box<dyn ConvolutionEngine>> {
+        None
+    }
+}
+
+/// Engines which can be constructed for a channel count and window length. The type is picked at
+/// compile time, [`new_engine`] turns one into an [`EngineFactory`] and an [`Engine`] names it, so
+/// an application compiled with several can pick one at runtime.
+pub trait FFTLogic: ConvolutionEngine + Sized {
+    /// Fails for windows the engine can't convolve, or backends which can't be set up, e.g. an
+    /// FFTW or GPU plan which couldn't be made.
+    fn new(channels: usize, length: usize) -> anyhow::Result<Self>;
+}
+
+/// windows need room for a block of output after the impulse responses
+pub(crate) fn check_window(length: usize) -> anyhow::Result<()> {
+    if length <= BLOCK_SIZE {
+        anyhow::bail!(
+            "Engines need a window longer than a block of {} samples, got {}",
+            BLOCK_SIZE,
+            length
+        );
+    }
+
+    Ok(())
+}
+
+/// Creates a boxed engine for a channel count and window length
+pub type EngineFactory = fn(usize, usize) -> anyhow::Result<Box<dyn ConvolutionEngine>>;
+
+/// [`EngineFactory`] for any [`FFTLogic`] implementation, e.g. `new_engine::<RustFFTLogic>`
+pub fn new_engine<T: FFTLogic + 'static>(
+    channels: usize,
+    length: usize,
+) -> anyhow::Result<Box<dyn ConvolutionEngine>> {
+    Ok(Box::new(T::new(channels, length)?))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SimdLevel {
+    Scalar,
+    Sse4_1,
+    Avx,
+    Neon,
+}
+
+impl SimdLevel {
+    /// best instruction set rustfft style runtime dispatch will use on this machine
+    pub fn detect() -> SimdLevel {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx") && is_x86_feature_detected!("fma") {
+                return SimdLevel::Avx;
+            }
+
+            if is_x86_feature_detected!("sse4.1") {
+                return SimdLevel::Sse4_1;
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                return SimdLevel::Neon;
+            }
+        }
+
+        SimdLevel::Scalar
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EngineCapabilities {
+    pub simd: SimdLevel,
+    pub gpu: bool,
+    /// convolves in f64, slower but with a lower noise floor
+    pub double_precision: bool,
+    /// convolves in fixed point, which rounds and saturates unlike the float engines
+    pub fixed_point: bool,
+}
+
+/// A named convolution engine which can be picked at runtime
+#[derive(Copy, Clone)]
+pub struct Engine {
+    name: &'static str,
+    capabilities: fn() -> EngineCapabilities,
+    factory: EngineFactory,
+}
+
+impl Debug for Engine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Engine")
+            .field("name", &self.name)
+            .field("capabilities", &self.capabilities())
+            .finish()
+    }
+}
+
+impl Engine {
+    /// describe an engine, e.g. one from another crate, so it can be used like the built-in ones
+    pub fn new(
+        name: &'static str,
+        capabilities: fn() -> EngineCapabilities,
+        factory: EngineFactory,
+    ) -> Self {
+        Engine {
+            name,
+            capabilities,
+            factory,
+        }
+    }
+
+    /// engines compiled into this build, in order of preference
+    pub fn available() -> Vec<Engine> {
+        vec![
+            #[cfg(feature = "rustfft")]
+            Engine::new(
+                "rustfft",
+                || EngineCapabilities {
+                    simd: SimdLevel::detect(),
+                    gpu: false,
+                    double_precision: false,
+                    fixed_point: false,
+                },
+                new_engine::<crate::RustFFTLogic>,
+            ),
+            #[cfg(feature = "rustfft")]
+            Engine::new(
+                "rustfft-f64",
+                || EngineCapabilities {
+                    simd: SimdLevel::detect(),
+                    gpu: false,
+                    double_precision: true,
+                    fixed_point: false,
+                },
+                new_engine::<crate::RustFFT64Logic>,
+            ),
+            #[cfg(feature = "rustfft")]
+            Engine::new(
+                "rustfft-partitioned",
+                || EngineCapabilities {
+                    simd: SimdLevel::detect(),
+                    gpu: false,
+                    double_precision: false,
+                    fixed_point: false,
+                },
+                new_engine::<crate::PartitionedLogic>,
+            ),
+            #[cfg(feature = "rustfft")]
+            Engine::new(
+                "rustfft-partitioned-burst",
+                || EngineCapabilities {
+                    simd: SimdLevel::detect(),
+                    gpu: false,
+                    double_precision: false,
+                    fixed_point: false,
+                },
+                |channels, length| {
+                    Ok(Box::new(crate::PartitionedLogic::with_scheduling(
+                        channels,
+                        length,
+                        crate::TailScheduling::Burst,
+                    )?))
+                },
+            ),
+            #[cfg(feature = "rustfft")]
+            Engine::new(
+                "rustfft-overlap-save",
+                || EngineCapabilities {
+                    simd: SimdLevel::detect(),
+                    gpu: false,
+                    double_precision: false,
+                    fixed_point: false,
+                },
+                crate::ConvolutionStrategy::OverlapSave.factory(),
+            ),
+            #[cfg(feature = "rustfft")]
+            Engine::new(
+                "rustfft-overlap-add",
+                || EngineCapabilities {
+                    simd: SimdLevel::detect(),
+                    gpu: false,
+                    double_precision: false,
+                    fixed_point: false,
+                },
+                crate::ConvolutionStrategy::OverlapAdd.factory(),
+            ),
+            #[cfg(feature = "fixed-point")]
+            Engine::new(
+                "fixed-q31",
+                || EngineCapabilities {
+                    simd: SimdLevel::Scalar,
+                    gpu: false,
+                    double_precision: false,
+                    fixed_point: true,
+                },
+                new_engine::<crate::FixedPointLogic>,
+            ),
+        ]
+    }
+
+    pub fn by_name(name: &str) -> anyhow::Result<Engine> {
+        let engines = Self::available();
+
+        match engines.iter().find(|x| x.name == name) {
+            Some(engine) => Ok(*engine),
+            None => anyhow::bail!(
+                "Unknown engine {}, available engines are: {}",
+                name,
+                engines
+                    .iter()
+                    .map(|x| x.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// First of the engines named in `names` which is compiled into this build, e.g.
+    /// `["fftw", "rustfft"]` to use an FFTW engine where there is one and rustfft otherwise.
+    pub fn preferred(names: &[&str]) -> anyhow::Result<Engine> {
+        let engines = Self::available();
+
+        match names
+            .iter()
+            .find_map(|name| engines.iter().find(|x| x.name == *name))
+        {
+            Some(engine) => Ok(*engine),
+            None => anyhow::bail!(
+                "None of the engines {} are compiled in, available engines are: {}",
+                names.join(", "),
+                engines
+                    .iter()
+                    .map(|x| x.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// first available engine whose capabilities satisfy `predicate`
+    pub fn find<F: Fn(&EngineCapabilities) -> bool>(predicate: F) -> Option<Engine> {
+        Self::available()
+            .into_iter()
+            .find(|x| predicate(&x.capabilities()))
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn capabilities(&self) -> EngineCapabilities {
+        (self.capabilities)()
+    }
+
+    pub fn factory(&self) -> EngineFactory {
+        self.factory
+    }
+
+    /// time how long this engine takes to render one block for `channels` channels with a window
+    /// of `length` samples
+    pub fn benchmark(&self, channels: usize, length: usize) -> anyhow::Result<Duration> {
+        let mut engine = (self.factory)(channels, length)?;
+
+        let mut impulse = vec![0f32; length];
+        impulse[0] = 1.0;
+        for ir_index in 0..channels * 2 {
+            engine.init_ir(&impulse, ir_index)?;
+        }
+
+        let window = (0..length)
+            .map(|x| (x as f32 * 0.01).sin())
+            .collect::<Vec<_>>();
+        let windows = vec![&window[..]; channels];
+        let all = (0..channels).collect::<Vec<_>>();
+        let mut left = vec![0f32; BLOCK_SIZE];
+        let mut right = vec![0f32; BLOCK_SIZE];
+
+        // every channel at once like the filter renders them, the first round warms up caches and
+        // lazily planned ffts
+        let mut best = Duration::MAX;
+        for _ in 0..=BENCHMARK_ROUNDS {
+            let start = Instant::now();
+            engine.process_channels(&all, &windows, &mut left, &mut right)?;
+
+            best = best.min(start.elapsed());
+        }
+
+        Ok(best)
+    }
+
+    /// Benchmark the available f32 floating point engines and return the fastest, like FFTW's
+    /// planning. Fixed point and f64 engines render differently, so they're left out, see
+    /// [`fastest_of`](Engine::fastest_of) to let them compete.
+    ///
+    /// With a `cache` path the choice is remembered per channel count and window length, so only
+    /// the first construction pays for the benchmark. The cache is a plain text file with one
+    /// `<channels> <length> <engine>` line per entry; failing to write it is not an error.
+    pub fn fastest(channels: usize, length: usize, cache: Option<&Path>) -> anyhow::Result<Engine> {
+        let engines = Self::available()
+            .into_iter()
+            .filter(|x| {
+                let capabilities = x.capabilities();
+                !capabilities.double_precision && !capabilities.fixed_point
+            })
+            .collect::<Vec<_>>();
+
+        Self::fastest_of(&engines, channels, length, cache)
+    }
+
+    /// [`fastest`](Engine::fastest) of `engines`, a cached pick which isn't among them is
+    /// benchmarked over
+    pub fn fastest_of(
+        engines: &[Engine],
+        channels: usize,
+        length: usize,
+        cache: Option<&Path>,
+    ) -> anyhow::Result<Engine> {
+        let mut entries = cache
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|cache| {
+                cache
+                    .lines()
+                    .filter_map(|line| {
+                        let mut fields = line.split_whitespace();
+                        Some((
+                            fields.next()?.parse::<usize>().ok()?,
+                            fields.next()?.parse::<usize>().ok()?,
+                            fields.next()?.to_string(),
+                        ))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let cached = entries
+            .iter()
+            .find(|x| x.0 == channels && x.1 == length)
+            .and_then(|x| engines.iter().find(|engine| engine.name == x.2));
+
+        if let Some(engine) = cached {
+            trace_event!(
+                INFO,
+                engine = engine.name,
+                "using the cached fastest engine"
+            );
+            return Ok(*engine);
+        }
+
+        let mut fastest: Option<(Engine, Duration)> = None;
+
+        for engine in engines {
+            let engine = *engine;
+            let time = engine.benchmark(channels, length)?;
+            trace_event!(DEBUG, engine = engine.name, ?time, "benchmarked engine");
+
+            match fastest {
+                Some((_, best)) if best <= time => {}
+                _ => fastest = Some((engine, time)),
+            }
+        }
+
+        let engine = match fastest {
+            Some((engine, _)) => engine,
+            None => anyhow::bail!("No convolution engines to pick from"),
+        };
+
+        if let Some(path) = cache {
+            entries.retain(|x| x.0 != channels || x.1 != length);
+            entries.push((channels, length, engine.name.to_string()));
+
+            let contents = entries
+                .iter()
+                .map(|x| format!("{} {} {}\n", x.0, x.1, x.2))
+                .collect::<String>();
+
+            if let Some(parent) = path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+
+            let _ = fs::write(path, contents);
+        }
+
+        Ok(engine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConvolutionEngine, Engine, EngineCapabilities, SimdLevel};
+    use crate::BLOCK_SIZE;
+    use std::fs;
+
+    #[test]
+    fn finds_engines_by_name() {
+        for engine in Engine::available() {
+            assert_eq!(
+                Engine::by_name(engine.name()).unwrap().name(),
+                engine.name()
+            );
+        }
+
+        let error = Engine::by_name("no-such-engine").unwrap_err().to_string();
+        assert!(error.contains("no-such-engine"), "{}", error);
+        assert!(Engine::preferred(&["no-such-engine"]).is_err());
+    }
+
+    #[cfg(feature = "rustfft")]
+    #[test]
+    fn falls_back_to_an_available_engine() {
+        assert_eq!(
+            Engine::preferred(&["no-such-engine", "rustfft"])
+                .unwrap()
+                .name(),
+            "rustfft"
+        );
+
+        // rustfft dispatches to whatever the machine has, so there's always one at its level
+        let simd = SimdLevel::detect();
+        let engine = Engine::find(|x| x.simd == simd).unwrap();
+        assert!(Engine::available()
+            .iter()
+            .any(|x| x.name() == engine.name()));
+        assert!(Engine::find(|x| x.gpu).is_none());
+    }
+
+    #[cfg(feature = "rustfft")]
+    #[test]
+    fn benchmarks_a_block() {
+        let engine = Engine::by_name("rustfft").unwrap();
+        assert!(engine.benchmark(2, BLOCK_SIZE * 4).unwrap() > std::time::Duration::ZERO);
+        assert!(engine.benchmark(2, BLOCK_SIZE).is_err());
+    }
+
+    /// renders every channel at once and refuses to render them one by one
+    #[derive(Debug)]
+    struct SummingOnly;
+
+    impl ConvolutionEngine for SummingOnly {
+        fn init_ir(&mut self, _impulse: &[f32], _ir_index: usize) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn process(
+            &mut self,
+            _: usize,
+            _: &[f32],
+            _: &mut [f32],
+            _: &mut [f32],
+        ) -> anyhow::Result<()> {
+            anyhow::bail!("rendered a single channel")
+        }
+
+        fn process_channels(
+            &mut self,
+            channels: &[usize],
+            input: &[&[f32]],
+            _: &mut [f32],
+            _: &mut [f32],
+        ) -> anyhow::Result<()> {
+            assert_eq!(channels, [0, 1, 2]);
+            assert_eq!(input.len(), 3);
+            Ok(())
+        }
+
+        fn latency(&self) -> usize {
+            0
+        }
+
+        fn reset(&mut self) {}
+    }
+
+    #[test]
+    fn benchmarks_every_channel_at_once() {
+        let engine = Engine::new(
+            "summing-only",
+            || EngineCapabilities {
+                simd: SimdLevel::Scalar,
+                gpu: false,
+                double_precision: false,
+                fixed_point: false,
+            },
+            |_, _| Ok(Box::new(SummingOnly)),
+        );
+        engine.benchmark(3, BLOCK_SIZE * 4).unwrap();
+    }
+
+    #[cfg(feature = "rustfft")]
+    #[test]
+    fn uses_the_cached_engine() {
+        let path = std::env::temp_dir().join(format!("vsf-engines-{}", std::process::id()));
+        let length = BLOCK_SIZE * 4;
+
+        let contents = format!("8 4096 rustfft\n2 {} rustfft-overlap-add\n", length);
+        fs::write(&path, &contents).unwrap();
+        let engine = Engine::fastest(2, length, Some(&path)).unwrap();
+        assert_eq!(engine.name(), "rustfft-overlap-add");
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+
+        // an engine which isn't compiled in anymore and a garbled line are benchmarked over
+        fs::write(&path, format!("8 4096 rustfft\n2 {} gone\n2 x\n", length)).unwrap();
+        let engine = Engine::fastest(2, length, Some(&path)).unwrap();
+        assert_ne!(engine.name(), "gone");
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("8 4096 rustfft\n2 {} {}\n", length, engine.name())
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "rustfft")]
+    #[test]
+    fn only_picks_engines_which_render_alike() {
+        let path = std::env::temp_dir().join(format!("vsf-alike-{}", std::process::id()));
+        let length = BLOCK_SIZE * 4;
+
+        // a cached f64 engine is benchmarked over too
+        fs::write(&path, format!("2 {} rustfft-f64\n", length)).unwrap();
+        let engine = Engine::fastest(2, length, Some(&path)).unwrap();
+        let capabilities = engine.capabilities();
+        assert!(!capabilities.double_precision && !capabilities.fixed_point);
+        assert_ne!(
+            fs::read_to_string(&path).unwrap(),
+            format!("2 {} rustfft-f64\n", length)
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,498 @@
+use crate::biquad::Biquad;
+use crate::dsp::{interpolate_log, minimum_phase};
+use crate::wav::WavData;
+use crate::{fft_len_for, ConvolutionEngine, EngineFactory, BLOCK_SIZE};
+use anyhow::Context;
+use std::any::Any;
+use std::io::{BufRead, BufReader, Read, Seek};
+
+/// length of the FIR generated from a frequency response
+const RESPONSE_TAPS: usize = 2048;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum EqBand {
+    Peaking {
+        frequency: f32,
+        q: f32,
+        gain_db: f32,
+    },
+    LowShelf {
+        frequency: f32,
+        q: f32,
+        gain_db: f32,
+    },
+    HighShelf {
+        frequency: f32,
+        q: f32,
+        gain_db: f32,
+    },
+    LowPass {
+        frequency: f32,
+        q: f32,
+    },
+    HighPass {
+        frequency: f32,
+        q: f32,
+    },
+}
+
+impl EqBand {
+    fn biquad(&self, sample_rate: usize) -> Biquad {
+        match *self {
+            EqBand::Peaking {
+                frequency,
+                q,
+                gain_db,
+            } => Biquad::peaking(sample_rate, frequency, q, gain_db),
+            EqBand::LowShelf {
+                frequency,
+                q,
+                gain_db,
+            } => Biquad::low_shelf(sample_rate, frequency, q, gain_db),
+            EqBand::HighShelf {
+                frequency,
+                q,
+                gain_db,
+            } => Biquad::high_shelf(sample_rate, frequency, q, gain_db),
+            EqBand::LowPass { frequency, q } => Biquad::low_pass(sample_rate, frequency, q),
+            EqBand::HighPass { frequency, q } => Biquad::high_pass(sample_rate, frequency, q),
+        }
+    }
+}
+
+/// Headphone correction applied to the binaural output
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum HeadphoneEq {
+    /// chain of biquads, used for both ears
+    Parametric { preamp_db: f32, bands: Vec<EqBand> },
+    /// compensation impulse response per ear
+    Impulse {
+        sample_rate: u32,
+        left: Vec<f32>,
+        right: Vec<f32>,
+    },
+    /// `(frequency, gain_db)` curve, turned into a minimum phase FIR at the filter sample rate
+    Response {
+        preamp_db: f32,
+        points: Vec<(f32, f32)>,
+    },
+}
+
+impl HeadphoneEq {
+    pub fn parametric(preamp_db: f32, bands: Vec<EqBand>) -> Self {
+        HeadphoneEq::Parametric { preamp_db, bands }
+    }
+
+    /// mono or stereo float wav containing the compensation impulse response
+    pub fn from_wav<R: Read + Seek>(reader: R) -> anyhow::Result<Self> {
+        let wav = WavData::read(reader)?;
+
+        let (left, right) = match wav.channels {
+            1 => (wav.column(0), wav.column(0)),
+            2 => (wav.column(0), wav.column(1)),
+            n => anyhow::bail!("Headphone EQ impulse needs 1 or 2 channels, got {}", n),
+        };
+
+        Ok(HeadphoneEq::Impulse {
+            sample_rate: wav.sample_rate,
+            left,
+            right,
+        })
+    }
+
+    /// AutoEq result csv, uses the `equalization` column, or the second column for plain
+    /// `frequency,gain` files. The preamp is set so the curve never boosts.
+    pub fn from_autoeq_csv<R: Read>(reader: R) -> anyhow::Result<Self> {
+        let mut lines = BufReader::new(reader).lines();
+
+        let header = lines
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("AutoEq csv is empty"))??;
+        let columns = header.trim().split(',').collect::<Vec<_>>();
+
+        let frequency_column = columns
+            .iter()
+            .position(|x| *x == "frequency")
+            .ok_or_else(|| anyhow::anyhow!("AutoEq csv has no frequency column"))?;
+
+        let gain_column = match columns.iter().position(|x| *x == "equalization") {
+            Some(column) => column,
+            None if columns.len() == 2 => 1 - frequency_column,
+            None => anyhow::bail!("AutoEq csv has no equalization column"),
+        };
+
+        let mut points = vec![];
+
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let fields = line.trim().split(',').collect::<Vec<_>>();
+            let parse = |column: usize| -> anyhow::Result<f32> {
+                Ok(fields
+                    .get(column)
+                    .ok_or_else(|| anyhow::anyhow!("AutoEq csv line too short: {}", line))?
+                    .parse()?)
+            };
+
+            points.push((parse(frequency_column)?, parse(gain_column)?));
+        }
+
+        if points.is_empty() {
+            anyhow::bail!("AutoEq csv contains no data");
+        }
+
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let max_gain = points.iter().map(|x| x.1).fold(0f32, f32::max);
+
+        Ok(HeadphoneEq::Response {
+            preamp_db: -max_gain,
+            points,
+        })
+    }
+
+    fn impulse(&self, sample_rate: usize) -> anyhow::Result<Option<(Vec<f32>, Vec<f32>)>> {
+        match self {
+            HeadphoneEq::Parametric { .. } => Ok(None),
+            HeadphoneEq::Impulse {
+                sample_rate: rate,
+                left,
+                right,
+            } => {
+                if *rate as usize == sample_rate {
+                    return Ok(Some((left.clone(), right.clone())));
+                }
+
+                #[cfg(any(feature = "resample", feature = "resample-rubato"))]
+                {
+                    use crate::resample::{resample, ResampleQuality};
+
+                    let rate = (*rate, sample_rate as u32);
+                    let left = resample(left, 1, rate.0, rate.1, ResampleQuality::Best)?;
+                    let right = resample(right, 1, rate.0, rate.1, ResampleQuality::Best)?;
+
+                    Ok(Some((left, right)))
+                }
+
+                #[cfg(not(any(feature = "resample", feature = "resample-rubato")))]
+                anyhow::bail!(
+                    "Headphone EQ impulse is {}hz, filter runs at {}hz and resampling isn't compiled in",
+                    rate,
+                    sample_rate
+                )
+            }
+            HeadphoneEq::Response { preamp_db, points } => {
+                let n = RESPONSE_TAPS * 2;
+                let magnitude = (0..=n / 2)
+                    .map(|k| {
+                        let frequency = (k * sample_rate) as f32 / n as f32;
+                        let gain_db = interpolate_log(points, frequency) + preamp_db;
+                        10f64.powf(gain_db as f64 / 20.0)
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut ir = minimum_phase(&magnitude, n)
+                    .into_iter()
+                    .take(RESPONSE_TAPS)
+                    .map(|x| x as f32)
+                    .collect::<Vec<_>>();
+
+                // fade out the truncated tail
+                let fade = RESPONSE_TAPS / 8;
+                for i in 0..fade {
+                    ir[RESPONSE_TAPS - fade + i] *= 1.0 - (i as f32 / fade as f32);
+                }
+
+                Ok(Some((ir.clone(), ir)))
+            }
+        }
+    }
+}
+
+/// what an [`EqProcessor`] carries from one block to the next
+pub(crate) enum EqState {
+    Biquads([Vec<Biquad>; 2]),
+    Convolution {
+        engine: Option<Box<dyn Any + Send>>,
+        window: [Vec<f32>; 2],
+    },
+}
+
+#[derive(Debug)]
+pub(crate) enum EqProcessor {
+    Biquads {
+        preamp: f32,
+        left: Vec<Biquad>,
+        right: Vec<Biquad>,
+    },
+    Convolution {
+        engine: Box<dyn ConvolutionEngine>,
+        window: [Vec<f32>; 2],
+        output: [Vec<f32>; 2],
+    },
+}
+
+impl EqProcessor {
+    pub fn new(
+        eq: &HeadphoneEq,
+        sample_rate: usize,
+        engine: EngineFactory,
+    ) -> anyhow::Result<Self> {
+        if let HeadphoneEq::Parametric { preamp_db, bands } = eq {
+            let biquads = bands
+                .iter()
+                .map(|x| x.biquad(sample_rate))
+                .collect::<Vec<_>>();
+
+            return Ok(EqProcessor::Biquads {
+                preamp: 10f32.powf(preamp_db / 20.0),
+                left: biquads.clone(),
+                right: biquads,
+            });
+        }
+
+        let (left, right) = eq
+            .impulse(sample_rate)?
+            .expect("non parametric eq has an impulse");
+        let length = left.len().max(right.len());
+        let fft_len = fft_len_for(length);
+
+        // channel 0 is the left ear, channel 1 the right, neither bleeds into the other ear
+        let mut engine = engine(2, fft_len)?;
+        let mut impulse = vec![0f32; fft_len];
+
+        for (index, ir) in [Some(&left), None, None, Some(&right)].iter().enumerate() {
+            impulse.fill(0f32);
+            if let Some(ir) = ir {
+                impulse[..ir.len()].copy_from_slice(ir);
+            }
+
+            engine.init_ir(&impulse, index)?;
+        }
+
+        Ok(EqProcessor::Convolution {
+            engine,
+            window: [vec![0f32; fft_len], vec![0f32; fft_len]],
+            output: [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]],
+        })
+    }
+
+    pub fn reset(&mut self) {
+        match self {
+            EqProcessor::Biquads { left, right, .. } => {
+                for biquad in left.iter_mut().chain(right) {
+                    biquad.reset();
+                }
+            }
+            EqProcessor::Convolution { engine, window, .. } => {
+                engine.reset();
+                for window in window {
+                    window.fill(0f32);
+                }
+            }
+        }
+    }
+
+    /// the same eq without state, sharing the spectra of the convolution engine
+    pub fn clone_fresh(&self) -> anyhow::Result<Self> {
+        let mut eq = match self {
+            EqProcessor::Biquads {
+                preamp,
+                left,
+                right,
+            } => EqProcessor::Biquads {
+                preamp: *preamp,
+                left: left.clone(),
+                right: right.clone(),
+            },
+            EqProcessor::Convolution { engine, window, .. } => EqProcessor::Convolution {
+                engine: engine
+                    .clone_fresh()
+                    .context("Headphone EQ engine can't be cloned, set the EQ again instead")?,
+                window: window.clone(),
+                output: [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]],
+            },
+        };
+        eq.reset();
+
+        Ok(eq)
+    }
+
+    pub fn save_state(&self) -> EqState {
+        match self {
+            EqProcessor::Biquads { left, right, .. } => {
+                EqState::Biquads([left.clone(), right.clone()])
+            }
+            EqProcessor::Convolution { engine, window, .. } => EqState::Convolution {
+                engine: engine.save_state(),
+                window: window.clone(),
+            },
+        }
+    }
+
+    pub fn load_state(&mut self, state: &EqState) -> anyhow::Result<()> {
+        match (self, state) {
+            (EqProcessor::Biquads { left, right, .. }, EqState::Biquads([l, r]))
+                if left.len() == l.len() && right.len() == r.len() =>
+            {
+                left.clone_from(l);
+                right.clone_from(r);
+            }
+            (
+                EqProcessor::Convolution { engine, window, .. },
+                EqState::Convolution {
+                    engine: engine_state,
+                    window: saved,
+                },
+            ) if window[0].len() == saved[0].len() => {
+                engine.load_state(engine_state.as_deref())?;
+                window.clone_from(saved);
+            }
+            _ => anyhow::bail!("Headphone EQ changed since the state was saved"),
+        }
+
+        Ok(())
+    }
+
+    pub fn process(&mut self, left: &mut [f32], right: &mut [f32]) -> anyhow::Result<()> {
+        match self {
+            EqProcessor::Biquads {
+                preamp,
+                left: left_chain,
+                right: right_chain,
+            } => {
+                for (samples, chain) in [(left, left_chain), (right, right_chain)] {
+                    for sample in samples.iter_mut() {
+                        *sample = chain
+                            .iter_mut()
+                            .fold(*sample * *preamp, |x, biquad| biquad.process(x));
+                    }
+                }
+            }
+            EqProcessor::Convolution {
+                engine,
+                window,
+                output,
+            } => {
+                for (window, samples) in window.iter_mut().zip([&*left, &*right]) {
+                    window.copy_within(samples.len().., 0);
+                    let offset = window.len() - samples.len();
+                    window[offset..].copy_from_slice(samples);
+                }
+
+                let [left_output, right_output] = output;
+                left_output.fill(0f32);
+                right_output.fill(0f32);
+
+                for (channel, window) in window.iter().enumerate() {
+                    engine.process(channel, window, left_output, right_output)?;
+                }
+
+                left.copy_from_slice(left_output);
+                right.copy_from_slice(right_output);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EqBand, EqProcessor, HeadphoneEq};
+    use crate::{new_engine, CurrentFFTLogic, BLOCK_SIZE};
+    use std::f32::consts::TAU;
+
+    /// gain in dB of `eq` on a sine at `frequency` once it settled, the same on both ears
+    fn gain_db(eq: &HeadphoneEq, frequency: f32) -> f32 {
+        let mut processor = EqProcessor::new(eq, 48000, new_engine::<CurrentFFTLogic>).unwrap();
+        let mut left = (0..48000)
+            .map(|x| (x as f32 * frequency / 48000.0 * TAU).sin())
+            .collect::<Vec<_>>();
+        let mut right = left.clone();
+        for (left, right) in left
+            .chunks_exact_mut(BLOCK_SIZE)
+            .zip(right.chunks_exact_mut(BLOCK_SIZE))
+        {
+            processor.process(left, right).unwrap();
+        }
+        assert_eq!(left, right);
+
+        let settled = &left[24000..];
+        let rms = (settled.iter().map(|x| x * x).sum::<f32>() / settled.len() as f32).sqrt();
+        20.0 * (rms * std::f32::consts::SQRT_2).log10()
+    }
+
+    #[test]
+    fn autoeq_csv() {
+        let csv = "frequency,raw,equalization\n20,1.0,3.5\n1000,0.0,0.0\n20000,-2.0,-1.5\n";
+
+        match HeadphoneEq::from_autoeq_csv(csv.as_bytes()).unwrap() {
+            HeadphoneEq::Response { preamp_db, points } => {
+                assert_eq!(preamp_db, -3.5);
+                assert_eq!(points, vec![(20.0, 3.5), (1000.0, 0.0), (20000.0, -1.5)]);
+            }
+            eq => panic!("unexpected eq {:?}", eq),
+        }
+    }
+
+    #[test]
+    fn parametric_gain() {
+        let eq = HeadphoneEq::parametric(
+            -3.0,
+            vec![
+                EqBand::Peaking {
+                    frequency: 1000.0,
+                    q: 2.0,
+                    gain_db: 6.0,
+                },
+                EqBand::HighShelf {
+                    frequency: 4000.0,
+                    q: 0.707,
+                    gain_db: -6.0,
+                },
+            ],
+        );
+
+        for (frequency, expected) in [(100.0, -3.0), (1000.0, 3.0), (16000.0, -9.0)] {
+            let gain = gain_db(&eq, frequency);
+            assert!(
+                (gain - expected).abs() < 0.5,
+                "{} dB at {}hz",
+                gain,
+                frequency
+            );
+        }
+    }
+
+    #[test]
+    fn response_gain() {
+        let eq = HeadphoneEq::Response {
+            preamp_db: -2.0,
+            points: vec![(20.0, 6.0), (500.0, 6.0), (2000.0, -6.0), (20000.0, -6.0)],
+        };
+
+        for (frequency, expected) in [(100.0, 4.0), (1000.0, -2.0), (8000.0, -8.0)] {
+            let gain = gain_db(&eq, frequency);
+            assert!(
+                (gain - expected).abs() < 0.5,
+                "{} dB at {}hz",
+                gain,
+                frequency
+            );
+        }
+    }
+}
@@ -0,0 +1,187 @@
+#![cfg(feature = "fixed-point")]
+
+use crate::engine::check_window;
+use crate::{ConvolutionEngine, FFTLogic, BLOCK_SIZE};
+use std::sync::Arc;
+
+/// 1.0 in Q31
+const Q31_ONE: f32 = 2_147_483_648.0;
+
+/// most an impulse response can be scaled down by, the products are shifted back by `31 - shift`
+const MAX_SHIFT: u32 = 31;
+
+/// `value` in Q31, clipped to the range it can hold. Scaling by a power of two is exact in f32
+/// and the cast saturates, so this is all the float work a sample takes.
+fn to_q31(value: f32) -> i32 {
+    (value * Q31_ONE).round() as i32
+}
+
+/// An impulse response in Q31, scaled down by `2^shift` so its peak fits
+#[derive(Debug, Clone, Default)]
+struct FixedIr {
+    taps: Vec<i32>,
+    shift: u32,
+}
+
+/// Convolves in the time domain in Q31 fixed point, for MCUs and DSP chips without a fast FPU.
+/// Every output sample is a 64 bit multiply-accumulate over the impulse response, the way their
+/// MAC units work, so it's only fast enough for short HRIRs. Input beyond full scale is clipped,
+/// and accumulators which overflow saturate instead of wrapping around. Impulse responses are
+/// scaled by a power of two each to keep their precision whatever their peak.
+#[derive(Debug)]
+pub struct FixedPointLogic {
+    length: usize,
+    window: Vec<i32>,
+    /// shared with the engines [`clone_fresh`](ConvolutionEngine::clone_fresh) made until one
+    /// of them loads another impulse response
+    ir: Arc<Vec<FixedIr>>,
+}
+
+impl FFTLogic for FixedPointLogic {
+    fn new(channels: usize, length: usize) -> anyhow::Result<Self> {
+        check_window(length)?;
+
+        Ok(FixedPointLogic {
+            length,
+            window: vec![0; length],
+            ir: Arc::new(vec![FixedIr::default(); channels * 2]),
+        })
+    }
+}
+
+impl ConvolutionEngine for FixedPointLogic {
+    fn init_ir(&mut self, impulse: &[f32], ir_index: usize) -> anyhow::Result<()> {
+        // the zero padding doesn't change the result, only the time it takes
+        let end = impulse.iter().rposition(|x| *x != 0.0).map_or(0, |x| x + 1);
+        let peak = impulse[..end].iter().fold(0f32, |x, y| x.max(y.abs()));
+        if !impulse[..end].iter().all(|x| x.is_finite()) || peak >= (1u64 << MAX_SHIFT) as f32 {
+            anyhow::bail!(
+                "Impulse response {} peaks at {}, Q31 fits impulse responses up to 2^{}",
+                ir_index,
+                peak,
+                MAX_SHIFT
+            );
+        }
+
+        let mut shift = 0;
+        while peak >= (1u64 << shift) as f32 {
+            shift += 1;
+        }
+
+        let scale = 1.0 / (1u64 << shift) as f32;
+        Arc::make_mut(&mut self.ir)[ir_index] = FixedIr {
+            taps: impulse[..end].iter().map(|x| to_q31(x * scale)).collect(),
+            shift,
+        };
+
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        channel: usize,
+        samples: &[f32],
+        left_output: &mut [f32],
+        right_output: &mut [f32],
+    ) -> anyhow::Result<()> {
+        for (window, sample) in self.window.iter_mut().zip(samples) {
+            *window = to_q31(*sample);
+        }
+
+        let start = self.length - BLOCK_SIZE;
+        for (ear, output) in [left_output, right_output].iter_mut().enumerate() {
+            let ir = &self.ir[channel * 2 + ear];
+
+            for (s, output) in output[..BLOCK_SIZE].iter_mut().enumerate() {
+                // Q62 products, scaled down by the shift of the impulse response
+                let sum = ir
+                    .taps
+                    .iter()
+                    .zip(self.window[..=start + s].iter().rev())
+                    .fold(0i64, |sum, (tap, sample)| {
+                        sum.saturating_add(*tap as i64 * *sample as i64)
+                    });
+
+                *output += (sum >> (31 - ir.shift)) as f32 / Q31_ONE;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn latency(&self) -> usize {
+        self.length - BLOCK_SIZE
+    }
+
+    fn reset(&mut self) {}
+
+    fn clone_fresh(&self) -> Option<Box<dyn ConvolutionEngine>> {
+        let mut engine = Self::new(self.ir.len() / 2, self.length).ok()?;
+        engine.ir = self.ir.clone();
+
+        Some(Box::new(engine))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FixedPointLogic;
+    use crate::{ConvolutionEngine, FFTLogic, BLOCK_SIZE};
+
+    #[test]
+    fn convolves_in_fixed_point() {
+        let length = BLOCK_SIZE * 2;
+        let mut state = 0x1234_5678u32;
+        let mut noise = || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+        };
+
+        // a peak above full scale takes a shift, the other impulse response none
+        let mut loud = (0..200).map(|_| noise() * 0.1).collect::<Vec<_>>();
+        loud[0] = 2.5;
+        let quiet = (0..200).map(|_| noise() * 0.01).collect::<Vec<_>>();
+        let window = (0..length).map(|_| noise() * 0.5).collect::<Vec<_>>();
+
+        let mut engine = FixedPointLogic::new(1, length).unwrap();
+        engine.init_ir(&loud, 0).unwrap();
+        engine.init_ir(&quiet, 1).unwrap();
+        let mut left = vec![0f32; BLOCK_SIZE];
+        let mut right = vec![0f32; BLOCK_SIZE];
+        engine.process(0, &window, &mut left, &mut right).unwrap();
+
+        for (ir, output) in [(&loud, &left), (&quiet, &right)] {
+            for (s, output) in output.iter().enumerate() {
+                let n = BLOCK_SIZE + s;
+                let expected = ir
+                    .iter()
+                    .enumerate()
+                    .map(|(k, x)| *x as f64 * window[n - k] as f64)
+                    .sum::<f64>();
+                assert!((*output as f64 - expected).abs() < 1e-6);
+            }
+        }
+
+        // the accumulators saturate at 2^63, four times full scale with a shift of one, instead
+        // of wrapping around
+        let mut engine = FixedPointLogic::new(1, length).unwrap();
+        engine.init_ir(&[1.0; 8], 0).unwrap();
+        engine.init_ir(&[-1.0; 8], 1).unwrap();
+        let mut left = vec![0f32; BLOCK_SIZE];
+        let mut right = vec![0f32; BLOCK_SIZE];
+        engine
+            .process(0, &vec![0.9; length], &mut left, &mut right)
+            .unwrap();
+        assert!(left.iter().all(|x| *x == 4.0));
+        assert!(right.iter().all(|x| *x == -4.0));
+
+        // the largest shift still renders, anything which would need more is refused
+        let mut engine = FixedPointLogic::new(1, length).unwrap();
+        engine.init_ir(&[2f32.powi(30)], 0).unwrap();
+        engine
+            .process(0, &vec![0.0; length], &mut left, &mut right)
+            .unwrap();
+        assert!(engine.init_ir(&[2f32.powi(31)], 1).is_err());
+        assert!(engine.init_ir(&[f32::NAN], 1).is_err());
+    }
+}
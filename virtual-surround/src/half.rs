@@ -0,0 +1,103 @@
+/// scale of the smallest subnormal half
+const SUBNORMAL_STEP: f32 = 1.0 / 16_777_216.0;
+
+/// Half precision bits of `value`, rounded to the nearest with ties to even. Out of range values
+/// turn into infinity, and ones too small for a subnormal into zero.
+pub(crate) fn f16_from_f32(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    // rebias from 127 to 15
+    let exponent = exponent - 112;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    let (half, rest, halfway) = if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        (
+            mantissa >> shift,
+            mantissa & ((1 << shift) - 1),
+            1 << (shift - 1),
+        )
+    } else {
+        (
+            ((exponent as u32) << 10) | (mantissa >> 13),
+            mantissa & 0x1fff,
+            0x1000,
+        )
+    };
+
+    // a carry out of the mantissa moves up the exponent, up to infinity
+    let round = rest > halfway || (rest == halfway && half & 1 == 1);
+    sign | (half + round as u32) as u16
+}
+
+pub(crate) fn f32_from_f16(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+
+    match exponent {
+        0 => {
+            let value = mantissa as f32 * SUBNORMAL_STEP;
+            if sign != 0 {
+                -value
+            } else {
+                value
+            }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{f16_from_f32, f32_from_f16};
+
+    #[test]
+    fn converts_to_half_and_back() {
+        for (value, bits) in [
+            (0.0, 0x0000),
+            (-0.0, 0x8000),
+            (1.0, 0x3c00),
+            (-2.0, 0xc000),
+            (65504.0, 0x7bff),
+            (6.1035156e-5, 0x0400),
+            (5.9604645e-8, 0x0001),
+            (f32::INFINITY, 0x7c00),
+        ] {
+            assert_eq!(f16_from_f32(value), bits, "{}", value);
+            assert_eq!(f32_from_f16(bits), value);
+        }
+
+        // rounding to the nearest, ties to even, out of range
+        assert_eq!(f16_from_f32(1.0 + 1.0 / 2048.0), 0x3c00);
+        assert_eq!(f16_from_f32(1.0 + 3.0 / 2048.0), 0x3c02);
+        assert_eq!(f16_from_f32(65520.0), 0x7c00);
+        assert_eq!(f16_from_f32(1e-9), 0x0000);
+        assert!(f32_from_f16(f16_from_f32(f32::NAN)).is_nan());
+
+        for x in 0..1000 {
+            let value = (x as f32 * 0.37).sin() * 10f32.powi(x % 9 - 4);
+            let back = f32_from_f16(f16_from_f32(value));
+            assert!(
+                (back - value).abs() <= value.abs() / 2048.0 + 3e-8,
+                "{}",
+                value
+            );
+        }
+    }
+}
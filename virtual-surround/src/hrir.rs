@@ -0,0 +1,1405 @@
+use crate::diagnostics::{Diagnostic, Diagnostics};
+use crate::dsp::{fft, minimum_phase};
+use crate::hrtf::{SpeakerDirection, SphericalHead};
+#[cfg(any(feature = "resample", feature = "resample-rubato"))]
+use crate::resample::{resample, ResampleQuality};
+use crate::wav::{write_float, WavData};
+use crate::{channel_from_name, get_channel_name, mirror_channel, standard_layout, Speaker};
+use crate::{ChannelMap, MAX_CHANNELS};
+use anyhow::Context;
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+/// head radius of the KEMAR mannequin most generic HRIRs are measured on, in centimeters
+pub const KEMAR_HEAD_RADIUS_CM: f32 = 8.75;
+
+/// How the channels of an HRIR wav map onto speakers and ears
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum EarLayout {
+    /// one channel per speaker containing the left ear response, the right ear response is taken
+    /// from the mirrored speaker
+    #[default]
+    Mirrored,
+    /// two channels per speaker, left ear followed by right ear, the channel mask of the file
+    /// describes the speakers
+    StereoPairs,
+}
+
+/// level relative to the loudest peak of an HRIR below which [`Hrir::trim_leading_silence`]
+/// takes samples as silence
+const SILENCE_DB: f32 = -50.0;
+/// samples [`Hrir::trim_leading_silence`] leaves before the first one above [`SILENCE_DB`]
+const TRIM_MARGIN: usize = 8;
+
+/// Where [`Hrir::align_onsets`] moves the onset of every speaker
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OnsetAlignment {
+    /// the earliest onset of any speaker, which adds no latency
+    Earliest,
+    /// a fixed sample, the impulse responses grow if it's later than the latest onset
+    At(usize),
+}
+
+/// How [`Hrir::normalize_with`] scales the impulse responses
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum Normalization {
+    /// PulseAudio's method, the largest sum of every speaker's absolute sample at the same time
+    /// times `factor` becomes full scale. Leaves headroom for every speaker playing at once.
+    SumPeak { factor: f32 },
+    /// the largest sample of any impulse response becomes full scale, louder than `SumPeak` but
+    /// loud content in several channels can clip
+    PerChannelPeak,
+    /// the energy of the average impulse response becomes `target_db`, so HRIRs which differ in
+    /// peakiness play at a similar level
+    Rms { target_db: f32 },
+    /// keep the levels of the file
+    None,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Normalization::SumPeak { factor: 2.5 }
+    }
+}
+
+/// Fade-out [`Hrir::truncate`] applies to the end of the shortened impulse responses, so the cut
+/// doesn't click or smear into a broadband step
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum FadeWindow {
+    /// cut without a fade
+    Rectangular,
+    /// the second half of a Hann window over the last `ms` milliseconds
+    Hann { ms: f32 },
+    /// a Tukey window, flat and then a cosine taper over the last `ratio` (0 to 1) of the
+    /// truncated length
+    Tukey { ratio: f32 },
+}
+
+impl Default for FadeWindow {
+    fn default() -> Self {
+        FadeWindow::Hann { ms: 2.0 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpeakerIr {
+    pub position: Speaker,
+    pub left: Vec<f32>,
+    pub right: Vec<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Hrir {
+    pub sample_rate: u32,
+    pub speakers: Vec<SpeakerIr>,
+}
+
+impl Hrir {
+    /// Load an HRIR wav. A file without a channel mask gets the [`standard_layout`] of its
+    /// speaker count, see [`from_wav_with_speakers`](Hrir::from_wav_with_speakers) for others.
+    pub fn from_wav<R: Read + Seek>(reader: R, layout: EarLayout) -> anyhow::Result<Hrir> {
+        Self::from_wav_with_speakers(reader, layout, None)
+    }
+
+    /// like [`from_wav`](Hrir::from_wav) with the speakers of the file given in order, instead
+    /// of taken from its channel mask
+    pub fn from_wav_with_speakers<R: Read + Seek>(
+        reader: R,
+        layout: EarLayout,
+        order: Option<&[Speaker]>,
+    ) -> anyhow::Result<Hrir> {
+        Self::read_wav(reader, layout, order, false, None)
+    }
+
+    /// [`from_wav_with_speakers`](Hrir::from_wav_with_speakers) which, given `diagnostics`,
+    /// reports a missing mirrored side instead of failing on it. With `skip_unusable` channels
+    /// without a position or a mirrored side are left out instead.
+    pub(crate) fn read_wav<R: Read + Seek>(
+        reader: R,
+        layout: EarLayout,
+        order: Option<&[Speaker]>,
+        skip_unusable: bool,
+        diagnostics: Option<&dyn Diagnostics>,
+    ) -> anyhow::Result<Hrir> {
+        let wav = WavData::read(reader)?;
+        let channels = wav.channels;
+        trace_span!(INFO, "load_hrir", channels, sample_rate = wav.sample_rate);
+
+        let speakers = match layout {
+            EarLayout::Mirrored => channels,
+            EarLayout::StereoPairs => {
+                if channels % 2 != 0 {
+                    anyhow::bail!(
+                        "Input HRIR file has {} channels, which can't be split into left/right ear pairs",
+                        channels
+                    );
+                }
+
+                channels / 2
+            }
+        };
+
+        if speakers > MAX_CHANNELS {
+            anyhow::bail!("Input HRIR file has {} speakers, VirtualSurroundFilter is compiled with only support for max {} channels", speakers, MAX_CHANNELS);
+        }
+
+        let positions = &match order {
+            Some(order) if order.len() != speakers => anyhow::bail!(
+                "Layout lists {} speakers, the HRIR file has {}",
+                order.len(),
+                speakers
+            ),
+            Some(order) => order.to_vec(),
+            None if wav.positions.iter().all(|x| *x == Speaker::DirectOut) => {
+                standard_layout(speakers)
+                    .with_context(|| {
+                        format!(
+                            "Input HRIR file has no channel mask and {} speakers have no standard layout, pass one",
+                            speakers
+                        )
+                    })?
+                    .to_vec()
+            }
+            None => wav.positions.clone(),
+        };
+
+        if positions.len() != speakers
+            || (!skip_unusable && positions.contains(&Speaker::DirectOut))
+        {
+            anyhow::bail!(
+                "Input HRIR file describes {} speaker positions, expected {}",
+                positions
+                    .iter()
+                    .filter(|x| **x != Speaker::DirectOut)
+                    .count(),
+                speakers
+            );
+        }
+
+        let channel_map = ChannelMap::from_iter(positions.iter().copied())?;
+        let mut irs = Vec::with_capacity(speakers);
+
+        for (i, position) in positions.iter().copied().enumerate() {
+            let unusable = position == Speaker::DirectOut
+                || (layout == EarLayout::Mirrored && channel_map.find_mirror(position).is_none());
+            if skip_unusable && unusable {
+                if let Some(diagnostics) = diagnostics {
+                    diagnostics.report(Diagnostic::SkippedChannel {
+                        channel: i,
+                        position,
+                    });
+                }
+
+                continue;
+            }
+
+            let (left, right) = match layout {
+                EarLayout::Mirrored => {
+                    let mirror = match (channel_map.find_mirror(position), diagnostics) {
+                        (Some(mirror), _) => mirror,
+                        (None, Some(diagnostics)) => {
+                            diagnostics.report(Diagnostic::MissingMirror { speaker: position });
+                            i
+                        }
+                        (None, None) => anyhow::bail!(
+                            "hrir file isn't symmetrical can't find the mirrored side of {:?}",
+                            position
+                        ),
+                    };
+
+                    (wav.column(i), wav.column(mirror))
+                }
+                EarLayout::StereoPairs => (wav.column(i * 2), wav.column(i * 2 + 1)),
+            };
+
+            irs.push(SpeakerIr {
+                position,
+                left,
+                right,
+            });
+        }
+
+        if irs.is_empty() {
+            anyhow::bail!("Input HRIR file has no channels which can be placed");
+        }
+
+        Ok(Hrir {
+            sample_rate: wav.sample_rate,
+            speakers: irs,
+        })
+    }
+
+    /// Load an HRIR from a wav per speaker, each with the left and right ear response of its
+    /// speaker, or only the left ear with the right one taken from the mirrored speaker, the way
+    /// many measurement tools write them. Shorter responses are padded with silence.
+    pub fn from_speaker_wavs<R: Read + Seek>(files: Vec<(Speaker, R)>) -> anyhow::Result<Hrir> {
+        trace_span!(INFO, "load_speaker_hrirs", files = files.len());
+        if files.is_empty() {
+            anyhow::bail!("No speaker HRIR files given");
+        }
+
+        if files.len() > MAX_CHANNELS {
+            anyhow::bail!("{} speaker HRIR files given, VirtualSurroundFilter is compiled with only support for max {} channels", files.len(), MAX_CHANNELS);
+        }
+
+        let mut wavs = Vec::with_capacity(files.len());
+        for (speaker, reader) in files {
+            let name = get_channel_name(speaker);
+            if wavs.iter().any(|(x, _)| *x == speaker) {
+                anyhow::bail!("More than one HRIR file given for {}", name);
+            }
+
+            let wav = WavData::read(reader)
+                .with_context(|| format!("Failed to read the HRIR file of {}", name))?;
+            if wav.channels != 1 && wav.channels != 2 {
+                anyhow::bail!(
+                    "HRIR file of {} has {} channels, expected the left ear or both ears",
+                    name,
+                    wav.channels
+                );
+            }
+
+            wavs.push((speaker, wav));
+        }
+
+        let sample_rate = wavs[0].1.sample_rate;
+        if let Some((speaker, wav)) = wavs.iter().find(|(_, x)| x.sample_rate != sample_rate) {
+            anyhow::bail!(
+                "HRIR file of {} is at {} Hz, the others at {} Hz",
+                get_channel_name(*speaker),
+                wav.sample_rate,
+                sample_rate
+            );
+        }
+
+        let channel_map = ChannelMap::from_iter(wavs.iter().map(|(x, _)| *x))?;
+        let length = wavs
+            .iter()
+            .map(|(_, x)| x.data.len() / x.channels)
+            .max()
+            .unwrap_or(0);
+        let mut irs = Vec::with_capacity(wavs.len());
+
+        for (position, wav) in &wavs {
+            let mut left = wav.column(0);
+            let mut right = if wav.channels == 2 {
+                wav.column(1)
+            } else {
+                let mirror = channel_map.find_mirror(*position).with_context(|| {
+                    format!(
+                        "HRIR file of {} only has the left ear and there's none for the mirrored side",
+                        get_channel_name(*position)
+                    )
+                })?;
+
+                wavs[mirror].1.column(0)
+            };
+
+            left.resize(length, 0.0);
+            right.resize(length, 0.0);
+            irs.push(SpeakerIr {
+                position: *position,
+                left,
+                right,
+            });
+        }
+
+        Ok(Hrir {
+            sample_rate,
+            speakers: irs,
+        })
+    }
+
+    /// [`from_speaker_wavs`](Hrir::from_speaker_wavs) with the speakers taken from the file
+    /// names, e.g. `FL.wav` and `FR.wav`, see [`channel_from_name`]
+    pub fn from_files<P: AsRef<Path>>(paths: &[P]) -> anyhow::Result<Hrir> {
+        let mut files = paths
+            .iter()
+            .map(|path| {
+                let path = path.as_ref();
+                let speaker = speaker_of_file(path).with_context(|| {
+                    format!("Can't tell which speaker {} is for", path.display())
+                })?;
+
+                Ok((speaker, path))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        files.sort_by_key(|(speaker, _)| *speaker);
+
+        let files = files
+            .into_iter()
+            .map(|(speaker, path)| {
+                let file = File::open(path)
+                    .with_context(|| format!("Failed to open {}", path.display()))?;
+
+                Ok((speaker, file))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Self::from_speaker_wavs(files)
+    }
+
+    /// [`from_files`](Hrir::from_files) with the wavs in `dir` named after a speaker, other
+    /// files are ignored
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> anyhow::Result<Hrir> {
+        let dir = dir.as_ref();
+        let mut paths = vec![];
+
+        for entry in
+            std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?
+        {
+            let path = entry?.path();
+            if speaker_of_file(&path).is_some() {
+                paths.push(path);
+            }
+        }
+
+        if paths.is_empty() {
+            anyhow::bail!("{} has no wav files named after a speaker", dir.display());
+        }
+
+        Self::from_files(&paths)
+    }
+
+    /// earliest onset over all impulse responses, see [`onset`]
+    pub fn onset(&self) -> usize {
+        self.speakers
+            .iter()
+            .flat_map(|x| [onset(&x.left), onset(&x.right)])
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// `(left, right)` [`onset`] of every speaker
+    pub fn onsets(&self) -> Vec<(usize, usize)> {
+        self.speakers
+            .iter()
+            .map(|x| (onset(&x.left), onset(&x.right)))
+            .collect()
+    }
+
+    /// Cut the silence every impulse response starts with, up to 8 samples before the first one
+    /// rises above -50dB of the loudest peak. Everything moves by the
+    /// same amount, so the delays between the ears and speakers stay, only latency and window
+    /// length go. Returns the number of samples cut.
+    pub fn trim_leading_silence(&mut self) -> usize {
+        let peak = self
+            .speakers
+            .iter()
+            .flat_map(|x| x.left.iter().chain(&x.right))
+            .fold(0f32, |max, x| max.max(x.abs()));
+        let threshold = peak * 10f32.powf(SILENCE_DB / 20.0);
+
+        let start = self
+            .speakers
+            .iter()
+            .flat_map(|x| [&x.left, &x.right])
+            .filter_map(|ir| ir.iter().position(|x| x.abs() > threshold))
+            .min()
+            .unwrap_or(0);
+        let trim = start.saturating_sub(TRIM_MARGIN);
+
+        for speaker in &mut self.speakers {
+            speaker.left.drain(..trim);
+            speaker.right.drain(..trim);
+        }
+
+        trim
+    }
+
+    /// shift every speaker so its earlier ear starts at the same sample, for datasets where
+    /// directions were measured with different rig latencies. Both ears of a speaker move
+    /// together, so the ITD within the pair is preserved.
+    pub fn align_onsets(&mut self, alignment: OnsetAlignment) {
+        let onsets = self
+            .speakers
+            .iter()
+            .map(|x| onset(&x.left).min(onset(&x.right)))
+            .collect::<Vec<_>>();
+
+        let target = match alignment {
+            OnsetAlignment::Earliest => onsets.iter().copied().min().unwrap_or(0),
+            OnsetAlignment::At(sample) => sample,
+        };
+
+        let grow = onsets
+            .iter()
+            .map(|x| target.saturating_sub(*x))
+            .max()
+            .unwrap_or(0);
+        let length = self.ir_length() + grow;
+
+        for (speaker, onset) in self.speakers.iter_mut().zip(onsets) {
+            for ir in [&mut speaker.left, &mut speaker.right] {
+                let mut shifted = vec![0f32; length];
+                if target >= onset {
+                    shifted[target - onset..target - onset + ir.len()].copy_from_slice(ir);
+                } else {
+                    let advance = onset - target;
+                    shifted[..ir.len() - advance].copy_from_slice(&ir[advance..]);
+                }
+
+                *ir = shifted;
+            }
+        }
+    }
+
+    /// Scale the ITD of every speaker by `factor`, the later (contralateral) ear moves while the
+    /// earlier one stays put. Generic HRIRs localize poorly for heads of another size than the
+    /// mannequin's, the ITD grows about linearly with the head radius, see
+    /// [`FilterBuilder::head_radius`](crate::FilterBuilder::head_radius). Shifts are rounded to
+    /// whole samples.
+    pub fn scale_itd(&mut self, factor: f32) {
+        let factor = factor.max(0.0);
+        let shifts = self
+            .speakers
+            .iter()
+            .map(|x| {
+                let (left, right) = (onset(&x.left), onset(&x.right));
+                let shift = (left.abs_diff(right) as f32 * (factor - 1.0)).round() as isize;
+                (left > right, shift)
+            })
+            .collect::<Vec<_>>();
+
+        let grow = shifts
+            .iter()
+            .map(|x| x.1.max(0) as usize)
+            .max()
+            .unwrap_or(0);
+        let length = self.ir_length() + grow;
+
+        for (speaker, (left_later, shift)) in self.speakers.iter_mut().zip(shifts) {
+            let (later, earlier) = if left_later {
+                (&mut speaker.left, &mut speaker.right)
+            } else {
+                (&mut speaker.right, &mut speaker.left)
+            };
+
+            let mut shifted = vec![0f32; length];
+            if shift >= 0 {
+                let shift = shift as usize;
+                shifted[shift..shift + later.len()].copy_from_slice(later);
+            } else {
+                let advance = shift.unsigned_abs();
+                shifted[..later.len() - advance].copy_from_slice(&later[advance..]);
+            }
+
+            *later = shifted;
+            earlier.resize(length, 0f32);
+        }
+    }
+
+    pub fn ir_length(&self) -> usize {
+        self.speakers.first().map_or(0, |x| x.left.len())
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = Speaker> + '_ {
+        self.speakers.iter().map(|x| x.position)
+    }
+
+    /// resample with [`ResampleQuality::Best`], see [`resample_with`](Hrir::resample_with)
+    #[cfg(any(feature = "resample", feature = "resample-rubato"))]
+    pub fn resample(&mut self, sample_rate: u32) -> anyhow::Result<()> {
+        self.resample_with(sample_rate, ResampleQuality::Best)
+    }
+
+    #[cfg(any(feature = "resample", feature = "resample-rubato"))]
+    pub fn resample_with(
+        &mut self,
+        sample_rate: u32,
+        quality: ResampleQuality,
+    ) -> anyhow::Result<()> {
+        if sample_rate == self.sample_rate {
+            return Ok(());
+        }
+
+        trace_span!(
+            INFO,
+            "resample_hrir",
+            from = self.sample_rate,
+            to = sample_rate
+        );
+        // the channels are resampled apart either way, so every speaker can go on its own thread
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+
+            let from = self.sample_rate;
+            self.speakers
+                .par_iter_mut()
+                .try_for_each(|speaker| -> anyhow::Result<()> {
+                    let pair = speaker
+                        .left
+                        .iter()
+                        .zip(&speaker.right)
+                        .flat_map(|(left, right)| [*left, *right])
+                        .collect::<Vec<_>>();
+                    let data = resample(&pair, 2, from, sample_rate, quality)?;
+
+                    speaker.left = data.iter().step_by(2).copied().collect();
+                    speaker.right = data.iter().skip(1).step_by(2).copied().collect();
+                    Ok(())
+                })?;
+        }
+
+        #[cfg(not(feature = "parallel"))]
+        {
+            let channels = self.speakers.len() * 2;
+            let length = self.ir_length();
+            let mut data = vec![0f32; length * channels];
+
+            for (i, speaker) in self.speakers.iter().enumerate() {
+                for s in 0..length {
+                    data[s * channels + i * 2] = speaker.left[s];
+                    data[s * channels + i * 2 + 1] = speaker.right[s];
+                }
+            }
+
+            let data = resample(&data, channels, self.sample_rate, sample_rate, quality)?;
+
+            let length = data.len() / channels;
+
+            for (i, speaker) in self.speakers.iter_mut().enumerate() {
+                speaker.left = (0..length).map(|s| data[s * channels + i * 2]).collect();
+                speaker.right = (0..length)
+                    .map(|s| data[s * channels + i * 2 + 1])
+                    .collect();
+            }
+        }
+
+        self.sample_rate = sample_rate;
+
+        Ok(())
+    }
+
+    /// normalize with [`Normalization::default`], see [`normalize_with`](Hrir::normalize_with)
+    pub fn normalize(&mut self) {
+        self.normalize_with(Normalization::default());
+    }
+
+    /// `SumPeak` is from https://github.com/pulseaudio/pulseaudio/blob/19adddee31ca34bf4e0db95df01b4ec595f2d267/src/modules/module-virtual-surround-sink.c#L192
+    pub fn normalize_with(&mut self, normalization: Normalization) {
+        let samples = || {
+            self.speakers
+                .iter()
+                .flat_map(|x| x.left.iter().chain(&x.right))
+        };
+
+        let scale = match normalization {
+            Normalization::SumPeak { factor } => {
+                let mut hrir_max: f32 = 0.0;
+
+                for i in 0..self.ir_length() {
+                    let left: f32 = self.speakers.iter().map(|x| x.left[i].abs()).sum();
+                    let right: f32 = self.speakers.iter().map(|x| x.right[i].abs()).sum();
+
+                    hrir_max = hrir_max.max(left).max(right);
+                }
+
+                1.0 / (hrir_max * factor)
+            }
+            Normalization::PerChannelPeak => 1.0 / samples().fold(0f32, |a, x| a.max(x.abs())),
+            Normalization::Rms { target_db } => {
+                let energy =
+                    samples().map(|x| x * x).sum::<f32>() / (self.speakers.len() * 2) as f32;
+                10f32.powf(target_db / 20.0) / energy.sqrt()
+            }
+            Normalization::None => return,
+        };
+
+        if !scale.is_finite() {
+            return;
+        }
+
+        for speaker in &mut self.speakers {
+            for sample in speaker.left.iter_mut().chain(speaker.right.iter_mut()) {
+                *sample *= scale;
+            }
+        }
+    }
+}
+
+/// What cutting every impulse response of an [`Hrir`] down to `length` samples would cost, the
+/// figures are for the impulse response which suffers the most
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TruncationEstimate {
+    pub length: usize,
+    /// energy kept relative to the full impulse response, 0 dB is lossless
+    pub energy_loss_db: f32,
+    /// largest magnitude response difference between 20 hz and 20 khz
+    pub max_deviation_db: f32,
+    /// rms of the magnitude response difference between 20 hz and 20 khz
+    pub rms_deviation_db: f32,
+    pub worst_speaker: Speaker,
+}
+
+impl Hrir {
+    /// estimate the energy loss and spectral deviation of truncating to `length` samples
+    pub fn estimate_truncation(&self, length: usize) -> TruncationEstimate {
+        let n = self.ir_length().max(1).next_power_of_two() * 2;
+        let bin_hz = self.sample_rate as f64 / n as f64;
+        let bins = ((20.0 / bin_hz).ceil() as usize).max(1)
+            ..=((20000f64.min(self.sample_rate as f64 / 2.0) / bin_hz) as usize).min(n / 2);
+
+        let magnitude_db = |ir: &[f32]| {
+            let mut re = vec![0f64; n];
+            let mut im = vec![0f64; n];
+            for (re, sample) in re.iter_mut().zip(ir) {
+                *re = *sample as f64;
+            }
+
+            fft(&mut re, &mut im, false);
+
+            bins.clone()
+                .map(|k| 10.0 * (re[k] * re[k] + im[k] * im[k]).max(1e-12).log10())
+                .collect::<Vec<_>>()
+        };
+
+        let mut estimate = TruncationEstimate {
+            length,
+            energy_loss_db: 0.0,
+            max_deviation_db: 0.0,
+            rms_deviation_db: 0.0,
+            worst_speaker: Speaker::DirectOut,
+        };
+
+        for speaker in &self.speakers {
+            for ir in [&speaker.left, &speaker.right] {
+                let cut = length.min(ir.len());
+                let energy = |ir: &[f32]| ir.iter().map(|x| (*x as f64).powi(2)).sum::<f64>();
+                let total = energy(ir);
+                let energy_loss_db = if total > 0.0 {
+                    (10.0 * (energy(&ir[..cut]) / total).max(1e-12).log10()) as f32
+                } else {
+                    0.0
+                };
+
+                let deviations = magnitude_db(ir)
+                    .iter()
+                    .zip(magnitude_db(&ir[..cut]))
+                    .map(|(full, cut)| (full - cut).abs())
+                    .collect::<Vec<_>>();
+                let max_deviation_db = deviations.iter().fold(0f64, |a, b| a.max(*b)) as f32;
+                let rms_deviation_db = (deviations.iter().map(|x| x * x).sum::<f64>()
+                    / deviations.len().max(1) as f64)
+                    .sqrt() as f32;
+
+                if max_deviation_db > estimate.max_deviation_db
+                    || estimate.worst_speaker == Speaker::DirectOut
+                {
+                    estimate.max_deviation_db = max_deviation_db;
+                    estimate.worst_speaker = speaker.position;
+                }
+
+                estimate.energy_loss_db = estimate.energy_loss_db.min(energy_loss_db);
+                estimate.rms_deviation_db = estimate.rms_deviation_db.max(rms_deviation_db);
+            }
+        }
+
+        estimate
+    }
+}
+
+impl Hrir {
+    /// cut every impulse response down to `length` samples, fading out over the end as `window`
+    /// says. See [`estimate_truncation`](Hrir::estimate_truncation) for what it costs.
+    pub fn truncate(&mut self, length: usize, window: FadeWindow) {
+        if length >= self.ir_length() {
+            return;
+        }
+
+        let fade = match window {
+            FadeWindow::Rectangular => 0,
+            FadeWindow::Hann { ms } => (ms.max(0.0) / 1000.0 * self.sample_rate as f32) as usize,
+            FadeWindow::Tukey { ratio } => (ratio.clamp(0.0, 1.0) * length as f32) as usize,
+        }
+        .min(length);
+
+        let gains = (0..fade)
+            .map(|i| {
+                let phase = (i as f32 + 0.5) / fade as f32;
+                0.5 * (1.0 + (std::f32::consts::PI * phase).cos())
+            })
+            .collect::<Vec<_>>();
+
+        for speaker in &mut self.speakers {
+            for ir in [&mut speaker.left, &mut speaker.right] {
+                ir.truncate(length);
+                for (sample, gain) in ir[length - fade..].iter_mut().zip(&gains) {
+                    *sample *= gain;
+                }
+            }
+        }
+    }
+}
+
+impl Hrir {
+    /// Replace every impulse response with the minimum phase one of the same magnitude response,
+    /// delayed by its onset. The time of arrival at each ear, and with it the ITD, is kept as a
+    /// pure delay, the rest of the energy moves right behind it. The silence before the earliest
+    /// onset is dropped, so the impulse responses get shorter and latency-sensitive uses can
+    /// [`truncate`](Hrir::truncate) them further.
+    pub fn to_minimum_phase(&mut self) {
+        let earliest = self.onset();
+        let length = self.ir_length() - earliest;
+        let n = self.ir_length().max(1).next_power_of_two() * 4;
+
+        for speaker in &mut self.speakers {
+            for ir in [&mut speaker.left, &mut speaker.right] {
+                let delay = onset(ir) - earliest;
+
+                let mut re = vec![0f64; n];
+                let mut im = vec![0f64; n];
+                for (re, sample) in re.iter_mut().zip(ir.iter()) {
+                    *re = *sample as f64;
+                }
+
+                fft(&mut re, &mut im, false);
+                let magnitude = (0..=n / 2).map(|k| re[k].hypot(im[k])).collect::<Vec<_>>();
+
+                let mut output = vec![0f32; delay];
+                output.extend(
+                    minimum_phase(&magnitude, n)
+                        .into_iter()
+                        .take(length - delay)
+                        .map(|x| x as f32),
+                );
+
+                *ir = output;
+            }
+        }
+    }
+}
+
+/// largest boost or cut of [`Hrir::diffuse_field_equalize`], in either direction
+const DIFFUSE_FIELD_LIMIT_DB: f64 = 12.0;
+
+impl Hrir {
+    /// Equalize out the diffuse-field average, the power response of every ear averaged over all
+    /// speakers but the LFE, smoothed to a third octave. What every direction shares, like an
+    /// ear canal resonance of the measurement, goes away while the differences between
+    /// directions and ears stay. The correction is minimum phase and limited to ±12dB, the
+    /// length of the impulse responses doesn't change.
+    pub fn diffuse_field_equalize(&mut self) {
+        let length = self.ir_length();
+        let n = length.max(1).next_power_of_two() * 4;
+        let spectrum = |ir: &[f32]| {
+            let mut re = vec![0f64; n];
+            let mut im = vec![0f64; n];
+            for (re, sample) in re.iter_mut().zip(ir) {
+                *re = *sample as f64;
+            }
+
+            fft(&mut re, &mut im, false);
+            (re, im)
+        };
+
+        let mut power = vec![0f64; n / 2 + 1];
+        let mut count = 0;
+        for speaker in self.speakers.iter().filter(|x| !x.position.is_lfe()) {
+            for ir in [&speaker.left, &speaker.right] {
+                let (re, im) = spectrum(ir);
+                for (k, power) in power.iter_mut().enumerate() {
+                    *power += re[k] * re[k] + im[k] * im[k];
+                }
+                count += 1;
+            }
+        }
+
+        if count == 0 {
+            return;
+        }
+
+        // third octave smoothing, a sixth to each side of every bin
+        let ratio = 2f64.powf(1.0 / 6.0);
+        let average = (0..=n / 2)
+            .map(|k| {
+                let low = ((k as f64 / ratio).floor() as usize).min(k);
+                let high = ((k as f64 * ratio).ceil() as usize).clamp(k, n / 2);
+                let sum = power[low..=high].iter().sum::<f64>();
+                (sum / ((high - low + 1) * count) as f64).sqrt()
+            })
+            .collect::<Vec<_>>();
+
+        let mean = average.iter().sum::<f64>() / average.len() as f64;
+        let limit = 10f64.powf(DIFFUSE_FIELD_LIMIT_DB / 20.0);
+        let inverse = average
+            .iter()
+            .map(|x| (mean / x.max(1e-12)).clamp(1.0 / limit, limit))
+            .collect::<Vec<_>>();
+
+        // half the window for the correction, which leaves room for its convolution with the
+        // impulse response at most a quarter long
+        let mut correction = minimum_phase(&inverse, n);
+        correction.truncate(n / 2);
+        let (correction_re, correction_im) = {
+            let mut re = correction;
+            re.resize(n, 0.0);
+            let mut im = vec![0f64; n];
+            fft(&mut re, &mut im, false);
+            (re, im)
+        };
+
+        for speaker in &mut self.speakers {
+            for ir in [&mut speaker.left, &mut speaker.right] {
+                let (mut re, mut im) = spectrum(ir);
+                for k in 0..n {
+                    let (a, b) = (re[k], im[k]);
+                    re[k] = a * correction_re[k] - b * correction_im[k];
+                    im[k] = a * correction_im[k] + b * correction_re[k];
+                }
+
+                fft(&mut re, &mut im, true);
+                *ir = re.into_iter().take(length).map(|x| x as f32).collect();
+            }
+        }
+    }
+}
+
+impl Hrir {
+    /// Write the HRIR as a 32 bit float wav which [`from_wav`](Hrir::from_wav) reads back with
+    /// `layout`, with the speakers in channel mask order. [`EarLayout::Mirrored`] only keeps the
+    /// left ears, so every speaker needs its mirrored side, and every speaker needs a channel
+    /// mask bit.
+    pub fn write_wav<W: Write>(&self, writer: W, layout: EarLayout) -> anyhow::Result<()> {
+        let mut speakers = self.speakers.iter().collect::<Vec<_>>();
+        speakers.sort_by_key(|x| x.position);
+
+        let mut mask = 0;
+        for speaker in &speakers {
+            let bit = match speaker.position.mask() {
+                Some(bit) if speaker.position != Speaker::DirectOut => bit as u32,
+                _ => anyhow::bail!(
+                    "Speaker {} can't be described by a channel mask",
+                    get_channel_name(speaker.position)
+                ),
+            };
+
+            if layout == EarLayout::Mirrored
+                && !speakers
+                    .iter()
+                    .any(|x| x.position == mirror_channel(speaker.position))
+            {
+                anyhow::bail!(
+                    "Speaker {} has no mirrored side to take its right ear from",
+                    get_channel_name(speaker.position)
+                );
+            }
+
+            mask |= bit;
+        }
+
+        let ears = match layout {
+            EarLayout::Mirrored => 1,
+            EarLayout::StereoPairs => 2,
+        };
+        let mut data = Vec::with_capacity(self.ir_length() * speakers.len() * ears);
+        for i in 0..self.ir_length() {
+            for speaker in &speakers {
+                data.push(speaker.left[i]);
+                if layout == EarLayout::StereoPairs {
+                    data.push(speaker.right[i]);
+                }
+            }
+        }
+
+        write_float(writer, self.sample_rate, speakers.len() * ears, mask, &data)
+    }
+}
+
+/// summary of an HRIR wav, read without preparing a filter from it, e.g. to list the HRIRs
+/// available to pick from
+#[derive(Debug, Clone, PartialEq)]
+pub struct HrirInfo {
+    pub sample_rate: u32,
+    /// position of each channel, from the channel mask or the [`standard_layout`] of the
+    /// channel count without one, [`Speaker::DirectOut`] for channels without either
+    pub positions: Vec<Speaker>,
+    pub ir_length: usize,
+    /// linear peak of each channel
+    pub peaks: Vec<f32>,
+    /// [`onset`] of each channel in samples
+    pub onsets: Vec<usize>,
+    /// positions which lack their mirrored side, and so can't be loaded as
+    /// [`EarLayout::Mirrored`]
+    pub unmirrored: Vec<Speaker>,
+}
+
+impl HrirInfo {
+    pub fn probe<R: Read + Seek>(reader: R) -> anyhow::Result<HrirInfo> {
+        let wav = WavData::read(reader)?;
+        let positions = match standard_layout(wav.channels) {
+            Some(layout) if wav.positions.iter().all(|x| *x == Speaker::DirectOut) => {
+                layout.to_vec()
+            }
+            _ => wav.positions.clone(),
+        };
+        let columns = (0..wav.channels).map(|x| wav.column(x)).collect::<Vec<_>>();
+
+        Ok(HrirInfo {
+            sample_rate: wav.sample_rate,
+            ir_length: columns.first().map_or(0, |x| x.len()),
+            peaks: columns
+                .iter()
+                .map(|x| x.iter().fold(0f32, |max, x| max.max(x.abs())))
+                .collect(),
+            onsets: columns.iter().map(|x| onset(x)).collect(),
+            unmirrored: positions
+                .iter()
+                .copied()
+                .filter(|x| *x != Speaker::DirectOut && !positions.contains(&mirror_channel(*x)))
+                .collect(),
+            positions,
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_symmetrical(&self) -> bool {
+        self.unmirrored.is_empty()
+    }
+
+    pub fn peak_db(&self, channel: usize) -> f32 {
+        20.0 * self.peaks[channel].log10()
+    }
+
+    pub fn onset_ms(&self, channel: usize) -> f32 {
+        self.onsets[channel] as f32 * 1000.0 / self.sample_rate as f32
+    }
+}
+
+/// speaker of a wav named like `FL.wav`
+fn speaker_of_file(path: &Path) -> Option<Speaker> {
+    if !path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("wav"))
+    {
+        return None;
+    }
+
+    channel_from_name(path.file_stem()?.to_str()?).filter(|x| *x != Speaker::DirectOut)
+}
+
+/// HRIR of `layout` at the standard angles of [`SpeakerDirection::standard`] from a
+/// [`SphericalHead`] of `head_radius_cm` (e.g. [`KEMAR_HEAD_RADIUS_CM`]), for when no measured
+/// HRIR is at hand
+pub fn synthesize(
+    layout: &[Speaker],
+    head_radius_cm: f32,
+    sample_rate: u32,
+) -> anyhow::Result<Hrir> {
+    let directions = layout
+        .iter()
+        .map(|x| {
+            SpeakerDirection::standard(*x)
+                .with_context(|| format!("Speaker {} has no direction", get_channel_name(*x)))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Hrir::from_hrtf(
+        &SphericalHead::new(head_radius_cm, sample_rate),
+        &directions,
+    )
+}
+
+/// index of the first sample within 20dB of the peak of `ir`
+pub fn onset(ir: &[f32]) -> usize {
+    let peak = ir.iter().fold(0f32, |max, x| max.max(x.abs()));
+
+    ir.iter().position(|x| x.abs() >= peak * 0.1).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{onset, FadeWindow, Hrir, Normalization, OnsetAlignment, SpeakerIr};
+    use crate::Speaker;
+
+    #[test]
+    fn onsets_align_and_keep_the_itd() {
+        let impulse = |at: usize| {
+            let mut ir = vec![0f32; 64];
+            ir[at] = 1.0;
+            ir
+        };
+
+        let mut hrir = Hrir {
+            sample_rate: 48000,
+            speakers: vec![
+                SpeakerIr {
+                    position: Speaker::FrontLeft,
+                    left: impulse(10),
+                    right: impulse(14),
+                },
+                SpeakerIr {
+                    position: Speaker::FrontRight,
+                    left: impulse(31),
+                    right: impulse(30),
+                },
+            ],
+        };
+        let onsets = |hrir: &Hrir| {
+            hrir.speakers
+                .iter()
+                .map(|x| (onset(&x.left), onset(&x.right)))
+                .collect::<Vec<_>>()
+        };
+
+        hrir.align_onsets(OnsetAlignment::Earliest);
+        assert_eq!(hrir.ir_length(), 64);
+        assert_eq!(onsets(&hrir), vec![(10, 14), (11, 10)]);
+
+        hrir.align_onsets(OnsetAlignment::At(40));
+        assert_eq!(hrir.ir_length(), 94);
+        assert_eq!(onsets(&hrir), vec![(40, 44), (41, 40)]);
+    }
+
+    #[test]
+    fn scaling_the_itd_moves_the_far_ear() {
+        let impulse = |at: usize| {
+            let mut ir = vec![0f32; 64];
+            ir[at] = 1.0;
+            ir
+        };
+        let hrir = Hrir {
+            sample_rate: 48000,
+            speakers: vec![
+                SpeakerIr {
+                    position: Speaker::FrontLeft,
+                    left: impulse(10),
+                    right: impulse(20),
+                },
+                SpeakerIr {
+                    position: Speaker::FrontCenter,
+                    left: impulse(10),
+                    right: impulse(10),
+                },
+                SpeakerIr {
+                    position: Speaker::FrontRight,
+                    left: impulse(16),
+                    right: impulse(10),
+                },
+            ],
+        };
+        let onsets = |hrir: &Hrir| {
+            hrir.speakers
+                .iter()
+                .map(|x| (onset(&x.left), onset(&x.right)))
+                .collect::<Vec<_>>()
+        };
+
+        let mut bigger = hrir.clone();
+        bigger.scale_itd(1.5);
+        assert_eq!(bigger.ir_length(), 69);
+        assert_eq!(onsets(&bigger), vec![(10, 25), (10, 10), (19, 10)]);
+
+        let mut smaller = hrir;
+        smaller.scale_itd(0.5);
+        assert_eq!(smaller.ir_length(), 64);
+        assert_eq!(onsets(&smaller), vec![(10, 15), (10, 10), (13, 10)]);
+    }
+
+    #[test]
+    fn truncation_estimate() {
+        let mut left = vec![0f32; 256];
+        left[0] = 1.0;
+        left[200] = 0.5;
+
+        let hrir = Hrir {
+            sample_rate: 48000,
+            speakers: vec![SpeakerIr {
+                position: Speaker::FrontCenter,
+                right: left.clone(),
+                left,
+            }],
+        };
+
+        let lossless = hrir.estimate_truncation(201);
+        assert_eq!(lossless.energy_loss_db, 0.0);
+        assert!(lossless.max_deviation_db < 1e-6);
+
+        // dropping the echo loses a fifth of the energy and the comb filtering it causes, which
+        // swings between +3.5 and -6 dB
+        let lossy = hrir.estimate_truncation(200);
+        assert!((lossy.energy_loss_db - 10.0 * 0.8f32.log10()).abs() < 1e-4);
+        assert!(lossy.max_deviation_db > 5.0 && lossy.max_deviation_db < 6.03);
+        assert_eq!(lossy.worst_speaker, Speaker::FrontCenter);
+    }
+
+    #[test]
+    fn minimum_phase_keeps_the_itd() {
+        // the right ear hears a delayed, mixed phase version of the left ear
+        let mut left = vec![0f32; 128];
+        left[20..24].copy_from_slice(&[0.2, -0.5, 1.0, 0.3]);
+        let mut right = vec![0f32; 128];
+        right[32..36].copy_from_slice(&[0.1, -0.25, 0.5, 0.15]);
+
+        let mut hrir = Hrir {
+            sample_rate: 48000,
+            speakers: vec![SpeakerIr {
+                position: Speaker::FrontLeft,
+                left,
+                right,
+            }],
+        };
+        let energy = |ir: &[f32]| ir.iter().map(|x| x * x).sum::<f32>();
+        let energies = (
+            energy(&hrir.speakers[0].left),
+            energy(&hrir.speakers[0].right),
+        );
+
+        hrir.to_minimum_phase();
+        let speaker = &hrir.speakers[0];
+
+        // the leading silence is gone, the 12 samples between the ears are kept
+        assert_eq!(hrir.ir_length(), 108);
+        assert_eq!(onset(&speaker.left), 0);
+        assert_eq!(onset(&speaker.right), 12);
+        assert!((energy(&speaker.left) - energies.0).abs() < 1e-3);
+        assert!((energy(&speaker.right) - energies.1).abs() < 1e-3);
+
+        // a minimum phase response has its energy as early as possible
+        assert!(energy(&speaker.left[..2]) > 0.8 * energies.0);
+    }
+
+    #[test]
+    fn truncation_fades_out() {
+        let hrir = Hrir {
+            sample_rate: 1000,
+            speakers: vec![SpeakerIr {
+                position: Speaker::FrontCenter,
+                left: vec![1.0; 100],
+                right: vec![0.5; 100],
+            }],
+        };
+        let truncated = |length, window| {
+            let mut hrir = hrir.clone();
+            hrir.truncate(length, window);
+            hrir
+        };
+
+        let cut = truncated(40, FadeWindow::Rectangular);
+        assert_eq!(cut.ir_length(), 40);
+        assert_eq!(cut.speakers[0].left, vec![1.0; 40]);
+        assert_eq!(truncated(200, FadeWindow::Rectangular).ir_length(), 100);
+
+        // 10ms at 1 kHz, the last 10 samples fade out towards zero
+        let hann = truncated(40, FadeWindow::Hann { ms: 10.0 });
+        let left = &hann.speakers[0].left;
+        assert_eq!(left[29], 1.0);
+        assert!(left[30] < 1.0 && left[30] > 0.95);
+        assert!(left[39] > 0.0 && left[39] < 0.05);
+        assert!(left.windows(2).all(|x| x[1] <= x[0]));
+        assert_eq!(hann.speakers[0].right[35] * 2.0, left[35]);
+
+        let tukey = truncated(40, FadeWindow::Tukey { ratio: 0.25 });
+        assert_eq!(tukey.speakers[0].left, hann.speakers[0].left);
+    }
+
+    #[test]
+    fn normalization_strategies() {
+        let speaker = |position, peak: f32| SpeakerIr {
+            position,
+            left: vec![peak, -peak / 2.0, 0.0, 0.0],
+            right: vec![0.0, peak / 2.0, 0.0, 0.0],
+        };
+        let hrir = Hrir {
+            sample_rate: 48000,
+            speakers: vec![
+                speaker(Speaker::FrontLeft, 0.5),
+                speaker(Speaker::FrontRight, 0.25),
+            ],
+        };
+        let normalized = |normalization| {
+            let mut hrir = hrir.clone();
+            hrir.normalize_with(normalization);
+            hrir
+        };
+        let samples = |hrir: &Hrir| {
+            hrir.speakers
+                .iter()
+                .map(|x| (x.left.clone(), x.right.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        // the left ears add up to 0.75 on the first sample
+        let sum_peak = normalized(Normalization::SumPeak { factor: 2.0 });
+        assert!((sum_peak.speakers[0].left[0] - 0.5 / 1.5).abs() < 1e-6);
+        let mut default = hrir.clone();
+        default.normalize();
+        assert_eq!(
+            samples(&normalized(Normalization::default())),
+            samples(&default)
+        );
+
+        assert_eq!(
+            normalized(Normalization::PerChannelPeak).speakers[0].left[0],
+            1.0
+        );
+
+        let rms = normalized(Normalization::Rms { target_db: -6.0 });
+        let energy = rms
+            .speakers
+            .iter()
+            .flat_map(|x| x.left.iter().chain(&x.right))
+            .map(|x| x * x)
+            .sum::<f32>()
+            / 4.0;
+        assert!((10.0 * energy.log10() + 6.0).abs() < 1e-3);
+
+        assert_eq!(samples(&normalized(Normalization::None)), samples(&hrir));
+    }
+
+    #[test]
+    fn layout_overrides_the_channel_mask() {
+        use super::EarLayout;
+        use std::fs::File;
+
+        let load = |order: Option<&[Speaker]>| {
+            let file = File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap();
+            Hrir::from_wav_with_speakers(file, EarLayout::Mirrored, order)
+        };
+
+        let order = [
+            Speaker::FrontLeft,
+            Speaker::FrontRight,
+            Speaker::FrontCenter,
+            Speaker::LowFrequency,
+            Speaker::SideLeft,
+            Speaker::SideRight,
+        ];
+        let hrir = load(Some(&order)).unwrap();
+        assert_eq!(hrir.positions().collect::<Vec<_>>(), order);
+
+        let masked = load(None).unwrap();
+        assert_eq!(masked.positions().nth(4), Some(Speaker::BackLeft));
+        assert_eq!(hrir.speakers[4].left, masked.speakers[4].left);
+
+        assert!(load(Some(&order[..4])).is_err());
+    }
+
+    #[test]
+    fn probing_the_kemar() {
+        use super::{EarLayout, HrirInfo};
+        use std::fs::File;
+
+        let file = File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap();
+        let info = HrirInfo::probe(file).unwrap();
+        let file = File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap();
+        let hrir = Hrir::from_wav(file, EarLayout::Mirrored).unwrap();
+
+        assert_eq!(info.sample_rate, 44100);
+        assert_eq!(info.channels(), 6);
+        assert_eq!(info.positions, hrir.positions().collect::<Vec<_>>());
+        assert_eq!(info.ir_length, hrir.ir_length());
+        assert!(info.is_symmetrical());
+        for (i, speaker) in hrir.speakers.iter().enumerate() {
+            assert_eq!(info.onsets[i], onset(&speaker.left));
+            assert!(info.peaks[i] > 0.0 && info.peak_db(i) <= 0.0);
+        }
+        assert!(info.onset_ms(0) < 5.0);
+    }
+
+    #[test]
+    fn written_wavs_read_back() {
+        use super::EarLayout;
+        use std::fs::File;
+        use std::io::Cursor;
+
+        let file = File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap();
+        let mut hrir = Hrir::from_wav(file, EarLayout::Mirrored).unwrap();
+        hrir.speakers.reverse();
+        hrir.speakers[0].right[0] = 0.25;
+
+        for layout in [EarLayout::Mirrored, EarLayout::StereoPairs] {
+            let mut wav = vec![];
+            hrir.write_wav(&mut wav, layout).unwrap();
+            let read = Hrir::from_wav(Cursor::new(wav), layout).unwrap();
+
+            assert_eq!(read.sample_rate, hrir.sample_rate);
+            assert_eq!(read.speakers.len(), 6);
+            for speaker in &read.speakers {
+                let written = hrir
+                    .speakers
+                    .iter()
+                    .find(|x| x.position == speaker.position);
+                let written = written.unwrap();
+                assert_eq!(speaker.left, written.left);
+                assert_eq!(
+                    speaker.right == written.right,
+                    layout == EarLayout::StereoPairs || speaker.position != Speaker::BackRight
+                );
+            }
+        }
+
+        hrir.speakers.retain(|x| x.position != Speaker::FrontRight);
+        assert!(hrir.write_wav(vec![], EarLayout::Mirrored).is_err());
+        assert!(hrir.write_wav(vec![], EarLayout::StereoPairs).is_ok());
+    }
+
+    #[test]
+    fn diffuse_field_eq_flattens_the_shared_response() {
+        use crate::dsp::fft;
+
+        // every ear has the same resonance, the far ears are quieter
+        let ir = |gain: f32, delay: usize| {
+            let mut ir = vec![0f32; 128];
+            ir[delay] = gain;
+            ir[delay + 3] = 0.7 * gain;
+            ir[delay + 6] = 0.5 * gain;
+            ir
+        };
+        let mut hrir = Hrir {
+            sample_rate: 48000,
+            speakers: vec![
+                SpeakerIr {
+                    position: Speaker::FrontLeft,
+                    left: ir(1.0, 10),
+                    right: ir(0.5, 14),
+                },
+                SpeakerIr {
+                    position: Speaker::FrontRight,
+                    left: ir(0.5, 14),
+                    right: ir(1.0, 10),
+                },
+            ],
+        };
+        let spread_db = |ir: &[f32]| {
+            let mut re = ir.iter().map(|x| *x as f64).collect::<Vec<_>>();
+            let mut im = vec![0f64; re.len()];
+            fft(&mut re, &mut im, false);
+            let db = (1..re.len() / 2)
+                .map(|k| 20.0 * re[k].hypot(im[k]).log10())
+                .collect::<Vec<_>>();
+            db.iter().cloned().fold(f64::MIN, f64::max)
+                - db.iter().cloned().fold(f64::MAX, f64::min)
+        };
+        let energy = |ir: &[f32]| ir.iter().map(|x| x * x).sum::<f32>();
+
+        let before = spread_db(&hrir.speakers[0].left);
+        hrir.diffuse_field_equalize();
+        assert_eq!(hrir.ir_length(), 128);
+        assert!(spread_db(&hrir.speakers[0].left) < before / 2.0);
+
+        let ild = energy(&hrir.speakers[0].left) / energy(&hrir.speakers[0].right);
+        assert!((ild - 4.0).abs() < 0.1, "{}", ild);
+        assert!(onset(&hrir.speakers[0].right) > onset(&hrir.speakers[0].left));
+    }
+}
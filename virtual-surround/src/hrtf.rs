@@ -0,0 +1,757 @@
+//! Rendering speakers at arbitrary directions from a dense HRTF dataset instead of the
+//! directions baked into an HRIR wav, interpolated between the measured directions
+
+use crate::distance::SPEED_OF_SOUND;
+use crate::dsp::{fft, minimum_phase};
+use crate::hrir::{onset, Hrir, SpeakerIr};
+use crate::wav::WavData;
+use crate::Speaker;
+use crate::{get_channel_name, MAX_CHANNELS};
+use anyhow::Context;
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::fs::File;
+use std::path::Path;
+
+/// Where a speaker is placed, in degrees. Azimuth goes counterclockwise from the front, so left
+/// is positive, elevation is positive upwards, like SOFA's spherical coordinates.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SpeakerDirection {
+    pub position: Speaker,
+    pub azimuth: f32,
+    pub elevation: f32,
+}
+
+impl SpeakerDirection {
+    pub fn new(position: Speaker, azimuth: f32, elevation: f32) -> Self {
+        SpeakerDirection {
+            position,
+            azimuth,
+            elevation,
+        }
+    }
+
+    /// direction of `position` in an ITU-R BS.775 / BS.2051 style setup, `None` for DirectOut
+    pub fn standard(position: Speaker) -> Option<Self> {
+        let (azimuth, elevation) = match position {
+            Speaker::DirectOut => return None,
+            Speaker::FrontLeft => (30.0, 0.0),
+            Speaker::FrontRight => (-30.0, 0.0),
+            Speaker::FrontCenter | Speaker::LowFrequency | Speaker::LowFrequency2 => (0.0, 0.0),
+            Speaker::BackLeft => (150.0, 0.0),
+            Speaker::BackRight => (-150.0, 0.0),
+            Speaker::FrontCenterLeft => (15.0, 0.0),
+            Speaker::FrontCenterRight => (-15.0, 0.0),
+            Speaker::BackCenter => (180.0, 0.0),
+            Speaker::SideLeft => (90.0, 0.0),
+            Speaker::SideRight => (-90.0, 0.0),
+            Speaker::TopCenter => (0.0, 90.0),
+            Speaker::TopFrontLeft => (30.0, 45.0),
+            Speaker::TopFrontCenter => (0.0, 45.0),
+            Speaker::TopFrontRight => (-30.0, 45.0),
+            Speaker::TopBackLeft => (150.0, 45.0),
+            Speaker::TopBackCenter => (180.0, 45.0),
+            Speaker::TopBackRight => (-150.0, 45.0),
+            Speaker::FrontWideLeft => (60.0, 0.0),
+            Speaker::FrontWideRight => (-60.0, 0.0),
+            Speaker::TopSideLeft => (90.0, 45.0),
+            Speaker::TopSideRight => (-90.0, 45.0),
+            Speaker::BottomFrontLeft => (30.0, -30.0),
+            Speaker::BottomFrontCenter => (0.0, -30.0),
+            Speaker::BottomFrontRight => (-30.0, -30.0),
+        };
+
+        Some(Self::new(position, azimuth, elevation))
+    }
+
+    /// unit vector with x to the front, y to the left and z up
+    pub fn cartesian(&self) -> [f32; 3] {
+        let (azimuth, elevation) = (self.azimuth.to_radians(), self.elevation.to_radians());
+
+        [
+            elevation.cos() * azimuth.cos(),
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+        ]
+    }
+}
+
+/// Anything which can produce a left and right ear impulse response for a direction
+pub trait HrtfSource {
+    fn sample_rate(&self) -> u32;
+
+    /// `(left, right)` impulse response for `direction`, interpolated if it's not on the
+    /// measurement grid
+    fn impulse(&self, direction: &SpeakerDirection) -> anyhow::Result<(Vec<f32>, Vec<f32>)>;
+}
+
+impl Hrir {
+    /// render one speaker per `speakers` entry from `source`
+    pub fn from_hrtf<S: HrtfSource + ?Sized>(
+        source: &S,
+        speakers: &[SpeakerDirection],
+    ) -> anyhow::Result<Hrir> {
+        if speakers.len() > MAX_CHANNELS {
+            anyhow::bail!(
+                "{} speakers requested, VirtualSurroundFilter is compiled with only support for max {} channels",
+                speakers.len(),
+                MAX_CHANNELS
+            );
+        }
+
+        let mut irs: Vec<SpeakerIr> = Vec::with_capacity(speakers.len());
+
+        for speaker in speakers {
+            if speaker.position == Speaker::DirectOut {
+                anyhow::bail!("Speakers need a position, got DirectOut");
+            }
+
+            if irs.iter().any(|x| x.position == speaker.position) {
+                anyhow::bail!(
+                    "Speaker {} is placed twice",
+                    get_channel_name(speaker.position)
+                );
+            }
+
+            let (left, right) = source.impulse(speaker)?;
+
+            irs.push(SpeakerIr {
+                position: speaker.position,
+                left,
+                right,
+            });
+        }
+
+        // the filter expects every impulse response to be equally long
+        let length = irs
+            .iter()
+            .map(|x| x.left.len().max(x.right.len()))
+            .max()
+            .unwrap_or(0);
+
+        for ir in &mut irs {
+            ir.left.resize(length, 0f32);
+            ir.right.resize(length, 0f32);
+        }
+
+        Ok(Hrir {
+            sample_rate: source.sample_rate(),
+            speakers: irs,
+        })
+    }
+}
+
+/// Impulse responses measured for a direction, in degrees like [`SpeakerDirection`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    pub azimuth: f32,
+    pub elevation: f32,
+    pub left: Vec<f32>,
+    pub right: Vec<f32>,
+}
+
+impl Measurement {
+    fn cartesian(&self) -> [f32; 3] {
+        SpeakerDirection::new(Speaker::DirectOut, self.azimuth, self.elevation).cartesian()
+    }
+}
+
+/// How [`MeasuredHrtf`] gets the impulse responses of a direction between its measurements
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    /// the nearest measurement
+    #[default]
+    Nearest,
+    /// weighted sum of the measurements around the direction, the two azimuths around it on
+    /// each of the two elevation rings around it
+    Bilinear,
+    /// the bilinear weights applied to the magnitude responses and the onsets separately, as a
+    /// minimum phase response delayed by the interpolated onset. Avoids the comb filtering of
+    /// summing responses which arrive at different times.
+    MagnitudePhase,
+}
+
+/// HRTF dataset of measurements on a grid of directions, directions between them are
+/// interpolated as picked with [`with_interpolation`](MeasuredHrtf::with_interpolation)
+#[derive(Debug, Clone)]
+pub struct MeasuredHrtf {
+    pub sample_rate: u32,
+    pub measurements: Vec<Measurement>,
+    pub interpolation: Interpolation,
+}
+
+impl MeasuredHrtf {
+    pub fn new(sample_rate: u32, measurements: Vec<Measurement>) -> anyhow::Result<Self> {
+        if measurements.is_empty() {
+            anyhow::bail!("An HRTF needs at least one measurement");
+        }
+
+        Ok(MeasuredHrtf {
+            sample_rate,
+            measurements,
+            interpolation: Interpolation::default(),
+        })
+    }
+
+    pub fn with_interpolation(mut self, interpolation: Interpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    /// Load the "compact" set of the MIT KEMAR measurements, `dir` being the `compact`
+    /// directory (or the one containing it) with its `elev<e>/H<e>e<aaa>a.wav` files. The set
+    /// only holds the right half, the left half is the right one with the ears swapped.
+    pub fn mit_kemar<P: AsRef<Path>>(dir: P) -> anyhow::Result<Self> {
+        let mut dir = dir.as_ref().to_path_buf();
+        if dir.join("compact").is_dir() {
+            dir.push("compact");
+        }
+
+        let mut sample_rate = None;
+        let mut measurements = vec![];
+
+        for elevation in
+            std::fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?
+        {
+            let elevation = elevation?.path();
+            if !elevation.is_dir() {
+                continue;
+            }
+
+            for file in std::fs::read_dir(&elevation)? {
+                let path = file?.path();
+                let (mit_elevation, mit_azimuth) = match parse_kemar_name(&path) {
+                    Some(x) => x,
+                    None => continue,
+                };
+
+                let wav = File::open(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(WavData::read)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                if wav.channels != 2 {
+                    anyhow::bail!(
+                        "{} has {} channels, compact KEMAR files have both ears",
+                        path.display(),
+                        wav.channels
+                    );
+                }
+
+                if *sample_rate.get_or_insert(wav.sample_rate) != wav.sample_rate {
+                    anyhow::bail!("{} has another sample rate than the rest", path.display());
+                }
+
+                // MIT's azimuth goes clockwise, the measurements are of a source on the right
+                let (left, right) = (wav.column(0), wav.column(1));
+                if mit_azimuth != 0.0 && mit_azimuth != 180.0 {
+                    measurements.push(Measurement {
+                        azimuth: mit_azimuth,
+                        elevation: mit_elevation,
+                        left: right.clone(),
+                        right: left.clone(),
+                    });
+                }
+
+                measurements.push(Measurement {
+                    azimuth: -mit_azimuth,
+                    elevation: mit_elevation,
+                    left,
+                    right,
+                });
+            }
+        }
+
+        let sample_rate = sample_rate.with_context(|| {
+            format!(
+                "{} has no compact KEMAR measurements (elev<e>/H<e>e<aaa>a.wav)",
+                dir.display()
+            )
+        })?;
+
+        Self::new(sample_rate, measurements)
+    }
+
+    /// the measurement closest to `direction`
+    pub fn nearest(&self, direction: &SpeakerDirection) -> &Measurement {
+        let target = direction.cartesian();
+        let closeness = |x: &Measurement| {
+            let position = x.cartesian();
+            (0..3).map(|i| position[i] * target[i]).sum::<f32>()
+        };
+
+        self.measurements
+            .iter()
+            .max_by(|a, b| closeness(a).total_cmp(&closeness(b)))
+            .unwrap()
+    }
+
+    /// `(measurement index, weight)` of the measurements around `direction`, see
+    /// [`Interpolation::Bilinear`]
+    pub fn weights(&self, direction: &SpeakerDirection) -> Vec<(usize, f32)> {
+        let elevation = direction.elevation;
+        let below = self
+            .measurements
+            .iter()
+            .map(|x| x.elevation)
+            .filter(|x| *x <= elevation)
+            .max_by(f32::total_cmp);
+        let above = self
+            .measurements
+            .iter()
+            .map(|x| x.elevation)
+            .filter(|x| *x >= elevation)
+            .min_by(f32::total_cmp);
+
+        let rings = match (below, above) {
+            (Some(below), Some(above)) if below != above => {
+                let t = (elevation - below) / (above - below);
+                vec![(below, 1.0 - t), (above, t)]
+            }
+            (Some(ring), _) | (_, Some(ring)) => vec![(ring, 1.0)],
+            (None, None) => unreachable!("a MeasuredHrtf has measurements"),
+        };
+
+        let mut weights = vec![];
+        for (ring, ring_weight) in rings {
+            // distance counterclockwise from the direction to each measurement on the ring
+            let ring = self
+                .measurements
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| x.elevation == ring)
+                .map(|(i, x)| (i, (x.azimuth - direction.azimuth).rem_euclid(360.0)))
+                .collect::<Vec<_>>();
+            let next = ring.iter().min_by(|a, b| a.1.total_cmp(&b.1)).unwrap();
+            let previous = ring.iter().max_by(|a, b| a.1.total_cmp(&b.1)).unwrap();
+
+            let (gap_next, gap_previous) = (next.1, 360.0 - previous.1);
+            if next.0 == previous.0 || gap_next == 0.0 {
+                weights.push((next.0, ring_weight));
+            } else {
+                let t = gap_next / (gap_next + gap_previous);
+                weights.push((next.0, ring_weight * (1.0 - t)));
+                weights.push((previous.0, ring_weight * t));
+            }
+        }
+
+        weights.retain(|x| x.1 > 0.0);
+        weights
+    }
+
+    fn interpolate(&self, weights: &[(usize, f32)], ear: fn(&Measurement) -> &[f32]) -> Vec<f32> {
+        let length = weights
+            .iter()
+            .map(|(i, _)| ear(&self.measurements[*i]).len())
+            .max()
+            .unwrap_or(0);
+
+        if self.interpolation != Interpolation::MagnitudePhase {
+            let mut output = vec![0f32; length];
+            for (i, weight) in weights {
+                for (output, sample) in output.iter_mut().zip(ear(&self.measurements[*i])) {
+                    *output += sample * weight;
+                }
+            }
+
+            return output;
+        }
+
+        let n = (length * 2).next_power_of_two();
+        let mut magnitude = vec![0f64; n / 2 + 1];
+        let mut delay = 0.0;
+
+        for (i, weight) in weights {
+            let ir = ear(&self.measurements[*i]);
+            let mut re = vec![0f64; n];
+            let mut im = vec![0f64; n];
+            for (re, sample) in re.iter_mut().zip(ir) {
+                *re = *sample as f64;
+            }
+
+            fft(&mut re, &mut im, false);
+            for (k, magnitude) in magnitude.iter_mut().enumerate() {
+                *magnitude += re[k].hypot(im[k]) * *weight as f64;
+            }
+
+            delay += onset(ir) as f32 * weight;
+        }
+
+        let delay = (delay.round() as usize).min(length);
+        let mut output = vec![0f32; delay];
+        output.extend(
+            minimum_phase(&magnitude, n)
+                .into_iter()
+                .take(length - delay)
+                .map(|x| x as f32),
+        );
+
+        output
+    }
+}
+
+/// elevation and azimuth of a compact KEMAR file named like `H-10e030a.wav`
+fn parse_kemar_name(path: &Path) -> Option<(f32, f32)> {
+    let name = path.file_name()?.to_str()?;
+    let (elevation, azimuth) = name
+        .strip_prefix('H')?
+        .strip_suffix("a.wav")?
+        .split_once('e')?;
+
+    Some((elevation.parse().ok()?, azimuth.parse().ok()?))
+}
+
+impl HrtfSource for MeasuredHrtf {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn impulse(&self, direction: &SpeakerDirection) -> anyhow::Result<(Vec<f32>, Vec<f32>)> {
+        if self.interpolation == Interpolation::Nearest {
+            let measurement = self.nearest(direction);
+            return Ok((measurement.left.clone(), measurement.right.clone()));
+        }
+
+        let weights = self.weights(direction);
+
+        Ok((
+            self.interpolate(&weights, |x| &x.left),
+            self.interpolate(&weights, |x| &x.right),
+        ))
+    }
+}
+
+/// HRTF dataset from a SOFA file, directions between measurements are interpolated between the
+/// nearest neighbours by libmysofa
+#[cfg(feature = "sofa")]
+pub struct SofaHrtf {
+    sofa: sofar::reader::Sofar,
+    sample_rate: u32,
+}
+
+#[cfg(feature = "sofa")]
+impl SofaHrtf {
+    /// open a SOFA file, resampled to `sample_rate` if it was measured at another rate
+    pub fn open<P: AsRef<std::path::Path>>(path: P, sample_rate: u32) -> anyhow::Result<Self> {
+        let sofa = sofar::reader::OpenOptions::new()
+            .sample_rate(sample_rate as f32)
+            .open(path)
+            .map_err(|err| anyhow::anyhow!("Failed to open SOFA file: {}", err))?;
+
+        Ok(SofaHrtf { sofa, sample_rate })
+    }
+}
+
+#[cfg(feature = "sofa")]
+impl std::fmt::Debug for SofaHrtf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SofaHrtf")
+            .field("sample_rate", &self.sample_rate)
+            .field("filter_len", &self.sofa.filter_len())
+            .finish()
+    }
+}
+
+#[cfg(feature = "sofa")]
+impl HrtfSource for SofaHrtf {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn impulse(&self, direction: &SpeakerDirection) -> anyhow::Result<(Vec<f32>, Vec<f32>)> {
+        let mut filter = sofar::reader::Filter::new(self.sofa.filter_len());
+        let [x, y, z] = direction.cartesian();
+        self.sofa.filter(x, y, z, &mut filter);
+
+        // sofa stores broadband delays separately, bake them into the impulse responses
+        let delayed = |ir: &[f32], delay: f32| {
+            let delay = (delay * self.sample_rate as f32).round().max(0.0) as usize;
+            let mut output = vec![0f32; delay];
+            output.extend_from_slice(ir);
+            output
+        };
+
+        Ok((
+            delayed(&filter.left, filter.ldelay),
+            delayed(&filter.right, filter.rdelay),
+        ))
+    }
+}
+
+/// Brown and Duda's spherical head model, a head shadow filter and the Woodworth ITD for each
+/// ear of a rigid sphere, without pinna or torso. Needs no measurements, so it stands in when no
+/// HRIR is at hand, but localizes far worse than a measured one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SphericalHead {
+    pub radius_cm: f32,
+    pub sample_rate: u32,
+}
+
+impl SphericalHead {
+    pub fn new(radius_cm: f32, sample_rate: u32) -> Self {
+        SphericalHead {
+            radius_cm,
+            sample_rate,
+        }
+    }
+
+    /// response of the ear at `side` (1 left, -1 right) to a source at `direction`
+    fn ear(&self, direction: &SpeakerDirection, side: f32) -> Vec<f32> {
+        let fs = self.sample_rate as f32;
+        let radius = self.radius_cm / 100.0;
+        // angle between the source and the ear's axis
+        let incidence = (direction.cartesian()[1] * side).clamp(-1.0, 1.0).acos();
+
+        // Woodworth's path around the sphere, relative to the point of the head nearest the source
+        let delay = if incidence < FRAC_PI_2 {
+            1.0 - incidence.cos()
+        } else {
+            1.0 + incidence - FRAC_PI_2
+        } * radius
+            / SPEED_OF_SOUND
+            * fs;
+
+        let length = (self.sample_rate as usize / 200).next_power_of_two();
+        let mut ir = vec![0f32; length];
+        // Hann windowed sinc for the fractional part of the delay
+        let start = delay + SINC_TAPS as f32 / 2.0;
+        for (i, x) in ir.iter_mut().enumerate().take(start as usize + SINC_TAPS) {
+            let t = i as f32 - start;
+            if t.abs() < SINC_TAPS as f32 / 2.0 {
+                let sinc = if t == 0.0 {
+                    1.0
+                } else {
+                    (PI * t).sin() / (PI * t)
+                };
+                *x = sinc * (0.5 + 0.5 * (2.0 * PI * t / SINC_TAPS as f32).cos());
+            }
+        }
+
+        // one-pole one-zero shelf, +6dB towards the ear to -20dB in its shadow above c / radius
+        let alpha = (1.0 + MIN_ALPHA / 2.0)
+            + (1.0 - MIN_ALPHA / 2.0) * (incidence / SHADOW_ANGLE.to_radians() * PI).cos();
+        let beta = 2.0 * SPEED_OF_SOUND / radius;
+        let k = 2.0 * fs;
+        let b0 = (beta + alpha * k) / (beta + k);
+        let b1 = (beta - alpha * k) / (beta + k);
+        let a1 = (beta - k) / (beta + k);
+
+        let (mut x1, mut y1) = (0f32, 0f32);
+        for x in &mut ir {
+            let y = b0 * *x + b1 * x1 - a1 * y1;
+            x1 = *x;
+            y1 = y;
+            *x = y;
+        }
+
+        ir
+    }
+}
+
+/// taps of the fractional delay of [`SphericalHead`]
+const SINC_TAPS: usize = 16;
+/// shadow of the far ear at [`SHADOW_ANGLE`] from its axis, -20dB
+const MIN_ALPHA: f32 = 0.1;
+const SHADOW_ANGLE: f32 = 150.0;
+
+impl HrtfSource for SphericalHead {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn impulse(&self, direction: &SpeakerDirection) -> anyhow::Result<(Vec<f32>, Vec<f32>)> {
+        if self.radius_cm <= 0.0 {
+            anyhow::bail!("A head radius of {}cm can't be modelled", self.radius_cm);
+        }
+
+        Ok((self.ear(direction, 1.0), self.ear(direction, -1.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HrtfSource, Interpolation, MeasuredHrtf, Measurement, SpeakerDirection};
+    use crate::hrir::Hrir;
+    use crate::Speaker;
+
+    /// impulse delayed by a sample per degree to the left for the right ear and vice versa
+    struct Spherical;
+
+    impl HrtfSource for Spherical {
+        fn sample_rate(&self) -> u32 {
+            48000
+        }
+
+        fn impulse(&self, direction: &SpeakerDirection) -> anyhow::Result<(Vec<f32>, Vec<f32>)> {
+            let delay = direction.azimuth.abs() as usize;
+            let mut near = vec![0f32; 4];
+            let mut far = vec![0f32; delay + 4];
+            near[0] = 1.0;
+            far[delay] = 0.5;
+
+            Ok(if direction.azimuth >= 0.0 {
+                (near, far)
+            } else {
+                (far, near)
+            })
+        }
+    }
+
+    #[test]
+    fn custom_directions() {
+        let speakers = [
+            SpeakerDirection::new(Speaker::FrontLeft, 20.0, 0.0),
+            SpeakerDirection::new(Speaker::FrontRight, -40.0, 0.0),
+        ];
+
+        let hrir = Hrir::from_hrtf(&Spherical, &speakers).unwrap();
+
+        assert_eq!(hrir.ir_length(), 44);
+        assert_eq!(hrir.speakers[0].right[20], 0.5);
+        assert_eq!(hrir.speakers[1].left[40], 0.5);
+        assert!(Hrir::from_hrtf(&Spherical, &[speakers[0], speakers[0]]).is_err());
+    }
+
+    #[test]
+    fn mit_kemar_compact_set() {
+        let dir = std::env::temp_dir().join(format!("vsf-kemar-{}", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        // the near ear gets a louder impulse, the far one a quieter one a sample later
+        for (elevation, azimuth) in [(0, 0), (0, 30), (0, 90), (0, 180), (-10, 30), (40, 90)] {
+            let path = dir.join(format!("compact/elev{}", elevation));
+            std::fs::create_dir_all(&path).unwrap();
+            let mut writer = hound::WavWriter::create(
+                path.join(format!("H{}e{:03}a.wav", elevation, azimuth)),
+                spec,
+            )
+            .unwrap();
+            for sample in [0, 16000, 8000 + azimuth as i16, 0] {
+                writer.write_sample(sample / 2).unwrap();
+                writer.write_sample(sample).unwrap();
+            }
+            writer.finalize().unwrap();
+        }
+
+        let hrtf = MeasuredHrtf::mit_kemar(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let hrtf = hrtf.unwrap();
+
+        // every azimuth but 0 and 180 is mirrored onto the left
+        assert_eq!(hrtf.sample_rate, 44100);
+        assert_eq!(hrtf.measurements.len(), 10);
+
+        let at = |azimuth, elevation| {
+            hrtf.nearest(&SpeakerDirection::new(
+                Speaker::FrontCenter,
+                azimuth,
+                elevation,
+            ))
+        };
+        let right = at(-30.0, 0.0);
+        let left = at(35.0, 2.0);
+        assert_eq!((right.azimuth, right.elevation), (-30.0, 0.0));
+        assert_eq!((left.azimuth, left.elevation), (30.0, 0.0));
+        assert_eq!(left.left, right.right);
+        assert!(right.right[1] > right.left[1]);
+        assert_eq!(at(80.0, 45.0).elevation, 40.0);
+
+        let hrir = Hrir::from_hrtf(
+            &hrtf,
+            &[
+                SpeakerDirection::standard(Speaker::FrontLeft).unwrap(),
+                SpeakerDirection::standard(Speaker::FrontRight).unwrap(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(hrir.speakers[0].left, hrir.speakers[1].right);
+    }
+
+    /// impulses delayed by a sample per 3 degrees of azimuth, every 30 degrees on two rings
+    fn grid() -> MeasuredHrtf {
+        let mut measurements = vec![];
+        for elevation in [0.0, 30.0] {
+            for azimuth in (0..12).map(|x| x as f32 * 30.0) {
+                let mut left = vec![0f32; 128];
+                left[(azimuth / 3.0) as usize] = 1.0 + elevation / 30.0;
+                measurements.push(Measurement {
+                    azimuth,
+                    elevation,
+                    right: left.clone(),
+                    left,
+                });
+            }
+        }
+
+        MeasuredHrtf::new(48000, measurements).unwrap()
+    }
+
+    #[test]
+    fn interpolates_between_measurements() {
+        let direction =
+            |azimuth, elevation| SpeakerDirection::new(Speaker::FrontCenter, azimuth, elevation);
+
+        let nearest = grid();
+        assert_eq!(nearest.impulse(&direction(40.0, 5.0)).unwrap().0[10], 1.0);
+
+        let bilinear = grid().with_interpolation(Interpolation::Bilinear);
+        let weights = bilinear.weights(&direction(345.0, 7.5));
+        assert_eq!(weights.len(), 4);
+        assert!((weights.iter().map(|x| x.1).sum::<f32>() - 1.0).abs() < 1e-6);
+
+        let (left, _) = bilinear.impulse(&direction(15.0, 15.0)).unwrap();
+        assert!((left[0] - 0.75).abs() < 1e-6);
+        assert!((left[10] - 0.75).abs() < 1e-6);
+        // clamped to the highest ring, on a measurement
+        assert_eq!(bilinear.weights(&direction(-60.0, 60.0)), vec![(22, 1.0)]);
+
+        // a single peak halfway instead of two half as loud
+        let magnitude_phase = grid().with_interpolation(Interpolation::MagnitudePhase);
+        let (left, _) = magnitude_phase.impulse(&direction(15.0, 0.0)).unwrap();
+        let peak = (0..left.len())
+            .max_by(|a, b| left[*a].abs().total_cmp(&left[*b].abs()))
+            .unwrap();
+        assert_eq!(peak, 5);
+        assert!(left[peak] > 0.9);
+    }
+
+    #[test]
+    fn spherical_head_shadows_the_far_ear() {
+        use super::SphericalHead;
+        use crate::hrir::{onset, synthesize, KEMAR_HEAD_RADIUS_CM};
+
+        let hrir = synthesize(
+            &[Speaker::FrontCenter, Speaker::SideLeft, Speaker::BackRight],
+            KEMAR_HEAD_RADIUS_CM,
+            48000,
+        )
+        .unwrap();
+        assert_eq!(hrir.ir_length(), 256);
+        let energy = |ir: &[f32]| ir.iter().map(|x| x * x).sum::<f32>();
+
+        let center = &hrir.speakers[0];
+        assert_eq!(center.left, center.right);
+
+        // about 0.65ms of ITD for a source at the side
+        let side = &hrir.speakers[1];
+        let itd = onset(&side.right) - onset(&side.left);
+        assert!((28..=35).contains(&itd), "{}", itd);
+        assert!(energy(&side.left) > energy(&side.right) * 2.0);
+
+        let back = &hrir.speakers[2];
+        assert!(onset(&back.left) > onset(&back.right));
+        assert!(hrir
+            .speakers
+            .iter()
+            .all(|x| x.left.iter().all(|x| x.is_finite())));
+
+        assert!(synthesize(&[Speaker::DirectOut], KEMAR_HEAD_RADIUS_CM, 48000).is_err());
+        let head = SphericalHead::new(0.0, 48000);
+        assert!(head
+            .impulse(&SpeakerDirection::new(Speaker::FrontLeft, 30.0, 0.0))
+            .is_err());
+    }
+}
@@ -0,0 +1,179 @@
+#![cfg(feature = "rustfft")]
+
+//! The spectrum products the engines spend most of a block on besides the ffts. On AArch64 they
+//! run on NEON, which every AArch64 cpu has, e.g. the Raspberry Pi 3 and later on a 64-bit os.
+//! Elsewhere the scalar loops are left to the autovectorizer.
+
+use realfft::num_complex::Complex;
+
+/// add the products of `ir` and `input` to `accumulator`
+pub(crate) fn multiply_accumulate(
+    accumulator: &mut [Complex<f32>],
+    ir: &[Complex<f32>],
+    input: &[Complex<f32>],
+) {
+    let bins = accumulator.len().min(ir.len()).min(input.len());
+
+    #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+    let done = unsafe { neon::multiply_accumulate(&mut accumulator[..bins], ir, input) };
+    #[cfg(not(all(target_arch = "aarch64", target_feature = "neon")))]
+    let done = 0;
+
+    for ((acc, ir), input) in accumulator[done..bins]
+        .iter_mut()
+        .zip(&ir[done..])
+        .zip(&input[done..])
+    {
+        *acc += ir * input;
+    }
+}
+
+/// add the products of `input` with the impulse responses of both ears, stored next to each other
+/// per bin, to `left` and `right`
+pub(crate) fn multiply_accumulate_ears(
+    left: &mut [Complex<f32>],
+    right: &mut [Complex<f32>],
+    ir: &[[Complex<f32>; 2]],
+    input: &[Complex<f32>],
+) {
+    let bins = left.len().min(right.len()).min(ir.len()).min(input.len());
+
+    #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+    let done =
+        unsafe { neon::multiply_accumulate_ears(&mut left[..bins], &mut right[..bins], ir, input) };
+    #[cfg(not(all(target_arch = "aarch64", target_feature = "neon")))]
+    let done = 0;
+
+    for (((left, right), ir), input) in left[done..bins]
+        .iter_mut()
+        .zip(&mut right[done..bins])
+        .zip(&ir[done..])
+        .zip(&input[done..])
+    {
+        *left += ir[0] * input;
+        *right += ir[1] * input;
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
+mod neon {
+    use realfft::num_complex::Complex;
+    use std::arch::aarch64::*;
+
+    /// bins per vector
+    const LANES: usize = 4;
+
+    /// the products of the bins in whole vectors, returns how many bins that was. Loads split the
+    /// real and imaginary parts into vectors of their own, `Complex` is laid out like `[re, im]`.
+    ///
+    /// # Safety
+    /// `ir` and `input` are at least as long as `accumulator`
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn multiply_accumulate(
+        accumulator: &mut [Complex<f32>],
+        ir: &[Complex<f32>],
+        input: &[Complex<f32>],
+    ) -> usize {
+        let vectors = accumulator.len() / LANES;
+        let acc = accumulator.as_mut_ptr() as *mut f32;
+        let ir = ir.as_ptr() as *const f32;
+        let input = input.as_ptr() as *const f32;
+
+        for v in 0..vectors {
+            let offset = v * LANES * 2;
+            let x = vld2q_f32(input.add(offset));
+            let h = vld2q_f32(ir.add(offset));
+            let mut sum = vld2q_f32(acc.add(offset));
+
+            sum.0 = vfmsq_f32(vfmaq_f32(sum.0, h.0, x.0), h.1, x.1);
+            sum.1 = vfmaq_f32(vfmaq_f32(sum.1, h.0, x.1), h.1, x.0);
+            vst2q_f32(acc.add(offset), sum);
+        }
+
+        vectors * LANES
+    }
+
+    /// like [`multiply_accumulate`], the impulse responses load as left real, left imaginary,
+    /// right real and right imaginary vectors
+    ///
+    /// # Safety
+    /// `right`, `ir` and `input` are at least as long as `left`
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn multiply_accumulate_ears(
+        left: &mut [Complex<f32>],
+        right: &mut [Complex<f32>],
+        ir: &[[Complex<f32>; 2]],
+        input: &[Complex<f32>],
+    ) -> usize {
+        let vectors = left.len() / LANES;
+        let left = left.as_mut_ptr() as *mut f32;
+        let right = right.as_mut_ptr() as *mut f32;
+        let ir = ir.as_ptr() as *const f32;
+        let input = input.as_ptr() as *const f32;
+
+        for v in 0..vectors {
+            let offset = v * LANES * 2;
+            let x = vld2q_f32(input.add(offset));
+            let h = vld4q_f32(ir.add(offset * 2));
+
+            let mut sum = vld2q_f32(left.add(offset));
+            sum.0 = vfmsq_f32(vfmaq_f32(sum.0, h.0, x.0), h.1, x.1);
+            sum.1 = vfmaq_f32(vfmaq_f32(sum.1, h.0, x.1), h.1, x.0);
+            vst2q_f32(left.add(offset), sum);
+
+            let mut sum = vld2q_f32(right.add(offset));
+            sum.0 = vfmsq_f32(vfmaq_f32(sum.0, h.2, x.0), h.3, x.1);
+            sum.1 = vfmaq_f32(vfmaq_f32(sum.1, h.2, x.1), h.3, x.0);
+            vst2q_f32(right.add(offset), sum);
+        }
+
+        vectors * LANES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{multiply_accumulate, multiply_accumulate_ears};
+    use realfft::num_complex::Complex;
+
+    #[test]
+    fn matches_the_complex_products() {
+        // not a whole number of vectors, so the scalar loop finishes every kernel
+        let bins = 257;
+        let mut state = 0x1234_5678u32;
+        let mut noise = || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+        };
+        let mut spectrum = |bins: usize| {
+            (0..bins)
+                .map(|_| Complex::new(noise(), noise()))
+                .collect::<Vec<_>>()
+        };
+
+        let input = spectrum(bins);
+        let ears = [spectrum(bins), spectrum(bins)];
+        let start = [spectrum(bins), spectrum(bins)];
+        let interleaved = ears[0]
+            .iter()
+            .zip(&ears[1])
+            .map(|(left, right)| [*left, *right])
+            .collect::<Vec<_>>();
+
+        let mut single = start.clone();
+        for (accumulator, ir) in single.iter_mut().zip(&ears) {
+            multiply_accumulate(accumulator, ir, &input);
+        }
+        let [mut left, mut right] = start.clone();
+        multiply_accumulate_ears(&mut left, &mut right, &interleaved, &input);
+
+        for ear in 0..2 {
+            for bin in 0..bins {
+                let expected = start[ear][bin] + ears[ear][bin] * input[bin];
+                for result in [single[ear][bin], [left[bin], right[bin]][ear]] {
+                    assert!((result - expected).norm() < 1e-6, "{} {}", ear, bin);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{
+    BlockAdapter, Speaker, VirtualSurroundFilter, VsfController, VsfProcessor, BLOCK_SIZE,
+};
+use kira::clock::clock_info::ClockInfoProvider;
+use kira::dsp::Frame;
+use kira::modulator::value_provider::ModulatorValueProvider;
+use kira::track::effect::{Effect, EffectBuilder};
+use std::sync::Mutex;
+
+/// Builds a kira effect virtualizing a mixer track, added with `TrackBuilder::add_effect`. The
+/// track is played from the front pair of the filter, and the handle is a [`VsfController`] to
+/// change the mix, bypass and gain or swap the filter while it plays. The track has to run at the
+/// sample rate of the filter, at any other rate the effect leaves it alone.
+pub struct VirtualSurroundEffectBuilder {
+    filter: VirtualSurroundFilter,
+}
+
+impl VirtualSurroundEffectBuilder {
+    pub fn new(mut filter: VirtualSurroundFilter) -> anyhow::Result<Self> {
+        if filter.input_layout() != [Speaker::FrontLeft, Speaker::FrontRight] {
+            filter.set_input_layout(&[Speaker::FrontLeft, Speaker::FrontRight])?;
+        }
+
+        Ok(VirtualSurroundEffectBuilder { filter })
+    }
+}
+
+impl EffectBuilder for VirtualSurroundEffectBuilder {
+    type Handle = VsfController;
+
+    fn build(self) -> (Box<dyn Effect>, VsfController) {
+        let sample_rate = self.filter.sample_rate();
+        let (processor, controller) = VsfProcessor::new(self.filter);
+        let effect = VirtualSurroundEffect {
+            processor: Mutex::new(processor),
+            adapter: BlockAdapter::new(2, BLOCK_SIZE, 1).expect("Stereo blocks are valid"),
+            sample_rate,
+            matching: true,
+        };
+
+        (Box::new(effect), controller)
+    }
+}
+
+struct VirtualSurroundEffect {
+    /// kira wants effects to be `Sync`, the lock is never taken since processing has `&mut self`
+    processor: Mutex<VsfProcessor>,
+    /// kira processes a frame at a time
+    adapter: BlockAdapter,
+    sample_rate: usize,
+    /// the track runs at the sample rate of the filter
+    matching: bool,
+}
+
+impl Effect for VirtualSurroundEffect {
+    fn init(&mut self, sample_rate: u32) {
+        self.on_change_sample_rate(sample_rate);
+    }
+
+    fn on_change_sample_rate(&mut self, sample_rate: u32) {
+        self.matching = sample_rate as usize == self.sample_rate;
+        self.adapter.reset();
+    }
+
+    fn process(
+        &mut self,
+        input: Frame,
+        _dt: f64,
+        _clock_info_provider: &ClockInfoProvider,
+        _modulator_value_provider: &ModulatorValueProvider,
+    ) -> Frame {
+        let processor = match self.processor.get_mut() {
+            Ok(processor) if self.matching => processor,
+            _ => return input,
+        };
+
+        let mut output = [0f32; 2];
+        let rendered =
+            self.adapter
+                .process(&[input.left, input.right], &mut output, |input, output| {
+                    processor.transform(input, output)
+                });
+
+        match rendered {
+            Ok(()) => Frame::new(output[0], output[1]),
+            Err(_) => Frame::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VirtualSurroundEffectBuilder;
+    use crate::hrir::{synthesize, KEMAR_HEAD_RADIUS_CM};
+    use crate::{FilterBuilder, Speaker, BLOCK_SIZE};
+    use kira::clock::clock_info::MockClockInfoProviderBuilder;
+    use kira::dsp::Frame;
+    use kira::modulator::value_provider::MockModulatorValueProviderBuilder;
+    use kira::track::effect::EffectBuilder;
+
+    #[test]
+    fn virtualizes_the_track_frame_by_frame() {
+        let layout = [
+            Speaker::FrontCenter,
+            Speaker::FrontLeft,
+            Speaker::FrontRight,
+        ];
+        let filter = FilterBuilder::new()
+            .build_from_hrir(synthesize(&layout, KEMAR_HEAD_RADIUS_CM, 48000).unwrap())
+            .unwrap();
+        let (mut effect, mut controller) =
+            VirtualSurroundEffectBuilder::new(filter).unwrap().build();
+        let clocks = MockClockInfoProviderBuilder::new(0).build();
+        let modulators = MockModulatorValueProviderBuilder::new(0).build();
+        let process = |effect: &mut Box<dyn kira::track::effect::Effect>, frame| {
+            effect.process(frame, 1.0 / 48000.0, &clocks, &modulators)
+        };
+        effect.init(48000);
+
+        // a click on the left of the track comes out a block later, louder on the left
+        let click = BLOCK_SIZE * 2;
+        let mut output = vec![];
+        for s in 0..click + BLOCK_SIZE + 256 {
+            let input = if s == click {
+                Frame::new(1.0, 0.0)
+            } else {
+                Frame::ZERO
+            };
+            output.push(process(&mut effect, input));
+        }
+        let onset = output.iter().position(|x| x.left.abs() > 1e-3).unwrap();
+        assert!((click + BLOCK_SIZE - 1..click + BLOCK_SIZE + 64).contains(&onset));
+        let energy = |ear: fn(&Frame) -> f32| output.iter().map(|x| ear(x).powi(2)).sum::<f32>();
+        assert!(energy(|x| x.left) > energy(|x| x.right) * 2.0);
+
+        // bypassed the track goes through as it is
+        controller.set_bypass(true).unwrap();
+        let mut bypassed = vec![];
+        for s in 0..BLOCK_SIZE * 8 {
+            let input = Frame::new((s % 7) as f32 / 7.0, 0.0);
+            bypassed.push(process(&mut effect, input));
+        }
+        let last = bypassed[BLOCK_SIZE * 8 - 1];
+        assert!(last.left != 0.0 && last.right.abs() < 1e-6);
+
+        // at another rate it passes the track through
+        effect.on_change_sample_rate(44100);
+        let frame = Frame::new(0.25, -0.5);
+        assert_eq!(process(&mut effect, frame), frame);
+    }
+}
@@ -1,43 +1,188 @@
-use bwavfile::{CommonFormat, WaveFmt, WaveReader};
+use bwavfile::{CommonFormat, WaveFmt};
 use std::io::{Read, Seek};
 
 pub use bwavfile::ChannelMask;
-use std::convert::{TryFrom, TryInto};
+use std::any::Any;
+use std::convert::TryFrom;
 use std::fmt::{Debug, Formatter};
-
+use std::sync::Arc;
+use std::time::Instant;
+
+#[macro_use]
+mod trace;
+
+mod adapter;
+mod ambisonic;
+mod bass;
+mod biquad;
+mod blend;
+mod builder;
+mod calibration;
+mod capabilities;
+mod coloration;
+mod config;
+mod dcblock;
+mod diagnostics;
+mod distance;
+mod drift;
+mod dry;
+mod dsp;
+mod engine;
+mod eq;
+mod fixed;
+mod half;
+pub mod hrir;
+pub mod hrtf;
+mod kernel;
+#[cfg(feature = "kira")]
+mod kira_effect;
+mod load;
+mod loudness;
+mod matrix;
+mod meter;
+mod nearfield;
+mod output;
+mod overlap;
+mod partitioned;
+mod planner;
+mod prepared;
+mod profile;
+mod protection;
+mod raw;
+mod realtime;
+mod recenter;
+mod reference;
+mod resample;
+mod reverb;
+#[cfg(feature = "rodio")]
+mod rodio_source;
+mod room;
+mod rotation;
 #[cfg(feature = "rustfft")]
 mod rustfft;
-
+mod scene;
+mod smooth;
+mod speaker;
+mod spectrum;
+mod state;
+mod testsignal;
+mod tracking;
+mod transaural;
+mod upmix;
+mod wav;
+
+pub use crate::adapter::BlockAdapter;
+pub use crate::ambisonic::{
+    ambisonic_channels, AmbisonicFormat, AmbisonicVirtualizer, MAX_AMBISONIC_ORDER,
+};
+pub use crate::bass::BassManagement;
+use crate::bass::BassManager;
+use crate::blend::BlendProcessor;
+pub use crate::blend::{BlendBand, BlendPreset};
+pub use crate::builder::*;
+pub use crate::calibration::{CalibratedTracker, CalibrationStep, Calibrator, TrackerCalibration};
+pub use crate::capabilities::{capabilities, Capabilities};
+pub use crate::coloration::{ColorationAnalyzer, ColorationBand, ColorationReport};
+pub use crate::config::FilterConfig;
+use crate::dcblock::DcBlocker;
+pub use crate::dcblock::DEFAULT_DC_BLOCK_HZ;
+pub use crate::diagnostics::{Diagnostic, Diagnostics};
+use crate::distance::SpeakerDistances;
+pub use crate::distance::REFERENCE_DISTANCE;
+pub use crate::drift::DriftCompensator;
+use crate::dry::DryPath;
+pub use crate::engine::*;
+use crate::eq::EqProcessor;
+pub use crate::eq::{EqBand, HeadphoneEq};
+#[cfg(feature = "fixed-point")]
+pub use crate::fixed::FixedPointLogic;
+use crate::hrir::Hrir;
+#[cfg(feature = "kira")]
+pub use crate::kira_effect::VirtualSurroundEffectBuilder;
+use crate::load::LoadTiming;
+pub use crate::load::{DspLoad, LoadSnapshot};
+use crate::loudness::LoudnessMatcher;
+pub use crate::loudness::{LoudnessMeter, LoudnessReading};
+pub use crate::matrix::MixingMatrix;
+use crate::meter::Metering;
+pub use crate::meter::{Level, MeterSnapshot, Meters};
+use crate::nearfield::{apply_near_field, near_field_gains};
+pub use crate::output::{IntegerSample, NoiseShaping, PcmConverter, I24};
+#[cfg(feature = "rustfft")]
+pub use crate::overlap::{ConvolutionStrategy, OverlapMethod, SpectrumPrecision, UniformLogic};
+pub use crate::partitioned::{PartitionedLogic, TailScheduling};
+use crate::profile::ProfileSwitch;
+#[cfg(feature = "rustfft")]
+pub use crate::profile::DEFAULT_PROFILE;
+pub use crate::protection::OutputProtection;
+use crate::protection::OutputProtector;
+pub use crate::raw::{RawStreamHeader, RawStreamReader, RAW_STREAM_MAGIC};
+pub use crate::realtime::{VsfController, VsfProcessor};
+pub use crate::recenter::{AutoRecenter, RecenterTrigger, Recentering, RecenteringTracker};
+#[cfg(feature = "reference")]
+pub use crate::reference::{reference_error, ReferenceLogic};
+pub use crate::resample::ResampleQuality;
+use crate::reverb::FdnReverb;
+pub use crate::reverb::Reverb;
+#[cfg(feature = "rodio")]
+pub use crate::rodio_source::VirtualSurroundSource;
+pub use crate::room::RoomModel;
+use crate::rotation::SpeakerRotation;
 #[cfg(feature = "rustfft")]
 pub use crate::rustfft::*;
-use anyhow::Context;
-use samplerate::ConverterType;
-
-// "biggest" surround sound system is 22.2
-// so 24 should be enough, for now
+pub use crate::scene::{BinauralScene, SourceHandle, SourcePosition};
+use crate::smooth::ramp_samples;
+pub use crate::smooth::Smoothed;
+pub use crate::speaker::{layout_from_mask, standard_layout, Speaker};
+use crate::spectrum::SpectrumAnalyzer;
+pub use crate::spectrum::{Spectrum, SpectrumTap};
+pub use crate::state::FilterState;
+pub use crate::testsignal::{generate_test_signal, SignalKind};
+pub use crate::tracking::*;
+use crate::transaural::CrosstalkCanceller;
+pub use crate::transaural::Transaural;
+pub use crate::upmix::Upmix;
+use crate::upmix::Upmixer;
+
+// "biggest" surround sound system is 22.2, so 24 should be enough for now. Only a limit, the
+// buffers are sized from the channels of the HRIR.
 pub const MAX_CHANNELS: usize = 24;
 
 pub const BLOCK_SIZE: usize = 512;
 
+/// input below -120dBFS counts as silence for the state audit
+const AUDIT_SILENCE: f32 = 1e-6;
+/// default ramp from silence when output starts, see [`VirtualSurroundFilter::set_fade_in`]
+const FADE_IN_MS: f32 = 10.0;
+
+/// sample formats HRIR wavs can be read from, they're converted to float
 #[derive(Debug, Copy, Clone)]
 pub enum SampleFormat {
     F32,
+    /// integer PCM, e.g. the 16 bit MIT KEMAR measurements
+    Int(u16),
 }
 
-pub fn mirror_channel(channel: ChannelMask) -> ChannelMask {
+pub fn mirror_channel(channel: Speaker) -> Speaker {
     match channel {
-        ChannelMask::FrontLeft => ChannelMask::FrontRight,
-        ChannelMask::FrontRight => ChannelMask::FrontLeft,
-        ChannelMask::BackLeft => ChannelMask::BackRight,
-        ChannelMask::BackRight => ChannelMask::BackLeft,
-        ChannelMask::FrontCenterLeft => ChannelMask::FrontCenterRight,
-        ChannelMask::FrontCenterRight => ChannelMask::FrontCenterLeft,
-        ChannelMask::SideLeft => ChannelMask::SideRight,
-        ChannelMask::SideRight => ChannelMask::SideLeft,
-        ChannelMask::TopFrontLeft => ChannelMask::TopFrontRight,
-        ChannelMask::TopFrontRight => ChannelMask::TopFrontLeft,
-        ChannelMask::TopBackLeft => ChannelMask::TopBackRight,
-        ChannelMask::TopBackRight => ChannelMask::TopBackLeft,
+        Speaker::FrontLeft => Speaker::FrontRight,
+        Speaker::FrontRight => Speaker::FrontLeft,
+        Speaker::BackLeft => Speaker::BackRight,
+        Speaker::BackRight => Speaker::BackLeft,
+        Speaker::FrontCenterLeft => Speaker::FrontCenterRight,
+        Speaker::FrontCenterRight => Speaker::FrontCenterLeft,
+        Speaker::SideLeft => Speaker::SideRight,
+        Speaker::SideRight => Speaker::SideLeft,
+        Speaker::TopFrontLeft => Speaker::TopFrontRight,
+        Speaker::TopFrontRight => Speaker::TopFrontLeft,
+        Speaker::TopBackLeft => Speaker::TopBackRight,
+        Speaker::TopBackRight => Speaker::TopBackLeft,
+        Speaker::FrontWideLeft => Speaker::FrontWideRight,
+        Speaker::FrontWideRight => Speaker::FrontWideLeft,
+        Speaker::TopSideLeft => Speaker::TopSideRight,
+        Speaker::TopSideRight => Speaker::TopSideLeft,
+        Speaker::BottomFrontLeft => Speaker::BottomFrontRight,
+        Speaker::BottomFrontRight => Speaker::BottomFrontLeft,
 
         // center channels
         center => center,
@@ -50,6 +195,7 @@ impl TryFrom<WaveFmt> for SampleFormat {
     fn try_from(value: WaveFmt) -> Result<Self, Self::Error> {
         match (value.common_format(), value.bits_per_sample) {
             (CommonFormat::IeeeFloatPCM, 32) => Ok(SampleFormat::F32),
+            (CommonFormat::IntegerPCM, bits @ (16 | 24 | 32)) => Ok(SampleFormat::Int(bits)),
             (format, bits) => {
                 anyhow::bail!(
                     "VirtualSurround doesn't currently support {:?} at {} bits",
@@ -61,57 +207,169 @@ impl TryFrom<WaveFmt> for SampleFormat {
     }
 }
 
-#[derive(Copy, Clone)]
+/// smallest power of two window which fits an impulse response of `samples` and a block
+pub(crate) fn fft_len_for(samples: usize) -> usize {
+    let goal = samples + BLOCK_SIZE + 1;
+    let mut i = 5;
+    let mut m = 0usize;
+    while m < goal {
+        i += 1;
+        m = 2usize.pow(i);
+    }
+
+    m
+}
+
+/// Render a block. With the `assert-no-alloc` feature debug builds abort when it allocates or
+/// frees, buffers are checked before so only a bug fails in here.
+#[inline]
+pub(crate) fn no_alloc<T, F: FnOnce() -> T>(render: F) -> T {
+    #[cfg(feature = "assert-no-alloc")]
+    {
+        assert_no_alloc::assert_no_alloc(render)
+    }
+
+    #[cfg(not(feature = "assert-no-alloc"))]
+    {
+        render()
+    }
+}
+
+/// let `work` allocate inside [`no_alloc`]
+#[cfg(feature = "parallel")]
+#[inline]
+fn permit_alloc<T, F: FnOnce() -> T>(work: F) -> T {
+    #[cfg(feature = "assert-no-alloc")]
+    {
+        assert_no_alloc::permit_alloc(work)
+    }
+
+    #[cfg(not(feature = "assert-no-alloc"))]
+    {
+        work()
+    }
+}
+
+#[derive(Clone)]
 struct ChannelMap {
     channels: usize,
-    map: [ChannelMask; MAX_CHANNELS],
+    map: Vec<Speaker>,
 }
 
-pub fn get_channel_name(mask: ChannelMask) -> &'static str {
+/// (left, right) gains of a speaker in a plain stereo downmix
+pub fn stereo_downmix_gains(mask: Speaker) -> (f32, f32) {
+    const HALF: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
     match mask {
-        ChannelMask::DirectOut => "NA",
-        ChannelMask::FrontLeft => "FL",
-        ChannelMask::FrontRight => "FR",
-        ChannelMask::FrontCenter => "FC",
-        ChannelMask::LowFrequency => "LFE",
-        ChannelMask::BackLeft => "RL",
-        ChannelMask::BackRight => "RR",
-        ChannelMask::FrontCenterLeft => "FLC",
-        ChannelMask::FrontCenterRight => "FRC",
-        ChannelMask::BackCenter => "RC",
-        ChannelMask::SideLeft => "SL",
-        ChannelMask::SideRight => "SR",
-        ChannelMask::TopCenter => "TC",
-        ChannelMask::TopFrontLeft => "TFL",
-        ChannelMask::TopFrontCenter => "TFC",
-        ChannelMask::TopFrontRight => "TFR",
-        ChannelMask::TopBackLeft => "TRL",
-        ChannelMask::TopBackCenter => "TRC",
-        ChannelMask::TopBackRight => "RTR",
+        Speaker::FrontLeft | Speaker::FrontCenterLeft | Speaker::FrontWideLeft => (1.0, 0.0),
+        Speaker::FrontRight | Speaker::FrontCenterRight | Speaker::FrontWideRight => (0.0, 1.0),
+        Speaker::FrontCenter
+        | Speaker::TopCenter
+        | Speaker::TopFrontCenter
+        | Speaker::BottomFrontCenter => (HALF, HALF),
+        Speaker::BackLeft
+        | Speaker::SideLeft
+        | Speaker::TopFrontLeft
+        | Speaker::TopBackLeft
+        | Speaker::TopSideLeft
+        | Speaker::BottomFrontLeft => (HALF, 0.0),
+        Speaker::BackRight
+        | Speaker::SideRight
+        | Speaker::TopFrontRight
+        | Speaker::TopBackRight
+        | Speaker::TopSideRight
+        | Speaker::BottomFrontRight => (0.0, HALF),
+        Speaker::BackCenter | Speaker::TopBackCenter => (0.5, 0.5),
+        Speaker::LowFrequency | Speaker::LowFrequency2 | Speaker::DirectOut => (0.0, 0.0),
     }
 }
 
-impl ChannelMap {
-    pub fn from_iter<I: Iterator<Item = ChannelMask>>(iter: I) -> anyhow::Result<ChannelMap> {
-        let mut channels: usize = 0;
-        let mut map = [ChannelMask::DirectOut; MAX_CHANNELS];
+pub fn get_channel_name(mask: Speaker) -> &'static str {
+    match mask {
+        Speaker::DirectOut => "NA",
+        Speaker::FrontLeft => "FL",
+        Speaker::FrontRight => "FR",
+        Speaker::FrontCenter => "FC",
+        Speaker::LowFrequency => "LFE",
+        Speaker::BackLeft => "RL",
+        Speaker::BackRight => "RR",
+        Speaker::FrontCenterLeft => "FLC",
+        Speaker::FrontCenterRight => "FRC",
+        Speaker::BackCenter => "RC",
+        Speaker::SideLeft => "SL",
+        Speaker::SideRight => "SR",
+        Speaker::TopCenter => "TC",
+        Speaker::TopFrontLeft => "TFL",
+        Speaker::TopFrontCenter => "TFC",
+        Speaker::TopFrontRight => "TFR",
+        Speaker::TopBackLeft => "TRL",
+        Speaker::TopBackCenter => "TRC",
+        Speaker::TopBackRight => "RTR",
+        Speaker::FrontWideLeft => "FLW",
+        Speaker::FrontWideRight => "FRW",
+        Speaker::TopSideLeft => "TSL",
+        Speaker::TopSideRight => "TSR",
+        Speaker::BottomFrontLeft => "BFL",
+        Speaker::BottomFrontCenter => "BFC",
+        Speaker::BottomFrontRight => "BFR",
+        Speaker::LowFrequency2 => "LFE2",
+    }
+}
 
-        for mask in iter {
-            if channels >= MAX_CHANNELS {
-                anyhow::bail!(
-                    "Iterator returns more channels than supported ({})",
-                    MAX_CHANNELS
-                );
-            }
+/// inverse of [`get_channel_name`], also takes the BL/BR spelling of the back pair
+pub fn channel_from_name(name: &str) -> Option<Speaker> {
+    let mask = match name.to_ascii_uppercase().as_str() {
+        "FL" => Speaker::FrontLeft,
+        "FR" => Speaker::FrontRight,
+        "FC" => Speaker::FrontCenter,
+        "LFE" => Speaker::LowFrequency,
+        "RL" | "BL" => Speaker::BackLeft,
+        "RR" | "BR" => Speaker::BackRight,
+        "FLC" => Speaker::FrontCenterLeft,
+        "FRC" => Speaker::FrontCenterRight,
+        "RC" | "BC" => Speaker::BackCenter,
+        "SL" => Speaker::SideLeft,
+        "SR" => Speaker::SideRight,
+        "TC" => Speaker::TopCenter,
+        "TFL" => Speaker::TopFrontLeft,
+        "TFC" => Speaker::TopFrontCenter,
+        "TFR" => Speaker::TopFrontRight,
+        "TRL" | "TBL" => Speaker::TopBackLeft,
+        "TRC" | "TBC" => Speaker::TopBackCenter,
+        "RTR" | "TRR" | "TBR" => Speaker::TopBackRight,
+        "FLW" | "WL" => Speaker::FrontWideLeft,
+        "FRW" | "WR" => Speaker::FrontWideRight,
+        "TSL" => Speaker::TopSideLeft,
+        "TSR" => Speaker::TopSideRight,
+        "BFL" => Speaker::BottomFrontLeft,
+        "BFC" => Speaker::BottomFrontCenter,
+        "BFR" => Speaker::BottomFrontRight,
+        "LFE2" => Speaker::LowFrequency2,
+        "NA" => Speaker::DirectOut,
+        _ => return None,
+    };
+
+    Some(mask)
+}
 
-            map[channels] = mask;
-            channels += 1;
+impl ChannelMap {
+    pub fn from_iter<I: Iterator<Item = Speaker>>(iter: I) -> anyhow::Result<ChannelMap> {
+        let map = iter.collect::<Vec<_>>();
+
+        if map.len() > MAX_CHANNELS {
+            anyhow::bail!(
+                "Iterator returns more channels than supported ({})",
+                MAX_CHANNELS
+            );
         }
 
-        Ok(ChannelMap { channels, map })
+        Ok(ChannelMap {
+            channels: map.len(),
+            map,
+        })
     }
 
-    pub fn find(&self, channel: ChannelMask) -> Option<usize> {
+    pub fn find(&self, channel: Speaker) -> Option<usize> {
         for i in 0..self.channels {
             if self.map[i] == channel {
                 return Some(i);
@@ -121,9 +379,38 @@ impl ChannelMap {
         None
     }
 
-    pub fn find_mirror(&self, channel: ChannelMask) -> Option<usize> {
+    pub fn find_mirror(&self, channel: Speaker) -> Option<usize> {
         self.find(mirror_channel(channel))
     }
+
+    /// gain per channel for a mono source, the center speaker if there is one, otherwise a
+    /// phantom center between the front pair, otherwise spread over every non LFE speaker
+    pub fn mono_gains(&self) -> Vec<f32> {
+        let mut gains = vec![0f32; self.channels];
+
+        if let Some(center) = self.find(Speaker::FrontCenter) {
+            gains[center] = 1.0;
+        } else if let (Some(left), Some(right)) = (
+            self.find(Speaker::FrontLeft),
+            self.find(Speaker::FrontRight),
+        ) {
+            gains[left] = std::f32::consts::FRAC_1_SQRT_2;
+            gains[right] = std::f32::consts::FRAC_1_SQRT_2;
+        } else {
+            let speakers = self.map[..self.channels]
+                .iter()
+                .filter(|x| !x.is_lfe())
+                .count();
+
+            for (gain, position) in gains.iter_mut().zip(&self.map) {
+                if !position.is_lfe() {
+                    *gain = 1.0 / (speakers as f32).sqrt();
+                }
+            }
+        }
+
+        gains
+    }
 }
 
 impl Debug for ChannelMap {
@@ -135,154 +422,471 @@ impl Debug for ChannelMap {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MismatchReport {
+    /// (filter, content) sample rates, if they differ
+    pub sample_rate: Option<(usize, usize)>,
+    /// channels in the content the filter has no HRIR for
+    pub missing: Vec<Speaker>,
+    /// channels the filter expects which aren't in the content
+    pub unused: Vec<Speaker>,
+    /// all channels are present, but not in the order the filter expects
+    pub reordered: bool,
+}
+
+impl MismatchReport {
+    fn new(rate: usize, map: &ChannelMap, content_rate: usize, layout: &[Speaker]) -> Self {
+        let positions = &map.map[..map.channels];
+
+        MismatchReport {
+            sample_rate: if rate == content_rate {
+                None
+            } else {
+                Some((rate, content_rate))
+            },
+            missing: layout
+                .iter()
+                .copied()
+                .filter(|x| map.find(*x).is_none())
+                .collect(),
+            unused: positions
+                .iter()
+                .copied()
+                .filter(|x| !layout.contains(x))
+                .collect(),
+            reordered: positions.len() == layout.len()
+                && positions.iter().all(|x| layout.contains(x))
+                && positions != layout,
+        }
+    }
+
+    pub fn is_match(&self) -> bool {
+        self.sample_rate.is_none()
+            && self.missing.is_empty()
+            && self.unused.is_empty()
+            && !self.reordered
+    }
+}
+
+impl std::fmt::Display for MismatchReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let names = |list: &[Speaker]| {
+            list.iter()
+                .map(|x| get_channel_name(*x))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let mut problems = vec![];
+
+        if let Some((filter, content)) = self.sample_rate {
+            problems.push(format!(
+                "content is {}hz but the filter runs at {}hz",
+                content, filter
+            ));
+        }
+
+        if !self.missing.is_empty() {
+            problems.push(format!("filter has no HRIR for {}", names(&self.missing)));
+        }
+
+        if !self.unused.is_empty() {
+            problems.push(format!("content doesn't contain {}", names(&self.unused)));
+        }
+
+        if self.reordered {
+            problems.push("content channel order differs from the filter".to_string());
+        }
+
+        write!(f, "{}", problems.join(", "))
+    }
+}
+
+impl std::error::Error for MismatchReport {}
+
+/// copy the left (`offset` 0) or right samples of an interleaved `stereo` block into `column` of
+/// the interleaved `output` with `channels` per frame
+fn write_column(output: &mut [f32], channels: usize, column: usize, stereo: &[f32], offset: usize) {
+    for (frame, sample) in output
+        .chunks_exact_mut(channels)
+        .zip(stereo.iter().skip(offset).step_by(2))
+    {
+        frame[column] = *sample;
+    }
+}
+
 #[derive(Debug)]
-pub struct VirtualSurroundFilter<T: FFTLogic = CurrentFFTLogic> {
-    inner: RawVirtualSurroundFilter<T>,
+pub struct VirtualSurroundFilter {
+    inner: RawVirtualSurroundFilter,
     available_data: usize,
     left_out_space: Vec<f32>,
     right_out_space: Vec<f32>,
-    in_space: [Vec<f32>; MAX_CHANNELS],
+    in_space: Vec<Vec<f32>>,
+    mono_gains: Vec<f32>,
+    expand_space: Vec<f32>,
+    matrix: Option<MixingMatrix>,
+    matrix_space: Vec<f32>,
+    /// gains the matrix crossfades from as the fade ramps to 1
+    matrix_previous: Vec<f32>,
+    matrix_fade: Smoothed,
+    upmix: Option<Upmix>,
+    upmixer: Upmixer,
+    rotation: SpeakerRotation,
+    distances: SpeakerDistances,
+    bass: Option<BassManager>,
+    eq: Option<EqProcessor>,
+    blend: Option<BlendProcessor>,
+    reverb: Option<FdnReverb>,
+    reverb_sends: Vec<f32>,
+    reverb_space: Vec<f32>,
+    dc_block: Option<DcBlocker>,
+    transaural: Option<CrosstalkCanceller>,
+    dry: DryPath,
+    mix: f32,
+    bypass: bool,
+    /// effective mix, 0.0 when bypassed
+    mix_ramp: Smoothed,
+    /// samples parameter changes ramp over
+    ramp: usize,
+    loudness: Option<LoudnessMatcher>,
+    /// make-up gain of the downmix towards the virtualized output, see
+    /// [`set_bypass_level_matching`](VirtualSurroundFilter::set_bypass_level_matching)
+    bypass_matching: Option<LoudnessMatcher>,
+    coloration: Option<ColorationAnalyzer>,
+    metering: Option<Metering>,
+    timing: Option<LoadTiming>,
+    spectrum: Option<SpectrumAnalyzer>,
+    protection: OutputProtector,
+    virtualized: Option<Vec<bool>>,
+    silence: Vec<f32>,
+    passthrough_space: Vec<f32>,
+    passthrough_protection: Vec<OutputProtector>,
+    /// input channels passed through and their output columns, the front pair first
+    passthrough_columns: Vec<(usize, usize)>,
+    state_audit: bool,
+    silent_frames: usize,
+    state_resets: usize,
+    pcm: PcmConverter,
+    pcm_input: Vec<f32>,
+    chunk_space: Vec<f32>,
+    chunk_fill: usize,
+    fade_in: usize,
+    /// frames of the fade-in output so far
+    faded: usize,
+    /// frames of silence fed by [`drain`](VirtualSurroundFilter::drain) since the last input
+    drained: usize,
+    low_latency: bool,
+    /// HRIR profiles by name in the order they were added, `None` in the slot of the active one
+    /// and of the one a switch fades out
+    profiles: Vec<(String, Option<RawVirtualSurroundFilter>)>,
+    profile: usize,
+    profile_switch: Option<ProfileSwitch>,
+    /// output of the profile a switch fades out, left then right
+    profile_space: Vec<f32>,
+}
+
+/// What a transform did with its output
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProcessStatus {
+    /// the input was buffered while the window is still filling, the output was zeroed
+    Priming,
+    /// a block of output was written
+    Rendered,
+}
+
+impl ProcessStatus {
+    /// frames written to the output
+    pub fn frames(&self) -> usize {
+        match self {
+            ProcessStatus::Priming => 0,
+            ProcessStatus::Rendered => BLOCK_SIZE,
+        }
+    }
+}
+
+/// Frames taken and written by [`transform_chunk`](VirtualSurroundFilter::transform_chunk)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ChunkStatus {
+    pub consumed: usize,
+    pub produced: usize,
 }
 
 #[derive(Debug)]
-pub struct RawVirtualSurroundFilter<T: FFTLogic = CurrentFFTLogic> {
+pub struct RawVirtualSurroundFilter {
     channel_map: ChannelMap,
     rate: usize,
-    format: SampleFormat,
-    fft_logic: T,
+    /// one per thread the channels are convolved on
+    workers: Vec<Worker>,
+    engine_factory: EngineFactory,
     fft_len: usize,
-    rev_space: Vec<f32>,
+    ir_delay: usize,
+    /// left and right impulse response per channel as loaded, for the near-field ones, shared
+    /// with the filters [`clone_fresh`](RawVirtualSurroundFilter::clone_fresh) made
+    irs: Arc<Vec<[Vec<f32>; 2]>>,
+    /// distance the impulse responses of every channel are loaded for
+    near_field: Vec<f32>,
 }
 
-impl RawVirtualSurroundFilter {
-    pub fn new<R: Read + Seek>(reader: R, sample_rate: Option<u32>) -> anyhow::Result<Self> {
-        if !cfg!(feature = "resample") && sample_rate.is_some() {
-            panic!("virtual-surround is compiled without resampling support, cannot request resampling");
-        }
+/// An engine convolving a share of the channels, with its own ear accumulators when there are
+/// more workers to merge with
+#[derive(Debug)]
+struct Worker {
+    engine: Box<dyn ConvolutionEngine>,
+    channels: Vec<usize>,
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
 
-        let mut item = WaveReader::new(reader)?;
+impl Worker {
+    #[cfg(feature = "parallel")]
+    fn process(&mut self, input: &[&[f32]]) -> anyhow::Result<()> {
+        self.left.fill(0f32);
+        self.right.fill(0f32);
 
-        let channels = item.channels()?;
+        self.engine
+            .process_channels(&self.channels, input, &mut self.left, &mut self.right)
+    }
+}
 
-        if channels.len() > MAX_CHANNELS {
-            anyhow::bail!("Input HRIR file has {} channels, VirtualSurroundFilter is compiled with only support for max {} channels", channels.len(), MAX_CHANNELS);
+impl RawVirtualSurroundFilter {
+    pub fn new<R: Read + Seek>(reader: R, sample_rate: Option<u32>) -> anyhow::Result<Self> {
+        let mut builder = FilterBuilder::new();
+        if let Some(sample_rate) = sample_rate {
+            builder = builder.sample_rate(sample_rate);
         }
 
-        let fmt = item.format()?;
-        let mut reader = item.audio_frame_reader()?;
-        let mut buffer = [0f32; MAX_CHANNELS];
+        builder.build_raw(reader)
+    }
 
-        let mut data = Vec::new();
+    pub fn from_hrir(hrir: &Hrir) -> anyhow::Result<Self> {
+        Self::from_hrir_with_engine(hrir, new_engine::<CurrentFFTLogic>)
+    }
 
-        let mut samples = 0;
-        while let Ok(1) = reader.read_float_frame(&mut buffer[..channels.len()]) {
-            data.extend_from_slice(&buffer[..channels.len()]);
-            samples += 1;
-        }
+    pub fn from_hrir_with_engine(hrir: &Hrir, engine: EngineFactory) -> anyhow::Result<Self> {
+        Self::from_hrir_with_threads(hrir, engine, 1)
+    }
 
-        let mut current_rate = fmt.sample_rate;
+    /// Convolve the channels on up to `threads` threads of the rayon pool, each with its own
+    /// engine, which pays off for 8 channels and up. More than one thread needs the `parallel`
+    /// feature.
+    pub fn from_hrir_with_threads(
+        hrir: &Hrir,
+        engine: EngineFactory,
+        threads: usize,
+    ) -> anyhow::Result<Self> {
+        let fft_len = fft_len_for(hrir.ir_length());
+
+        Self::from_irs(
+            hrir.positions(),
+            hrir.sample_rate,
+            hrir.speakers
+                .iter()
+                .map(|x| [x.left.clone(), x.right.clone()])
+                .collect(),
+            hrir.onset().min(fft_len - BLOCK_SIZE),
+            engine,
+            threads,
+            None,
+        )
+    }
 
-        #[cfg(feature = "resample")]
-        {
-            if let Some(target_sample_rate) = sample_rate {
-                if target_sample_rate != fmt.sample_rate {
-                    data = samplerate::convert(
-                        fmt.sample_rate,
-                        target_sample_rate as u32,
-                        channels.len(),
-                        ConverterType::SincBestQuality,
-                        &data,
-                    )?;
-
-                    samples = data.len() / channels.len();
-
-                    current_rate = target_sample_rate;
-                }
-            }
+    /// filter of the impulse responses of the speakers at `positions`, loading the engines with
+    /// `prepared` ones from [`ConvolutionEngine::export_ir`] where they take them
+    fn from_irs<I: Iterator<Item = Speaker>>(
+        positions: I,
+        sample_rate: u32,
+        irs: Vec<[Vec<f32>; 2]>,
+        ir_delay: usize,
+        engine: EngineFactory,
+        threads: usize,
+        prepared: Option<&[Vec<f32>]>,
+    ) -> anyhow::Result<Self> {
+        if threads > 1 && !cfg!(feature = "parallel") {
+            anyhow::bail!("virtual-surround is compiled without the parallel feature, cannot convolve on {} threads", threads);
         }
 
-        normalize_hrir(&mut data, samples, channels.len());
-
-        let fft_len: usize = {
-            let goal = samples + BLOCK_SIZE + 1;
-            let mut i = 5;
-            let mut m = 0usize;
-            while m < goal {
-                i += 1;
-                m = 2usize.pow(i);
-            }
-
-            m
-        };
-
-        let channel_map = ChannelMap::from_iter(channels.iter().map(|x| x.speaker))?;
+        let samples = irs.first().map_or(0, |x| x[0].len());
 
-        let mut fft_logic: CurrentFFTLogic = FFTLogic::new(channels.len(), fft_len);
+        let fft_len = fft_len_for(samples);
 
-        let rev_space = vec![0f32; fft_len];
+        let channel_map = ChannelMap::from_iter(positions)?;
 
-        let mut channels_left = [0; MAX_CHANNELS];
-        let mut channels_right = [0; MAX_CHANNELS];
+        let engine_factory = engine;
+        let threads = threads.clamp(1, channel_map.channels);
+        let mut workers = vec![];
 
-        for i in 0..channel_map.channels {
-            channels_left[i] = i;
-            channels_right[i] = channel_map
-                .find_mirror(channel_map.map[i])
-                .with_context(|| {
-                    format!(
-                        "hrir file isn't symmetrical can't find the mirrored side of {:?}",
-                        channel_map.map[i]
-                    )
-                })?;
+        for thread in 0..threads {
+            workers.push(Worker {
+                engine: engine_factory(channel_map.channels, fft_len)?,
+                channels: (thread..channel_map.channels).step_by(threads).collect(),
+                left: vec![0f32; BLOCK_SIZE],
+                right: vec![0f32; BLOCK_SIZE],
+            });
         }
 
+        // the impulse responses each engine transforms, together so they can go side by side
+        #[cfg(feature = "parallel")]
+        let mut pending = vec![vec![]; threads];
+        #[cfg(not(feature = "parallel"))]
         let mut impulse_temp = vec![0f32; fft_len];
 
-        for i in 0..channels.len() {
-            for ear in [0, 1] {
-                let index = (i * 2) + ear;
-                let impulse_index = if ear == 0 {
-                    channels_left[i]
-                } else {
-                    channels_right[i]
-                };
+        for (i, speaker) in irs.iter().enumerate() {
+            let worker = &mut workers[i % threads];
+
+            for (ear, impulse) in speaker.iter().enumerate() {
+                let ir_index = (i * 2) + ear;
+                if let Some(prepared) = prepared {
+                    if worker
+                        .engine
+                        .import_ir(&prepared[ir_index], ir_index)
+                        .is_ok()
+                    {
+                        continue;
+                    }
+                }
+
+                #[cfg(feature = "parallel")]
+                {
+                    let mut impulse_temp = vec![0f32; fft_len];
+                    impulse_temp[..samples].copy_from_slice(impulse);
 
-                for j in 0..samples {
-                    impulse_temp[j] = data[(j * channels.len()) + impulse_index];
+                    pending[i % threads].push((ir_index, impulse_temp));
                 }
 
-                fft_logic.init_ir(&mut impulse_temp, index)?;
+                #[cfg(not(feature = "parallel"))]
+                {
+                    impulse_temp.fill(0f32);
+                    impulse_temp[..samples].copy_from_slice(impulse);
+
+                    worker.engine.init_ir(&impulse_temp, ir_index)?;
+                }
             }
         }
 
+        #[cfg(feature = "parallel")]
+        for (worker, impulses) in workers.iter_mut().zip(&pending) {
+            worker.engine.init_irs(impulses)?;
+        }
+
         Ok(RawVirtualSurroundFilter {
             channel_map,
-            rate: current_rate as usize,
-            format: fmt.try_into()?,
-            fft_logic,
+            rate: sample_rate as usize,
+            workers,
+            engine_factory,
             fft_len,
-            rev_space,
+            ir_delay,
+            near_field: vec![REFERENCE_DISTANCE; irs.len()],
+            irs: Arc::new(irs),
         })
     }
 
     pub fn transform(
         &mut self,
-        input: &mut [&mut [f32]],
+        input: &[&[f32]],
         output: (&mut [f32], &mut [f32]),
     ) -> anyhow::Result<()> {
-        for channel in 0..self.channel_map.channels {
-            self.fft_logic.process_channel(
-                channel,
-                &mut input[channel],
-                &mut self.rev_space,
-                output.0,
-                output.1,
-            )?;
+        if let [worker] = self.workers.as_mut_slice() {
+            return worker
+                .engine
+                .process_channels(&worker.channels, input, output.0, output.1);
+        }
+
+        #[cfg(feature = "parallel")]
+        {
+            use rayon::prelude::*;
+
+            // handing the workers to the pool may allocate, which is up to rayon
+            permit_alloc(|| {
+                self.workers
+                    .par_iter_mut()
+                    .try_for_each(|worker| worker.process(input))
+            })?;
+        }
+
+        for worker in &self.workers {
+            for (out, sample) in output.0.iter_mut().zip(&worker.left) {
+                *out += sample;
+            }
+
+            for (out, sample) in output.1.iter_mut().zip(&worker.right) {
+                *out += sample;
+            }
         }
 
         Ok(())
     }
 
+    /// reload the impulse responses of `channel` with the near-field gains of a speaker `meters`
+    /// away, the loaded ones from the reference distance on
+    pub(crate) fn set_near_field(&mut self, channel: usize, meters: f32) -> anyhow::Result<()> {
+        self.near_field[channel] = meters;
+        let threads = self.workers.len();
+        let engine = &mut self.workers[channel % threads].engine;
+
+        load_near_field(
+            engine.as_mut(),
+            channel,
+            &self.irs[channel],
+            near_field_gains(self.channel_map.map[channel], meters),
+            self.rate,
+            self.fft_len,
+        )
+    }
+
+    /// Filter with the impulse responses and near-field distances of this one and none of its
+    /// state, for another stream. Engines which can share the spectra they made of the impulse
+    /// responses do, see [`ConvolutionEngine::clone_fresh`], so every clone costs the engines'
+    /// working space rather than another copy of the HRIR.
+    pub fn clone_fresh(&self) -> anyhow::Result<Self> {
+        let mut workers = vec![];
+        for worker in &self.workers {
+            let engine = match worker.engine.clone_fresh() {
+                Some(engine) => engine,
+                None => {
+                    let mut engine = (self.engine_factory)(self.channels(), self.fft_len)?;
+                    for channel in &worker.channels {
+                        load_near_field(
+                            engine.as_mut(),
+                            *channel,
+                            &self.irs[*channel],
+                            near_field_gains(
+                                self.channel_map.map[*channel],
+                                self.near_field[*channel],
+                            ),
+                            self.rate,
+                            self.fft_len,
+                        )?;
+                    }
+
+                    engine
+                }
+            };
+
+            workers.push(Worker {
+                engine,
+                channels: worker.channels.clone(),
+                left: vec![0f32; BLOCK_SIZE],
+                right: vec![0f32; BLOCK_SIZE],
+            });
+        }
+
+        Ok(RawVirtualSurroundFilter {
+            channel_map: self.channel_map.clone(),
+            rate: self.rate,
+            workers,
+            engine_factory: self.engine_factory,
+            fft_len: self.fft_len,
+            ir_delay: self.ir_delay,
+            irs: self.irs.clone(),
+            near_field: self.near_field.clone(),
+        })
+    }
+
+    /// threads the channels are convolved on
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
     pub fn samples_required(&self) -> usize {
         self.fft_len
     }
@@ -292,7 +896,42 @@ impl RawVirtualSurroundFilter {
     }
 
     pub fn sample_latency(&self) -> usize {
-        self.fft_len - BLOCK_SIZE
+        self.workers[0].engine.latency()
+    }
+
+    pub fn reset(&mut self) {
+        for worker in &mut self.workers {
+            worker.engine.reset();
+        }
+    }
+
+    /// [`ConvolutionEngine::save_state`] of every worker's engine
+    pub(crate) fn save_state(&self) -> Vec<Option<Box<dyn Any + Send>>> {
+        self.workers.iter().map(|x| x.engine.save_state()).collect()
+    }
+
+    pub(crate) fn load_state(
+        &mut self,
+        state: &[Option<Box<dyn Any + Send>>],
+    ) -> anyhow::Result<()> {
+        if state.len() != self.workers.len() {
+            anyhow::bail!(
+                "State was saved with {} threads, the filter runs {}",
+                state.len(),
+                self.workers.len()
+            );
+        }
+
+        for (worker, state) in self.workers.iter_mut().zip(state) {
+            worker.engine.load_state(state.as_deref())?;
+        }
+
+        Ok(())
+    }
+
+    /// samples before the earliest onset in the HRIR
+    pub fn ir_delay(&self) -> usize {
+        self.ir_delay
     }
 
     pub fn sample_rate(&self) -> usize {
@@ -303,43 +942,243 @@ impl RawVirtualSurroundFilter {
         self.channel_map.channels
     }
 
-    pub fn positions(&self) -> impl Iterator<Item = ChannelMask> + '_ {
+    pub fn positions(&self) -> impl Iterator<Item = Speaker> + '_ {
         self.channel_map.map[..self.channels()].iter().copied()
     }
+
+    pub fn compatible_with(&self, rate: usize, layout: &[Speaker]) -> Result<(), MismatchReport> {
+        let report = MismatchReport::new(self.rate, &self.channel_map, rate, layout);
+
+        if report.is_match() {
+            Ok(())
+        } else {
+            Err(report)
+        }
+    }
+}
+
+/// load both impulse responses of `channel` into `engine` with the near-field `gains`, padded to
+/// `fft_len`
+fn load_near_field(
+    engine: &mut dyn ConvolutionEngine,
+    channel: usize,
+    irs: &[Vec<f32>; 2],
+    gains: [f32; 2],
+    rate: usize,
+    fft_len: usize,
+) -> anyhow::Result<()> {
+    let mut impulse = vec![0f32; fft_len];
+
+    for (ear, (ir, gain)) in irs.iter().zip(gains).enumerate() {
+        impulse.fill(0f32);
+        if gain == 0.0 {
+            impulse[..ir.len()].copy_from_slice(ir);
+        } else {
+            impulse[..ir.len()].copy_from_slice(&apply_near_field(ir, rate, gain));
+        }
+
+        engine.init_ir(&impulse, channel * 2 + ear)?;
+    }
+
+    Ok(())
 }
 
 impl VirtualSurroundFilter {
-    #[cfg(feature = "resample")]
+    #[cfg(any(feature = "resample", feature = "resample-rubato"))]
     pub fn new_from_hrir_and_sample_rate<R: Read + Seek>(
         reader: R,
         sample_rate: u32,
     ) -> anyhow::Result<Self> {
-        Self::new(reader, Some(sample_rate))
+        FilterBuilder::new().sample_rate(sample_rate).build(reader)
     }
 
     pub fn new_from_hrir<R: Read + Seek>(reader: R) -> anyhow::Result<Self> {
-        Self::new(reader, None)
+        FilterBuilder::new().build(reader)
     }
 
-    fn new<R: Read + Seek>(reader: R, sample_rate: Option<u32>) -> anyhow::Result<Self> {
-        let inner = RawVirtualSurroundFilter::new(reader, sample_rate)?;
-
-        const EMPTY_VEC: Vec<f32> = Vec::new();
-        let mut in_space = [EMPTY_VEC; MAX_CHANNELS];
-        for i in 0..inner.channels() {
-            in_space[i] = vec![0f32; inner.samples_required()];
-        }
+    pub fn from_raw(inner: RawVirtualSurroundFilter) -> anyhow::Result<Self> {
+        let in_space = vec![vec![0f32; inner.samples_required()]; inner.channels()];
+        let pcm_input = Vec::with_capacity(BLOCK_SIZE * inner.channels());
 
         let left_out_space = vec![0f32; inner.block_size() * 4];
         let right_out_space = vec![0f32; inner.block_size() * 4];
 
+        let dry = DryPath::new(inner.positions(), inner.block_size());
+        let mono_gains = inner.channel_map.mono_gains();
+        let expand_space = vec![0f32; inner.block_size() * inner.channels()];
+        let chunk_space = vec![0f32; BLOCK_SIZE * inner.channels()];
+        let distances = SpeakerDistances::new(inner.channels());
+        let protection = OutputProtector::new(OutputProtection::default(), inner.sample_rate());
+        let silence = vec![0f32; inner.samples_required()];
+        let upmixer = Upmixer::new(None, inner.positions(), inner.sample_rate());
+        let rotation = SpeakerRotation::new(inner.positions());
+        let fade_in = (FADE_IN_MS / 1000.0 * inner.sample_rate() as f32) as usize;
+        let reverb_sends = inner
+            .positions()
+            .map(|x| if x.is_lfe() { 0.0 } else { 1.0 })
+            .collect();
+
         let filter = VirtualSurroundFilter {
             inner,
             available_data: 0,
             left_out_space,
             right_out_space,
             in_space,
+            mono_gains,
+            expand_space,
+            matrix: None,
+            matrix_space: vec![],
+            matrix_previous: vec![],
+            matrix_fade: Smoothed::new(1.0, BLOCK_SIZE),
+            upmix: None,
+            upmixer,
+            rotation,
+            distances,
+            bass: None,
+            eq: None,
+            blend: None,
+            reverb: None,
+            reverb_sends,
+            reverb_space: vec![0f32; BLOCK_SIZE],
+            dc_block: None,
+            transaural: None,
+            dry,
+            mix: 1.0,
+            bypass: false,
+            mix_ramp: Smoothed::new(1.0, BLOCK_SIZE),
+            ramp: BLOCK_SIZE,
+            loudness: None,
+            bypass_matching: None,
+            coloration: None,
+            metering: None,
+            timing: None,
+            spectrum: None,
+            protection,
+            virtualized: None,
+            silence,
+            passthrough_space: vec![0f32; BLOCK_SIZE * 2],
+            passthrough_protection: vec![],
+            passthrough_columns: vec![],
+            state_audit: false,
+            silent_frames: 0,
+            state_resets: 0,
+            pcm: PcmConverter::new(false),
+            pcm_input,
+            chunk_space,
+            chunk_fill: 0,
+            fade_in,
+            faded: 0,
+            drained: 0,
+            low_latency: false,
+            profiles: vec![(DEFAULT_PROFILE.to_string(), None)],
+            profile: 0,
+            profile_switch: None,
+            profile_space: vec![],
+        };
+
+        Ok(filter)
+    }
+
+    /// Filter with the HRIR, profiles and settings of this one for another stream, e.g. one per
+    /// client of a sound server or per voice of a game. The impulse responses and the spectra the
+    /// engines made of them are shared, see [`RawVirtualSurroundFilter::clone_fresh`]. The clone
+    /// starts like a filter after [`reset`](VirtualSurroundFilter::reset), without the
+    /// [`meters`](VirtualSurroundFilter::meters), [`dsp_load`](VirtualSurroundFilter::dsp_load)
+    /// or [`spectrum_tap`](VirtualSurroundFilter::spectrum_tap) of this one, and finishes any
+    /// profile switch under way.
+    pub fn clone_fresh(&self) -> anyhow::Result<Self> {
+        let mut profiles = vec![];
+        for (index, (name, profile)) in self.profiles.iter().enumerate() {
+            let profile = match (profile, &self.profile_switch) {
+                (Some(profile), _) => Some(profile.clone_fresh()?),
+                (None, Some(switch)) if switch.index == index => {
+                    Some(switch.outgoing.clone_fresh()?)
+                }
+                (None, _) => None,
+            };
+
+            profiles.push((name.clone(), profile));
+        }
+
+        let eq = match &self.eq {
+            Some(eq) => Some(eq.clone_fresh()?),
+            None => None,
+        };
+        let sample_rate = self.sample_rate();
+
+        let mut filter = VirtualSurroundFilter {
+            inner: self.inner.clone_fresh()?,
+            available_data: 0,
+            left_out_space: self.left_out_space.clone(),
+            right_out_space: self.right_out_space.clone(),
+            in_space: self.in_space.clone(),
+            mono_gains: self.mono_gains.clone(),
+            expand_space: self.expand_space.clone(),
+            matrix: self.matrix.clone(),
+            matrix_space: self.matrix_space.clone(),
+            matrix_previous: self.matrix_previous.clone(),
+            matrix_fade: self.matrix_fade,
+            upmix: self.upmix,
+            upmixer: self.upmixer.clone(),
+            rotation: self.rotation.clone(),
+            distances: self.distances.clone(),
+            bass: self.bass.clone(),
+            eq,
+            blend: self.blend.clone(),
+            reverb: self.reverb.clone(),
+            reverb_sends: self.reverb_sends.clone(),
+            reverb_space: self.reverb_space.clone(),
+            dc_block: self
+                .dc_block
+                .as_ref()
+                .map(|x| DcBlocker::new(sample_rate, x.cutoff())),
+            transaural: self
+                .transaural
+                .as_ref()
+                .map(|x| CrosstalkCanceller::new(x.settings(), sample_rate)),
+            dry: self.dry.clone(),
+            mix: self.mix,
+            bypass: self.bypass,
+            mix_ramp: self.mix_ramp,
+            ramp: self.ramp,
+            loudness: self
+                .loudness
+                .as_ref()
+                .map(|_| LoudnessMatcher::new(sample_rate)),
+            bypass_matching: self
+                .bypass_matching
+                .as_ref()
+                .map(|_| LoudnessMatcher::new(sample_rate)),
+            coloration: self
+                .coloration
+                .as_ref()
+                .map(|_| ColorationAnalyzer::new(sample_rate)),
+            metering: None,
+            timing: None,
+            spectrum: None,
+            protection: self.protection.clone(),
+            virtualized: self.virtualized.clone(),
+            silence: self.silence.clone(),
+            passthrough_space: self.passthrough_space.clone(),
+            passthrough_protection: self.passthrough_protection.clone(),
+            passthrough_columns: self.passthrough_columns.clone(),
+            state_audit: self.state_audit,
+            silent_frames: 0,
+            state_resets: 0,
+            pcm: self.pcm.fresh(),
+            pcm_input: Vec::with_capacity(self.pcm_input.capacity()),
+            chunk_space: self.chunk_space.clone(),
+            chunk_fill: 0,
+            fade_in: self.fade_in,
+            faded: 0,
+            drained: 0,
+            low_latency: self.low_latency,
+            profiles,
+            profile: self.profile,
+            profile_switch: None,
+            profile_space: self.profile_space.clone(),
         };
+        filter.reset();
 
         Ok(filter)
     }
@@ -352,8 +1191,35 @@ impl VirtualSurroundFilter {
         self.inner.block_size()
     }
 
+    /// engine latency plus the lookahead of the output limiter, if any. Only the lookahead with
+    /// [`set_low_latency`](VirtualSurroundFilter::set_low_latency).
     pub fn sample_latency(&self) -> usize {
-        self.inner.sample_latency()
+        if self.low_latency {
+            return self.protection.latency();
+        }
+
+        self.inner.sample_latency() + self.protection.latency()
+    }
+
+    /// Render from the first block of a stream instead of once the window is full, the window
+    /// starts out as silence. The window is as long as the impulse responses, so with room
+    /// impulse responses (BRIRs) this saves waiting seconds for the first output. The impulse
+    /// responses and the window are still held whole. Starts a new stream when it changes, see
+    /// [`reset`](VirtualSurroundFilter::reset).
+    pub fn set_low_latency(&mut self, low_latency: bool) {
+        if self.low_latency != low_latency {
+            self.low_latency = low_latency;
+            self.reset();
+        }
+    }
+
+    pub fn low_latency(&self) -> bool {
+        self.low_latency
+    }
+
+    /// samples before the earliest onset in the HRIR
+    pub fn ir_delay(&self) -> usize {
+        self.inner.ir_delay()
     }
 
     pub fn sample_rate(&self) -> usize {
@@ -364,121 +1230,1475 @@ impl VirtualSurroundFilter {
         self.inner.channels()
     }
 
-    pub fn positions(&self) -> impl Iterator<Item = ChannelMask> + '_ {
+    pub fn positions(&self) -> impl Iterator<Item = Speaker> + '_ {
         self.inner.positions()
     }
 
-    pub fn transform(&mut self, input: &[f32], output: &mut [f32]) -> anyhow::Result<()> {
-        let sample_count = input.len() / self.channels();
-        let move_data = if self.available_data + sample_count > self.samples_required() {
-            self.available_data = self.samples_required() - sample_count;
-            sample_count
-        } else {
-            0
+    /// channels per frame [`transform`](VirtualSurroundFilter::transform) takes, the inputs of
+    /// the mixing matrix if one is set
+    pub fn input_channels(&self) -> usize {
+        match &self.matrix {
+            Some(matrix) => matrix.inputs().len(),
+            None => self.channels(),
+        }
+    }
+
+    pub fn compatible_with(&self, rate: usize, layout: &[Speaker]) -> Result<(), MismatchReport> {
+        self.inner.compatible_with(rate, layout)
+    }
+
+    pub fn set_bass_management(&mut self, config: Option<BassManagement>) {
+        self.bass = config.map(|config| {
+            BassManager::new(
+                config,
+                self.sample_rate(),
+                self.channels(),
+                self.inner.channel_map.find(Speaker::LowFrequency),
+                self.samples_required(),
+            )
+        });
+    }
+
+    pub fn set_headphone_eq(&mut self, eq: Option<HeadphoneEq>) -> anyhow::Result<()> {
+        self.eq = match eq {
+            Some(eq) => Some(EqProcessor::new(
+                &eq,
+                self.sample_rate(),
+                self.inner.engine_factory,
+            )?),
+            None => None,
         };
 
-        for c in 0..self.channels() {
-            if move_data > 0 {
-                self.in_space[c].copy_within(move_data.., 0);
-            }
+        Ok(())
+    }
 
-            for s in 0..sample_count {
-                self.in_space[c][self.available_data + s] = input[s * self.channels() + c];
-            }
-        }
+    /// place `speaker` `meters` away instead of at [`REFERENCE_DISTANCE`], its level follows the
+    /// inverse distance law and it's delayed by the extra travel time compared to the nearest
+    /// speaker. Closer than the reference distance the near ear also gets more of the bass than
+    /// the far one, the way it does in near-field HRTFs.
+    pub fn set_speaker_distance(&mut self, speaker: Speaker, meters: f32) -> anyhow::Result<()> {
+        let channel = match self.inner.channel_map.find(speaker) {
+            Some(channel) => channel,
+            None => anyhow::bail!("HRIR has no {} speaker", get_channel_name(speaker)),
+        };
 
-        self.available_data += sample_count;
+        if !(meters > 0.0 && meters.is_finite()) {
+            anyhow::bail!("Speaker distance has to be positive, got {}", meters);
+        }
 
-        if self.available_data < self.samples_required() {
-            return Ok(());
+        let ramp = self.live_ramp();
+        let was_active = self.distances.is_active();
+        self.distances
+            .set_distance(channel, meters, self.sample_rate(), ramp);
+        if !was_active && self.distances.is_active() {
+            self.distances.prime(&self.in_space, self.available_data);
+        }
+        self.inner.set_near_field(channel, meters)?;
+        for profile in self.profiles.iter_mut().filter_map(|(_, x)| x.as_mut()) {
+            profile.set_near_field(channel, meters)?;
+        }
+        if let Some(switch) = &mut self.profile_switch {
+            switch.outgoing.set_near_field(channel, meters)?;
         }
 
-        self.left_out_space.fill(0f32);
-        self.right_out_space.fill(0f32);
+        Ok(())
+    }
 
-        let left = &mut self.left_out_space;
-        let right = &mut self.right_out_space;
+    pub fn speaker_distance(&self, speaker: Speaker) -> Option<f32> {
+        self.inner
+            .channel_map
+            .find(speaker)
+            .map(|channel| self.distances.distance(channel))
+    }
 
-        self.inner.transform(
-            &mut self
-                .in_space
-                .iter_mut()
-                .map(|x| x.as_mut_slice())
-                .collect::<Vec<_>>(),
-            (left, right),
-        )?;
+    /// High pass the output at `cutoff` hz to remove the DC offset some HRIRs add, which builds
+    /// up in the convolution. [`DEFAULT_DC_BLOCK_HZ`] suits most, `None` turns it off.
+    pub fn set_dc_block(&mut self, cutoff: Option<f32>) -> anyhow::Result<()> {
+        self.dc_block = match cutoff {
+            Some(cutoff) => {
+                if !(cutoff > 0.0 && cutoff < self.sample_rate() as f32 / 2.0) {
+                    anyhow::bail!(
+                        "DC block cutoff has to be between 0hz and Nyquist, got {}",
+                        cutoff
+                    );
+                }
 
-        for s in 0..BLOCK_SIZE {
-            let mut sample = self.left_out_space[s];
-            if sample > 1.0 {
-                sample = 1.0;
+                Some(DcBlocker::new(self.sample_rate(), cutoff))
             }
+            None => None,
+        };
 
-            if sample < -1.0 {
-                sample = -1.0;
-            }
-            output[s * 2] = sample;
+        Ok(())
+    }
+
+    pub fn dc_block(&self) -> Option<f32> {
+        self.dc_block.as_ref().map(|x| x.cutoff())
+    }
 
-            let mut sample = self.right_out_space[s];
-            if sample > 1.0 {
-                sample = 1.0;
+    /// Play the output on a stereo speaker pair instead of headphones, with crosstalk
+    /// cancellation so each ear mostly hears the speaker on its side. It only works with the
+    /// listener's head where [`Transaural`] says, `None` turns it off for headphones.
+    pub fn set_transaural(&mut self, transaural: Option<Transaural>) -> anyhow::Result<()> {
+        self.transaural = match transaural {
+            Some(transaural) => {
+                transaural.validate()?;
+                Some(CrosstalkCanceller::new(transaural, self.sample_rate()))
             }
+            None => None,
+        };
 
-            if sample < -1.0 {
-                sample = -1.0;
+        Ok(())
+    }
+
+    pub fn transaural(&self) -> Option<Transaural> {
+        self.transaural.as_ref().map(|x| x.settings())
+    }
+
+    /// mix the HRTF rendering with a crossfeed of the stereo downmix per frequency band
+    pub fn set_blend_preset(&mut self, preset: Option<BlendPreset>) -> anyhow::Result<()> {
+        self.blend = match preset {
+            Some(preset) => {
+                preset.validate()?;
+                Some(BlendProcessor::new(&preset, self.sample_rate()))
             }
-            output[s * 2 + 1] = sample;
-        }
+            None => None,
+        };
 
         Ok(())
     }
-}
 
-/// from https://github.com/pulseaudio/pulseaudio/blob/19adddee31ca34bf4e0db95df01b4ec595f2d267/src/modules/module-virtual-surround-sink.c#L192
-fn normalize_hrir(data: &mut [f32], samples: usize, channels: usize) {
-    let scaling_factor = 2.5f32;
+    /// Add a feedback delay network reverb tail to the virtualized output, following the early
+    /// reflections of a [`RoomModel`] or a dry HRIR for speakers in a room without measured
+    /// BRIRs. `None` turns it off.
+    pub fn set_reverb(&mut self, reverb: Option<Reverb>) {
+        self.reverb = reverb.map(|x| FdnReverb::new(x, self.sample_rate(), self.ir_delay()));
+    }
+
+    pub fn reverb(&self) -> Option<Reverb> {
+        self.reverb.as_ref().map(|x| x.settings())
+    }
 
-    let mut hrir_max: f32 = 0.0;
+    /// gain `speaker` feeds the reverb with, 1.0 for every speaker but the LFE by default
+    pub fn set_reverb_send(&mut self, speaker: Speaker, gain: f32) -> anyhow::Result<()> {
+        let channel = match self.inner.channel_map.find(speaker) {
+            Some(channel) => channel,
+            None => anyhow::bail!("HRIR has no {} speaker", get_channel_name(speaker)),
+        };
 
-    for i in 0..samples {
-        let mut hrir_sum = 0.0;
-        for c in 0..channels {
-            hrir_sum += data[i * channels + c].abs();
+        if !(gain >= 0.0 && gain.is_finite()) {
+            anyhow::bail!("Reverb send can't be negative, got {}", gain);
         }
 
-        if hrir_sum > hrir_max {
-            hrir_max = hrir_sum;
+        self.reverb_sends[channel] = gain;
+
+        Ok(())
+    }
+
+    pub fn reverb_send(&self, speaker: Speaker) -> Option<f32> {
+        self.inner
+            .channel_map
+            .find(speaker)
+            .map(|channel| self.reverb_sends[channel])
+    }
+
+    /// Time in milliseconds changes to the mix, bypass and speaker distances ramp over, so they
+    /// don't click. A block by default, see [`FilterBuilder::ramp_time`]. Changes made before
+    /// the first block is rendered apply right away.
+    pub fn set_ramp_time(&mut self, ms: f32) {
+        self.ramp = ramp_samples(ms, self.sample_rate());
+        self.mix_ramp.set_ramp(self.ramp);
+    }
+
+    pub fn ramp_time(&self) -> f32 {
+        self.ramp as f32 * 1000.0 / self.sample_rate() as f32
+    }
+
+    /// samples a change ramps over, none while nothing was rendered yet
+    fn live_ramp(&self) -> usize {
+        if self.available_data < self.samples_required() {
+            0
+        } else {
+            self.ramp
         }
     }
 
-    for i in 0..samples {
-        for c in 0..channels {
-            data[i * channels + c] /= hrir_max * scaling_factor;
+    fn update_mix(&mut self) {
+        let mix = if self.bypass { 0.0 } else { self.mix };
+        if self.live_ramp() == 0 {
+            self.mix_ramp.set_immediately(mix);
+        } else {
+            self.mix_ramp.set(mix);
         }
     }
-}
 
-pub trait FFTLogic: Sized {
-    fn new(channels: usize, length: usize) -> Self;
+    /// blend between the plain stereo downmix (0.0) and the virtualized output (1.0)
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+        self.update_mix();
+    }
 
-    fn init_ir(&mut self, impulse: &mut [f32], ir_index: usize) -> anyhow::Result<()>;
+    pub fn mix(&self) -> f32 {
+        self.mix
+    }
 
-    fn process_channel(
-        &mut self,
-        channel: usize,
-        samples: &mut [f32],
-        rev_space: &mut [f32],
-        left_output: &mut [f32],
-        right_output: &mut [f32],
-    ) -> anyhow::Result<()>;
-}
+    /// Output only the plain stereo downmix, processing continues so toggling is seamless. The
+    /// downmix is taken from the same window as the virtualized output, delayed by the onset of
+    /// the HRIR, so it's in time with the direct sound of the virtualized output and both go
+    /// through the same [`sample_latency`](VirtualSurroundFilter::sample_latency).
+    pub fn set_bypass(&mut self, bypass: bool) {
+        self.bypass = bypass;
+        self.update_mix();
+    }
 
+    pub fn bypass(&self) -> bool {
+        self.bypass
+    }
+
+    /// match the loudness of the virtualized output to the plain stereo downmix with a slowly
+    /// adapting make-up gain
+    pub fn set_loudness_matching(&mut self, enabled: bool) {
+        if enabled != self.loudness.is_some() {
+            self.loudness = if enabled {
+                Some(LoudnessMatcher::new(self.sample_rate()))
+            } else {
+                None
+            };
+        }
+    }
+
+    /// loudness measured by the matching stage, `None` when it's disabled
+    pub fn loudness(&self) -> Option<LoudnessReading> {
+        self.loudness.as_ref().map(|x| x.reading())
+    }
+
+    /// Match the loudness of the downmix the [`bypass`](VirtualSurroundFilter::set_bypass) and
+    /// [`mix`](VirtualSurroundFilter::set_mix) blend in to the virtualized output with a slowly
+    /// adapting make-up gain, so an A/B comparison isn't won by whichever is louder. Unlike
+    /// [`set_loudness_matching`](VirtualSurroundFilter::set_loudness_matching) the virtualized
+    /// output is left as it is.
+    pub fn set_bypass_level_matching(&mut self, enabled: bool) {
+        if enabled != self.bypass_matching.is_some() {
+            self.bypass_matching = if enabled {
+                Some(LoudnessMatcher::new(self.sample_rate()))
+            } else {
+                None
+            };
+        }
+    }
+
+    pub fn bypass_level_matching(&self) -> bool {
+        self.bypass_matching.is_some()
+    }
+
+    /// make-up gain in dB the downmix gets, `None` when bypass level matching is disabled
+    pub fn bypass_gain_db(&self) -> Option<f32> {
+        self.bypass_matching.as_ref().map(|x| x.reading().gain_db)
+    }
+
+    /// Start metering the speaker channels as they go into the filter and the binaural output,
+    /// the handle reads their peak and RMS from any thread. Later calls share the same meters.
+    pub fn meters(&mut self) -> Meters {
+        let channels = self.channels();
+        let sample_rate = self.sample_rate();
+        self.metering
+            .get_or_insert_with(|| Metering::new(channels, sample_rate))
+            .meters()
+    }
+
+    /// Start timing how long every block takes to render, the handle reads the statistics from
+    /// any thread to show the DSP load or warn when the block size is too small for the machine.
+    /// Later calls share the same statistics.
+    pub fn dsp_load(&mut self) -> DspLoad {
+        let sample_rate = self.sample_rate();
+        self.timing
+            .get_or_insert_with(|| LoadTiming::new(BLOCK_SIZE, sample_rate))
+            .dsp_load()
+    }
+
+    /// Publish the magnitude spectrum of the binaural output before the output protection, of
+    /// the mono sum of the ears over the last `fft_len` frames, every `decimation` blocks. The
+    /// tap reads them from a UI thread, so analyzers don't have to run an fft of their own.
+    /// Replaces an earlier tap, `fft_len` has to be a power of two.
+    pub fn spectrum_tap(
+        &mut self,
+        fft_len: usize,
+        decimation: usize,
+    ) -> anyhow::Result<SpectrumTap> {
+        let analyzer = SpectrumAnalyzer::new(self.sample_rate(), fft_len, decimation)?;
+        let tap = analyzer.tap();
+        self.spectrum = Some(analyzer);
+        Ok(tap)
+    }
+
+    pub fn remove_spectrum_tap(&mut self) {
+        self.spectrum = None;
+    }
+
+    /// Re-zero the recursive filters, delay lines and the limiter once the input has been silent
+    /// for the whole window and another second, so rounding errors and denormals collected over
+    /// days of uptime don't linger. State which stopped being finite is cleared right away, the
+    /// block it broke is output as silence.
+    pub fn set_state_audit(&mut self, enabled: bool) {
+        self.state_audit = enabled;
+        self.silent_frames = 0;
+    }
+
+    pub fn state_audit(&self) -> bool {
+        self.state_audit
+    }
+
+    /// times the state audit cleared the state
+    pub fn state_resets(&self) -> usize {
+        self.state_resets
+    }
+
+    /// Ramp the output up from silence over `ms` when the first block is rendered and after the
+    /// state was cleared, so starting mid stream never clicks. 0 outputs the first block as is.
+    pub fn set_fade_in(&mut self, ms: f32) {
+        self.fade_in = (ms.max(0.0) / 1000.0 * self.sample_rate() as f32) as usize;
+    }
+
+    pub fn fade_in(&self) -> f32 {
+        self.fade_in as f32 * 1000.0 / self.sample_rate() as f32
+    }
+
+    /// skip the fade-in still ahead, for a filter which is crossfaded to instead
+    pub(crate) fn skip_fade_in(&mut self) {
+        self.faded = self.fade_in;
+    }
+
+    /// apply the fade-in to a rendered block of `channels` interleaved channels in each of
+    /// `outputs`
+    fn apply_fade_in(&mut self, outputs: &mut [&mut [f32]], channels: usize) {
+        if self.faded >= self.fade_in {
+            return;
+        }
+
+        for output in outputs {
+            for (s, frame) in output[..BLOCK_SIZE * channels]
+                .chunks_exact_mut(channels)
+                .enumerate()
+            {
+                let gain = ((self.faded + s) as f32 / self.fade_in as f32).min(1.0);
+                frame.iter_mut().for_each(|x| *x *= gain);
+            }
+        }
+
+        self.faded += BLOCK_SIZE;
+    }
+
+    /// compare the long-term spectrum of the virtualized output, before blending and headphone
+    /// eq, with the plain stereo downmix. Runs an fft per 16384 frames, meant for measuring
+    /// rather than staying on.
+    pub fn set_coloration_analysis(&mut self, enabled: bool) {
+        if enabled != self.coloration.is_some() {
+            self.coloration = if enabled {
+                Some(ColorationAnalyzer::new(self.sample_rate()))
+            } else {
+                None
+            };
+        }
+    }
+
+    /// coloration per third octave measured so far, `None` when the analysis is disabled or
+    /// hasn't seen enough output yet
+    pub fn coloration(&self) -> Option<ColorationReport> {
+        self.coloration.as_ref().and_then(|x| x.report())
+    }
+
+    /// replaces the limiter state, so changing it mid stream may click
+    pub fn set_output_protection(&mut self, protection: OutputProtection) {
+        self.protection = OutputProtector::new(protection, self.sample_rate());
+        self.passthrough_protection = self.passthrough_protectors();
+    }
+
+    /// virtualize only `speakers` and pass every other channel through to real speakers, for
+    /// e.g. a 5.1 setup playing 7.1.4 content with the heights binauralized into the front
+    /// pair. See [`transform_passthrough`](VirtualSurroundFilter::transform_passthrough), `None`
+    /// virtualizes everything again. Bass management still feeds the binaural rendering.
+    pub fn set_virtualized_channels(&mut self, speakers: Option<&[Speaker]>) -> anyhow::Result<()> {
+        let speakers = match speakers {
+            Some(speakers) => speakers,
+            None => {
+                self.virtualized = None;
+                self.passthrough_protection = vec![];
+                self.passthrough_columns = vec![];
+                self.dry = DryPath::new(self.positions(), BLOCK_SIZE);
+                return Ok(());
+            }
+        };
+
+        let mut virtualized = vec![false; self.channels()];
+        for speaker in speakers {
+            match self.inner.channel_map.find(*speaker) {
+                Some(channel) => virtualized[channel] = true,
+                None => anyhow::bail!("HRIR has no {} speaker", get_channel_name(*speaker)),
+            }
+        }
+
+        for front in [Speaker::FrontLeft, Speaker::FrontRight] {
+            match self.inner.channel_map.find(front) {
+                Some(channel) if !virtualized[channel] => {}
+                _ => anyhow::bail!(
+                    "The {} speaker has to be passed through to carry the binaural rendering",
+                    get_channel_name(front)
+                ),
+            }
+        }
+
+        self.dry = DryPath::new(self.positions(), BLOCK_SIZE);
+        for (channel, virtualized) in virtualized.iter().enumerate() {
+            if !virtualized {
+                self.dry.exclude(channel);
+            }
+        }
+
+        // the front pair carries the binaural rendering, the others go out by pairs
+        let fronts = [Speaker::FrontLeft, Speaker::FrontRight]
+            .map(|x| self.inner.channel_map.find(x).unwrap());
+        let mut columns = (0..self.channels())
+            .filter(|c| !virtualized[*c])
+            .enumerate()
+            .map(|(column, channel)| (channel, column))
+            .collect::<Vec<_>>();
+        columns.sort_by_key(|(channel, _)| {
+            fronts
+                .iter()
+                .position(|x| x == channel)
+                .unwrap_or(fronts.len())
+        });
+
+        self.virtualized = Some(virtualized);
+        self.passthrough_protection = self.passthrough_protectors();
+        self.passthrough_columns = columns;
+
+        Ok(())
+    }
+
+    /// channels [`transform_passthrough`](VirtualSurroundFilter::transform_passthrough) outputs,
+    /// in input order, empty when everything is virtualized
+    pub fn passthrough_positions(&self) -> Vec<Speaker> {
+        match &self.virtualized {
+            Some(virtualized) => self
+                .positions()
+                .zip(virtualized)
+                .filter(|(_, virtualized)| !**virtualized)
+                .map(|(position, _)| position)
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// one protector per pair of passed through channels besides the front pair
+    fn passthrough_protectors(&self) -> Vec<OutputProtector> {
+        let others = match &self.virtualized {
+            Some(virtualized) => virtualized.iter().filter(|x| !**x).count() - 2,
+            None => 0,
+        };
+
+        (0..others.div_ceil(2))
+            .map(|_| OutputProtector::new(self.output_protection(), self.sample_rate()))
+            .collect()
+    }
+
+    pub fn output_protection(&self) -> OutputProtection {
+        self.protection.protection()
+    }
+
+    /// mix the input of [`transform`](VirtualSurroundFilter::transform) and
+    /// [`transform_passthrough`](VirtualSurroundFilter::transform_passthrough) to the speakers of
+    /// the HRIR first, e.g. with [`MixingMatrix::automatic`] for content with channels the HRIR
+    /// lacks. The outputs have to be the HRIR's [`positions`](VirtualSurroundFilter::positions).
+    /// Replacing a matrix by one with the same inputs while audio runs crossfades between their
+    /// gains over the [`ramp_time`](VirtualSurroundFilter::set_ramp_time).
+    pub fn set_mixing_matrix(&mut self, matrix: Option<MixingMatrix>) -> anyhow::Result<()> {
+        if let Some(matrix) = &matrix {
+            if !matrix.outputs().iter().copied().eq(self.positions()) {
+                anyhow::bail!(
+                    "Mixing matrix outputs don't match the HRIR speakers ({:?})",
+                    self.inner.channel_map
+                );
+            }
+
+            if matrix.inputs().is_empty() {
+                anyhow::bail!("Mixing matrix needs at least one input");
+            }
+        }
+
+        match (&self.matrix, &matrix) {
+            (Some(old), Some(new)) if old.inputs() == new.inputs() => self.start_matrix_fade(),
+            _ => {
+                self.matrix_previous = matrix
+                    .as_ref()
+                    .map(|x| x.gains().to_vec())
+                    .unwrap_or_default();
+                self.matrix_fade.set_immediately(1.0);
+            }
+        }
+
+        self.matrix_space = vec![0f32; BLOCK_SIZE * self.channels()];
+        self.matrix = matrix;
+        // a partial chunk in another layout is dropped
+        if self.chunk_space.len() != BLOCK_SIZE * self.input_channels() {
+            self.chunk_space = vec![0f32; BLOCK_SIZE * self.input_channels()];
+            self.chunk_fill = 0;
+        }
+        // integer input is converted into this, sized once so rendering doesn't allocate
+        self.pcm_input = Vec::with_capacity(BLOCK_SIZE * self.input_channels());
+
+        Ok(())
+    }
+
+    pub fn mixing_matrix(&self) -> Option<&MixingMatrix> {
+        self.matrix.as_ref()
+    }
+
+    /// change one gain of the mixing matrix, ramped like replacing it is and without allocating
+    pub fn set_mixing_gain(
+        &mut self,
+        input: usize,
+        output: usize,
+        gain: f32,
+    ) -> anyhow::Result<()> {
+        let (inputs, outputs) = match &self.matrix {
+            Some(matrix) => (matrix.inputs().len(), matrix.outputs().len()),
+            None => anyhow::bail!("No mixing matrix is set"),
+        };
+
+        if input >= inputs || output >= outputs {
+            anyhow::bail!(
+                "Mixing matrix has {} inputs and {} outputs, there's no gain from {} to {}",
+                inputs,
+                outputs,
+                input,
+                output
+            );
+        }
+
+        self.start_matrix_fade();
+        self.matrix.as_mut().unwrap().set_gain(input, output, gain);
+
+        Ok(())
+    }
+
+    /// crossfade from the gains the matrix is at now to the ones set next
+    fn start_matrix_fade(&mut self) {
+        let matrix = match &self.matrix {
+            Some(matrix) => matrix,
+            None => return,
+        };
+
+        let fade = self.matrix_fade.value();
+        for (previous, gain) in self.matrix_previous.iter_mut().zip(matrix.gains()) {
+            *previous += (gain - *previous) * fade;
+        }
+
+        self.matrix_fade.set_ramp(self.live_ramp());
+        self.matrix_fade.set_immediately(0.0);
+        self.matrix_fade.set(1.0);
+    }
+
+    /// mix `frames` of sample `s` of input `c` through the mixing matrix into the matrix space
+    fn mix_input<F: Fn(usize, usize) -> f32>(&mut self, frames: usize, input: F) {
+        let matrix = match &self.matrix {
+            Some(matrix) => matrix,
+            None => return,
+        };
+
+        let fading = self.matrix_fade.value() < 1.0 || self.matrix_fade.is_ramping();
+        let previous = if fading {
+            Some((self.matrix_previous.as_slice(), &mut self.matrix_fade))
+        } else {
+            None
+        };
+        matrix.process_with(frames, input, previous, &mut self.matrix_space);
+    }
+
+    /// take input interleaved in `layout` instead of the HRIR's order, e.g. the order PipeWire,
+    /// WAVEFORMATEXTENSIBLE or FFmpeg use. Every channel needs a speaker in the HRIR, speakers
+    /// missing from `layout` stay silent. Content with other channels needs a
+    /// [`MixingMatrix`], which this replaces.
+    pub fn set_input_layout(&mut self, layout: &[Speaker]) -> anyhow::Result<()> {
+        let positions = self.positions().collect::<Vec<_>>();
+
+        for (index, channel) in layout.iter().enumerate() {
+            if !positions.contains(channel) {
+                anyhow::bail!(
+                    "HRIR has no {} speaker, use a mixing matrix to fold it into others",
+                    get_channel_name(*channel)
+                );
+            }
+
+            if layout[..index].contains(channel) {
+                anyhow::bail!("Input layout has {} twice", get_channel_name(*channel));
+            }
+        }
+
+        if layout == positions.as_slice() {
+            return self.set_mixing_matrix(None);
+        }
+
+        self.set_mixing_matrix(Some(MixingMatrix::automatic(layout, &positions)))
+    }
+
+    /// channel order [`transform`](VirtualSurroundFilter::transform) expects
+    pub fn input_layout(&self) -> Vec<Speaker> {
+        match &self.matrix {
+            Some(matrix) => matrix.inputs().to_vec(),
+            None => self.positions().collect(),
+        }
+    }
+
+    /// [`generate_test_signal`] in the [`input_layout`](VirtualSurroundFilter::input_layout) and
+    /// at the rate of this filter
+    pub fn test_signal(
+        &self,
+        speaker: Speaker,
+        kind: SignalKind,
+        seconds: f32,
+    ) -> anyhow::Result<Vec<f32>> {
+        generate_test_signal(
+            speaker,
+            kind,
+            &self.input_layout(),
+            self.sample_rate(),
+            seconds,
+        )
+    }
+
+    /// like [`transform`](VirtualSurroundFilter::transform) for a mono `input`, which is played
+    /// from the center speaker, or a phantom center if the HRIR has none
+    pub fn transform_mono(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> anyhow::Result<ProcessStatus> {
+        self.check_block(input, output, 1, 2)?;
+
+        no_alloc(|| {
+            let channels = self.channels();
+            let mut expand_space = std::mem::take(&mut self.expand_space);
+            expand_space.resize(BLOCK_SIZE * channels, 0f32);
+
+            for (frame, sample) in expand_space.chunks_exact_mut(channels).zip(input) {
+                for (output, gain) in frame.iter_mut().zip(&self.mono_gains) {
+                    *output = sample * gain;
+                }
+            }
+
+            let result = self.transform_speakers(&expand_space, output);
+            self.expand_space = expand_space;
+            result
+        })
+    }
+
+    /// spread stereo input over the surrounds in
+    /// [`transform_stereo`](VirtualSurroundFilter::transform_stereo), `None` plays it from the
+    /// front pair only
+    pub fn set_upmix(&mut self, upmix: Option<Upmix>) {
+        self.upmix = upmix;
+        self.upmixer = Upmixer::new(upmix, self.positions(), self.sample_rate());
+    }
+
+    pub fn upmix(&self) -> Option<Upmix> {
+        self.upmix
+    }
+
+    /// Turn the virtual speakers against `orientation`, e.g. from a [`HeadTracker`], so they
+    /// stay in place while the head turns. Only the yaw is followed, every speaker is panned
+    /// between the two at its height it ends up between, the LFE and speakers alone at their
+    /// height stay put. Changes ramp over the [`ramp_time`](VirtualSurroundFilter::set_ramp_time)
+    /// and don't allocate, so it can be called every block.
+    pub fn set_head_orientation(&mut self, orientation: Orientation) {
+        let ramp = self.live_ramp();
+        self.rotation.set_yaw(orientation.yaw, ramp);
+    }
+
+    /// yaw of the head the speakers are turned against
+    pub fn head_yaw(&self) -> f32 {
+        self.rotation.yaw()
+    }
+
+    /// like [`transform`](VirtualSurroundFilter::transform) for interleaved stereo `input`,
+    /// placed on the front pair and upmixed if [`set_upmix`](VirtualSurroundFilter::set_upmix)
+    /// is set
+    pub fn transform_stereo(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> anyhow::Result<ProcessStatus> {
+        if !self.upmixer.has_front() {
+            anyhow::bail!("HRIR has no front left and right speakers to play stereo from");
+        }
+
+        if input.len() != BLOCK_SIZE * 2 {
+            anyhow::bail!(
+                "transform takes blocks of {} frames, got {} input frames, use a BlockAdapter for other buffer sizes",
+                BLOCK_SIZE,
+                input.len() / 2
+            );
+        }
+
+        no_alloc(|| {
+            let mut expand_space = std::mem::take(&mut self.expand_space);
+            expand_space.resize(BLOCK_SIZE * self.channels(), 0f32);
+            self.upmixer.process(input, &mut expand_space);
+
+            let result = self.transform_speakers(&expand_space, output);
+            self.expand_space = expand_space;
+            result
+        })
+    }
+
+    /// Takes interleaved frames and writes a [`block_size`](VirtualSurroundFilter::block_size)
+    /// of stereo frames to `output`, which is zeroed while the window is still filling. Fed a
+    /// block at a time the output follows the input, streams of other sizes go through
+    /// [`transform_chunk`](VirtualSurroundFilter::transform_chunk) or a [`BlockAdapter`]. Other
+    /// lengths up to the window are shifted in as they are and the newest block of the window is
+    /// rendered, which only lines up with engines transforming the whole window
+    /// ([`ConvolutionStrategy::Window`]).
+    pub fn transform(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> anyhow::Result<ProcessStatus> {
+        if self.matrix.is_none() {
+            return self.transform_speakers(input, output);
+        }
+
+        let frames = self.check_frames(input, output, self.input_channels())?;
+        let mixed = frames * self.channels();
+        if self.matrix_space.len() < mixed {
+            self.matrix_space.resize(mixed, 0f32);
+        }
+
+        no_alloc(|| {
+            let channels = self.input_channels();
+            self.mix_input(frames, |c, s| input[s * channels + c]);
+
+            let matrix_space = std::mem::take(&mut self.matrix_space);
+            let result = self.transform_speakers(&matrix_space[..mixed], output);
+            self.matrix_space = matrix_space;
+            result
+        })
+    }
+
+    /// like [`transform`](VirtualSurroundFilter::transform) for any number of interleaved frames.
+    /// Input is collected into blocks, and every completed block renders into `output` behind
+    /// the frames already produced. Input is only consumed as long as `output` has room for the
+    /// block it would complete, so call again with the rest once the output has been drained.
+    pub fn transform_chunk(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> anyhow::Result<ChunkStatus> {
+        let channels = self.input_channels();
+        let frames = input.chunks_exact(channels);
+        if !frames.remainder().is_empty() {
+            anyhow::bail!(
+                "transform_chunk takes whole frames of {} channels, got {} samples",
+                channels,
+                input.len()
+            );
+        }
+
+        let mut status = ChunkStatus::default();
+
+        for frame in frames {
+            if self.chunk_fill == BLOCK_SIZE - 1
+                && output.len() < (status.produced + BLOCK_SIZE) * 2
+            {
+                break;
+            }
+
+            let offset = self.chunk_fill * channels;
+            self.chunk_space[offset..offset + channels].copy_from_slice(frame);
+            self.chunk_fill += 1;
+            status.consumed += 1;
+
+            if self.chunk_fill == BLOCK_SIZE {
+                self.chunk_fill = 0;
+
+                let chunk_space = std::mem::take(&mut self.chunk_space);
+                let result = self.transform(&chunk_space, &mut output[status.produced * 2..]);
+                self.chunk_space = chunk_space;
+
+                status.produced += result?.frames();
+            }
+        }
+
+        Ok(status)
+    }
+
+    /// Render the tail left in the filter once the input ended, by feeding it silence. Every call
+    /// writes a block of stereo frames to `output` and returns how many, frames still collected
+    /// by [`transform_chunk`](VirtualSurroundFilter::transform_chunk) come first padded with
+    /// silence. Returns 0 once the tail is out, [`reset`](VirtualSurroundFilter::reset) before
+    /// the filter is used for the next stream.
+    pub fn drain(&mut self, output: &mut [f32]) -> anyhow::Result<usize> {
+        let channels = self.input_channels();
+        let tail = self.samples_required() - BLOCK_SIZE + self.protection.latency();
+
+        loop {
+            let fill = std::mem::replace(&mut self.chunk_fill, 0);
+            if fill == 0 && self.drained >= tail {
+                return Ok(0);
+            }
+
+            // the rest of a partial chunk, or a block of silence, without allocating
+            let drained = self.drained;
+            let mut chunk_space = std::mem::take(&mut self.chunk_space);
+            chunk_space[fill * channels..].fill(0f32);
+            let status = self.transform(&chunk_space, output);
+            self.chunk_space = chunk_space;
+            let status = status?;
+
+            self.drained = if fill > 0 {
+                BLOCK_SIZE - fill
+            } else {
+                drained + BLOCK_SIZE
+            };
+
+            if let ProcessStatus::Rendered = status {
+                return Ok(status.frames());
+            }
+        }
+    }
+
+    /// forget the stream so far, like a newly built filter the next input primes the window
+    /// again. Settings are kept.
+    pub fn reset(&mut self) {
+        self.clear_state();
+        self.available_data = if self.low_latency {
+            self.samples_required() - BLOCK_SIZE
+        } else {
+            0
+        };
+        self.chunk_fill = 0;
+        self.drained = 0;
+        self.silent_frames = 0;
+        self.mix_ramp.set_immediately(self.mix_ramp.target());
+        self.matrix_fade.set_immediately(1.0);
+        self.rotation.settle();
+    }
+
+    /// [`transform`](VirtualSurroundFilter::transform) of 16 bit PCM, see
+    /// [`set_dither`](VirtualSurroundFilter::set_dither) for the conversion of the output
+    pub fn transform_i16(
+        &mut self,
+        input: &[i16],
+        output: &mut [i16],
+    ) -> anyhow::Result<ProcessStatus> {
+        self.transform_integer(input, output)
+    }
+
+    /// [`transform`](VirtualSurroundFilter::transform) of 24 bit PCM
+    pub fn transform_i24(
+        &mut self,
+        input: &[I24],
+        output: &mut [I24],
+    ) -> anyhow::Result<ProcessStatus> {
+        self.transform_integer(input, output)
+    }
+
+    /// [`transform`](VirtualSurroundFilter::transform) of 32 bit PCM
+    pub fn transform_i32(
+        &mut self,
+        input: &[i32],
+        output: &mut [i32],
+    ) -> anyhow::Result<ProcessStatus> {
+        self.transform_integer(input, output)
+    }
+
+    /// TPDF dither the output of [`transform_i16`](VirtualSurroundFilter::transform_i16),
+    /// [`transform_i24`](VirtualSurroundFilter::transform_i24) and
+    /// [`transform_i32`](VirtualSurroundFilter::transform_i32) instead of rounding it
+    pub fn set_dither(&mut self, dither: bool) {
+        self.pcm.set_dither(dither);
+    }
+
+    pub fn dither(&self) -> bool {
+        self.pcm.dither()
+    }
+
+    /// shape the quantization error of the integer transforms, see [`NoiseShaping`]
+    pub fn set_noise_shaping(&mut self, shaping: NoiseShaping) {
+        self.pcm.set_noise_shaping(shaping);
+    }
+
+    pub fn noise_shaping(&self) -> NoiseShaping {
+        self.pcm.noise_shaping()
+    }
+
+    fn transform_integer<T: IntegerSample>(
+        &mut self,
+        input: &[T],
+        output: &mut [T],
+    ) -> anyhow::Result<ProcessStatus> {
+        self.check_block(input, output, self.input_channels(), 2)?;
+
+        no_alloc(|| {
+            let mut input_space = std::mem::take(&mut self.pcm_input);
+            input_space.clear();
+            input_space.extend(input.iter().map(|x| x.to_float()));
+
+            let mut output_space = [0f32; BLOCK_SIZE * 2];
+            let result = self.transform(&input_space, &mut output_space);
+            self.pcm_input = input_space;
+
+            let status = result?;
+            match status {
+                ProcessStatus::Rendered => self
+                    .pcm
+                    .convert(&output_space, &mut output[..BLOCK_SIZE * 2]),
+                ProcessStatus::Priming => output[..BLOCK_SIZE * 2].fill(T::from_scaled(0.0)),
+            }
+
+            Ok(status)
+        })
+    }
+
+    /// [`transform`](VirtualSurroundFilter::transform) of input in the HRIR's layout
+    fn transform_speakers(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> anyhow::Result<ProcessStatus> {
+        self.check_frames(input, output, self.channels())?;
+
+        no_alloc(|| {
+            if !self.render(input)? {
+                output[..BLOCK_SIZE * 2].fill(0f32);
+                return Ok(ProcessStatus::Priming);
+            }
+
+            self.protection.process(
+                &self.left_out_space[..BLOCK_SIZE],
+                &self.right_out_space[..BLOCK_SIZE],
+                &mut output[..BLOCK_SIZE * 2],
+            );
+            self.apply_fade_in(&mut [output], 2);
+
+            Ok(ProcessStatus::Rendered)
+        })
+    }
+
+    /// like [`transform`](VirtualSurroundFilter::transform) with one buffer of
+    /// [`block_size`](VirtualSurroundFilter::block_size) frames per input channel and separate
+    /// left and right outputs, the way JACK and plugin hosts hand them over
+    pub fn transform_planar(
+        &mut self,
+        input: &[&[f32]],
+        left: &mut [f32],
+        right: &mut [f32],
+    ) -> anyhow::Result<ProcessStatus> {
+        if input.len() != self.input_channels()
+            || input.iter().any(|x| x.len() != BLOCK_SIZE)
+            || left.len() < BLOCK_SIZE
+            || right.len() < BLOCK_SIZE
+        {
+            anyhow::bail!(
+                "transform_planar takes {} buffers of {} frames",
+                self.input_channels(),
+                BLOCK_SIZE
+            );
+        }
+
+        no_alloc(|| {
+            let rendered = match self.matrix {
+                Some(_) => {
+                    self.mix_input(BLOCK_SIZE, |c, s| input[c][s]);
+
+                    let matrix_space = std::mem::take(&mut self.matrix_space);
+                    let rendered = self.render(&matrix_space);
+                    self.matrix_space = matrix_space;
+                    rendered
+                }
+                None => self.render_with(BLOCK_SIZE, |c, s| input[c][s]),
+            };
+
+            if !rendered? {
+                left[..BLOCK_SIZE].fill(0f32);
+                right[..BLOCK_SIZE].fill(0f32);
+                return Ok(ProcessStatus::Priming);
+            }
+
+            self.protection.process_planar(
+                (
+                    &self.left_out_space[..BLOCK_SIZE],
+                    &self.right_out_space[..BLOCK_SIZE],
+                ),
+                left,
+                right,
+            );
+            self.apply_fade_in(&mut [left, right], 1);
+
+            Ok(ProcessStatus::Rendered)
+        })
+    }
+
+    fn check_block<T>(
+        &self,
+        input: &[T],
+        output: &[T],
+        input_channels: usize,
+        output_channels: usize,
+    ) -> anyhow::Result<()> {
+        if input.len() != BLOCK_SIZE * input_channels || output.len() < BLOCK_SIZE * output_channels
+        {
+            anyhow::bail!(
+                "transform takes blocks of {} frames, got {} input and {} output frames, use a BlockAdapter for other buffer sizes",
+                BLOCK_SIZE,
+                input.len() / input_channels,
+                output.len() / output_channels
+            );
+        }
+
+        Ok(())
+    }
+
+    /// frames in `input` for [`transform`](VirtualSurroundFilter::transform), whole ones and no
+    /// more than the window holds, with room for a block in `output`
+    fn check_frames(
+        &self,
+        input: &[f32],
+        output: &[f32],
+        input_channels: usize,
+    ) -> anyhow::Result<usize> {
+        let frames = input.len() / input_channels;
+        if input.len() % input_channels != 0
+            || frames > self.samples_required()
+            || output.len() < BLOCK_SIZE * 2
+        {
+            anyhow::bail!(
+                "transform takes whole frames of {} channels up to the window of {}, and room for {} output frames, got {} input samples and {} output frames",
+                input_channels,
+                self.samples_required(),
+                BLOCK_SIZE,
+                input.len(),
+                output.len() / 2
+            );
+        }
+
+        Ok(frames)
+    }
+
+    /// ingest a block and render the stereo output before protection into the out spaces,
+    /// `false` while the window is still filling
+    fn render(&mut self, input: &[f32]) -> anyhow::Result<bool> {
+        let channels = self.channels();
+        self.render_with(input.len() / channels, |c, s| input[s * channels + c])
+    }
+
+    /// [`render`](VirtualSurroundFilter::render) taking sample `s` of channel `c` from `input`
+    fn render_with<F: Fn(usize, usize) -> f32>(
+        &mut self,
+        sample_count: usize,
+        input: F,
+    ) -> anyhow::Result<bool> {
+        trace_span!(TRACE, "render", frames = sample_count);
+        let started = self.timing.as_ref().map(|_| Instant::now());
+        self.drained = 0;
+
+        let move_data = if self.available_data + sample_count > self.samples_required() {
+            self.available_data = self.samples_required() - sample_count;
+            sample_count
+        } else {
+            0
+        };
+
+        let mut peak = 0f32;
+
+        for c in 0..self.channels() {
+            if move_data > 0 {
+                self.in_space[c].copy_within(move_data.., 0);
+            }
+
+            for s in 0..sample_count {
+                self.in_space[c][self.available_data + s] = input(c, s);
+            }
+        }
+
+        let channels = self.inner.channels();
+        self.rotation.process(
+            &mut self.in_space[..channels],
+            self.available_data,
+            sample_count,
+        );
+
+        for c in 0..self.channels() {
+            if self.state_audit {
+                let range = self.available_data..self.available_data + sample_count;
+                peak = self.in_space[c][range]
+                    .iter()
+                    .fold(peak, |peak, x| peak.max(x.abs()));
+            }
+
+            if let Some(metering) = &mut self.metering {
+                let range = self.available_data..self.available_data + sample_count;
+                metering.add(c, &self.in_space[c][range]);
+            }
+
+            if self.distances.is_active() {
+                let range = self.available_data..self.available_data + sample_count;
+                self.distances.process(c, &mut self.in_space[c][range]);
+            }
+        }
+
+        if let Some(bass) = &mut self.bass {
+            if move_data > 0 {
+                bass.shift(move_data);
+            }
+
+            let channels = self.inner.channels();
+            bass.process(
+                &mut self.in_space[..channels],
+                self.available_data,
+                sample_count,
+            );
+        }
+
+        self.available_data += sample_count;
+
+        if self.available_data < self.samples_required() {
+            if let Some(metering) = &mut self.metering {
+                metering.publish();
+            }
+
+            return Ok(false);
+        }
+
+        self.left_out_space.fill(0f32);
+        self.right_out_space.fill(0f32);
+
+        let left = &mut self.left_out_space;
+        let right = &mut self.right_out_space;
+        let silence = &self.silence;
+        let virtualized = &self.virtualized;
+
+        // on the stack, a channel map holds no more than MAX_CHANNELS
+        let mut channels: [&[f32]; MAX_CHANNELS] = [&[]; MAX_CHANNELS];
+        for (c, (channel, x)) in channels.iter_mut().zip(&self.in_space).enumerate() {
+            *channel = match virtualized {
+                Some(virtualized) if virtualized.get(c) == Some(&false) => silence.as_slice(),
+                _ => x.as_slice(),
+            };
+        }
+        let input = &channels[..self.in_space.len()];
+        self.inner.transform(input, (left, right))?;
+
+        let switched = match &mut self.profile_switch {
+            Some(switch) => switch.process(
+                input,
+                &mut self.profile_space,
+                &mut self.left_out_space[..BLOCK_SIZE],
+                &mut self.right_out_space[..BLOCK_SIZE],
+            )?,
+            None => false,
+        };
+        if switched {
+            self.finish_profile_switch();
+        }
+
+        if let Some(bass) = &self.bass {
+            bass.mix_into(
+                self.samples_required(),
+                &mut self.left_out_space[..BLOCK_SIZE],
+                &mut self.right_out_space[..BLOCK_SIZE],
+            );
+        }
+
+        let start = self.samples_required() - BLOCK_SIZE;
+        if let Some(reverb) = &mut self.reverb {
+            self.reverb_space.fill(0f32);
+            for (c, channel) in self.in_space.iter().enumerate() {
+                let send = self.reverb_sends[c];
+                if send == 0.0
+                    || self
+                        .virtualized
+                        .as_ref()
+                        .is_some_and(|x| x.get(c) == Some(&false))
+                {
+                    continue;
+                }
+
+                for (sample, x) in self.reverb_space.iter_mut().zip(&channel[start..]) {
+                    *sample += x * send;
+                }
+            }
+
+            reverb.process(
+                &self.reverb_space,
+                &mut self.left_out_space[..BLOCK_SIZE],
+                &mut self.right_out_space[..BLOCK_SIZE],
+            );
+        }
+
+        let blending = self.mix_ramp.value() < 1.0 || self.mix_ramp.is_ramping();
+        if blending
+            || self.loudness.is_some()
+            || self.bypass_matching.is_some()
+            || self.blend.is_some()
+            || self.coloration.is_some()
+        {
+            let end = self.samples_required() - self.inner.ir_delay();
+            let channels = self.channels();
+            self.dry
+                .render(&self.in_space[..channels], end, self.bass.as_ref());
+        }
+
+        if let Some(coloration) = &mut self.coloration {
+            coloration.process(
+                self.dry.output(),
+                (
+                    &self.left_out_space[..BLOCK_SIZE],
+                    &self.right_out_space[..BLOCK_SIZE],
+                ),
+            );
+        }
+
+        if let Some(blend) = &mut self.blend {
+            blend.process(
+                self.dry.output(),
+                &mut self.left_out_space[..BLOCK_SIZE],
+                &mut self.right_out_space[..BLOCK_SIZE],
+            );
+        }
+
+        if let Some(eq) = &mut self.eq {
+            eq.process(
+                &mut self.left_out_space[..BLOCK_SIZE],
+                &mut self.right_out_space[..BLOCK_SIZE],
+            )?;
+        }
+
+        if let Some(loudness) = &mut self.loudness {
+            loudness.process(
+                self.dry.output(),
+                &mut self.left_out_space[..BLOCK_SIZE],
+                &mut self.right_out_space[..BLOCK_SIZE],
+            );
+        }
+
+        if let Some(matching) = &mut self.bypass_matching {
+            let (left, right) = self.dry.output_mut();
+            matching.process(
+                (
+                    &self.left_out_space[..BLOCK_SIZE],
+                    &self.right_out_space[..BLOCK_SIZE],
+                ),
+                left,
+                right,
+            );
+        }
+
+        if blending {
+            self.dry.blend(
+                &mut self.mix_ramp,
+                &mut self.left_out_space[..BLOCK_SIZE],
+                &mut self.right_out_space[..BLOCK_SIZE],
+            );
+        }
+
+        if let Some(dc_block) = &mut self.dc_block {
+            dc_block.process(
+                &mut self.left_out_space[..BLOCK_SIZE],
+                &mut self.right_out_space[..BLOCK_SIZE],
+            );
+        }
+
+        if let Some(transaural) = &mut self.transaural {
+            transaural.process(
+                &mut self.left_out_space[..BLOCK_SIZE],
+                &mut self.right_out_space[..BLOCK_SIZE],
+            );
+        }
+
+        let channels = self.channels();
+        if let Some(metering) = &mut self.metering {
+            metering.add(channels, &self.left_out_space[..BLOCK_SIZE]);
+            metering.add(channels + 1, &self.right_out_space[..BLOCK_SIZE]);
+            metering.publish();
+        }
+
+        if let Some(spectrum) = &mut self.spectrum {
+            spectrum.process(
+                &self.left_out_space[..BLOCK_SIZE],
+                &self.right_out_space[..BLOCK_SIZE],
+            );
+        }
+
+        if self.state_audit {
+            self.audit_state(peak, sample_count);
+        }
+
+        if let (Some(timing), Some(started)) = (&self.timing, started) {
+            timing.add(started.elapsed());
+        }
+
+        Ok(true)
+    }
+
+    /// clears the state once the input has been silent long enough for everything to have
+    /// decayed, or when the output stopped being finite
+    fn audit_state(&mut self, peak: f32, sample_count: usize) {
+        let settled = self.samples_required() + self.sample_rate();
+        let was_silent = self.silent_frames;
+        self.silent_frames = if peak < AUDIT_SILENCE {
+            self.silent_frames.saturating_add(sample_count)
+        } else {
+            0
+        };
+
+        let broken = self.left_out_space[..BLOCK_SIZE]
+            .iter()
+            .chain(&self.right_out_space[..BLOCK_SIZE])
+            .any(|x| !x.is_finite());
+
+        if broken || (was_silent < settled && self.silent_frames >= settled) {
+            trace_event!(DEBUG, broken, "state audit cleared the filter state");
+            self.clear_state();
+            self.left_out_space.fill(0f32);
+            self.right_out_space.fill(0f32);
+            self.state_resets += 1;
+        }
+    }
+
+    /// zeroes everything carried from one block to the next, the window included
+    fn clear_state(&mut self) {
+        self.faded = 0;
+        self.finish_profile_switch();
+
+        for channel in &mut self.in_space {
+            channel.fill(0f32);
+        }
+
+        self.inner.reset();
+        self.upmixer.reset();
+        self.distances.reset();
+        self.protection.reset();
+        self.pcm.reset();
+
+        if let Some(bass) = &mut self.bass {
+            bass.reset();
+        }
+
+        if let Some(eq) = &mut self.eq {
+            eq.reset();
+        }
+
+        if let Some(blend) = &mut self.blend {
+            blend.reset();
+        }
+
+        if let Some(reverb) = &mut self.reverb {
+            reverb.reset();
+        }
+
+        if let Some(dc_block) = &mut self.dc_block {
+            dc_block.reset();
+        }
+
+        if let Some(transaural) = &mut self.transaural {
+            transaural.reset();
+        }
+
+        for protection in &mut self.passthrough_protection {
+            protection.reset();
+        }
+    }
+
+    /// like [`transform`](VirtualSurroundFilter::transform) once
+    /// [`set_virtualized_channels`](VirtualSurroundFilter::set_virtualized_channels) picked the
+    /// channels to virtualize, `output` gets the interleaved
+    /// [`passthrough_positions`](VirtualSurroundFilter::passthrough_positions) with the binaural
+    /// rendering mixed into the front pair
+    pub fn transform_passthrough(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> anyhow::Result<ProcessStatus> {
+        let passthrough = match &self.virtualized {
+            Some(virtualized) => virtualized.iter().filter(|x| !**x).count(),
+            None => anyhow::bail!("No channels are passed through, use transform instead"),
+        };
+
+        self.check_block(input, output, self.input_channels(), passthrough)?;
+
+        no_alloc(|| {
+            let rendered = match self.matrix {
+                Some(_) => {
+                    let channels = self.input_channels();
+                    self.mix_input(BLOCK_SIZE, |c, s| input[s * channels + c]);
+
+                    let matrix_space = std::mem::take(&mut self.matrix_space);
+                    let rendered = self.render(&matrix_space);
+                    self.matrix_space = matrix_space;
+                    rendered
+                }
+                None => self.render(input),
+            };
+
+            if !rendered? {
+                output[..BLOCK_SIZE * passthrough].fill(0f32);
+                return Ok(ProcessStatus::Priming);
+            }
+
+            // aligned with the direct sound of the HRIR, like the dry path
+            let end = self.samples_required() - self.inner.ir_delay();
+            let range = end - BLOCK_SIZE..end;
+            let (front, others) = self.passthrough_columns.split_at(2);
+
+            for (out, (channel, _)) in [
+                (&mut self.left_out_space, front[0]),
+                (&mut self.right_out_space, front[1]),
+            ] {
+                for (sample, input) in out.iter_mut().zip(&self.in_space[channel][range.clone()]) {
+                    *sample += input;
+                }
+            }
+
+            let stereo = &mut self.passthrough_space;
+            self.protection.process(
+                &self.left_out_space[..BLOCK_SIZE],
+                &self.right_out_space[..BLOCK_SIZE],
+                stereo,
+            );
+
+            for (offset, (_, column)) in front.iter().enumerate() {
+                write_column(output, passthrough, *column, stereo, offset);
+            }
+
+            // the other speakers go through their own protection, so a limiter delays them equally
+            for (pair, protection) in others.chunks(2).zip(&mut self.passthrough_protection) {
+                let first = &self.in_space[pair[0].0][range.clone()];
+                let second = match pair.get(1) {
+                    Some((channel, _)) => &self.in_space[*channel][range.clone()],
+                    None => &self.silence[..BLOCK_SIZE],
+                };
+
+                protection.process(first, second, stereo);
+
+                for (offset, (_, column)) in pair.iter().enumerate() {
+                    write_column(output, passthrough, *column, stereo, offset);
+                }
+            }
+
+            self.apply_fade_in(&mut [output], passthrough);
+
+            Ok(ProcessStatus::Rendered)
+        })
+    }
+}
+
+/// engine [`RawVirtualSurroundFilter::from_hrir`] convolves with, other builds pick one at runtime
+/// with an [`Engine`]
 #[cfg(feature = "rustfft")]
 pub type CurrentFFTLogic = rustfft::RustFFTLogic;
 
 #[cfg(test)]
 mod tests {
-    use crate::VirtualSurroundFilter;
+    use crate::{BassManagement, OutputProtection, Speaker, VirtualSurroundFilter, BLOCK_SIZE};
     use std::fs::File;
 
     #[test]
@@ -490,4 +2710,66 @@ mod tests {
 
         println!("{:#?}", filter)
     }
+
+    #[test]
+    pub fn compatible_with_reports_mismatch() {
+        let filter = VirtualSurroundFilter::new_from_hrir(
+            File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap(),
+        )
+        .unwrap();
+
+        let layout = filter.positions().collect::<Vec<_>>();
+        assert!(filter.compatible_with(44100, &layout).is_ok());
+
+        let report = filter
+            .compatible_with(
+                48000,
+                &[Speaker::FrontLeft, Speaker::FrontRight, Speaker::TopCenter],
+            )
+            .unwrap_err();
+
+        assert_eq!(report.sample_rate, Some((44100, 48000)));
+        assert_eq!(report.missing, vec![Speaker::TopCenter]);
+        assert_eq!(report.unused.len(), 4);
+        assert!(!report.reordered);
+    }
+
+    #[test]
+    pub fn state_audit_clears_state_in_silence() {
+        let mut filter = VirtualSurroundFilter::new_from_hrir(
+            File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap(),
+        )
+        .unwrap();
+        filter.set_bass_management(Some(BassManagement::default()));
+        filter.set_output_protection(OutputProtection::lookahead_limiter());
+        filter.set_state_audit(true);
+
+        let channels = filter.channels();
+        let noise = (0..BLOCK_SIZE * channels)
+            .map(|x| ((x * 7919) % 1000) as f32 / 5000.0 - 0.1)
+            .collect::<Vec<_>>();
+        let silence = vec![0f32; noise.len()];
+        let mut output = vec![0f32; BLOCK_SIZE * 2];
+
+        for _ in 0..20 {
+            filter.transform(&noise, &mut output).unwrap();
+        }
+        assert_eq!(filter.state_resets(), 0);
+
+        let settled = (filter.samples_required() + filter.sample_rate()) / BLOCK_SIZE + 1;
+        for _ in 0..settled + 10 {
+            filter.transform(&silence, &mut output).unwrap();
+        }
+        assert_eq!(filter.state_resets(), 1);
+        assert!(output.iter().all(|x| *x == 0.0));
+
+        let mut broken = noise.clone();
+        broken[0] = f32::NAN;
+        filter.transform(&broken, &mut output).unwrap();
+        assert_eq!(filter.state_resets(), 2);
+        assert!(output.iter().all(|x| x.is_finite()));
+
+        filter.transform(&noise, &mut output).unwrap();
+        assert!(output.iter().all(|x| x.is_finite()));
+    }
 }
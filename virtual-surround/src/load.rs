@@ -0,0 +1,187 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// histogram buckets per doubling of the block time, the 99th percentile is this precise
+const BUCKETS_PER_OCTAVE: f32 = 8.0;
+/// the lowest bucket starts at a microsecond, the highest ends after about a second
+const OCTAVES: usize = 20;
+const PERCENTILE: f64 = 0.99;
+
+/// Time taken to render the blocks since the previous [`DspLoad::snapshot`], against the
+/// `budget` a block of audio lasts. `blocks` is 0 if nothing was rendered in between.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct LoadSnapshot {
+    pub blocks: u64,
+    pub min: Duration,
+    pub mean: Duration,
+    pub max: Duration,
+    /// 99th percentile, rounded up to about 9%
+    pub p99: Duration,
+    pub budget: Duration,
+}
+
+impl LoadSnapshot {
+    /// mean time taken as a fraction of the budget
+    pub fn load(&self) -> f32 {
+        self.mean.as_secs_f32() / self.budget.as_secs_f32()
+    }
+
+    /// 99th percentile as a fraction of the budget
+    pub fn p99_load(&self) -> f32 {
+        self.p99.as_secs_f32() / self.budget.as_secs_f32()
+    }
+
+    /// more than one in a hundred blocks took longer than they last, the host can't keep up at
+    /// this block size on this machine, and the audio server will drop out
+    pub fn is_overloaded(&self) -> bool {
+        self.blocks > 0 && self.p99 >= self.budget
+    }
+}
+
+#[derive(Debug)]
+struct SharedTimes {
+    blocks: AtomicU64,
+    total_ns: AtomicU64,
+    min_ns: AtomicU64,
+    max_ns: AtomicU64,
+    histogram: Vec<AtomicU32>,
+}
+
+/// Handle to the block timing of a [`VirtualSurroundFilter`](crate::VirtualSurroundFilter),
+/// taken with [`dsp_load`](crate::VirtualSurroundFilter::dsp_load). Cheap to clone and read from
+/// any thread while the audio thread adds every block, nothing locks.
+#[derive(Debug, Clone)]
+pub struct DspLoad {
+    times: Arc<SharedTimes>,
+    budget: Duration,
+}
+
+impl DspLoad {
+    /// statistics of the blocks since the previous snapshot, which start over. A block finishing
+    /// while the snapshot is taken may be counted in this one or the next.
+    pub fn snapshot(&self) -> LoadSnapshot {
+        let times = &self.times;
+        let blocks = times.blocks.swap(0, Ordering::Relaxed);
+        let total = times.total_ns.swap(0, Ordering::Relaxed);
+        let min = times.min_ns.swap(u64::MAX, Ordering::Relaxed);
+        let max = times.max_ns.swap(0, Ordering::Relaxed);
+        let histogram = times
+            .histogram
+            .iter()
+            .map(|x| x.swap(0, Ordering::Relaxed) as u64)
+            .collect::<Vec<_>>();
+
+        if blocks == 0 {
+            return LoadSnapshot {
+                budget: self.budget,
+                ..LoadSnapshot::default()
+            };
+        }
+
+        let wanted = (histogram.iter().sum::<u64>() as f64 * PERCENTILE).ceil() as u64;
+        let mut counted = 0;
+        let bucket = histogram
+            .iter()
+            .position(|x| {
+                counted += x;
+                counted >= wanted
+            })
+            .unwrap_or(histogram.len() - 1);
+
+        LoadSnapshot {
+            blocks,
+            min: Duration::from_nanos(min),
+            mean: Duration::from_nanos(total / blocks),
+            max: Duration::from_nanos(max),
+            p99: Duration::from_nanos(bucket_end(bucket).min(max)),
+            budget: self.budget,
+        }
+    }
+}
+
+/// nanoseconds the histogram `bucket` ends at
+fn bucket_end(bucket: usize) -> u64 {
+    (1000.0 * 2f32.powf((bucket + 1) as f32 / BUCKETS_PER_OCTAVE)) as u64
+}
+
+/// The audio thread side of the [`DspLoad`]
+#[derive(Debug)]
+pub(crate) struct LoadTiming {
+    times: Arc<SharedTimes>,
+    budget: Duration,
+}
+
+impl LoadTiming {
+    /// for blocks of `block_size` frames at `sample_rate`
+    pub fn new(block_size: usize, sample_rate: usize) -> Self {
+        let buckets = OCTAVES * BUCKETS_PER_OCTAVE as usize;
+
+        LoadTiming {
+            times: Arc::new(SharedTimes {
+                blocks: AtomicU64::new(0),
+                total_ns: AtomicU64::new(0),
+                min_ns: AtomicU64::new(u64::MAX),
+                max_ns: AtomicU64::new(0),
+                histogram: (0..buckets).map(|_| AtomicU32::new(0)).collect(),
+            }),
+            budget: Duration::from_secs_f64(block_size as f64 / sample_rate as f64),
+        }
+    }
+
+    pub fn dsp_load(&self) -> DspLoad {
+        DspLoad {
+            times: self.times.clone(),
+            budget: self.budget,
+        }
+    }
+
+    pub fn add(&self, elapsed: Duration) {
+        let ns = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+        let times = &self.times;
+        times.blocks.fetch_add(1, Ordering::Relaxed);
+        times.total_ns.fetch_add(ns, Ordering::Relaxed);
+        times.min_ns.fetch_min(ns, Ordering::Relaxed);
+        times.max_ns.fetch_max(ns, Ordering::Relaxed);
+
+        let octaves = (ns.max(1000) as f32 / 1000.0).log2();
+        let bucket = ((octaves * BUCKETS_PER_OCTAVE) as usize).min(times.histogram.len() - 1);
+        times.histogram[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoadTiming;
+    use std::time::Duration;
+
+    #[test]
+    fn reports_block_time_statistics() {
+        // 512 frames at 48khz last 10.67ms
+        let timing = LoadTiming::new(512, 48000);
+        let load = timing.dsp_load();
+
+        for _ in 0..98 {
+            timing.add(Duration::from_millis(2));
+        }
+        timing.add(Duration::from_millis(8));
+        timing.add(Duration::from_millis(20));
+
+        let snapshot = load.snapshot();
+        assert_eq!(snapshot.blocks, 100);
+        assert_eq!(snapshot.min, Duration::from_millis(2));
+        assert_eq!(snapshot.max, Duration::from_millis(20));
+        assert_eq!(snapshot.mean, Duration::from_micros(2240));
+        assert!(snapshot.p99 >= Duration::from_millis(8));
+        assert!(snapshot.p99 < Duration::from_micros(8800));
+        assert!((snapshot.load() - 0.21).abs() < 1e-3);
+        assert!(!snapshot.is_overloaded());
+
+        // the next snapshot starts over
+        assert_eq!(load.snapshot().blocks, 0);
+        for _ in 0..10 {
+            timing.add(Duration::from_millis(11));
+        }
+        assert!(load.snapshot().is_overloaded());
+    }
+}
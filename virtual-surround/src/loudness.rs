@@ -0,0 +1,236 @@
+use crate::biquad::{Biquad, BUTTERWORTH_Q};
+
+/// blocks quieter than this never count towards the integrated loudness
+const ABSOLUTE_GATE: f32 = -70.0;
+/// histogram resolution and range for gating, in LU
+const HISTOGRAM_STEP: f32 = 0.1;
+const HISTOGRAM_MAX: f32 = 10.0;
+/// make-up gain is kept within this many dB
+const MAX_MAKEUP_DB: f32 = 12.0;
+
+fn energy_to_lufs(energy: f64) -> f32 {
+    (-0.691 + 10.0 * energy.log10()) as f32
+}
+
+fn lufs_to_energy(lufs: f32) -> f64 {
+    10f64.powf((lufs as f64 + 0.691) / 10.0)
+}
+
+/// EBU R128 / ITU-R BS.1770 loudness meter for a stereo signal
+#[derive(Debug, Clone)]
+pub struct LoudnessMeter {
+    filters: [[Biquad; 2]; 2],
+    step_len: usize,
+    step_count: usize,
+    step_energy: f64,
+    steps: [f64; 4],
+    steps_filled: usize,
+    momentary: f32,
+    histogram: Vec<u32>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: usize) -> Self {
+        // k-weighting, head shelf followed by the RLB high pass, as cookbook parameters
+        let k_weighting = [
+            Biquad::high_shelf(sample_rate, 1500.0, BUTTERWORTH_Q, 4.0),
+            Biquad::high_pass(sample_rate, 38.0, 0.5),
+        ];
+
+        LoudnessMeter {
+            filters: [k_weighting, k_weighting],
+            step_len: (sample_rate / 10).max(1),
+            step_count: 0,
+            step_energy: 0.0,
+            steps: [0.0; 4],
+            steps_filled: 0,
+            momentary: f32::NEG_INFINITY,
+            histogram: vec![0; ((HISTOGRAM_MAX - ABSOLUTE_GATE) / HISTOGRAM_STEP) as usize],
+        }
+    }
+
+    pub fn process(&mut self, left: &[f32], right: &[f32]) {
+        for (left, right) in left.iter().zip(right) {
+            let [left_filter, right_filter] = &mut self.filters;
+            let left = left_filter.iter_mut().fold(*left, |x, f| f.process(x)) as f64;
+            let right = right_filter.iter_mut().fold(*right, |x, f| f.process(x)) as f64;
+
+            self.step_energy += left * left + right * right;
+            self.step_count += 1;
+
+            if self.step_count == self.step_len {
+                self.finish_step();
+            }
+        }
+    }
+
+    /// every 100ms a 400ms block is complete
+    fn finish_step(&mut self) {
+        self.steps.rotate_left(1);
+        self.steps[3] = self.step_energy / self.step_len as f64;
+        self.step_energy = 0.0;
+        self.step_count = 0;
+
+        if self.steps_filled < 4 {
+            self.steps_filled += 1;
+            if self.steps_filled < 4 {
+                return;
+            }
+        }
+
+        self.momentary = energy_to_lufs(self.steps.iter().sum::<f64>() / 4.0);
+
+        if self.momentary >= ABSOLUTE_GATE {
+            let bin = ((self.momentary - ABSOLUTE_GATE) / HISTOGRAM_STEP) as usize;
+            let bin = bin.min(self.histogram.len() - 1);
+            self.histogram[bin] += 1;
+        }
+    }
+
+    /// loudness of the last 400ms in LUFS, `-inf` until 400ms have been measured
+    pub fn momentary(&self) -> f32 {
+        self.momentary
+    }
+
+    /// gated loudness of everything measured since construction or [`reset`](LoudnessMeter::reset)
+    /// in LUFS, `-inf` if nothing was loud enough to pass the gate
+    pub fn integrated(&self) -> f32 {
+        let bin_energy =
+            |bin: usize| lufs_to_energy(ABSOLUTE_GATE + (bin as f32 + 0.5) * HISTOGRAM_STEP);
+
+        let mean = |from: usize| {
+            let (count, energy) = self.histogram[from..].iter().enumerate().fold(
+                (0u64, 0f64),
+                |(count, energy), (bin, blocks)| {
+                    (
+                        count + *blocks as u64,
+                        energy + bin_energy(from + bin) * *blocks as f64,
+                    )
+                },
+            );
+
+            if count == 0 {
+                None
+            } else {
+                Some(energy / count as f64)
+            }
+        };
+
+        let relative_gate = match mean(0) {
+            Some(energy) => energy_to_lufs(energy) - 10.0,
+            None => return f32::NEG_INFINITY,
+        };
+
+        let from = ((relative_gate - ABSOLUTE_GATE) / HISTOGRAM_STEP).max(0.0) as usize;
+
+        mean(from.min(self.histogram.len()))
+            .map(energy_to_lufs)
+            .unwrap_or(f32::NEG_INFINITY)
+    }
+
+    pub fn reset(&mut self) {
+        for filter in self.filters.iter_mut().flatten() {
+            filter.reset();
+        }
+
+        self.step_count = 0;
+        self.step_energy = 0.0;
+        self.steps = [0.0; 4];
+        self.steps_filled = 0;
+        self.momentary = f32::NEG_INFINITY;
+        self.histogram.fill(0);
+    }
+}
+
+/// Loudness of the virtualized output, before make-up gain, and of the plain stereo downmix it's
+/// matched to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LoudnessReading {
+    pub momentary: f32,
+    pub integrated: f32,
+    pub reference_momentary: f32,
+    pub reference_integrated: f32,
+    /// make-up gain currently applied to the virtualized output
+    pub gain_db: f32,
+}
+
+/// Measures the virtualized output and the downmix and moves the output gain towards the
+/// difference in integrated loudness
+#[derive(Debug, Clone)]
+pub(crate) struct LoudnessMatcher {
+    output: LoudnessMeter,
+    reference: LoudnessMeter,
+    gain_db: f32,
+}
+
+impl LoudnessMatcher {
+    pub fn new(sample_rate: usize) -> Self {
+        LoudnessMatcher {
+            output: LoudnessMeter::new(sample_rate),
+            reference: LoudnessMeter::new(sample_rate),
+            gain_db: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, reference: (&[f32], &[f32]), left: &mut [f32], right: &mut [f32]) {
+        self.reference.process(reference.0, reference.1);
+        self.output.process(left, right);
+
+        let target = self.reference.integrated() - self.output.integrated();
+        let target = if target.is_finite() {
+            target.clamp(-MAX_MAKEUP_DB, MAX_MAKEUP_DB)
+        } else {
+            self.gain_db
+        };
+
+        // ramp over the block so gain changes don't click
+        let from = 10f32.powf(self.gain_db / 20.0);
+        let to = 10f32.powf(target / 20.0);
+        let len = left.len() as f32;
+
+        for (s, (left, right)) in left.iter_mut().zip(right.iter_mut()).enumerate() {
+            let gain = from + (to - from) * (s + 1) as f32 / len;
+            *left *= gain;
+            *right *= gain;
+        }
+
+        self.gain_db = target;
+    }
+
+    pub fn reading(&self) -> LoudnessReading {
+        LoudnessReading {
+            momentary: self.output.momentary(),
+            integrated: self.output.integrated(),
+            reference_momentary: self.reference.momentary(),
+            reference_integrated: self.reference.integrated(),
+            gain_db: self.gain_db,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoudnessMeter;
+
+    #[test]
+    fn full_scale_stereo_sine_reads_zero() {
+        // a 1khz full scale sine reads -3.01 LUFS per channel, 0 LUFS in both
+        let mut meter = LoudnessMeter::new(48000);
+        let sine = (0..48000 * 3)
+            .map(|x| (x as f32 * 2.0 * std::f32::consts::PI * 1000.0 / 48000.0).sin())
+            .collect::<Vec<_>>();
+
+        meter.process(&sine, &sine);
+
+        assert!(
+            (meter.momentary() - 0.0).abs() < 0.1,
+            "{}",
+            meter.momentary()
+        );
+        assert!(
+            (meter.integrated() - 0.0).abs() < 0.1,
+            "{}",
+            meter.integrated()
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use crate::{stereo_downmix_gains, Smoothed, Speaker};
+
+const HALF: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Gains from every input channel to every speaker of the filter, applied to the interleaved
+/// input before it's virtualized. See [`automatic`](MixingMatrix::automatic) for the standard
+/// downmix of content with more channels than the HRIR.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixingMatrix {
+    inputs: Vec<Speaker>,
+    outputs: Vec<Speaker>,
+    /// row per output, column per input
+    gains: Vec<f32>,
+}
+
+/// ways to fold `mask` into other speakers, the first one with all of its speakers present wins
+fn fold_options(mask: Speaker) -> &'static [&'static [(Speaker, f32)]] {
+    use Speaker::*;
+
+    match mask {
+        FrontCenter => &[&[(FrontLeft, HALF), (FrontRight, HALF)]],
+        FrontCenterLeft => &[
+            &[(FrontLeft, HALF), (FrontCenter, HALF)],
+            &[(FrontLeft, 1.0)],
+        ],
+        FrontCenterRight => &[
+            &[(FrontRight, HALF), (FrontCenter, HALF)],
+            &[(FrontRight, 1.0)],
+        ],
+        SideLeft => &[&[(BackLeft, 1.0)]],
+        SideRight => &[&[(BackRight, 1.0)]],
+        BackLeft => &[&[(SideLeft, 1.0)]],
+        BackRight => &[&[(SideRight, 1.0)]],
+        BackCenter => &[
+            &[(BackLeft, HALF), (BackRight, HALF)],
+            &[(SideLeft, HALF), (SideRight, HALF)],
+        ],
+        TopFrontLeft => &[&[(FrontLeft, HALF)]],
+        TopFrontRight => &[&[(FrontRight, HALF)]],
+        TopFrontCenter => &[
+            &[(FrontCenter, HALF)],
+            &[(FrontLeft, 0.5), (FrontRight, 0.5)],
+        ],
+        TopBackLeft => &[&[(BackLeft, HALF)], &[(SideLeft, HALF)]],
+        TopBackRight => &[&[(BackRight, HALF)], &[(SideRight, HALF)]],
+        TopBackCenter => &[
+            &[(BackCenter, HALF)],
+            &[(BackLeft, 0.5), (BackRight, 0.5)],
+            &[(SideLeft, 0.5), (SideRight, 0.5)],
+        ],
+        TopCenter => &[
+            &[
+                (FrontLeft, 0.5),
+                (FrontRight, 0.5),
+                (BackLeft, 0.5),
+                (BackRight, 0.5),
+            ],
+            &[
+                (FrontLeft, 0.5),
+                (FrontRight, 0.5),
+                (SideLeft, 0.5),
+                (SideRight, 0.5),
+            ],
+        ],
+        FrontWideLeft => &[
+            &[(FrontLeft, HALF), (SideLeft, HALF)],
+            &[(FrontLeft, HALF), (BackLeft, HALF)],
+            &[(FrontLeft, 1.0)],
+        ],
+        FrontWideRight => &[
+            &[(FrontRight, HALF), (SideRight, HALF)],
+            &[(FrontRight, HALF), (BackRight, HALF)],
+            &[(FrontRight, 1.0)],
+        ],
+        TopSideLeft => &[
+            &[(TopFrontLeft, HALF), (TopBackLeft, HALF)],
+            &[(SideLeft, HALF)],
+            &[(BackLeft, HALF)],
+        ],
+        TopSideRight => &[
+            &[(TopFrontRight, HALF), (TopBackRight, HALF)],
+            &[(SideRight, HALF)],
+            &[(BackRight, HALF)],
+        ],
+        BottomFrontLeft => &[&[(FrontLeft, HALF)]],
+        BottomFrontRight => &[&[(FrontRight, HALF)]],
+        BottomFrontCenter => &[
+            &[(FrontCenter, HALF)],
+            &[(FrontLeft, 0.5), (FrontRight, 0.5)],
+        ],
+        LowFrequency2 => &[&[(LowFrequency, HALF)]],
+        // bass is left to bass management, direct outs have no place to go and the front pair
+        // is the stereo downmix
+        FrontLeft | FrontRight | LowFrequency | DirectOut => &[],
+    }
+}
+
+impl MixingMatrix {
+    /// a matrix of `inputs` to `outputs` with every gain at 0
+    pub fn new(inputs: &[Speaker], outputs: &[Speaker]) -> Self {
+        MixingMatrix {
+            inputs: inputs.to_vec(),
+            outputs: outputs.to_vec(),
+            gains: vec![0f32; inputs.len() * outputs.len()],
+        }
+    }
+
+    /// routes every input to the speaker with the same position, inputs without one are folded
+    /// into the nearest speakers with the usual -3dB downmix coefficients, e.g. side to back,
+    /// heights to the speaker below, center to the front pair. Whatever is left ends up in the
+    /// stereo downmix of the front pair, the LFE is dropped if there's no LFE speaker.
+    pub fn automatic(inputs: &[Speaker], outputs: &[Speaker]) -> Self {
+        let mut matrix = MixingMatrix::new(inputs, outputs);
+        let find = |mask: Speaker| outputs.iter().position(|x| *x == mask);
+
+        for (input, mask) in inputs.iter().copied().enumerate() {
+            if mask == Speaker::DirectOut {
+                continue;
+            }
+
+            if let Some(output) = find(mask) {
+                matrix.set_gain(input, output, 1.0);
+                continue;
+            }
+
+            let option = fold_options(mask)
+                .iter()
+                .find(|option| option.iter().all(|(mask, _)| find(*mask).is_some()));
+
+            if let Some(option) = option {
+                for (mask, gain) in option.iter() {
+                    matrix.set_gain(input, find(*mask).unwrap(), *gain);
+                }
+            } else if let (Some(left), Some(right)) =
+                (find(Speaker::FrontLeft), find(Speaker::FrontRight))
+            {
+                let (left_gain, right_gain) = stereo_downmix_gains(mask);
+                matrix.set_gain(input, left, left_gain);
+                matrix.set_gain(input, right, right_gain);
+            }
+        }
+
+        matrix
+    }
+
+    pub fn inputs(&self) -> &[Speaker] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[Speaker] {
+        &self.outputs
+    }
+
+    /// gain from the `input`th channel to the `output`th speaker
+    pub fn gain(&self, input: usize, output: usize) -> f32 {
+        self.gains[output * self.inputs.len() + input]
+    }
+
+    pub fn set_gain(&mut self, input: usize, output: usize, gain: f32) {
+        assert!(input < self.inputs.len() && output < self.outputs.len());
+        self.gains[output * self.inputs.len() + input] = gain;
+    }
+
+    /// inputs which don't reach any speaker
+    pub fn dropped(&self) -> Vec<Speaker> {
+        self.inputs
+            .iter()
+            .enumerate()
+            .filter(|(input, _)| (0..self.outputs.len()).all(|x| self.gain(*input, x) == 0.0))
+            .map(|(_, mask)| *mask)
+            .collect()
+    }
+
+    pub(crate) fn gains(&self) -> &[f32] {
+        &self.gains
+    }
+
+    /// `frames` of sample `s` of input `c` to interleaved frames of the outputs. Given the
+    /// gains of a `previous` matrix of the same shape they crossfade to these as `fade` ramps
+    /// from 0 to 1.
+    pub(crate) fn process_with<F: Fn(usize, usize) -> f32>(
+        &self,
+        frames: usize,
+        input: F,
+        previous: Option<(&[f32], &mut Smoothed)>,
+        output: &mut [f32],
+    ) {
+        let inputs = self.inputs.len();
+        let output = &mut output[..frames * self.outputs.len()];
+
+        match previous {
+            None => {
+                for (s, out) in output.chunks_exact_mut(self.outputs.len()).enumerate() {
+                    for (sample, row) in out.iter_mut().zip(self.gains.chunks_exact(inputs)) {
+                        *sample = row
+                            .iter()
+                            .enumerate()
+                            .map(|(c, gain)| gain * input(c, s))
+                            .sum();
+                    }
+                }
+            }
+            Some((previous, fade)) => {
+                for (s, out) in output.chunks_exact_mut(self.outputs.len()).enumerate() {
+                    let fade = fade.next_value();
+                    for (o, sample) in out.iter_mut().enumerate() {
+                        *sample = (0..inputs)
+                            .map(|c| {
+                                let from = previous[o * inputs + c];
+                                let gain = from + (self.gains[o * inputs + c] - from) * fade;
+                                gain * input(c, s)
+                            })
+                            .sum();
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MixingMatrix, HALF};
+    use crate::Smoothed;
+    use crate::Speaker::*;
+
+    #[test]
+    fn folds_7_1_4_into_5_1() {
+        let inputs = [
+            FrontLeft,
+            FrontRight,
+            FrontCenter,
+            LowFrequency,
+            BackLeft,
+            BackRight,
+            SideLeft,
+            SideRight,
+            TopFrontLeft,
+            TopFrontRight,
+            TopBackLeft,
+            TopBackRight,
+        ];
+        let outputs = [
+            FrontLeft,
+            FrontRight,
+            FrontCenter,
+            LowFrequency,
+            BackLeft,
+            BackRight,
+        ];
+        let matrix = MixingMatrix::automatic(&inputs, &outputs);
+
+        for channel in 0..outputs.len() {
+            assert_eq!(matrix.gain(channel, channel), 1.0);
+        }
+
+        assert_eq!(matrix.gain(6, 4), 1.0);
+        assert_eq!(matrix.gain(7, 5), 1.0);
+        assert_eq!(matrix.gain(8, 0), HALF);
+        assert_eq!(matrix.gain(11, 5), HALF);
+        assert_eq!(matrix.gain(11, 4), 0.0);
+        assert!(matrix.dropped().is_empty());
+
+        let mut output = vec![0f32; 6];
+        let mut input = [0f32; 12];
+        input[2] = 1.0;
+        input[6] = 0.5;
+        input[9] = 1.0;
+        matrix.process_with(1, |c, _| input[c], None, &mut output);
+        assert_eq!(output, vec![0.0, HALF, 1.0, 0.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn crossfades_from_the_previous_gains() {
+        let mut matrix = MixingMatrix::new(&[FrontLeft], &[FrontLeft, FrontRight]);
+        matrix.set_gain(0, 0, 1.0);
+        let previous = matrix.gains().to_vec();
+        matrix.set_gain(0, 0, 0.0);
+        matrix.set_gain(0, 1, 1.0);
+
+        let mut fade = Smoothed::new(0.0, 4);
+        fade.set(1.0);
+        let mut output = vec![0f32; 12];
+        matrix.process_with(6, |_, _| 1.0, Some((&previous, &mut fade)), &mut output);
+
+        assert_eq!(
+            output,
+            vec![0.75, 0.25, 0.5, 0.5, 0.25, 0.75, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn folds_what_is_left_into_stereo() {
+        let matrix = MixingMatrix::automatic(
+            &[FrontCenter, BackLeft, LowFrequency],
+            &[FrontLeft, FrontRight],
+        );
+
+        assert_eq!(matrix.gain(0, 0), HALF);
+        assert_eq!(matrix.gain(0, 1), HALF);
+        assert_eq!(matrix.gain(1, 0), HALF);
+        assert_eq!(matrix.gain(1, 1), 0.0);
+        assert_eq!(matrix.dropped(), vec![LowFrequency]);
+    }
+}
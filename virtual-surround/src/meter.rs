@@ -0,0 +1,187 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// the RMS is averaged over about this long
+const RMS_WINDOW_S: f32 = 0.3;
+/// a channel with an RMS below -90dBFS counts as silent
+const SILENCE: f32 = 3.16e-5;
+
+/// Level of a channel in a [`MeterSnapshot`], linear with 1.0 being full scale
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Level {
+    /// highest sample since the previous snapshot
+    pub peak: f32,
+    pub rms: f32,
+}
+
+impl Level {
+    pub fn peak_db(&self) -> f32 {
+        20.0 * self.peak.log10()
+    }
+
+    pub fn rms_db(&self) -> f32 {
+        20.0 * self.rms.log10()
+    }
+
+    pub fn is_clipping(&self) -> bool {
+        self.peak >= 1.0
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.rms < SILENCE
+    }
+}
+
+/// Levels of every speaker channel as it comes in, after any upmix or mixing matrix, and of the
+/// binaural output before the output protection, so clipping it catches shows up
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MeterSnapshot {
+    pub inputs: Vec<Level>,
+    pub left: Level,
+    pub right: Level,
+}
+
+/// peak and RMS per channel as f32 bits, the speakers followed by the left and right ear
+#[derive(Debug)]
+struct SharedLevels {
+    peaks: Vec<AtomicU32>,
+    rms: Vec<AtomicU32>,
+}
+
+/// Handle to the meters of a [`VirtualSurroundFilter`](crate::VirtualSurroundFilter), taken
+/// with [`meters`](crate::VirtualSurroundFilter::meters). Cheap to clone and read from any
+/// thread while the audio thread updates the levels every block, nothing locks.
+#[derive(Debug, Clone)]
+pub struct Meters {
+    levels: Arc<SharedLevels>,
+}
+
+impl Meters {
+    /// the current levels, the peaks are reset so the next snapshot has the ones since this one
+    pub fn snapshot(&self) -> MeterSnapshot {
+        let mut levels = self
+            .levels
+            .peaks
+            .iter()
+            .zip(&self.levels.rms)
+            .map(|(peak, rms)| Level {
+                peak: f32::from_bits(peak.swap(0, Ordering::Relaxed)),
+                rms: f32::from_bits(rms.load(Ordering::Relaxed)),
+            })
+            .collect::<Vec<_>>();
+
+        let right = levels.pop().unwrap_or_default();
+        let left = levels.pop().unwrap_or_default();
+
+        MeterSnapshot {
+            inputs: levels,
+            left,
+            right,
+        }
+    }
+}
+
+/// The audio thread side of the [`Meters`], collects a block and publishes it
+#[derive(Debug)]
+pub(crate) struct Metering {
+    levels: Arc<SharedLevels>,
+    sample_rate: usize,
+    peaks: Vec<f32>,
+    sums: Vec<f32>,
+    counts: Vec<usize>,
+    mean_squares: Vec<f32>,
+}
+
+impl Metering {
+    /// for `channels` speakers plus the two ears
+    pub fn new(channels: usize, sample_rate: usize) -> Self {
+        let channels = channels + 2;
+
+        Metering {
+            levels: Arc::new(SharedLevels {
+                peaks: (0..channels).map(|_| AtomicU32::new(0)).collect(),
+                rms: (0..channels).map(|_| AtomicU32::new(0)).collect(),
+            }),
+            sample_rate,
+            peaks: vec![0f32; channels],
+            sums: vec![0f32; channels],
+            counts: vec![0; channels],
+            mean_squares: vec![0f32; channels],
+        }
+    }
+
+    pub fn meters(&self) -> Meters {
+        Meters {
+            levels: self.levels.clone(),
+        }
+    }
+
+    /// add the samples of speaker `channel`, the ears come after the speakers
+    pub fn add(&mut self, channel: usize, samples: &[f32]) {
+        for sample in samples {
+            self.peaks[channel] = self.peaks[channel].max(sample.abs());
+            self.sums[channel] += sample * sample;
+        }
+        self.counts[channel] += samples.len();
+    }
+
+    /// publish the levels of the samples added since the last time, channels without any keep
+    /// their RMS
+    pub fn publish(&mut self) {
+        for channel in 0..self.peaks.len() {
+            let samples = std::mem::take(&mut self.counts[channel]);
+            if samples == 0 {
+                continue;
+            }
+
+            let keep = (-(samples as f32) / (RMS_WINDOW_S * self.sample_rate as f32)).exp();
+            let mean_square = &mut self.mean_squares[channel];
+            *mean_square = *mean_square * keep + self.sums[channel] / samples as f32 * (1.0 - keep);
+
+            // positive floats order like their bits
+            self.levels.peaks[channel].fetch_max(self.peaks[channel].to_bits(), Ordering::Relaxed);
+            self.levels.rms[channel].store(mean_square.sqrt().to_bits(), Ordering::Relaxed);
+
+            self.peaks[channel] = 0.0;
+            self.sums[channel] = 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metering;
+
+    #[test]
+    fn meters_peak_and_rms() {
+        let mut metering = Metering::new(2, 48000);
+        let meters = metering.meters();
+
+        let sine = (0..480)
+            .map(|s| (s as f32 / 48.0 * std::f32::consts::TAU).sin() * 0.5)
+            .collect::<Vec<_>>();
+        let clipping = vec![1.5f32; 480];
+
+        // well past the rms window
+        for _ in 0..500 {
+            metering.add(0, &sine);
+            metering.add(2, &clipping);
+            metering.publish();
+        }
+
+        let snapshot = meters.snapshot();
+        assert_eq!(snapshot.inputs.len(), 2);
+        assert!((snapshot.inputs[0].peak - 0.5).abs() < 1e-3);
+        assert!((snapshot.inputs[0].rms - 0.5 / 2f32.sqrt()).abs() < 1e-3);
+        assert!(snapshot.inputs[1].is_silent());
+        assert!(snapshot.left.is_clipping() && !snapshot.right.is_clipping());
+        assert!((snapshot.left.rms_db() - 20.0 * 1.5f32.log10()).abs() < 0.01);
+
+        // the peaks start over, the rms decays
+        metering.add(0, &[0f32; 480]);
+        metering.publish();
+        let snapshot = meters.snapshot();
+        assert_eq!(snapshot.inputs[0].peak, 0.0);
+        assert!(snapshot.inputs[0].rms > 0.3);
+    }
+}
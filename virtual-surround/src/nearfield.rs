@@ -0,0 +1,68 @@
+use crate::biquad::{Biquad, BUTTERWORTH_Q};
+use crate::distance::REFERENCE_DISTANCE;
+use crate::hrir::KEMAR_HEAD_RADIUS_CM;
+use crate::hrtf::SpeakerDirection;
+use crate::Speaker;
+
+/// below this the far-field HRIR has next to no ILD for the head to shadow, the near-field one
+/// comes from the ears' distances alone
+const SHELF_FREQUENCY: f32 = 1000.0;
+
+/// Gain in dB below [`SHELF_FREQUENCY`] at the left and right ear of a speaker `meters` away, on
+/// top of the inverse distance law. Each ear follows its own distance to the speaker on a
+/// spherical head, relative to the [`REFERENCE_DISTANCE`] the HRIR is measured at, which raises
+/// the ILD and the bass at the near ear as the speaker comes closer. Zero from the reference
+/// distance on and for the LFE.
+pub(crate) fn near_field_gains(position: Speaker, meters: f32) -> [f32; 2] {
+    let direction = match SpeakerDirection::standard(position) {
+        Some(direction) if !position.is_lfe() && meters < REFERENCE_DISTANCE => {
+            direction.cartesian()
+        }
+        _ => return [0.0; 2],
+    };
+
+    let radius = KEMAR_HEAD_RADIUS_CM / 100.0;
+    // ears at y = ±radius, the speaker kept outside the head
+    let amplitude = |ear: f32, distance: f32| {
+        let [x, y, z] = direction.map(|x| x * distance);
+        distance / (x * x + (y - ear * radius).powi(2) + z * z).sqrt()
+    };
+    let meters = meters.max(radius * 1.5);
+
+    [1.0, -1.0]
+        .map(|ear| 20.0 * (amplitude(ear, meters) / amplitude(ear, REFERENCE_DISTANCE)).log10())
+}
+
+/// `ir` with a low shelf of `gain_db`, see [`near_field_gains`]
+pub(crate) fn apply_near_field(ir: &[f32], sample_rate: usize, gain_db: f32) -> Vec<f32> {
+    let mut shelf = Biquad::low_shelf(sample_rate, SHELF_FREQUENCY, BUTTERWORTH_Q, gain_db);
+    ir.iter().map(|x| shelf.process(*x)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::near_field_gains;
+    use crate::Speaker;
+
+    #[test]
+    fn close_speakers_raise_the_ild() {
+        assert_eq!(near_field_gains(Speaker::SideLeft, 1.0), [0.0, 0.0]);
+        assert_eq!(near_field_gains(Speaker::SideLeft, 3.0), [0.0, 0.0]);
+        assert_eq!(near_field_gains(Speaker::LowFrequency, 0.2), [0.0, 0.0]);
+
+        let ild = |meters| {
+            let [near, far] = near_field_gains(Speaker::SideLeft, meters);
+            assert!(near > 0.0 && far < 0.0);
+            near - far
+        };
+        assert!(ild(0.5) > 1.0);
+        assert!(ild(0.25) > ild(0.5));
+        assert!(ild(0.01) > ild(0.25) && ild(0.01).is_finite());
+
+        let [left, right] = near_field_gains(Speaker::FrontRight, 0.5);
+        assert!(right > left);
+
+        let [left, right] = near_field_gains(Speaker::FrontCenter, 0.5);
+        assert!((left - right).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,274 @@
+/// Integer PCM samples, full scale maps to ±1.0
+pub trait IntegerSample: Copy {
+    /// float value of full scale
+    const SCALE: f64;
+    const MIN: f64;
+    const MAX: f64;
+
+    fn to_float(self) -> f32;
+    /// `value` is already scaled and rounded
+    fn from_scaled(value: f64) -> Self;
+}
+
+impl IntegerSample for i16 {
+    const SCALE: f64 = 32768.0;
+    const MIN: f64 = i16::MIN as f64;
+    const MAX: f64 = i16::MAX as f64;
+
+    fn to_float(self) -> f32 {
+        self as f32 / Self::SCALE as f32
+    }
+
+    fn from_scaled(value: f64) -> Self {
+        value as i16
+    }
+}
+
+impl IntegerSample for i32 {
+    const SCALE: f64 = 2147483648.0;
+    const MIN: f64 = i32::MIN as f64;
+    const MAX: f64 = i32::MAX as f64;
+
+    fn to_float(self) -> f32 {
+        (self as f64 / Self::SCALE) as f32
+    }
+
+    fn from_scaled(value: f64) -> Self {
+        value as i32
+    }
+}
+
+/// 24 bit PCM in the low bits of an `i32`, files and network streams mostly pack it into 3 bytes
+/// with [`to_le_bytes`](I24::to_le_bytes)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct I24(pub i32);
+
+impl I24 {
+    pub fn to_le_bytes(self) -> [u8; 3] {
+        let [a, b, c, _] = self.0.to_le_bytes();
+        [a, b, c]
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 3]) -> Self {
+        // the top byte sign extends
+        I24(i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8)
+    }
+}
+
+impl IntegerSample for I24 {
+    const SCALE: f64 = 8388608.0;
+    const MIN: f64 = -8388608.0;
+    const MAX: f64 = 8388607.0;
+
+    fn to_float(self) -> f32 {
+        (self.0 as f64 / Self::SCALE) as f32
+    }
+
+    fn from_scaled(value: f64) -> Self {
+        I24(value as i32)
+    }
+}
+
+/// Feeds the quantization error back so less of it ends up at low and mid frequencies, where
+/// hearing is most sensitive, and more towards Nyquist. Worth it at 16 bit and below.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum NoiseShaping {
+    #[default]
+    Off,
+    /// error shaped by `1 - z^-1`, rising 6dB per octave
+    FirstOrder,
+    /// error shaped by `(1 - z^-1)^2`, rising 12dB per octave, twice the noise in total
+    SecondOrder,
+}
+
+/// Converts float samples to integer PCM, rounding to the nearest step or with TPDF dither of ±1
+/// step so the quantization error doesn't follow the signal. Samples are interleaved stereo, the
+/// way the filter outputs them, when [`NoiseShaping`] keeps the error of each channel.
+#[derive(Debug, Clone)]
+pub struct PcmConverter {
+    dither: bool,
+    state: u32,
+    shaping: NoiseShaping,
+    /// last two errors of the left and right channel, in steps
+    error: [[f64; 2]; 2],
+}
+
+impl PcmConverter {
+    pub fn new(dither: bool) -> Self {
+        PcmConverter {
+            dither,
+            state: 0x2545_f491,
+            shaping: NoiseShaping::Off,
+            error: [[0.0; 2]; 2],
+        }
+    }
+
+    /// converter with the settings of this one and none of its state
+    pub(crate) fn fresh(&self) -> Self {
+        let mut converter = PcmConverter::new(self.dither);
+        converter.shaping = self.shaping;
+        converter
+    }
+
+    /// forget the errors fed back so far
+    pub fn reset(&mut self) {
+        self.error = [[0.0; 2]; 2];
+    }
+
+    pub fn dither(&self) -> bool {
+        self.dither
+    }
+
+    pub fn set_dither(&mut self, dither: bool) {
+        self.dither = dither;
+    }
+
+    pub fn noise_shaping(&self) -> NoiseShaping {
+        self.shaping
+    }
+
+    pub fn set_noise_shaping(&mut self, shaping: NoiseShaping) {
+        self.shaping = shaping;
+        self.reset();
+    }
+
+    /// uniform in -0.5..0.5
+    fn random(&mut self) -> f64 {
+        self.state = self
+            .state
+            .wrapping_mul(1_664_525)
+            .wrapping_add(1_013_904_223);
+        (self.state >> 8) as f64 / (1u32 << 24) as f64 - 0.5
+    }
+
+    fn quantize<T: IntegerSample>(&mut self, sample: f32, channel: usize) -> T {
+        let [last, before] = self.error[channel];
+        let value = sample as f64 * T::SCALE
+            - match self.shaping {
+                NoiseShaping::Off => 0.0,
+                NoiseShaping::FirstOrder => last,
+                NoiseShaping::SecondOrder => 2.0 * last - before,
+            };
+
+        let mut rounded = value;
+        if self.dither {
+            rounded += self.random() + self.random();
+        }
+        let rounded = rounded.round();
+
+        // clipping isn't fed back, it would only make the next samples clip too
+        if self.shaping != NoiseShaping::Off {
+            self.error[channel] = [(rounded - value).clamp(-2.0, 2.0), last];
+        }
+
+        T::from_scaled(rounded.clamp(T::MIN, T::MAX))
+    }
+
+    /// convert interleaved stereo `input` into `output`, samples beyond full scale are clamped
+    pub fn convert<T: IntegerSample>(&mut self, input: &[f32], output: &mut [T]) {
+        for (s, (out, sample)) in output.iter_mut().zip(input).enumerate() {
+            *out = self.quantize(*sample, s % 2);
+        }
+    }
+
+    /// [`convert`](PcmConverter::convert) separate `left` and `right` buffers, like the output of
+    /// [`transform_planar`](crate::VirtualSurroundFilter::transform_planar), into interleaved
+    /// `output`
+    pub fn convert_planar<T: IntegerSample>(
+        &mut self,
+        left: &[f32],
+        right: &[f32],
+        output: &mut [T],
+    ) {
+        for (frame, (left, right)) in output.chunks_exact_mut(2).zip(left.iter().zip(right)) {
+            frame[0] = self.quantize(*left, 0);
+            frame[1] = self.quantize(*right, 1);
+        }
+    }
+
+    /// [`convert`](PcmConverter::convert) into 24 bit PCM packed into 3 little endian bytes per
+    /// sample, the layout of 24 bit WAV files
+    pub fn convert_packed_i24(&mut self, input: &[f32], output: &mut [u8]) {
+        for (s, (out, sample)) in output.chunks_exact_mut(3).zip(input).enumerate() {
+            out.copy_from_slice(&self.quantize::<I24>(*sample, s % 2).to_le_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IntegerSample, NoiseShaping, PcmConverter, I24};
+
+    #[test]
+    fn rounds_clamps_and_dithers() {
+        let mut converter = PcmConverter::new(false);
+        let input = [0.5f32, -1.0, 1.0, 2.0, 1.0 / 32768.0 * 0.4];
+        let mut output = [0i16; 5];
+        converter.convert(&input, &mut output);
+        assert_eq!(output, [16384, -32768, 32767, 32767, 0]);
+        assert_eq!(16384i16.to_float(), 0.5);
+
+        // a constant a fraction of a step up averages out to that fraction
+        converter.set_dither(true);
+        let input = vec![0.25f32 / 32768.0; 100000];
+        let mut output = vec![0i16; input.len()];
+        converter.convert(&input, &mut output);
+        assert!(output.iter().all(|x| (-1..=1).contains(x)));
+        let mean = output.iter().map(|x| *x as f64).sum::<f64>() / output.len() as f64;
+        assert!((mean - 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn converts_to_24_bit_and_planar() {
+        for value in [0, 1, -1, 8388607, -8388608, 0x123456, -0x123456] {
+            assert_eq!(I24::from_le_bytes(I24(value).to_le_bytes()), I24(value));
+        }
+        assert_eq!(I24(-2).to_le_bytes(), [0xfe, 0xff, 0xff]);
+
+        let mut converter = PcmConverter::new(false);
+        let input = [0.5f32, -0.5, 1.0, -1.0];
+        let mut packed = [0u8; 12];
+        converter.convert_packed_i24(&input, &mut packed);
+        assert_eq!(
+            packed,
+            [0, 0, 0x40, 0, 0, 0xc0, 0xff, 0xff, 0x7f, 0, 0, 0x80]
+        );
+
+        let mut interleaved = [I24::default(); 4];
+        converter.convert(&input, &mut interleaved);
+        let mut planar = [I24::default(); 4];
+        converter.convert_planar(&[0.5, 1.0], &[-0.5, -1.0], &mut planar);
+        assert_eq!(planar, interleaved);
+        assert_eq!(interleaved[0].to_float(), 0.5);
+    }
+
+    #[test]
+    fn shapes_the_quantization_error() {
+        // a quiet low tone on the left, an offset on the right
+        let input = (0..20000)
+            .flat_map(|s| [(s as f32 * 0.01).sin() * 3.3 / 32768.0, 0.3 / 32768.0])
+            .collect::<Vec<_>>();
+
+        // the error summed over a stretch is its level at low frequencies, first order shaping
+        // leaves the sum of the last error only
+        let low_error = |shaping| {
+            let mut converter = PcmConverter::new(true);
+            converter.set_noise_shaping(shaping);
+            let mut output = vec![0i16; input.len()];
+            converter.convert(&input, &mut output);
+
+            output
+                .iter()
+                .zip(&input)
+                .map(|(x, y)| *x as f64 - *y as f64 * 32768.0)
+                .collect::<Vec<_>>()
+                .chunks_exact(2000)
+                .map(|x| x.iter().sum::<f64>().abs())
+                .fold(0f64, f64::max)
+        };
+
+        assert!(low_error(NoiseShaping::Off) > 20.0);
+        assert!(low_error(NoiseShaping::FirstOrder) < 5.0);
+        assert!(low_error(NoiseShaping::SecondOrder) < 5.0);
+    }
+}
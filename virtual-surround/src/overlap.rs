@@ -0,0 +1,741 @@
+#![cfg(feature = "rustfft")]
+
+use crate::engine::check_window;
+use crate::half::{f16_from_f32, f32_from_f16};
+use crate::kernel::multiply_accumulate;
+use crate::planner::real_plans;
+use crate::{ConvolutionEngine, EngineFactory, FFTLogic, BLOCK_SIZE};
+use anyhow::Context;
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealToComplex};
+use std::any::Any;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+/// bins of the spectrum of two blocks
+const BINS: usize = BLOCK_SIZE + 1;
+
+/// How [`UniformLogic`] stitches the blocks of output together
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OverlapMethod {
+    /// transforms the newest two blocks and keeps the second half of the output, which has no
+    /// wrap around
+    Save,
+    /// transforms the newest block padded with a block of zeros, and adds the second half of the
+    /// output to the next block
+    Add,
+}
+
+/// Precision [`UniformLogic`] keeps the spectra of the impulse responses in
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum SpectrumPrecision {
+    #[default]
+    Single,
+    /// f16, converted back while multiplying. Takes half the memory, for long BRIRs with many
+    /// filters on a small machine, the error stays around 70dB below the output.
+    Half,
+}
+
+/// spectra of the impulse response blocks per `ir_index`, in the precision they're kept in
+#[derive(Clone)]
+enum Spectra {
+    Single(Vec<Vec<Complex<f32>>>),
+    Half(Vec<Vec<Complex<u16>>>),
+}
+
+/// How the filter convolves, picked with [`FilterBuilder::strategy`](crate::FilterBuilder::strategy)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ConvolutionStrategy {
+    /// transform the whole window every block ([`RustFFTLogic`](crate::RustFFTLogic)), one fft
+    /// as long as the HRIR but no state between blocks. Far too slow for room impulse responses.
+    Window,
+    /// [`UniformLogic`] with [`OverlapMethod::Save`]
+    #[default]
+    OverlapSave,
+    /// [`UniformLogic`] with [`OverlapMethod::Add`]
+    OverlapAdd,
+}
+
+impl ConvolutionStrategy {
+    /// [`factory`](ConvolutionStrategy::factory) keeping the spectra of the impulse responses in
+    /// `precision`, which only the overlap strategies can lower
+    pub fn factory_with_precision(
+        self,
+        precision: SpectrumPrecision,
+    ) -> anyhow::Result<EngineFactory> {
+        Ok(match (self, precision) {
+            (_, SpectrumPrecision::Single) => self.factory(),
+            (ConvolutionStrategy::Window, SpectrumPrecision::Half) => anyhow::bail!(
+                "Half precision spectra need the overlap-save or overlap-add strategy"
+            ),
+            (ConvolutionStrategy::OverlapSave, SpectrumPrecision::Half) => |channels, length| {
+                Ok(Box::new(UniformLogic::with_precision(
+                    channels,
+                    length,
+                    OverlapMethod::Save,
+                    SpectrumPrecision::Half,
+                )?))
+            },
+            (ConvolutionStrategy::OverlapAdd, SpectrumPrecision::Half) => |channels, length| {
+                Ok(Box::new(UniformLogic::with_precision(
+                    channels,
+                    length,
+                    OverlapMethod::Add,
+                    SpectrumPrecision::Half,
+                )?))
+            },
+        })
+    }
+
+    pub fn factory(self) -> EngineFactory {
+        match self {
+            ConvolutionStrategy::Window => crate::new_engine::<crate::RustFFTLogic>,
+            ConvolutionStrategy::OverlapSave => crate::new_engine::<UniformLogic>,
+            ConvolutionStrategy::OverlapAdd => |channels, length| {
+                Ok(Box::new(UniformLogic::with_method(
+                    channels,
+                    length,
+                    OverlapMethod::Add,
+                )?))
+            },
+        }
+    }
+}
+
+/// Uniformly partitioned convolution: the impulse responses are cut into blocks, and every block
+/// only the newest block of input is transformed, its spectrum is kept for as many blocks as the
+/// impulse responses are long. Ffts two blocks long replace the whole window transform, the
+/// spectrum products stay about the same.
+pub struct UniformLogic {
+    method: OverlapMethod,
+    length: usize,
+    partitions: usize,
+    forward_plan: Arc<dyn RealToComplex<f32>>,
+    backward_plan: Arc<dyn ComplexToReal<f32>>,
+    forward_scratch: Vec<Complex<f32>>,
+    backward_scratch: Vec<Complex<f32>>,
+    window: Vec<f32>,
+    /// spectra of the output per ear
+    accumulator: [Vec<Complex<f32>>; 2],
+    rev_space: Vec<f32>,
+    /// shared with the engines [`clone_fresh`](ConvolutionEngine::clone_fresh) made until one
+    /// of them loads another impulse response
+    ir: Arc<Spectra>,
+    /// spectra of the input of the last `partitions + 1` blocks per channel, ring buffers
+    history: Vec<Vec<Complex<f32>>>,
+    /// slot in `history` the current block goes to, per channel
+    newest: Vec<usize>,
+    /// second half of the previous block's output per channel and ear, for overlap-add
+    overlap: Vec<[Vec<f32>; 2]>,
+    /// whether the history was filled from a window since the last reset, per channel
+    primed: Vec<bool>,
+}
+
+/// what [`UniformLogic`] carries from one block to the next
+#[derive(Clone)]
+struct UniformState {
+    history: Vec<Vec<Complex<f32>>>,
+    newest: Vec<usize>,
+    overlap: Vec<[Vec<f32>; 2]>,
+    primed: Vec<bool>,
+}
+
+impl Debug for UniformLogic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UniformLogic")
+            .field("method", &self.method)
+            .field("precision", &self.precision())
+            .field("length", &self.length)
+            .field("partitions", &self.partitions)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FFTLogic for UniformLogic {
+    fn new(channels: usize, length: usize) -> anyhow::Result<Self> {
+        Self::with_method(channels, length, OverlapMethod::Save)
+    }
+}
+
+impl UniformLogic {
+    pub fn with_method(
+        channels: usize,
+        length: usize,
+        method: OverlapMethod,
+    ) -> anyhow::Result<Self> {
+        Self::with_precision(channels, length, method, SpectrumPrecision::Single)
+    }
+
+    pub fn with_precision(
+        channels: usize,
+        length: usize,
+        method: OverlapMethod,
+        precision: SpectrumPrecision,
+    ) -> anyhow::Result<Self> {
+        check_window(length)?;
+        let zero = Complex::new(0.0, 0.0);
+        // the impulse responses end a block before the window does
+        let partitions = (length - BLOCK_SIZE).div_ceil(BLOCK_SIZE);
+
+        let (forward_plan, backward_plan) = real_plans(BLOCK_SIZE * 2);
+
+        Ok(UniformLogic {
+            method,
+            length,
+            partitions,
+            forward_scratch: forward_plan.make_scratch_vec(),
+            backward_scratch: backward_plan.make_scratch_vec(),
+            forward_plan,
+            backward_plan,
+            window: vec![0f32; BLOCK_SIZE * 2],
+            accumulator: [vec![zero; BINS], vec![zero; BINS]],
+            rev_space: vec![0f32; BLOCK_SIZE * 2],
+            ir: Arc::new(match precision {
+                SpectrumPrecision::Single => {
+                    Spectra::Single(vec![vec![zero; partitions * BINS]; channels * 2])
+                }
+                SpectrumPrecision::Half => {
+                    Spectra::Half(vec![
+                        vec![Complex::new(0, 0); partitions * BINS];
+                        channels * 2
+                    ])
+                }
+            }),
+            history: vec![vec![zero; (partitions + 1) * BINS]; channels],
+            newest: vec![0; channels],
+            overlap: vec![[vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]]; channels],
+            primed: vec![false; channels],
+        })
+    }
+
+    pub fn method(&self) -> OverlapMethod {
+        self.method
+    }
+
+    pub fn precision(&self) -> SpectrumPrecision {
+        match *self.ir {
+            Spectra::Single(_) => SpectrumPrecision::Single,
+            Spectra::Half(_) => SpectrumPrecision::Half,
+        }
+    }
+
+    /// spectra of the partitions of `impulse`, with space of their own so impulse responses can
+    /// be transformed side by side
+    fn ir_spectrum(&self, impulse: &[f32]) -> anyhow::Result<Vec<Complex<f32>>> {
+        let mut window = vec![0f32; BLOCK_SIZE * 2];
+        let mut scratch = self.forward_plan.make_scratch_vec();
+        let mut spectrum = vec![Complex::new(0.0, 0.0); self.partitions * BINS];
+
+        for (partition, bins) in spectrum.chunks_exact_mut(BINS).enumerate() {
+            let start = partition * BLOCK_SIZE;
+            let end = (start + BLOCK_SIZE).min(impulse.len());
+
+            window.fill(0f32);
+            window[..end - start].copy_from_slice(&impulse[start..end]);
+            self.forward_plan
+                .process_with_scratch(&mut window, bins, &mut scratch)
+                .map_err(|err| anyhow::Error::msg(err.to_string()))
+                .context("Failed to process IR")?;
+        }
+
+        Ok(spectrum)
+    }
+
+    /// keep `spectrum` as the one of `ir_index`, in the precision of the engine
+    fn store_ir(&mut self, ir_index: usize, spectrum: &[Complex<f32>]) {
+        match Arc::make_mut(&mut self.ir) {
+            Spectra::Single(ir) => ir[ir_index].copy_from_slice(spectrum),
+            Spectra::Half(ir) => {
+                for (bin, value) in ir[ir_index].iter_mut().zip(spectrum) {
+                    *bin = Complex::new(f16_from_f32(value.re), f16_from_f32(value.im));
+                }
+            }
+        }
+    }
+
+    /// history slot of the block `age` blocks before the newest of `channel`
+    fn slot(&self, channel: usize, age: usize) -> usize {
+        (self.newest[channel] + self.partitions + 1 - age) % (self.partitions + 1)
+    }
+
+    /// transform the input ending `age` blocks before the end of `samples` into its history slot.
+    /// Input from before the window is taken as silence, the impulse responses are too short to
+    /// reach it.
+    fn transform_input(
+        &mut self,
+        channel: usize,
+        samples: &[f32],
+        age: usize,
+    ) -> anyhow::Result<()> {
+        let end = self.length as isize - (age * BLOCK_SIZE) as isize;
+        let taken = match self.method {
+            OverlapMethod::Save => BLOCK_SIZE * 2,
+            OverlapMethod::Add => BLOCK_SIZE,
+        };
+
+        self.window.fill(0f32);
+        for (s, sample) in self.window[..taken].iter_mut().enumerate() {
+            let index = end - taken as isize + s as isize;
+            if index >= 0 {
+                *sample = samples[index as usize];
+            }
+        }
+
+        let slot = self.slot(channel, age) * BINS;
+        self.forward_plan
+            .process_with_scratch(
+                &mut self.window,
+                &mut self.history[channel][slot..slot + BINS],
+                &mut self.forward_scratch,
+            )
+            .map_err(|err| anyhow::Error::msg(err.to_string()))
+            .context("Failed to process channel")?;
+
+        Ok(())
+    }
+
+    /// inverse transform of the input `age` blocks old and before through every partition of
+    /// the impulse response of `channel` and `ear`, into `rev_space`
+    fn convolve(&mut self, channel: usize, ear: usize, age: usize) -> anyhow::Result<()> {
+        self.accumulator[ear].fill(Complex::new(0.0, 0.0));
+        self.multiply(channel, ear, age);
+        self.inverse(ear)
+    }
+
+    /// add the input `age` blocks old and before of `channel` through every partition of its
+    /// impulse response of `ear` to the spectrum of that ear
+    fn multiply(&mut self, channel: usize, ear: usize, age: usize) {
+        let ir_index = channel * 2 + ear;
+        for partition in 0..self.partitions {
+            let slot = self.slot(channel, age + partition) * BINS;
+            let input = &self.history[channel][slot..slot + BINS];
+            let bins = partition * BINS..(partition + 1) * BINS;
+
+            match &*self.ir {
+                Spectra::Single(ir) => {
+                    multiply_accumulate(&mut self.accumulator[ear], &ir[ir_index][bins], input)
+                }
+                Spectra::Half(ir) => {
+                    for ((acc, ir), input) in self.accumulator[ear]
+                        .iter_mut()
+                        .zip(&ir[ir_index][bins])
+                        .zip(input)
+                    {
+                        *acc += Complex::new(f32_from_f16(ir.re), f32_from_f16(ir.im)) * input;
+                    }
+                }
+            }
+        }
+    }
+
+    /// inverse transform the spectrum of `ear` into `rev_space`
+    fn inverse(&mut self, ear: usize) -> anyhow::Result<()> {
+        self.backward_plan
+            .process_with_scratch(
+                &mut self.accumulator[ear],
+                &mut self.rev_space,
+                &mut self.backward_scratch,
+            )
+            .map_err(|err| anyhow::Error::msg(err.to_string()))
+            .context("Failed to process channel")?;
+
+        Ok(())
+    }
+
+    /// fill the history of `channel` from the window after a reset, and the overlap from the
+    /// block before it
+    fn prime(&mut self, channel: usize, samples: &[f32]) -> anyhow::Result<()> {
+        let scale = 1.0 / (BLOCK_SIZE * 2) as f32;
+        for age in 1..=self.partitions {
+            self.transform_input(channel, samples, age)?;
+        }
+
+        if self.method == OverlapMethod::Add {
+            for ear in 0..2 {
+                self.convolve(channel, ear, 1)?;
+                for (overlap, sample) in self.overlap[channel][ear]
+                    .iter_mut()
+                    .zip(&self.rev_space[BLOCK_SIZE..])
+                {
+                    *overlap = sample * scale;
+                }
+            }
+        }
+
+        self.primed[channel] = true;
+        Ok(())
+    }
+}
+
+impl ConvolutionEngine for UniformLogic {
+    fn init_ir(&mut self, impulse: &[f32], ir_index: usize) -> anyhow::Result<()> {
+        let spectrum = self.ir_spectrum(impulse)?;
+        self.store_ir(ir_index, &spectrum);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "parallel")]
+    fn init_irs(&mut self, impulses: &[(usize, Vec<f32>)]) -> anyhow::Result<()> {
+        use rayon::prelude::*;
+
+        let spectra = impulses
+            .par_iter()
+            .map(|(_, impulse)| self.ir_spectrum(impulse))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        for ((ir_index, _), spectrum) in impulses.iter().zip(spectra) {
+            self.store_ir(*ir_index, &spectrum);
+        }
+
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        channel: usize,
+        samples: &[f32],
+        left_output: &mut [f32],
+        right_output: &mut [f32],
+    ) -> anyhow::Result<()> {
+        let scale = 1.0 / (BLOCK_SIZE * 2) as f32;
+
+        // the first block after a reset takes the older input from the window, after that only
+        // the newest block is transformed
+        if !self.primed[channel] {
+            self.prime(channel, samples)?;
+        }
+
+        self.transform_input(channel, samples, 0)?;
+
+        for (ear, output) in [left_output, right_output].iter_mut().enumerate() {
+            self.convolve(channel, ear, 0)?;
+            let output = &mut output[..BLOCK_SIZE];
+
+            match self.method {
+                OverlapMethod::Save => {
+                    for (out, sample) in output.iter_mut().zip(&self.rev_space[BLOCK_SIZE..]) {
+                        *out += sample * scale;
+                    }
+                }
+                OverlapMethod::Add => {
+                    let overlap = &mut self.overlap[channel][ear];
+                    let (head, tail) = self.rev_space.split_at(BLOCK_SIZE);
+                    for ((out, sample), overlap) in output.iter_mut().zip(head).zip(overlap) {
+                        *out += sample * scale + *overlap;
+                    }
+                    for (overlap, sample) in self.overlap[channel][ear].iter_mut().zip(tail) {
+                        *overlap = sample * scale;
+                    }
+                }
+            }
+        }
+
+        self.newest[channel] = (self.newest[channel] + 1) % (self.partitions + 1);
+
+        Ok(())
+    }
+
+    /// With overlap-save the channels are summed in the spectrum, so a block takes two inverse
+    /// ffts in total. Overlap-add keeps the overlap per channel and renders them one by one.
+    fn process_channels(
+        &mut self,
+        channels: &[usize],
+        input: &[&[f32]],
+        left_output: &mut [f32],
+        right_output: &mut [f32],
+    ) -> anyhow::Result<()> {
+        if self.method == OverlapMethod::Add {
+            for channel in channels {
+                self.process(*channel, input[*channel], left_output, right_output)?;
+            }
+
+            return Ok(());
+        }
+
+        let scale = 1.0 / (BLOCK_SIZE * 2) as f32;
+        for accumulator in &mut self.accumulator {
+            accumulator.fill(Complex::new(0.0, 0.0));
+        }
+
+        for channel in channels {
+            if !self.primed[*channel] {
+                self.prime(*channel, input[*channel])?;
+            }
+
+            self.transform_input(*channel, input[*channel], 0)?;
+            for ear in 0..2 {
+                self.multiply(*channel, ear, 0);
+            }
+
+            self.newest[*channel] = (self.newest[*channel] + 1) % (self.partitions + 1);
+        }
+
+        for (ear, output) in [left_output, right_output].iter_mut().enumerate() {
+            self.inverse(ear)?;
+            for (out, sample) in output[..BLOCK_SIZE]
+                .iter_mut()
+                .zip(&self.rev_space[BLOCK_SIZE..])
+            {
+                *out += sample * scale;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn latency(&self) -> usize {
+        self.length - BLOCK_SIZE
+    }
+
+    fn reset(&mut self) {
+        self.primed.fill(false);
+    }
+
+    fn clone_fresh(&self) -> Option<Box<dyn ConvolutionEngine>> {
+        let mut engine = Self::with_precision(
+            self.history.len(),
+            self.length,
+            self.method,
+            self.precision(),
+        )
+        .ok()?;
+        engine.ir = self.ir.clone();
+
+        Some(Box::new(engine))
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any + Send>> {
+        Some(Box::new(UniformState {
+            history: self.history.clone(),
+            newest: self.newest.clone(),
+            overlap: self.overlap.clone(),
+            primed: self.primed.clone(),
+        }))
+    }
+
+    fn load_state(&mut self, state: Option<&(dyn Any + Send)>) -> anyhow::Result<()> {
+        let state = match state.and_then(|x| x.downcast_ref::<UniformState>()) {
+            Some(state) if state.history.len() == self.history.len() => state,
+            _ => anyhow::bail!("State wasn't saved by a UniformLogic of the same size"),
+        };
+
+        if state.history[0].len() != self.history[0].len() {
+            anyhow::bail!("State wasn't saved by a UniformLogic of the same size");
+        }
+
+        self.history.clone_from(&state.history);
+        self.newest.clone_from(&state.newest);
+        self.overlap.clone_from(&state.overlap);
+        self.primed.clone_from(&state.primed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OverlapMethod, SpectrumPrecision, UniformLogic};
+    use crate::{ConvolutionEngine, FFTLogic, RustFFTLogic, BLOCK_SIZE};
+
+    fn noise(state: &mut u32) -> f32 {
+        *state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (*state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+    }
+
+    #[test]
+    fn matches_whole_window_convolution() {
+        let (channels, length) = (2, 4096);
+        let mut state = 0x1234_5678u32;
+        let impulses = (0..channels * 2)
+            .map(|_| {
+                let mut impulse = vec![0f32; length];
+                for sample in &mut impulse[..length - BLOCK_SIZE - 100] {
+                    *sample = noise(&mut state) * 0.05;
+                }
+                impulse
+            })
+            .collect::<Vec<_>>();
+        let input = (0..channels)
+            .map(|_| {
+                (0..length + BLOCK_SIZE * 20)
+                    .map(|_| noise(&mut state))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        for method in [OverlapMethod::Save, OverlapMethod::Add] {
+            let mut reference = RustFFTLogic::<f32>::new(channels, length).unwrap();
+            let mut uniform = UniformLogic::with_method(channels, length, method).unwrap();
+            for (ir_index, impulse) in impulses.iter().enumerate() {
+                reference.init_ir(impulse, ir_index).unwrap();
+                uniform.init_ir(impulse, ir_index).unwrap();
+            }
+
+            // starting over halfway picks up the older input from the window again
+            for block in (0..20).chain(5..10) {
+                if block == 5 {
+                    uniform.reset();
+                }
+
+                let window = block * BLOCK_SIZE..block * BLOCK_SIZE + length;
+                let mut expected = [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]];
+                let mut output = [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]];
+
+                for (channel, input) in input.iter().enumerate() {
+                    let samples = &input[window.clone()];
+                    let [left, right] = &mut expected;
+                    reference.process(channel, samples, left, right).unwrap();
+                    let [left, right] = &mut output;
+                    uniform.process(channel, samples, left, right).unwrap();
+                }
+
+                for (output, expected) in output.iter().zip(&expected) {
+                    for (x, y) in output.iter().zip(expected) {
+                        assert!((x - y).abs() < 1e-4, "{:?} {} {}", method, x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn half_precision_spectra_stay_close() {
+        let (channels, length) = (1, 8192);
+        let mut state = 0x1234_5678u32;
+        let mut single = UniformLogic::new(channels, length).unwrap();
+        let mut half = UniformLogic::with_precision(
+            channels,
+            length,
+            OverlapMethod::Save,
+            SpectrumPrecision::Half,
+        )
+        .unwrap();
+        assert_eq!(half.precision(), SpectrumPrecision::Half);
+
+        for ir_index in 0..2 {
+            let mut impulse = vec![0f32; length];
+            for (s, sample) in impulse[..length - BLOCK_SIZE].iter_mut().enumerate() {
+                *sample = noise(&mut state) * (-(s as f32) / 1000.0).exp();
+            }
+            single.init_ir(&impulse, ir_index).unwrap();
+            half.init_ir(&impulse, ir_index).unwrap();
+        }
+
+        let (mut signal, mut error) = (0f32, 0f32);
+        for _ in 0..8 {
+            let window = (0..length).map(|_| noise(&mut state)).collect::<Vec<_>>();
+            let mut expected = [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]];
+            let mut output = [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]];
+            let [left, right] = &mut expected;
+            single.process(0, &window, left, right).unwrap();
+            let [left, right] = &mut output;
+            half.process(0, &window, left, right).unwrap();
+
+            for (x, y) in output.iter().flatten().zip(expected.iter().flatten()) {
+                signal += y * y;
+                error += (x - y) * (x - y);
+            }
+        }
+
+        // at least 60dB down
+        let ratio = (error / signal).sqrt();
+        assert!(ratio > 0.0 && ratio < 1e-3, "{}", ratio);
+    }
+
+    #[test]
+    fn loads_impulse_responses_together_like_one_by_one() {
+        let (channels, length) = (2, 4096);
+        let mut state = 0x1234_5678u32;
+        let impulses = (0..channels * 2)
+            .map(|ir_index| {
+                let mut impulse = vec![0f32; length];
+                for sample in &mut impulse[..length - BLOCK_SIZE] {
+                    *sample = noise(&mut state);
+                }
+                (ir_index, impulse)
+            })
+            .collect::<Vec<_>>();
+
+        for precision in [SpectrumPrecision::Single, SpectrumPrecision::Half] {
+            let mut one_by_one =
+                UniformLogic::with_precision(channels, length, OverlapMethod::Save, precision)
+                    .unwrap();
+            let mut together =
+                UniformLogic::with_precision(channels, length, OverlapMethod::Save, precision)
+                    .unwrap();
+            for (ir_index, impulse) in &impulses {
+                one_by_one.init_ir(impulse, *ir_index).unwrap();
+            }
+            together.init_irs(&impulses).unwrap();
+
+            let window = (0..length).map(|_| noise(&mut state)).collect::<Vec<_>>();
+            for channel in 0..channels {
+                let mut expected = [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]];
+                let mut output = [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]];
+                let [left, right] = &mut expected;
+                one_by_one.process(channel, &window, left, right).unwrap();
+                let [left, right] = &mut output;
+                together.process(channel, &window, left, right).unwrap();
+                assert_eq!(output, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn renders_all_channels_like_one_by_one() {
+        let (channels, length) = (3, 4096);
+        let mut state = 0x1234_5678u32;
+        let engines = || -> [Box<dyn ConvolutionEngine>; 3] {
+            [
+                Box::new(RustFFTLogic::<f32>::new(channels, length).unwrap()),
+                Box::new(UniformLogic::with_method(channels, length, OverlapMethod::Save).unwrap()),
+                Box::new(UniformLogic::with_method(channels, length, OverlapMethod::Add).unwrap()),
+            ]
+        };
+        let (mut one_by_one, mut together) = (engines(), engines());
+
+        for ir_index in 0..channels * 2 {
+            let mut impulse = vec![0f32; length];
+            for sample in &mut impulse[..length - BLOCK_SIZE] {
+                *sample = noise(&mut state) * 0.1;
+            }
+            for engine in one_by_one.iter_mut().chain(&mut together) {
+                engine.init_ir(&impulse, ir_index).unwrap();
+            }
+        }
+
+        let input = (0..channels)
+            .map(|_| {
+                (0..length * 2)
+                    .map(|_| noise(&mut state))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        for block in 0..4 {
+            let window = block * BLOCK_SIZE..block * BLOCK_SIZE + length;
+            let windows = input.iter().map(|x| &x[window.clone()]).collect::<Vec<_>>();
+
+            for (single, summed) in one_by_one.iter_mut().zip(&mut together) {
+                let mut expected = [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]];
+                let mut output = [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]];
+                let [left, right] = &mut expected;
+                for (channel, samples) in windows.iter().enumerate() {
+                    single.process(channel, samples, left, right).unwrap();
+                }
+                let [left, right] = &mut output;
+                summed
+                    .process_channels(&[0, 1, 2], &windows, left, right)
+                    .unwrap();
+
+                for (x, y) in output.iter().flatten().zip(expected.iter().flatten()) {
+                    assert!((x - y).abs() < 1e-4, "{} {}", x, y);
+                }
+            }
+        }
+    }
+}
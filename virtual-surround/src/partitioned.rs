@@ -0,0 +1,387 @@
+#![cfg(feature = "rustfft")]
+
+use crate::engine::check_window;
+use crate::kernel::multiply_accumulate;
+use crate::planner::real_plans;
+use crate::{ConvolutionEngine, FFTLogic, BLOCK_SIZE};
+use anyhow::Context;
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealToComplex};
+use std::any::Any;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+/// blocks of output each tail convolution renders ahead, the head covers as many blocks of the
+/// impulse response
+const TAIL_BLOCKS: usize = 8;
+
+/// When [`PartitionedLogic`] convolves the tails of the impulse responses
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TailScheduling {
+    /// every channel's tail in the same block, one block in [`TAIL_BLOCKS`] takes most of the time
+    Burst,
+    /// the channels take turns, so every block does about the same work and the worst case
+    /// callback is shorter, which is what small periods need to avoid xruns
+    Distributed,
+}
+
+struct Segment {
+    length: usize,
+    forward_plan: Arc<dyn RealToComplex<f32>>,
+    backward_plan: Arc<dyn ComplexToReal<f32>>,
+    forward_scratch: Vec<Complex<f32>>,
+    backward_scratch: Vec<Complex<f32>>,
+    window: Vec<f32>,
+    input: Vec<Complex<f32>>,
+    output: Vec<Complex<f32>>,
+    rev_space: Vec<f32>,
+    ir: Arc<Vec<Vec<Complex<f32>>>>,
+}
+
+impl Segment {
+    fn new(channels: usize, length: usize) -> Self {
+        let zero = Complex::new(0.0, 0.0);
+        let ir = Arc::new(vec![vec![zero; length / 2 + 1]; channels * 2]);
+
+        let (forward_plan, backward_plan) = real_plans(length);
+
+        Segment {
+            length,
+            forward_scratch: forward_plan.make_scratch_vec(),
+            backward_scratch: backward_plan.make_scratch_vec(),
+            forward_plan,
+            backward_plan,
+            window: vec![0f32; length],
+            input: vec![zero; length / 2 + 1],
+            output: vec![zero; length / 2 + 1],
+            rev_space: vec![0f32; length],
+            ir,
+        }
+    }
+
+    /// `impulse` is zero padded to the segment length
+    fn init_ir(&mut self, impulse: &[f32], ir_index: usize) -> anyhow::Result<()> {
+        self.window.fill(0f32);
+        self.window[..impulse.len()].copy_from_slice(impulse);
+        self.forward_plan
+            .process_with_scratch(
+                &mut self.window,
+                &mut Arc::make_mut(&mut self.ir)[ir_index],
+                &mut self.forward_scratch,
+            )
+            .map_err(|err| anyhow::Error::msg(err.to_string()))
+            .context("Failed to process IR")?;
+        Ok(())
+    }
+
+    /// circular convolution of `samples` with both ears of `channel`, the last `outputs` samples
+    /// of each are added to the outputs
+    fn process(
+        &mut self,
+        channel: usize,
+        samples: &[f32],
+        mut outputs: [&mut [f32]; 2],
+    ) -> anyhow::Result<()> {
+        self.window.copy_from_slice(samples);
+        self.forward_plan
+            .process_with_scratch(&mut self.window, &mut self.input, &mut self.forward_scratch)
+            .map_err(|err| anyhow::Error::msg(err.to_string()))
+            .context("Failed to process channel")?;
+
+        let scale = 1.0 / self.length as f32;
+
+        for (ear, out) in outputs.iter_mut().enumerate() {
+            let ir = &self.ir[channel * 2 + ear];
+            self.output.fill(Complex::new(0.0, 0.0));
+            multiply_accumulate(&mut self.output, ir, &self.input);
+
+            self.backward_plan
+                .process_with_scratch(
+                    &mut self.output,
+                    &mut self.rev_space,
+                    &mut self.backward_scratch,
+                )
+                .map_err(|err| anyhow::Error::msg(err.to_string()))
+                .context("Failed to process channel")?;
+
+            let valid = &self.rev_space[self.length - out.len()..];
+            for (out, sample) in out.iter_mut().zip(valid) {
+                *out += sample * scale;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// tail output a channel rendered ahead
+#[derive(Debug, Clone)]
+struct Tail {
+    /// blocks already played from `output`
+    played: usize,
+    output: [Vec<f32>; 2],
+}
+
+/// Convolves the first [`TAIL_BLOCKS`] blocks of the impulse responses every block, and the rest
+/// once every [`TAIL_BLOCKS`] blocks rendering that many blocks of output ahead. The window the
+/// filter hands over already holds the input the tail needs, so this adds no latency, and long
+/// impulse responses cost a fraction of convolving the whole window every block.
+pub struct PartitionedLogic {
+    length: usize,
+    head: Segment,
+    tail: Option<Segment>,
+    scheduling: TailScheduling,
+    channels: usize,
+    tails: Vec<Tail>,
+    /// blocks processed per channel, modulo [`TAIL_BLOCKS`]
+    blocks: Vec<usize>,
+}
+
+impl Debug for PartitionedLogic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PartitionedLogic")
+            .field("length", &self.length)
+            .field("head", &self.head.length)
+            .field("tail", &self.tail.as_ref().map(|x| x.length))
+            .field("scheduling", &self.scheduling)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FFTLogic for PartitionedLogic {
+    fn new(channels: usize, length: usize) -> anyhow::Result<Self> {
+        Self::with_scheduling(channels, length, TailScheduling::Distributed)
+    }
+}
+
+impl PartitionedLogic {
+    pub fn with_scheduling(
+        channels: usize,
+        length: usize,
+        scheduling: TailScheduling,
+    ) -> anyhow::Result<Self> {
+        check_window(length)?;
+        // the impulse responses end a block before the window does
+        let span = length - BLOCK_SIZE;
+        let head = span.min(TAIL_BLOCKS * BLOCK_SIZE);
+
+        // the tail renders TAIL_BLOCKS blocks from input a head length old, so its convolution
+        // covers the whole span
+        let tail = if head < span {
+            Some(Segment::new(channels, span))
+        } else {
+            None
+        };
+
+        Ok(PartitionedLogic {
+            length,
+            head: Segment::new(channels, head + BLOCK_SIZE),
+            tail,
+            scheduling,
+            channels,
+            tails: vec![
+                Tail {
+                    played: TAIL_BLOCKS,
+                    output: [
+                        vec![0f32; TAIL_BLOCKS * BLOCK_SIZE],
+                        vec![0f32; TAIL_BLOCKS * BLOCK_SIZE]
+                    ],
+                };
+                channels
+            ],
+            blocks: vec![0; channels],
+        })
+    }
+
+    pub fn scheduling(&self) -> TailScheduling {
+        self.scheduling
+    }
+
+    fn head_length(&self) -> usize {
+        self.head.length - BLOCK_SIZE
+    }
+
+    /// whether `channel` renders its tail ahead in this block
+    fn tail_due(&self, channel: usize) -> bool {
+        let turn = match self.scheduling {
+            TailScheduling::Burst => 0,
+            TailScheduling::Distributed => channel * TAIL_BLOCKS / self.channels,
+        };
+
+        // nothing rendered ahead yet after a reset, so the first block can't wait for its turn
+        self.tails[channel].played >= TAIL_BLOCKS || self.blocks[channel] == turn
+    }
+}
+
+impl ConvolutionEngine for PartitionedLogic {
+    fn init_ir(&mut self, impulse: &[f32], ir_index: usize) -> anyhow::Result<()> {
+        let head = self.head_length();
+        self.head.init_ir(&impulse[..head], ir_index)?;
+
+        if let Some(tail) = &mut self.tail {
+            tail.init_ir(&impulse[head..self.length - BLOCK_SIZE], ir_index)?;
+        }
+
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        channel: usize,
+        samples: &[f32],
+        left_output: &mut [f32],
+        right_output: &mut [f32],
+    ) -> anyhow::Result<()> {
+        let head = self.head.length;
+        self.head.process(
+            channel,
+            &samples[self.length - head..],
+            [
+                &mut left_output[..BLOCK_SIZE],
+                &mut right_output[..BLOCK_SIZE],
+            ],
+        )?;
+
+        let due = self.tail_due(channel);
+        if let Some(tail) = &mut self.tail {
+            if due {
+                let ahead = &mut self.tails[channel];
+                let [left, right] = &mut ahead.output;
+                left.fill(0f32);
+                right.fill(0f32);
+
+                // input reaches the tail a head length late, so the newest block isn't needed
+                tail.process(channel, &samples[..tail.length], [left, right])?;
+                ahead.played = 0;
+            }
+
+            let ahead = &mut self.tails[channel];
+            let range = ahead.played * BLOCK_SIZE..(ahead.played + 1) * BLOCK_SIZE;
+            for (out, tail) in left_output.iter_mut().zip(&ahead.output[0][range.clone()]) {
+                *out += tail;
+            }
+            for (out, tail) in right_output.iter_mut().zip(&ahead.output[1][range]) {
+                *out += tail;
+            }
+            ahead.played += 1;
+        }
+
+        self.blocks[channel] = (self.blocks[channel] + 1) % TAIL_BLOCKS;
+
+        Ok(())
+    }
+
+    fn latency(&self) -> usize {
+        self.length - BLOCK_SIZE
+    }
+
+    fn reset(&mut self) {
+        for tail in &mut self.tails {
+            tail.played = TAIL_BLOCKS;
+        }
+
+        self.blocks.fill(0);
+    }
+
+    fn clone_fresh(&self) -> Option<Box<dyn ConvolutionEngine>> {
+        let mut engine = Self::with_scheduling(self.channels, self.length, self.scheduling).ok()?;
+        engine.head.ir = self.head.ir.clone();
+        if let (Some(tail), Some(shared)) = (&mut engine.tail, &self.tail) {
+            tail.ir = shared.ir.clone();
+        }
+
+        Some(Box::new(engine))
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any + Send>> {
+        Some(Box::new((self.tails.clone(), self.blocks.clone())))
+    }
+
+    fn load_state(&mut self, state: Option<&(dyn Any + Send)>) -> anyhow::Result<()> {
+        match state.and_then(|x| x.downcast_ref::<(Vec<Tail>, Vec<usize>)>()) {
+            Some((tails, blocks)) if tails.len() == self.tails.len() => {
+                self.tails.clone_from(tails);
+                self.blocks.clone_from(blocks);
+                Ok(())
+            }
+            _ => anyhow::bail!("State wasn't saved by a PartitionedLogic of the same size"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PartitionedLogic, TailScheduling, TAIL_BLOCKS};
+    use crate::{ConvolutionEngine, FFTLogic, RustFFTLogic, BLOCK_SIZE};
+
+    fn noise(state: &mut u32) -> f32 {
+        *state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (*state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+    }
+
+    #[test]
+    fn matches_whole_window_convolution() {
+        let (channels, length) = (3, 8192);
+        let mut state = 0x1234_5678u32;
+        let impulses = (0..channels * 2)
+            .map(|_| {
+                let mut impulse = vec![0f32; length];
+                for sample in &mut impulse[..length - BLOCK_SIZE - 1] {
+                    *sample = noise(&mut state) * 0.05;
+                }
+                impulse
+            })
+            .collect::<Vec<_>>();
+        let input = (0..channels)
+            .map(|_| {
+                (0..length + BLOCK_SIZE * 40)
+                    .map(|_| noise(&mut state))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        for scheduling in [TailScheduling::Burst, TailScheduling::Distributed] {
+            let mut reference = RustFFTLogic::<f32>::new(channels, length).unwrap();
+            let mut partitioned =
+                PartitionedLogic::with_scheduling(channels, length, scheduling).unwrap();
+            for (ir_index, impulse) in impulses.iter().enumerate() {
+                reference.init_ir(impulse, ir_index).unwrap();
+                partitioned.init_ir(impulse, ir_index).unwrap();
+            }
+
+            for block in 0..40 {
+                let window = block * BLOCK_SIZE..block * BLOCK_SIZE + length;
+                let mut expected = [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]];
+                let mut output = [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]];
+                let mut computed = 0;
+
+                for (channel, input) in input.iter().enumerate() {
+                    let samples = &input[window.clone()];
+                    let [left, right] = &mut expected;
+                    reference.process(channel, samples, left, right).unwrap();
+                    let [left, right] = &mut output;
+                    partitioned.process(channel, samples, left, right).unwrap();
+
+                    if partitioned.tails[channel].played == 1 {
+                        computed += 1;
+                    }
+                }
+
+                for (output, expected) in output.iter().zip(&expected) {
+                    for (x, y) in output.iter().zip(expected) {
+                        assert!((x - y).abs() < 1e-4, "{} {}", x, y);
+                    }
+                }
+
+                match (scheduling, block) {
+                    (_, 0) => assert_eq!(computed, channels),
+                    (TailScheduling::Burst, _) if block % TAIL_BLOCKS == 0 => {
+                        assert_eq!(computed, channels)
+                    }
+                    (TailScheduling::Burst, _) => assert_eq!(computed, 0),
+                    (TailScheduling::Distributed, _) => assert!(computed <= 1),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+#![cfg(feature = "rustfft")]
+
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use rustfft::FftNum;
+use std::sync::Arc;
+
+/// forward and inverse real fft of a length
+pub(crate) type RealPlans<T> = (Arc<dyn RealToComplex<T>>, Arc<dyn ComplexToReal<T>>);
+
+#[cfg(feature = "plan-cache")]
+mod cache {
+    use super::RealPlans;
+    use rustfft::FftNum;
+    use std::any::{Any, TypeId};
+    use std::sync::Mutex;
+
+    /// plans of a sample type and length, kept for the life of the process
+    type Cached = (TypeId, usize, Box<dyn Any + Send>);
+
+    static PLANS: Mutex<Vec<Cached>> = Mutex::new(vec![]);
+
+    pub(crate) fn get_or_plan<T: FftNum>(
+        length: usize,
+        plan: impl FnOnce() -> RealPlans<T>,
+    ) -> RealPlans<T> {
+        // a panic while planning leaves the plans in the cache as they were
+        let mut plans = PLANS.lock().unwrap_or_else(|err| err.into_inner());
+        let cached = plans
+            .iter()
+            .filter(|x| x.0 == TypeId::of::<T>() && x.1 == length)
+            .find_map(|x| x.2.downcast_ref::<RealPlans<T>>());
+
+        if let Some(cached) = cached {
+            return cached.clone();
+        }
+
+        trace_event!(DEBUG, length, "planning fft for the cache");
+        let planned = plan();
+        plans.push((TypeId::of::<T>(), length, Box::new(planned.clone())));
+
+        planned
+    }
+}
+
+/// Plans for real ffts of `length`. With the `plan-cache` feature they're planned once per
+/// process and shared by every engine after, which saves the planning and the twiddle tables of
+/// every filter built after the first, otherwise every call plans them again.
+pub(crate) fn real_plans<T: FftNum>(length: usize) -> RealPlans<T> {
+    let plan = || {
+        let mut planner = RealFftPlanner::<T>::new();
+        (
+            planner.plan_fft_forward(length),
+            planner.plan_fft_inverse(length),
+        )
+    };
+
+    #[cfg(feature = "plan-cache")]
+    {
+        cache::get_or_plan(length, plan)
+    }
+
+    #[cfg(not(feature = "plan-cache"))]
+    {
+        plan()
+    }
+}
+
+#[cfg(all(test, feature = "plan-cache"))]
+mod tests {
+    use super::real_plans;
+    use std::sync::Arc;
+
+    #[test]
+    fn shares_plans_of_a_length() {
+        let (forward, inverse) = real_plans::<f32>(3072);
+        let (again, _) = real_plans::<f32>(3072);
+        assert!(Arc::ptr_eq(&forward, &again));
+        assert_eq!(inverse.len(), 3072);
+
+        assert_eq!(real_plans::<f32>(1536).0.len(), 1536);
+        assert_eq!(real_plans::<f64>(3072).0.len(), 3072);
+    }
+}
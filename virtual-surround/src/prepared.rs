@@ -0,0 +1,230 @@
+use crate::{
+    channel_from_name, get_channel_name, EngineFactory, RawVirtualSurroundFilter, Speaker,
+    VirtualSurroundFilter, BLOCK_SIZE,
+};
+use anyhow::Context;
+use std::io::{Read, Write};
+
+/// first bytes of a prepared filter file
+const PREPARED_MAGIC: &[u8; 4] = b"VSPF";
+const PREPARED_VERSION: u32 = 1;
+/// upper bound on lengths read from a file, so a corrupt one fails instead of allocating
+const MAX_PREPARED_LENGTH: usize = 1 << 26;
+
+/// Contents of a prepared filter file: the HRIR as it was after
+/// [`FilterBuilder::prepare_hrir`](crate::FilterBuilder::prepare_hrir) and the engine's spectra
+/// of it, if it hands them out.
+pub(crate) struct Prepared {
+    pub(crate) sample_rate: u32,
+    pub(crate) positions: Vec<Speaker>,
+    pub(crate) ir_delay: usize,
+    pub(crate) irs: Vec<[Vec<f32>; 2]>,
+    /// per `channel * 2 + ear`, see [`ConvolutionEngine::export_ir`](crate::ConvolutionEngine::export_ir)
+    pub(crate) spectra: Option<Vec<Vec<f32>>>,
+}
+
+impl Prepared {
+    /// read a prepared filter, failing if it was saved for another block size or under another
+    /// `key`
+    pub(crate) fn read<R: Read>(mut reader: R, key: &str) -> anyhow::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .context("Prepared filter is empty")?;
+        if &magic != PREPARED_MAGIC {
+            anyhow::bail!("Not a prepared filter");
+        }
+
+        let version = read_u32(&mut reader)?;
+        if version != PREPARED_VERSION {
+            anyhow::bail!("Prepared filter has unsupported version {}", version);
+        }
+
+        let block_size = read_u32(&mut reader)? as usize;
+        if block_size != BLOCK_SIZE {
+            anyhow::bail!(
+                "Prepared filter is made for blocks of {} frames, not {}",
+                block_size,
+                BLOCK_SIZE
+            );
+        }
+
+        let sample_rate = read_u32(&mut reader)?;
+        if read_string(&mut reader)? != key {
+            anyhow::bail!("Prepared filter was saved with other settings");
+        }
+
+        let positions = read_string(&mut reader)?
+            .split(',')
+            .map(|name| {
+                channel_from_name(name)
+                    .with_context(|| format!("Prepared filter names unknown channel {}", name))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let ir_delay = read_u32(&mut reader)? as usize;
+        let mut irs = vec![];
+        for _ in &positions {
+            irs.push([read_samples(&mut reader)?, read_samples(&mut reader)?]);
+        }
+
+        if irs.iter().flatten().any(|x| x.len() != irs[0][0].len()) {
+            anyhow::bail!("Prepared filter has impulse responses of different lengths");
+        }
+
+        let spectra = match read_u32(&mut reader)? {
+            0 => None,
+            _ => Some(
+                (0..positions.len() * 2)
+                    .map(|_| read_samples(&mut reader))
+                    .collect::<anyhow::Result<Vec<_>>>()?,
+            ),
+        };
+
+        Ok(Prepared {
+            sample_rate,
+            positions,
+            ir_delay,
+            irs,
+            spectra,
+        })
+    }
+
+    pub(crate) fn into_filter(
+        self,
+        engine: EngineFactory,
+        threads: usize,
+    ) -> anyhow::Result<RawVirtualSurroundFilter> {
+        RawVirtualSurroundFilter::from_irs(
+            self.positions.into_iter(),
+            self.sample_rate,
+            self.irs,
+            self.ir_delay,
+            engine,
+            threads,
+            self.spectra.as_deref(),
+        )
+    }
+}
+
+impl RawVirtualSurroundFilter {
+    /// Save the impulse responses and the spectra the engines made of them, so
+    /// [`load_prepared`](RawVirtualSurroundFilter::load_prepared) or
+    /// [`FilterBuilder::build_prepared`](crate::FilterBuilder::build_prepared) can skip reading,
+    /// resampling and transforming the HRIR on the next start. `key` should change with every
+    /// setting that went into the filter, a file saved under another key isn't loaded.
+    pub fn save_prepared<W: Write>(&self, mut writer: W, key: &str) -> anyhow::Result<()> {
+        let names = self.positions().map(get_channel_name).collect::<Vec<_>>();
+
+        writer.write_all(PREPARED_MAGIC)?;
+        writer.write_all(&PREPARED_VERSION.to_le_bytes())?;
+        writer.write_all(&(BLOCK_SIZE as u32).to_le_bytes())?;
+        writer.write_all(&(self.rate as u32).to_le_bytes())?;
+        write_string(&mut writer, key)?;
+        write_string(&mut writer, &names.join(","))?;
+        writer.write_all(&(self.ir_delay as u32).to_le_bytes())?;
+
+        for ir in self.irs.iter().flatten() {
+            write_samples(&mut writer, ir)?;
+        }
+
+        let threads = self.workers.len();
+        let spectra = (0..self.channels() * 2)
+            .map(|ir_index| {
+                self.workers[(ir_index / 2) % threads]
+                    .engine
+                    .export_ir(ir_index)
+            })
+            .collect::<Option<Vec<_>>>();
+
+        match spectra {
+            Some(spectra) => {
+                writer.write_all(&1u32.to_le_bytes())?;
+                for spectrum in spectra {
+                    write_samples(&mut writer, &spectrum)?;
+                }
+            }
+            None => writer.write_all(&0u32.to_le_bytes())?,
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Filter from a file [`save_prepared`](RawVirtualSurroundFilter::save_prepared) wrote under
+    /// the same `key`, convolving on up to `threads` threads like
+    /// [`from_hrir_with_threads`](RawVirtualSurroundFilter::from_hrir_with_threads). Engines
+    /// which can't take the saved spectra transform the saved impulse responses instead.
+    pub fn load_prepared<R: Read>(
+        reader: R,
+        key: &str,
+        engine: EngineFactory,
+        threads: usize,
+    ) -> anyhow::Result<Self> {
+        Prepared::read(reader, key)?.into_filter(engine, threads)
+    }
+}
+
+impl VirtualSurroundFilter {
+    /// [`RawVirtualSurroundFilter::save_prepared`] of the convolution at the core of the filter,
+    /// load it again with [`FilterBuilder::build_prepared`](crate::FilterBuilder::build_prepared)
+    pub fn save_prepared<W: Write>(&self, writer: W, key: &str) -> anyhow::Result<()> {
+        self.inner.save_prepared(writer, key)
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> anyhow::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader
+        .read_exact(&mut bytes)
+        .context("Prepared filter is truncated")?;
+
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_length<R: Read>(reader: &mut R) -> anyhow::Result<usize> {
+    let length = read_u32(reader)? as usize;
+    if length > MAX_PREPARED_LENGTH {
+        anyhow::bail!("Prepared filter is corrupt");
+    }
+
+    Ok(length)
+}
+
+fn read_string<R: Read>(reader: &mut R) -> anyhow::Result<String> {
+    let mut bytes = vec![0u8; read_length(reader)?];
+    reader
+        .read_exact(&mut bytes)
+        .context("Prepared filter is truncated")?;
+
+    Ok(String::from_utf8(bytes)?)
+}
+
+fn read_samples<R: Read>(reader: &mut R) -> anyhow::Result<Vec<f32>> {
+    let mut bytes = vec![0u8; read_length(reader)? * 4];
+    reader
+        .read_exact(&mut bytes)
+        .context("Prepared filter is truncated")?;
+
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .collect())
+}
+
+fn write_string<W: Write>(writer: &mut W, string: &str) -> anyhow::Result<()> {
+    writer.write_all(&(string.len() as u32).to_le_bytes())?;
+    writer.write_all(string.as_bytes())?;
+
+    Ok(())
+}
+
+fn write_samples<W: Write>(writer: &mut W, samples: &[f32]) -> anyhow::Result<()> {
+    writer.write_all(&(samples.len() as u32).to_le_bytes())?;
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,205 @@
+use crate::hrir::Hrir;
+use crate::{
+    fft_len_for, get_channel_name, FilterBuilder, RawVirtualSurroundFilter, Smoothed,
+    VirtualSurroundFilter, BLOCK_SIZE, REFERENCE_DISTANCE,
+};
+use std::io::{Read, Seek};
+
+/// name of the profile of the HRIR a filter is built from
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Switch from one HRIR profile to another under way. The engine switched to starts out empty, so
+/// the profile switched from keeps rendering until a window of input went through the new one,
+/// then the two are crossfaded.
+#[derive(Debug)]
+pub(crate) struct ProfileSwitch {
+    pub(crate) outgoing: RawVirtualSurroundFilter,
+    /// index of the profile switched from
+    pub(crate) index: usize,
+    /// blocks until the output of the profile switched to is complete
+    priming: usize,
+    fade: Smoothed,
+}
+
+impl ProfileSwitch {
+    /// render the profile switched from into `space` and mix it into the output of the one
+    /// switched to, `true` once the crossfade is done
+    pub(crate) fn process(
+        &mut self,
+        input: &[&[f32]],
+        space: &mut [f32],
+        left: &mut [f32],
+        right: &mut [f32],
+    ) -> anyhow::Result<bool> {
+        space.fill(0f32);
+        let (previous_left, previous_right) = space.split_at_mut(BLOCK_SIZE);
+        self.outgoing
+            .transform(input, (previous_left, previous_right))?;
+
+        self.priming = self.priming.saturating_sub(1);
+        for s in 0..BLOCK_SIZE {
+            let mix = if self.priming > 0 {
+                0.0
+            } else {
+                self.fade.next_value()
+            };
+            left[s] = previous_left[s] + (left[s] - previous_left[s]) * mix;
+            right[s] = previous_right[s] + (right[s] - previous_right[s]) * mix;
+        }
+
+        Ok(self.priming == 0 && !self.fade.is_ramping())
+    }
+}
+
+impl VirtualSurroundFilter {
+    /// Load another HRIR as profile `name` to switch to with
+    /// [`set_profile`](VirtualSurroundFilter::set_profile), e.g. a personalized one to compare
+    /// with a generic one. It's prepared the way [`FilterBuilder::new`] would, resampled to the
+    /// rate of the filter, [`add_profile_from_hrir`](VirtualSurroundFilter::add_profile_from_hrir)
+    /// takes one prepared differently.
+    pub fn add_profile<R: Read + Seek>(&mut self, name: &str, reader: R) -> anyhow::Result<()> {
+        let mut builder = FilterBuilder::new();
+        if cfg!(any(feature = "resample", feature = "resample-rubato")) {
+            builder = builder.sample_rate(self.sample_rate() as u32);
+        }
+
+        self.add_profile_from_hrir(name, builder.load_hrir(reader)?)
+    }
+
+    /// Add an HRIR as profile `name`. It needs the speakers of the filter at its sample rate, and
+    /// impulse responses no longer than the window of the filter fits, shorter ones are padded.
+    /// The speakers are convolved with the engine and threads of the filter.
+    pub fn add_profile_from_hrir(&mut self, name: &str, hrir: Hrir) -> anyhow::Result<()> {
+        if self.profiles.iter().any(|(x, _)| x == name) {
+            anyhow::bail!("Filter already has a profile {}", name);
+        }
+
+        if hrir.sample_rate as usize != self.sample_rate() {
+            anyhow::bail!(
+                "Profile {} runs at {}Hz, the filter at {}Hz",
+                name,
+                hrir.sample_rate,
+                self.sample_rate()
+            );
+        }
+
+        if fft_len_for(hrir.ir_length()) > self.samples_required() {
+            anyhow::bail!(
+                "Profile {} has impulse responses of {} samples, longer than the window of the filter fits",
+                name,
+                hrir.ir_length()
+            );
+        }
+
+        // in the order of the filter and at least as long as the impulse responses it has, longer
+        // ones still fit the window
+        let length = self.inner.irs[0][0].len().max(hrir.ir_length());
+        let irs = self
+            .positions()
+            .map(|position| {
+                let speaker = match hrir.speakers.iter().find(|x| x.position == position) {
+                    Some(speaker) => speaker,
+                    None => anyhow::bail!(
+                        "Profile {} has no {} speaker",
+                        name,
+                        get_channel_name(position)
+                    ),
+                };
+
+                let padded = |ir: &[f32]| {
+                    let mut padded = ir.to_vec();
+                    padded.resize(length, 0.0);
+                    padded
+                };
+
+                Ok([padded(&speaker.left), padded(&speaker.right)])
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut profile = RawVirtualSurroundFilter::from_irs(
+            self.positions(),
+            hrir.sample_rate,
+            irs,
+            hrir.onset().min(self.samples_required() - BLOCK_SIZE),
+            self.inner.engine_factory,
+            self.inner.threads(),
+            None,
+        )?;
+
+        for channel in 0..self.channels() {
+            let meters = self.distances.distance(channel);
+            if meters != REFERENCE_DISTANCE {
+                profile.set_near_field(channel, meters)?;
+            }
+        }
+
+        self.profiles.push((name.to_string(), Some(profile)));
+        self.profile_space.resize(BLOCK_SIZE * 2, 0.0);
+
+        Ok(())
+    }
+
+    /// names of the profiles in the order they were added, [`DEFAULT_PROFILE`] first
+    pub fn profiles(&self) -> impl Iterator<Item = &str> + '_ {
+        self.profiles.iter().map(|(x, _)| x.as_str())
+    }
+
+    /// name of the profile rendering, or being switched to
+    pub fn profile(&self) -> &str {
+        &self.profiles[self.profile].0
+    }
+
+    /// Switch to the profile `name`. The profile switched to takes a window of input to catch up
+    /// and is then crossfaded to over the [`ramp_time`](VirtualSurroundFilter::set_ramp_time),
+    /// a switch before the first block is rendered applies right away. Switching again before
+    /// the crossfade ended cuts it short. Doesn't allocate, so it can be called from the audio
+    /// thread.
+    pub fn set_profile(&mut self, name: &str) -> anyhow::Result<()> {
+        match self.profiles.iter().position(|(x, _)| x == name) {
+            Some(index) => {
+                self.switch_profile(index);
+                Ok(())
+            }
+            None => anyhow::bail!("Filter has no profile {}", name),
+        }
+    }
+
+    /// [`set_profile`](VirtualSurroundFilter::set_profile) by the index in
+    /// [`profiles`](VirtualSurroundFilter::profiles)
+    pub(crate) fn switch_profile(&mut self, index: usize) {
+        if index == self.profile || index >= self.profiles.len() {
+            return;
+        }
+
+        self.finish_profile_switch();
+        let mut incoming = self.profiles[index]
+            .1
+            .take()
+            .expect("inactive profiles are kept in their slot");
+        incoming.reset();
+        let outgoing = std::mem::replace(&mut self.inner, incoming);
+        let previous = std::mem::replace(&mut self.profile, index);
+        trace_event!(DEBUG, profile = index, "switching profile");
+
+        if self.available_data < self.samples_required() {
+            self.profiles[previous].1 = Some(outgoing);
+            return;
+        }
+
+        let mut fade = Smoothed::new(0.0, self.ramp);
+        fade.set(1.0);
+        self.profile_switch = Some(ProfileSwitch {
+            outgoing,
+            index: previous,
+            priming: self.samples_required() / BLOCK_SIZE,
+            fade,
+        });
+    }
+
+    /// put the profile a switch fades out back into its slot
+    pub(crate) fn finish_profile_switch(&mut self) {
+        if let Some(switch) = self.profile_switch.take() {
+            self.profiles[switch.index].1 = Some(switch.outgoing);
+        }
+    }
+}
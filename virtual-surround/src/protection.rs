@@ -0,0 +1,290 @@
+/// soft clipping is linear up to this level
+const SOFT_CLIP_KNEE: f32 = 0.75;
+
+/// What happens to output which would exceed full scale
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum OutputProtection {
+    /// leave the output untouched, samples above ±1.0 are passed on
+    None,
+    /// clamp to ±1.0
+    #[default]
+    HardClip,
+    /// linear up to a knee, then a tanh curve which approaches ±1.0
+    SoftClip,
+    /// delays the output by `lookahead_ms` and lowers the gain ahead of peaks so the output never
+    /// exceeds `ceiling_db`, adds to the filter latency
+    LookaheadLimiter {
+        lookahead_ms: f32,
+        release_ms: f32,
+        ceiling_db: f32,
+    },
+}
+
+impl OutputProtection {
+    pub fn lookahead_limiter() -> Self {
+        OutputProtection::LookaheadLimiter {
+            lookahead_ms: 5.0,
+            release_ms: 50.0,
+            ceiling_db: -1.0,
+        }
+    }
+}
+
+fn soft_clip(sample: f32) -> f32 {
+    let magnitude = sample.abs();
+    if magnitude <= SOFT_CLIP_KNEE {
+        return sample;
+    }
+
+    let range = 1.0 - SOFT_CLIP_KNEE;
+    let shaped = SOFT_CLIP_KNEE + range * ((magnitude - SOFT_CLIP_KNEE) / range).tanh();
+    shaped.copysign(sample)
+}
+
+/// Stereo lookahead limiter, the required gain per sample is min filtered and then box filtered
+/// over the lookahead, so the gain has fully ramped down by the time the peak leaves the delay line
+#[derive(Debug, Clone)]
+struct Limiter {
+    ceiling: f32,
+    release: f32,
+    delay: [Vec<f32>; 2],
+    /// ring of the samples in the window which are lower than every later one with their sample
+    /// number, ascending from `minimum_start` so the first is the minimum of the window
+    minimum: Vec<(usize, f32)>,
+    minimum_start: usize,
+    minimum_len: usize,
+    samples: usize,
+    average: Vec<f32>,
+    average_sum: f64,
+    position: usize,
+    gain: f32,
+}
+
+impl Limiter {
+    fn new(sample_rate: usize, lookahead_ms: f32, release_ms: f32, ceiling_db: f32) -> Self {
+        let lookahead = ((lookahead_ms / 1000.0) * sample_rate as f32)
+            .round()
+            .max(1.0) as usize;
+        let release_samples = (release_ms / 1000.0) * sample_rate as f32;
+
+        Limiter {
+            ceiling: 10f32.powf(ceiling_db / 20.0),
+            release: 1.0 - (-1.0 / release_samples.max(1.0)).exp(),
+            delay: [vec![0f32; lookahead], vec![0f32; lookahead]],
+            minimum: vec![(0, 1f32); lookahead + 1],
+            minimum_start: 0,
+            minimum_len: 0,
+            samples: 0,
+            average: vec![1f32; lookahead + 1],
+            average_sum: (lookahead + 1) as f64,
+            position: 0,
+            gain: 1.0,
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.delay[0].len()
+    }
+
+    fn reset(&mut self) {
+        for delay in &mut self.delay {
+            delay.fill(0f32);
+        }
+
+        self.minimum_len = 0;
+        self.average.fill(1f32);
+        self.average_sum = self.average.len() as f64;
+        self.gain = 1.0;
+    }
+
+    /// add `target` to the window and return the lowest in it, constant per sample on average
+    fn window_minimum(&mut self, target: f32) -> f32 {
+        let window = self.minimum.len();
+        let at = |start: usize, index: usize| (start + index) % window;
+        if self.minimum_len > 0
+            && self
+                .samples
+                .wrapping_sub(self.minimum[self.minimum_start].0)
+                >= window
+        {
+            self.minimum_start = at(self.minimum_start, 1);
+            self.minimum_len -= 1;
+        }
+        while self.minimum_len > 0
+            && self.minimum[at(self.minimum_start, self.minimum_len - 1)].1 >= target
+        {
+            self.minimum_len -= 1;
+        }
+
+        self.minimum[at(self.minimum_start, self.minimum_len)] = (self.samples, target);
+        self.minimum_len += 1;
+        self.samples = self.samples.wrapping_add(1);
+
+        self.minimum[self.minimum_start].1
+    }
+
+    fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let peak = left.abs().max(right.abs());
+        let target = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+
+        let window = self.minimum.len();
+        let minimum = self.window_minimum(target);
+
+        let slot = self.position % window;
+        self.average_sum += (minimum - self.average[slot]) as f64;
+        self.average[slot] = minimum;
+        let average = (self.average_sum / window as f64) as f32;
+
+        if average < self.gain {
+            self.gain = average;
+        } else {
+            self.gain += (average - self.gain) * self.release;
+        }
+
+        let delay_slot = self.position % self.delay[0].len();
+        let delayed_left = std::mem::replace(&mut self.delay[0][delay_slot], left);
+        let delayed_right = std::mem::replace(&mut self.delay[1][delay_slot], right);
+
+        self.position = (self.position + 1) % (window * self.delay[0].len());
+
+        // rounding in the running sum shouldn't let anything through
+        (
+            (delayed_left * self.gain).clamp(-self.ceiling, self.ceiling),
+            (delayed_right * self.gain).clamp(-self.ceiling, self.ceiling),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct OutputProtector {
+    protection: OutputProtection,
+    limiter: Option<Limiter>,
+}
+
+impl OutputProtector {
+    pub fn new(protection: OutputProtection, sample_rate: usize) -> Self {
+        let limiter = match protection {
+            OutputProtection::LookaheadLimiter {
+                lookahead_ms,
+                release_ms,
+                ceiling_db,
+            } => Some(Limiter::new(
+                sample_rate,
+                lookahead_ms,
+                release_ms,
+                ceiling_db,
+            )),
+            _ => None,
+        };
+
+        OutputProtector {
+            protection,
+            limiter,
+        }
+    }
+
+    pub fn protection(&self) -> OutputProtection {
+        self.protection
+    }
+
+    pub fn latency(&self) -> usize {
+        self.limiter.as_ref().map_or(0, |x| x.latency())
+    }
+
+    /// forget the limiter state, recomputing its running sum which otherwise collects rounding
+    /// errors forever
+    pub fn reset(&mut self) {
+        if let Some(limiter) = &mut self.limiter {
+            limiter.reset();
+        }
+    }
+
+    fn process_frame(&mut self, left: f32, right: f32) -> (f32, f32) {
+        match (&mut self.limiter, self.protection) {
+            (Some(limiter), _) => limiter.process(left, right),
+            (None, OutputProtection::HardClip) => (left.clamp(-1.0, 1.0), right.clamp(-1.0, 1.0)),
+            (None, OutputProtection::SoftClip) => (soft_clip(left), soft_clip(right)),
+            (None, _) => (left, right),
+        }
+    }
+
+    /// protect `left` and `right` and interleave them into `output`
+    pub fn process(&mut self, left: &[f32], right: &[f32], output: &mut [f32]) {
+        for ((frame, left), right) in output.chunks_exact_mut(2).zip(left).zip(right) {
+            let (left, right) = self.process_frame(*left, *right);
+            frame[0] = left;
+            frame[1] = right;
+        }
+    }
+
+    /// like [`process`](OutputProtector::process) into separate left and right outputs
+    pub fn process_planar(&mut self, input: (&[f32], &[f32]), left: &mut [f32], right: &mut [f32]) {
+        for (((out_left, out_right), left), right) in left
+            .iter_mut()
+            .zip(right.iter_mut())
+            .zip(input.0)
+            .zip(input.1)
+        {
+            let frame = self.process_frame(*left, *right);
+            *out_left = frame.0;
+            *out_right = frame.1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Limiter, OutputProtection, OutputProtector};
+
+    #[test]
+    fn limiter_stays_under_ceiling() {
+        let mut protector = OutputProtector::new(OutputProtection::lookahead_limiter(), 48000);
+        let latency = protector.latency();
+
+        let left = (0..4096)
+            .map(|x| {
+                if x == 2000 {
+                    4.0
+                } else {
+                    (x as f32 * 0.05).sin() * 0.5
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut output = vec![0f32; left.len() * 2];
+
+        protector.process(&left, &left, &mut output);
+
+        let ceiling = 10f32.powf(-1.0 / 20.0);
+        assert!(output.iter().all(|x| x.abs() <= ceiling));
+        // the peak is delayed by the lookahead and reduced, not removed
+        assert!((output[(2000 + latency) * 2] - ceiling).abs() < 1e-3);
+        // quiet material before the peak's lookahead is untouched
+        assert_eq!(output[1000 * 2], left[1000 - latency]);
+    }
+
+    #[test]
+    fn limiter_tracks_the_minimum_of_the_window() {
+        let mut limiter = Limiter::new(48000, 0.1, 50.0, -1.0);
+        let window = limiter.minimum.len();
+        let targets = (0..1000)
+            .map(|x| ((x * 7919) % 101) as f32 / 100.0)
+            .collect::<Vec<_>>();
+
+        for (sample, target) in targets.iter().enumerate() {
+            let expected = targets[(sample + 1).saturating_sub(window)..=sample]
+                .iter()
+                .copied()
+                .fold(1f32, f32::min);
+            assert_eq!(limiter.window_minimum(*target), expected, "at {}", sample);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{channel_from_name, get_channel_name, Speaker};
+use std::io::{ErrorKind, Read, Write};
+
+/// first word of the header line
+pub const RAW_STREAM_MAGIC: &str = "VSRF";
+const MAX_HEADER: usize = 256;
+
+/// Header of a raw stream of interleaved little endian f32 frames, a single line like
+/// `VSRF 48000 FL,FR,FC,LFE,BL,BR\n` naming the sample rate and the channel of every column with
+/// the names of [`get_channel_name`]. Being text any program, or a shell script in front of
+/// one, can write it into a FIFO before the audio.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawStreamHeader {
+    pub sample_rate: u32,
+    pub layout: Vec<Speaker>,
+}
+
+impl RawStreamHeader {
+    /// reads the header line and nothing after it
+    pub fn read<R: Read>(reader: &mut R) -> anyhow::Result<Self> {
+        let mut line = vec![];
+        let mut byte = [0u8];
+
+        while byte[0] != b'\n' {
+            if line.len() > MAX_HEADER {
+                anyhow::bail!("Raw stream header is longer than {} bytes", MAX_HEADER);
+            }
+
+            reader.read_exact(&mut byte)?;
+            line.push(byte[0]);
+        }
+
+        let line = String::from_utf8(line)?;
+        let mut words = line.split_whitespace();
+
+        if words.next() != Some(RAW_STREAM_MAGIC) {
+            anyhow::bail!("Raw stream doesn't start with {}", RAW_STREAM_MAGIC);
+        }
+
+        let sample_rate = match words.next().map(str::parse) {
+            Some(Ok(rate)) if rate > 0 => rate,
+            _ => anyhow::bail!("Raw stream header has no sample rate"),
+        };
+
+        let layout = words
+            .next()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|x| !x.is_empty())
+            .map(|name| match channel_from_name(name) {
+                Some(mask) => Ok(mask),
+                None => anyhow::bail!("Raw stream header names unknown channel {}", name),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if layout.is_empty() {
+            anyhow::bail!("Raw stream header has no channels");
+        }
+
+        Ok(RawStreamHeader {
+            sample_rate,
+            layout,
+        })
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> anyhow::Result<()> {
+        let names = self
+            .layout
+            .iter()
+            .map(|x| get_channel_name(*x))
+            .collect::<Vec<_>>();
+
+        writeln!(
+            writer,
+            "{} {} {}",
+            RAW_STREAM_MAGIC,
+            self.sample_rate,
+            names.join(",")
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Reads a [`RawStreamHeader`] and the frames after it, e.g. from a FIFO
+#[derive(Debug)]
+pub struct RawStreamReader<R> {
+    reader: R,
+    header: RawStreamHeader,
+    /// bytes of a frame split over reads
+    pending: Vec<u8>,
+    bytes: Vec<u8>,
+}
+
+impl<R: Read> RawStreamReader<R> {
+    pub fn new(mut reader: R) -> anyhow::Result<Self> {
+        let header = RawStreamHeader::read(&mut reader)?;
+
+        Ok(RawStreamReader {
+            reader,
+            header,
+            pending: vec![],
+            bytes: vec![],
+        })
+    }
+
+    pub fn header(&self) -> &RawStreamHeader {
+        &self.header
+    }
+
+    pub fn channels(&self) -> usize {
+        self.header.layout.len()
+    }
+
+    /// read at least one and at most `output.len()` / channels whole frames into `output`,
+    /// blocking until they arrive, 0 at the end of the stream
+    pub fn read_frames(&mut self, output: &mut [f32]) -> anyhow::Result<usize> {
+        let frame = self.channels() * 4;
+        let frames = output.len() / self.channels();
+        if frames == 0 {
+            return Ok(0);
+        }
+
+        self.bytes.resize(frames * frame, 0u8);
+        let mut filled = self.pending.len();
+        self.bytes[..filled].copy_from_slice(&self.pending);
+
+        while filled < frame {
+            match self.reader.read(&mut self.bytes[filled..]) {
+                Ok(0) => return Ok(0),
+                Ok(read) => filled += read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let whole = filled / frame * frame;
+        self.pending.clear();
+        self.pending.extend_from_slice(&self.bytes[whole..filled]);
+
+        for (sample, bytes) in output.iter_mut().zip(self.bytes[..whole].chunks_exact(4)) {
+            *sample = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        Ok(whole / frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RawStreamHeader, RawStreamReader};
+    use crate::Speaker;
+
+    #[test]
+    fn reads_frames_after_the_header() {
+        let header = RawStreamHeader {
+            sample_rate: 48000,
+            layout: vec![Speaker::FrontLeft, Speaker::SideRight],
+        };
+
+        let mut stream = vec![];
+        header.write(&mut stream).unwrap();
+        assert_eq!(stream, b"VSRF 48000 FL,SR\n");
+
+        for sample in [0.25f32, -0.5, 1.0] {
+            stream.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        let mut reader = RawStreamReader::new(stream.as_slice()).unwrap();
+        assert_eq!(reader.header(), &header);
+
+        // the last frame is incomplete
+        let mut output = vec![0f32; 8];
+        assert_eq!(reader.read_frames(&mut output).unwrap(), 1);
+        assert_eq!(output[..2], [0.25, -0.5]);
+        assert_eq!(reader.read_frames(&mut output).unwrap(), 0);
+
+        assert!(RawStreamHeader::read(&mut &b"VSRF 48000 FL,XX\n"[..]).is_err());
+        assert!(RawStreamHeader::read(&mut &b"RIFF"[..]).is_err());
+    }
+}
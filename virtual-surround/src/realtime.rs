@@ -0,0 +1,284 @@
+use crate::{
+    no_alloc, Orientation, ProcessStatus, Recentering, Smoothed, VirtualSurroundFilter, BLOCK_SIZE,
+};
+use ringbuf::{Consumer, Producer, RingBuffer};
+use std::time::Duration;
+
+/// commands the controller can queue before the processor picks them up
+const COMMAND_QUEUE: usize = 64;
+
+enum Command {
+    Mix(f32),
+    Bypass(bool),
+    Gain(f32),
+    Orientation(Orientation),
+    Recenter,
+    /// index of the profile in [`VirtualSurroundFilter::profiles`]
+    Profile(usize),
+    Filter(Box<VirtualSurroundFilter>),
+}
+
+/// The audio thread half of a [`VirtualSurroundFilter`] split with [`VsfProcessor::new`].
+///
+/// Changes queued by the [`VsfController`] are applied at the start of the next block. Nothing is
+/// allocated or freed while processing, a replaced filter is handed back to the controller to be
+/// dropped there.
+pub struct VsfProcessor {
+    filter: Box<VirtualSurroundFilter>,
+    /// filter swapped in by the controller, rendered alongside until its window is filled
+    pending: Option<Box<VirtualSurroundFilter>>,
+    pending_space: Vec<f32>,
+    gain: Smoothed,
+    /// front of the orientations queued, moved by a recenter
+    recentering: Recentering,
+    /// samples rendered since the last orientation
+    since_orientation: usize,
+    commands: Consumer<Command>,
+    retired: Producer<Box<VirtualSurroundFilter>>,
+    /// replaced filters the retired queue had no room for, commands wait until they're handed
+    /// back so there are never more than the pending filter and the one it replaced
+    stalled: [Option<Box<VirtualSurroundFilter>>; 2],
+}
+
+/// The control thread half of a [`VirtualSurroundFilter`] split with [`VsfProcessor::new`],
+/// changes are sent to the processor over a lock-free queue
+pub struct VsfController {
+    sample_rate: usize,
+    input_channels: usize,
+    profiles: Vec<String>,
+    commands: Producer<Command>,
+    retired: Consumer<Box<VirtualSurroundFilter>>,
+}
+
+impl VsfProcessor {
+    pub fn new(filter: VirtualSurroundFilter) -> (VsfProcessor, VsfController) {
+        let (commands, command_consumer) = RingBuffer::new(COMMAND_QUEUE).split();
+        // every queued filter comes back at most once, plus the one running now
+        let (retired_producer, retired) = RingBuffer::new(COMMAND_QUEUE + 1).split();
+
+        let controller = VsfController {
+            sample_rate: filter.sample_rate(),
+            input_channels: filter.input_channels(),
+            profiles: filter.profiles().map(str::to_string).collect(),
+            commands,
+            retired,
+        };
+
+        let ramp = filter.ramp;
+        let processor = VsfProcessor {
+            filter: Box::new(filter),
+            pending: None,
+            pending_space: vec![0f32; BLOCK_SIZE * 2],
+            gain: Smoothed::new(1.0, ramp),
+            recentering: Recentering::new(None),
+            since_orientation: 0,
+            commands: command_consumer,
+            retired: retired_producer,
+            stalled: [None, None],
+        };
+
+        (processor, controller)
+    }
+
+    pub fn filter(&self) -> &VirtualSurroundFilter {
+        &self.filter
+    }
+
+    /// hand `filter` back to the controller to be dropped there, never on the audio thread
+    fn retire(&mut self, filter: Box<VirtualSurroundFilter>) {
+        if let Err(filter) = self.retired.push(filter) {
+            let slot = if self.stalled[0].is_none() { 0 } else { 1 };
+            self.stalled[slot] = Some(filter);
+        }
+    }
+
+    fn apply_commands(&mut self) {
+        for slot in 0..self.stalled.len() {
+            if let Some(filter) = self.stalled[slot].take() {
+                if let Err(filter) = self.retired.push(filter) {
+                    self.stalled[slot] = Some(filter);
+                    return;
+                }
+            }
+        }
+
+        while self.stalled.iter().all(Option::is_none) {
+            let command = match self.commands.pop() {
+                Some(command) => command,
+                None => break,
+            };
+
+            match command {
+                Command::Mix(mix) => {
+                    self.filter.set_mix(mix);
+                    if let Some(pending) = &mut self.pending {
+                        pending.set_mix(mix);
+                    }
+                }
+                Command::Bypass(bypass) => {
+                    self.filter.set_bypass(bypass);
+                    if let Some(pending) = &mut self.pending {
+                        pending.set_bypass(bypass);
+                    }
+                }
+                Command::Gain(gain) => self.gain.set(gain),
+                Command::Orientation(orientation) => {
+                    let elapsed = self.since_orientation as f32 / self.filter.sample_rate() as f32;
+                    let orientation = self
+                        .recentering
+                        .update(orientation, Duration::from_secs_f32(elapsed));
+                    self.since_orientation = 0;
+
+                    self.filter.set_head_orientation(orientation);
+                    if let Some(pending) = &mut self.pending {
+                        pending.set_head_orientation(orientation);
+                    }
+                }
+                Command::Recenter => self.recentering.recenter(),
+                Command::Profile(index) => {
+                    self.filter.switch_profile(index);
+                    if let Some(pending) = &mut self.pending {
+                        pending.switch_profile(index);
+                    }
+                }
+                Command::Filter(mut filter) => {
+                    // it's crossfaded to rather than faded in from silence
+                    filter.skip_fade_in();
+                    if let Some(previous) = self.pending.replace(filter) {
+                        self.retire(previous);
+                    }
+                }
+            }
+        }
+    }
+
+    /// [`transform`](VirtualSurroundFilter::transform) through the current filter. A filter
+    /// swapped in is fed alongside until it renders, and is crossfaded to over that block.
+    pub fn transform(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> anyhow::Result<ProcessStatus> {
+        no_alloc(|| self.apply_commands());
+        self.since_orientation += BLOCK_SIZE;
+
+        let mut status = self.filter.transform(input, output)?;
+
+        if let Some(pending) = &mut self.pending {
+            if let ProcessStatus::Rendered = pending.transform(input, &mut self.pending_space)? {
+                let fade = 1.0 / BLOCK_SIZE as f32;
+                for (s, (frame, next)) in output
+                    .chunks_exact_mut(2)
+                    .zip(self.pending_space.chunks_exact(2))
+                    .enumerate()
+                {
+                    let mix = (s + 1) as f32 * fade;
+                    frame[0] += (next[0] - frame[0]) * mix;
+                    frame[1] += (next[1] - frame[1]) * mix;
+                }
+
+                trace_event!(DEBUG, "crossfaded to the swapped in filter");
+                no_alloc(|| {
+                    let pending = self.pending.take().unwrap();
+                    let previous = std::mem::replace(&mut self.filter, pending);
+                    self.retire(previous);
+                });
+                status = ProcessStatus::Rendered;
+            }
+        }
+
+        for frame in output[..BLOCK_SIZE * 2].chunks_exact_mut(2) {
+            let gain = self.gain.next_value();
+            frame[0] *= gain;
+            frame[1] *= gain;
+        }
+
+        Ok(status)
+    }
+}
+
+impl VsfController {
+    fn send(&mut self, command: Command) -> anyhow::Result<()> {
+        self.collect();
+
+        if self.commands.push(command).is_err() {
+            anyhow::bail!("Command queue is full, the processor isn't keeping up");
+        }
+
+        Ok(())
+    }
+
+    /// see [`VirtualSurroundFilter::set_mix`]
+    pub fn set_mix(&mut self, mix: f32) -> anyhow::Result<()> {
+        self.send(Command::Mix(mix))
+    }
+
+    /// see [`VirtualSurroundFilter::set_bypass`]
+    pub fn set_bypass(&mut self, bypass: bool) -> anyhow::Result<()> {
+        self.send(Command::Bypass(bypass))
+    }
+
+    /// output gain, ramped over the [`ramp_time`](VirtualSurroundFilter::set_ramp_time) of the
+    /// filter the processor was made with
+    pub fn set_gain(&mut self, gain_db: f32) -> anyhow::Result<()> {
+        self.send(Command::Gain(10f32.powf(gain_db / 20.0)))
+    }
+
+    /// see [`VirtualSurroundFilter::set_head_orientation`], e.g. from a
+    /// [`HeadTracker`](crate::HeadTracker) subscribed on another thread
+    pub fn set_head_orientation(&mut self, orientation: Orientation) -> anyhow::Result<()> {
+        self.send(Command::Orientation(orientation))
+    }
+
+    /// make where the head points with the next orientation the front, see
+    /// [`Recentering`](crate::Recentering)
+    pub fn recenter(&mut self) -> anyhow::Result<()> {
+        self.send(Command::Recenter)
+    }
+
+    /// names of the profiles of the filter, see [`VirtualSurroundFilter::profiles`]
+    pub fn profiles(&self) -> impl Iterator<Item = &str> {
+        self.profiles.iter().map(String::as_str)
+    }
+
+    /// see [`VirtualSurroundFilter::set_profile`]
+    pub fn set_profile(&mut self, name: &str) -> anyhow::Result<()> {
+        match self.profiles.iter().position(|x| x == name) {
+            Some(index) => self.send(Command::Profile(index)),
+            None => anyhow::bail!("Filter has no profile {}", name),
+        }
+    }
+
+    /// Replace the filter, e.g. with another HRIR, built and configured on this thread. It has to
+    /// take the same input at the same sample rate, its own mix and bypass are kept until changed.
+    pub fn swap_filter(&mut self, filter: VirtualSurroundFilter) -> anyhow::Result<()> {
+        if filter.sample_rate() != self.sample_rate {
+            anyhow::bail!(
+                "New filter runs at {}hz, the processor at {}hz",
+                filter.sample_rate(),
+                self.sample_rate
+            );
+        }
+
+        if filter.input_channels() != self.input_channels {
+            anyhow::bail!(
+                "New filter takes {} channels, the processor is fed {}",
+                filter.input_channels(),
+                self.input_channels
+            );
+        }
+
+        self.profiles = filter.profiles().map(str::to_string).collect();
+        self.send(Command::Filter(Box::new(filter)))
+    }
+
+    /// drop the filters the processor replaced, returns how many. Sending anything does this too.
+    pub fn collect(&mut self) -> usize {
+        let mut count = 0;
+        while self.retired.pop().is_some() {
+            count += 1;
+        }
+
+        count
+    }
+}
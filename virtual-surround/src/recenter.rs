@@ -0,0 +1,224 @@
+use crate::{HeadTracker, Orientation};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// `degrees` wrapped to -180..180
+fn wrap(degrees: f32) -> f32 {
+    (degrees + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Slowly turns the front towards where the head points once it held still for a while, which
+/// undoes the yaw drift of trackers without a compass
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AutoRecenter {
+    /// degrees per second the front turns
+    pub rate: f32,
+    /// degrees the head can move and still count as holding still
+    pub tolerance: f32,
+    /// how long the head has to hold still before recentering starts
+    pub hold: Duration,
+}
+
+impl Default for AutoRecenter {
+    fn default() -> Self {
+        AutoRecenter {
+            rate: 2.0,
+            tolerance: 3.0,
+            hold: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Recenters [`Recentering`] from another thread, e.g. a control surface or a key binding
+#[derive(Debug, Clone)]
+pub struct RecenterTrigger(Arc<AtomicBool>);
+
+impl RecenterTrigger {
+    /// make where the head points now the front, on the next orientation
+    pub fn recenter(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Yaw offset between the tracker and the front, moved by a manual recenter or by
+/// [`AutoRecenter`]. Pitch and roll are left alone, gravity keeps those from drifting.
+#[derive(Debug, Clone)]
+pub struct Recentering {
+    auto: Option<AutoRecenter>,
+    /// tracker yaw of the front
+    offset: f32,
+    /// yaw the head started holding still at
+    anchor: f32,
+    still: Duration,
+    trigger: RecenterTrigger,
+}
+
+impl Recentering {
+    pub fn new(auto: Option<AutoRecenter>) -> Self {
+        Recentering {
+            auto,
+            offset: 0.0,
+            anchor: 0.0,
+            still: Duration::ZERO,
+            trigger: RecenterTrigger(Arc::new(AtomicBool::new(false))),
+        }
+    }
+
+    pub fn set_auto(&mut self, auto: Option<AutoRecenter>) {
+        self.auto = auto;
+        self.still = Duration::ZERO;
+    }
+
+    pub fn auto(&self) -> Option<AutoRecenter> {
+        self.auto
+    }
+
+    pub fn trigger(&self) -> RecenterTrigger {
+        self.trigger.clone()
+    }
+
+    /// see [`RecenterTrigger::recenter`]
+    pub fn recenter(&self) {
+        self.trigger.recenter();
+    }
+
+    /// tracker yaw which is taken as the front
+    pub fn offset(&self) -> f32 {
+        self.offset
+    }
+
+    /// orientation relative to the front for a tracker orientation `elapsed` after the last
+    pub fn update(&mut self, orientation: Orientation, elapsed: Duration) -> Orientation {
+        let yaw = orientation.yaw;
+
+        if self.trigger.0.swap(false, Ordering::Relaxed) {
+            self.offset = yaw;
+            self.anchor = yaw;
+            self.still = Duration::ZERO;
+        } else if let Some(auto) = self.auto {
+            if wrap(yaw - self.anchor).abs() <= auto.tolerance {
+                self.still += elapsed;
+            } else {
+                self.anchor = yaw;
+                self.still = Duration::ZERO;
+            }
+
+            if self.still >= auto.hold {
+                let step = auto.rate * elapsed.as_secs_f32();
+                self.offset = wrap(self.offset + wrap(yaw - self.offset).clamp(-step, step));
+            }
+        }
+
+        Orientation {
+            yaw: wrap(yaw - self.offset),
+            ..orientation
+        }
+    }
+}
+
+/// A tracker whose front follows [`Recentering`]
+pub struct RecenteringTracker<T: HeadTracker> {
+    tracker: T,
+    recentering: Recentering,
+    last: Option<Instant>,
+}
+
+impl<T: HeadTracker> RecenteringTracker<T> {
+    pub fn new(tracker: T, auto: Option<AutoRecenter>) -> Self {
+        RecenteringTracker {
+            tracker,
+            recentering: Recentering::new(auto),
+            last: None,
+        }
+    }
+
+    pub fn recentering(&mut self) -> &mut Recentering {
+        &mut self.recentering
+    }
+
+    pub fn trigger(&self) -> RecenterTrigger {
+        self.recentering.trigger()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.tracker
+    }
+}
+
+impl<T: HeadTracker> HeadTracker for RecenteringTracker<T> {
+    fn poll(&mut self) -> anyhow::Result<Option<Orientation>> {
+        let orientation = match self.tracker.poll()? {
+            Some(orientation) => orientation,
+            None => return Ok(None),
+        };
+
+        let now = Instant::now();
+        let elapsed = self.last.map_or(Duration::ZERO, |x| now - x);
+        self.last = Some(now);
+
+        Ok(Some(self.recentering.update(orientation, elapsed)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AutoRecenter, Recentering};
+    use crate::Orientation;
+    use std::time::Duration;
+
+    const TICK: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn recenters_on_request() {
+        let mut recentering = Recentering::new(None);
+        let trigger = recentering.trigger();
+
+        let turned = Orientation::new(170.0, 10.0, 0.0);
+        assert_eq!(recentering.update(turned, TICK), turned);
+
+        trigger.recenter();
+        assert_eq!(
+            recentering.update(turned, TICK),
+            Orientation::new(0.0, 10.0, 0.0)
+        );
+
+        // wraps around the back
+        let yaw = recentering
+            .update(Orientation::new(-170.0, 0.0, 0.0), TICK)
+            .yaw;
+        assert!((yaw - 20.0).abs() < 1e-3, "{}", yaw);
+    }
+
+    #[test]
+    fn recenters_slowly_while_holding_still() {
+        let auto = AutoRecenter::default();
+        let mut recentering = Recentering::new(Some(auto));
+
+        // moving around doesn't count as holding still
+        for tick in 0..100 {
+            let yaw = 20.0 + (tick % 4) as f32 * 5.0;
+            recentering.update(Orientation::new(yaw, 0.0, 0.0), TICK);
+        }
+        assert_eq!(recentering.offset(), 0.0);
+
+        let ticks = (auto.hold.as_secs_f32() / TICK.as_secs_f32()) as usize;
+        let mut yaws = vec![];
+        for _ in 0..ticks + 200 {
+            yaws.push(
+                recentering
+                    .update(Orientation::new(20.0, 0.0, 0.0), TICK)
+                    .yaw,
+            );
+        }
+
+        // nothing happens until the hold time passed, then the front turns at the set rate
+        assert!(yaws[..ticks - 1].iter().all(|x| *x == 20.0));
+        assert!(
+            (yaws[ticks + 9] - (20.0 - auto.rate)).abs() < 0.3,
+            "{}",
+            yaws[ticks + 9]
+        );
+        assert!(yaws.last().unwrap().abs() < 1e-3);
+    }
+}
@@ -0,0 +1,148 @@
+#![cfg(feature = "reference")]
+
+use crate::{ConvolutionEngine, EngineFactory, FFTLogic, BLOCK_SIZE};
+
+/// Convolves in the time domain, one multiply-add per impulse response sample for every output
+/// sample, in f64. Far too slow to listen through with real HRIRs, but simple enough to be
+/// obviously right, which makes it the ground truth to check other engines against with
+/// [`reference_error`].
+#[derive(Debug)]
+pub struct ReferenceLogic {
+    length: usize,
+    ir: Vec<Vec<f64>>,
+}
+
+impl FFTLogic for ReferenceLogic {
+    fn new(channels: usize, length: usize) -> anyhow::Result<Self> {
+        Ok(ReferenceLogic {
+            length,
+            ir: vec![vec![]; channels * 2],
+        })
+    }
+}
+
+impl ConvolutionEngine for ReferenceLogic {
+    fn init_ir(&mut self, impulse: &[f32], ir_index: usize) -> anyhow::Result<()> {
+        // the zero padding doesn't change the result, only the time it takes
+        let end = impulse.iter().rposition(|x| *x != 0.0).map_or(0, |x| x + 1);
+        self.ir[ir_index] = impulse[..end].iter().map(|x| *x as f64).collect();
+        Ok(())
+    }
+
+    fn process(
+        &mut self,
+        channel: usize,
+        samples: &[f32],
+        left_output: &mut [f32],
+        right_output: &mut [f32],
+    ) -> anyhow::Result<()> {
+        let start = self.length - BLOCK_SIZE;
+
+        for (ear, output) in [left_output, right_output].iter_mut().enumerate() {
+            let ir = &self.ir[channel * 2 + ear];
+
+            for (s, output) in output[..BLOCK_SIZE].iter_mut().enumerate() {
+                let position = start + s;
+                let sum: f64 = ir
+                    .iter()
+                    .take(position + 1)
+                    .enumerate()
+                    .map(|(k, x)| x * samples[position - k] as f64)
+                    .sum();
+
+                *output += sum as f32;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn latency(&self) -> usize {
+        self.length - BLOCK_SIZE
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// Worst difference between the output of the engine from `factory` and [`ReferenceLogic`],
+/// over `blocks` blocks of noise through `channels` channels of noise impulse responses filling
+/// windows of `length` samples. The input slides through the window like it does in the filter,
+/// so engines carrying state between blocks are checked too.
+pub fn reference_error(
+    factory: EngineFactory,
+    channels: usize,
+    length: usize,
+    blocks: usize,
+) -> anyhow::Result<f32> {
+    let mut state = 0x1234_5678u32;
+    let mut noise = || {
+        state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+    };
+
+    let mut engine = factory(channels, length)?;
+    let mut reference = ReferenceLogic::new(channels, length)?;
+
+    // the impulse responses end a block before the window does, like the filter's
+    let mut impulse = vec![0f32; length];
+    for ir_index in 0..channels * 2 {
+        for sample in &mut impulse[..length - BLOCK_SIZE - 1] {
+            *sample = noise() * 0.05;
+        }
+
+        engine.init_ir(&impulse, ir_index)?;
+        reference.init_ir(&impulse, ir_index)?;
+    }
+
+    let input = (0..channels)
+        .map(|_| {
+            (0..length + blocks * BLOCK_SIZE)
+                .map(|_| noise())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut error = 0f32;
+    for block in 0..blocks {
+        let window = block * BLOCK_SIZE..block * BLOCK_SIZE + length;
+        let mut expected = [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]];
+        let mut output = [vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]];
+
+        for (channel, input) in input.iter().enumerate() {
+            let [left, right] = &mut expected;
+            reference.process(channel, &input[window.clone()], left, right)?;
+            let [left, right] = &mut output;
+            engine.process(channel, &input[window.clone()], left, right)?;
+        }
+
+        for (output, expected) in output.iter().zip(&expected) {
+            for (x, y) in output.iter().zip(expected) {
+                error = error.max((x - y).abs());
+            }
+        }
+    }
+
+    Ok(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::reference_error;
+    use crate::{new_engine, Engine, BLOCK_SIZE};
+
+    #[test]
+    fn matches_itself() {
+        let error =
+            reference_error(new_engine::<super::ReferenceLogic>, 2, BLOCK_SIZE * 3, 4).unwrap();
+        assert_eq!(error, 0.0);
+    }
+
+    #[test]
+    fn engines_match_the_reference() {
+        // long enough for the partitioned engines to render tails
+        for engine in Engine::available() {
+            let error = reference_error(engine.factory(), 2, 8192, 12).unwrap();
+            assert!(error < 1e-3, "{} is off by {}", engine.name(), error);
+        }
+    }
+}
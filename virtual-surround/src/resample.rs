@@ -0,0 +1,124 @@
+/// Trade between the time resampling an HRIR takes at startup and how clean the result is,
+/// which matters for long BRIRs
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ResampleQuality {
+    /// libsamplerate's `SincBestQuality`, or a 256 tap sinc with rubato
+    #[default]
+    Best,
+    /// libsamplerate's `SincMediumQuality`, or a 128 tap sinc with rubato
+    Medium,
+    /// libsamplerate's `SincFastest`, or a 64 tap sinc with rubato
+    Fastest,
+    /// linear interpolation, aliases audibly but costs next to nothing
+    Linear,
+}
+
+/// resample interleaved `data` with `channels` channels
+#[cfg(all(feature = "resample", not(feature = "resample-rubato")))]
+pub(crate) fn resample(
+    data: &[f32],
+    channels: usize,
+    from: u32,
+    to: u32,
+    quality: ResampleQuality,
+) -> anyhow::Result<Vec<f32>> {
+    use samplerate::ConverterType;
+
+    let converter = match quality {
+        ResampleQuality::Best => ConverterType::SincBestQuality,
+        ResampleQuality::Medium => ConverterType::SincMediumQuality,
+        ResampleQuality::Fastest => ConverterType::SincFastest,
+        ResampleQuality::Linear => ConverterType::Linear,
+    };
+
+    Ok(samplerate::convert(from, to, channels, converter, data)?)
+}
+
+/// resample interleaved `data` with `channels` channels, with rubato taking the place of
+/// libsamplerate when the `resample-rubato` feature is on
+#[cfg(feature = "resample-rubato")]
+pub(crate) fn resample(
+    data: &[f32],
+    channels: usize,
+    from: u32,
+    to: u32,
+    quality: ResampleQuality,
+) -> anyhow::Result<Vec<f32>> {
+    use rubato::{
+        FastFixedIn, PolynomialDegree, SincFixedIn, SincInterpolationParameters,
+        SincInterpolationType, WindowFunction,
+    };
+
+    let frames = data.len() / channels.max(1);
+    if frames == 0 {
+        return Ok(vec![]);
+    }
+
+    let ratio = to as f64 / from as f64;
+    let sinc =
+        |sinc_len: usize, interpolation: SincInterpolationType| SincInterpolationParameters {
+            sinc_len,
+            f_cutoff: 0.95,
+            oversampling_factor: sinc_len,
+            interpolation,
+            window: WindowFunction::BlackmanHarris2,
+        };
+
+    let planar = (0..channels)
+        .map(|channel| {
+            data.iter()
+                .skip(channel)
+                .step_by(channels)
+                .copied()
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let parameters = match quality {
+        ResampleQuality::Best => sinc(256, SincInterpolationType::Cubic),
+        ResampleQuality::Medium => sinc(128, SincInterpolationType::Linear),
+        ResampleQuality::Fastest => sinc(64, SincInterpolationType::Linear),
+        ResampleQuality::Linear => {
+            let resampler =
+                FastFixedIn::new(ratio, 1.0, PolynomialDegree::Linear, frames, channels)?;
+            return run(resampler, &planar, ratio);
+        }
+    };
+
+    run(
+        SincFixedIn::new(ratio, 1.0, parameters, frames, channels)?,
+        &planar,
+        ratio,
+    )
+}
+
+/// `planar` through `resampler` as a single chunk, interleaved
+#[cfg(feature = "resample-rubato")]
+fn run<R: rubato::Resampler<f32>>(
+    mut resampler: R,
+    planar: &[Vec<f32>],
+    ratio: f64,
+) -> anyhow::Result<Vec<f32>> {
+    // silence is fed after the input until the delay of the resampler is made up for
+    let delay = resampler.output_delay();
+    let length = (planar[0].len() as f64 * ratio).round() as usize;
+    let mut output = resampler.process(planar, None)?;
+    while output[0].len() < delay + length {
+        let tail = resampler.process_partial(None::<&[Vec<f32>]>, None)?;
+        for (output, tail) in output.iter_mut().zip(tail) {
+            output.extend(tail);
+        }
+    }
+
+    let mut interleaved = Vec::with_capacity(length * planar.len());
+    for frame in delay..delay + length {
+        interleaved.extend(output.iter().map(|x| x[frame]));
+    }
+
+    Ok(interleaved)
+}
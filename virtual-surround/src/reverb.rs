@@ -0,0 +1,181 @@
+/// delay line lengths at 48khz, mutually prime so the echoes don't line up
+const LINES: [usize; 8] = [1187, 1423, 1637, 1811, 2003, 2179, 2357, 2531];
+
+/// Settings of the reverb tail [`VirtualSurroundFilter::set_reverb`](crate::VirtualSurroundFilter::set_reverb)
+/// adds after the early reflections, which speakers feed it is set per speaker with
+/// [`set_reverb_send`](crate::VirtualSurroundFilter::set_reverb_send)
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Reverb {
+    /// seconds the tail takes to decay by 60dB
+    pub decay_s: f32,
+    /// from 0 to 1, how much faster the highs die out than the lows
+    pub damping: f32,
+    /// milliseconds between the direct sound and the tail
+    pub pre_delay_ms: f32,
+    /// level of the tail
+    pub level_db: f32,
+}
+
+impl Default for Reverb {
+    /// a living room
+    fn default() -> Self {
+        Reverb {
+            decay_s: 0.5,
+            damping: 0.5,
+            pre_delay_ms: 20.0,
+            level_db: -12.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct DelayLine {
+    buffer: Vec<f32>,
+    position: usize,
+    /// per pass through the line, from the decay time
+    gain: f32,
+    lowpass: f32,
+}
+
+/// Feedback delay network of 8 lines mixed by a Hadamard matrix, the even lines feed the left
+/// ear and the odd ones the right so the ears get an uncorrelated tail
+#[derive(Debug, Clone)]
+pub(crate) struct FdnReverb {
+    settings: Reverb,
+    lines: [DelayLine; 8],
+    pre_delay: Vec<f32>,
+    position: usize,
+    damping: f32,
+    level: f32,
+}
+
+impl FdnReverb {
+    /// `ir_delay` is added to the pre-delay so the tail follows the direct sound of the HRIR
+    pub fn new(settings: Reverb, sample_rate: usize, ir_delay: usize) -> Self {
+        let decay = settings.decay_s.max(0.01) * sample_rate as f32;
+        let lines = LINES.map(|x| {
+            let length = (x * sample_rate / 48000).max(1);
+            DelayLine {
+                buffer: vec![0f32; length],
+                position: 0,
+                gain: 10f32.powf(-3.0 * length as f32 / decay),
+                lowpass: 0.0,
+            }
+        });
+        let pre_delay = (settings.pre_delay_ms.max(0.0) / 1000.0 * sample_rate as f32).round();
+
+        FdnReverb {
+            settings,
+            lines,
+            pre_delay: vec![0f32; pre_delay as usize + ir_delay + 1],
+            position: 0,
+            damping: settings.damping.clamp(0.0, 0.99),
+            // the 4 lines per ear add up uncorrelated
+            level: 10f32.powf(settings.level_db / 20.0) / 2.0,
+        }
+    }
+
+    pub fn settings(&self) -> Reverb {
+        self.settings
+    }
+
+    pub fn reset(&mut self) {
+        self.pre_delay.fill(0f32);
+        for line in &mut self.lines {
+            line.buffer.fill(0f32);
+            line.lowpass = 0.0;
+        }
+    }
+
+    /// add the tail of `input`, the sum of the speakers weighted by their sends, to `left` and
+    /// `right`
+    pub fn process(&mut self, input: &[f32], left: &mut [f32], right: &mut [f32]) {
+        for (s, sample) in input.iter().enumerate() {
+            let slot = self.position;
+            let delayed = std::mem::replace(&mut self.pre_delay[slot], *sample);
+            self.position = (self.position + 1) % self.pre_delay.len();
+
+            let mut outputs = [0f32; 8];
+            for (line, output) in self.lines.iter_mut().zip(&mut outputs) {
+                let x = line.buffer[line.position];
+                line.lowpass = x + self.damping * (line.lowpass - x);
+                *output = line.lowpass * line.gain;
+            }
+
+            left[s] += (outputs[0] + outputs[2] + outputs[4] + outputs[6]) * self.level;
+            right[s] += (outputs[1] + outputs[3] + outputs[5] + outputs[7]) * self.level;
+
+            hadamard(&mut outputs);
+            for (i, (line, output)) in self.lines.iter_mut().zip(outputs).enumerate() {
+                let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+                line.buffer[line.position] = output + delayed * sign;
+                line.position = (line.position + 1) % line.buffer.len();
+            }
+        }
+    }
+}
+
+/// orthonormal Hadamard mix of the lines, lossless so only the line gains decay the tail
+fn hadamard(x: &mut [f32; 8]) {
+    let mut h = 1;
+    while h < 8 {
+        for i in (0..8).step_by(h * 2) {
+            for j in i..i + h {
+                let (a, b) = (x[j], x[j + h]);
+                x[j] = a + b;
+                x[j + h] = a - b;
+            }
+        }
+        h *= 2;
+    }
+
+    for x in x.iter_mut() {
+        *x *= std::f32::consts::FRAC_1_SQRT_2 / 2.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FdnReverb, Reverb};
+
+    #[test]
+    fn decays_by_60db_over_the_decay_time() {
+        let rate = 48000;
+        let mut reverb = FdnReverb::new(
+            Reverb {
+                decay_s: 0.5,
+                damping: 0.0,
+                pre_delay_ms: 10.0,
+                level_db: 0.0,
+            },
+            rate,
+            0,
+        );
+
+        let mut input = vec![0f32; rate * 2];
+        input[0] = 1.0;
+        let mut left = vec![0f32; input.len()];
+        let mut right = vec![0f32; input.len()];
+        reverb.process(&input, &mut left, &mut right);
+
+        // nothing before the pre-delay and the shortest line
+        assert!(left[..480 + 1187].iter().all(|x| *x == 0.0));
+
+        let level = |ear: &[f32], from: f32| {
+            let range = (from * rate as f32) as usize..((from + 0.1) * rate as f32) as usize;
+            10.0 * (ear[range].iter().map(|x| x * x).sum::<f32>()).log10()
+        };
+        let decay = level(&left, 0.2) - level(&left, 0.7);
+        assert!((decay - 60.0).abs() < 6.0, "decayed by {}dB", decay);
+
+        let correlation = left.iter().zip(&right).map(|(l, r)| l * r).sum::<f32>()
+            / (left.iter().map(|x| x * x).sum::<f32>() * right.iter().map(|x| x * x).sum::<f32>())
+                .sqrt();
+        assert!(correlation.abs() < 0.2, "correlation {}", correlation);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::{VirtualSurroundFilter, BLOCK_SIZE};
+use rodio::{Sample, Source};
+use std::time::Duration;
+
+/// A rodio [`Source`] playing another one through a [`VirtualSurroundFilter`], so
+/// `sink.append(VirtualSurroundSource::new(decoder, filter)?)` virtualizes whatever rodio
+/// decodes. The inner source is taken in the filter's
+/// [`input_layout`](VirtualSurroundFilter::input_layout), or as mono or stereo like
+/// [`transform_mono`](VirtualSurroundFilter::transform_mono) and
+/// [`transform_stereo`](VirtualSurroundFilter::transform_stereo). Its output is as long as the
+/// inner source plus the tail of the filter, or ends when rendering fails.
+pub struct VirtualSurroundSource<S> {
+    source: S,
+    filter: VirtualSurroundFilter,
+    channels: usize,
+    input: Vec<f32>,
+    output: Vec<f32>,
+    /// next sample of `output`, interleaved
+    position: usize,
+    /// samples of `output` rendered
+    available: usize,
+    /// the inner source ended, what's left is the tail
+    ended: bool,
+}
+
+impl<S: Source> VirtualSurroundSource<S>
+where
+    S::Item: Sample,
+{
+    /// `source` has to play at the sample rate of `filter`, rodio's `UniformSourceIterator`
+    /// converts it
+    pub fn new(source: S, filter: VirtualSurroundFilter) -> anyhow::Result<Self> {
+        if source.sample_rate() as usize != filter.sample_rate() {
+            anyhow::bail!(
+                "Source plays at {}hz, the filter runs at {}hz",
+                source.sample_rate(),
+                filter.sample_rate()
+            );
+        }
+
+        let channels = source.channels() as usize;
+        if channels != filter.input_channels() && channels > 2 {
+            anyhow::bail!(
+                "Source has {} channels, the filter takes {}, mono or stereo",
+                channels,
+                filter.input_channels()
+            );
+        }
+
+        Ok(VirtualSurroundSource {
+            source,
+            filter,
+            channels,
+            input: vec![0f32; BLOCK_SIZE * channels],
+            output: vec![0f32; BLOCK_SIZE * 2],
+            position: 0,
+            available: 0,
+            ended: false,
+        })
+    }
+
+    pub fn filter(&self) -> &VirtualSurroundFilter {
+        &self.filter
+    }
+
+    pub fn filter_mut(&mut self) -> &mut VirtualSurroundFilter {
+        &mut self.filter
+    }
+
+    /// the next block of output, none once the tail is out
+    fn render(&mut self) -> anyhow::Result<()> {
+        self.position = 0;
+
+        if !self.ended {
+            let mut read = 0;
+            for sample in &mut self.input {
+                *sample = match self.source.next() {
+                    Some(x) => {
+                        read += 1;
+                        x.to_f32()
+                    }
+                    None => 0f32,
+                };
+            }
+
+            self.ended = read < self.input.len();
+            if read > 0 {
+                if self.channels == self.filter.input_channels() {
+                    self.filter.transform(&self.input, &mut self.output)
+                } else if self.channels == 1 {
+                    self.filter.transform_mono(&self.input, &mut self.output)
+                } else {
+                    self.filter.transform_stereo(&self.input, &mut self.output)
+                }?;
+
+                self.available = BLOCK_SIZE * 2;
+                return Ok(());
+            }
+        }
+
+        self.available = self.filter.drain(&mut self.output)? * 2;
+        Ok(())
+    }
+}
+
+impl<S: Source> Iterator for VirtualSurroundSource<S>
+where
+    S::Item: Sample,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position == self.available {
+            if self.render().is_err() {
+                self.available = 0;
+            }
+
+            if self.available == 0 {
+                return None;
+            }
+        }
+
+        self.position += 1;
+        Some(self.output[self.position - 1])
+    }
+}
+
+impl<S: Source> Source for VirtualSurroundSource<S>
+where
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.filter.sample_rate() as u32
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VirtualSurroundSource;
+    use crate::hrir::{synthesize, KEMAR_HEAD_RADIUS_CM};
+    use crate::{FilterBuilder, Speaker, BLOCK_SIZE};
+    use rodio::buffer::SamplesBuffer;
+    use rodio::Source;
+
+    #[test]
+    fn plays_the_source_and_its_tail() {
+        let layout = [
+            Speaker::FrontLeft,
+            Speaker::FrontRight,
+            Speaker::BackLeft,
+            Speaker::BackRight,
+        ];
+        let filter = || {
+            FilterBuilder::new()
+                .build_from_hrir(synthesize(&layout, KEMAR_HEAD_RADIUS_CM, 48000).unwrap())
+                .unwrap()
+        };
+
+        // a click on the back left, half way into the second block
+        let mut samples = vec![0f32; (BLOCK_SIZE * 3 / 2 + 100) * 4];
+        samples[BLOCK_SIZE * 3 / 2 * 4 + 2] = 1.0;
+        let source =
+            VirtualSurroundSource::new(SamplesBuffer::new(4, 48000, samples), filter()).unwrap();
+        assert_eq!((source.channels(), source.sample_rate()), (2, 48000));
+        let output = source.collect::<Vec<_>>();
+
+        // where it is in the source, louder on the left
+        assert!(output.len() >= (BLOCK_SIZE * 3 / 2 + 100) * 2);
+        assert_eq!(output.len() % (BLOCK_SIZE * 2), 0);
+        let onset = output.iter().position(|x| x.abs() > 1e-3).unwrap() / 2;
+        assert!((BLOCK_SIZE * 3 / 2..BLOCK_SIZE * 3 / 2 + 64).contains(&onset));
+        let energy = |ear: usize| {
+            output
+                .iter()
+                .skip(ear)
+                .step_by(2)
+                .map(|x| x * x)
+                .sum::<f32>()
+        };
+        assert!(energy(0) > energy(1) * 2.0);
+
+        // stereo goes on the front pair, other channel counts don't fit
+        let stereo = SamplesBuffer::new(2, 48000, vec![0.5f32; BLOCK_SIZE * 4]);
+        assert!(
+            VirtualSurroundSource::new(stereo, filter())
+                .unwrap()
+                .count()
+                > 0
+        );
+        let surround = SamplesBuffer::new(6, 48000, vec![0f32; 6]);
+        assert!(VirtualSurroundSource::new(surround, filter()).is_err());
+        let resampled = SamplesBuffer::new(4, 44100, vec![0f32; 4]);
+        assert!(VirtualSurroundSource::new(resampled, filter()).is_err());
+    }
+}
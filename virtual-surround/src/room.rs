@@ -0,0 +1,199 @@
+use crate::distance::SPEED_OF_SOUND;
+use crate::hrir::Hrir;
+use crate::hrtf::SpeakerDirection;
+
+/// A shoebox room around the listener, [`Hrir::add_early_reflections`] mirrors the speakers in
+/// its walls
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct RoomModel {
+    /// meters from front to back, the listener is in the middle of the room
+    pub length: f32,
+    /// meters from side to side
+    pub width: f32,
+    /// meters from floor to ceiling, the ears are halfway
+    pub height: f32,
+    /// share of the energy the walls absorb at every reflection, from 0 to 1
+    pub absorption: f32,
+    /// meters from the listener to the speakers
+    pub speaker_distance: f32,
+    /// reflections a path bounces off at most, 1 is a reflection per wall
+    pub order: usize,
+}
+
+impl Default for RoomModel {
+    /// a living room
+    fn default() -> Self {
+        RoomModel {
+            length: 5.0,
+            width: 4.0,
+            height: 2.6,
+            absorption: 0.4,
+            speaker_distance: 2.0,
+            order: 2,
+        }
+    }
+}
+
+impl RoomModel {
+    /// `(extra delay in seconds, gain, unit vector of arrival)` of the reflections of a speaker
+    /// in `direction`, relative to its direct sound
+    fn reflections(&self, direction: [f32; 3]) -> Vec<(f32, f32, [f32; 3])> {
+        let size = [self.length, self.width, self.height];
+        let distance = self.speaker_distance.max(0.1);
+        let source = direction.map(|x| x * distance);
+        let reflection = (1.0 - self.absorption.clamp(0.0, 1.0)).sqrt();
+        let order = self.order as i32;
+        let mut reflections = vec![];
+
+        for x in -order..=order {
+            let left = order - x.abs();
+            for y in -left..=left {
+                let left = left - y.abs();
+                for z in -left..=left {
+                    let bounces = x.abs() + y.abs() + z.abs();
+                    if bounces == 0 {
+                        continue;
+                    }
+
+                    // mirrored `n` times along an axis, every odd time flips the side
+                    let image = [x, y, z].map(|n| n as f32);
+                    let image = [0, 1, 2].map(|axis| {
+                        let sign = if [x, y, z][axis] % 2 == 0 { 1.0 } else { -1.0 };
+                        image[axis] * size[axis] + sign * source[axis]
+                    });
+
+                    let length = image.iter().map(|x| x * x).sum::<f32>().sqrt();
+                    reflections.push((
+                        (length - distance).max(0.0) / SPEED_OF_SOUND,
+                        distance / length * reflection.powi(bounces),
+                        image.map(|x| x / length),
+                    ));
+                }
+            }
+        }
+
+        reflections
+    }
+}
+
+impl Hrir {
+    /// Add the early reflections of `room` to every speaker, for anechoic HRIRs which sound
+    /// in-head without any room cues. Each reflection is the impulse response of the speaker
+    /// nearest to the direction it arrives from, delayed and attenuated by its longer path and
+    /// the walls. LFE channels get none.
+    pub fn add_early_reflections(&mut self, room: &RoomModel) {
+        let directions = self
+            .speakers
+            .iter()
+            .map(|x| {
+                if x.position.is_lfe() {
+                    None
+                } else {
+                    SpeakerDirection::standard(x.position).map(|x| x.cartesian())
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let nearest = |arrival: [f32; 3]| {
+            directions
+                .iter()
+                .enumerate()
+                .filter_map(|(i, x)| x.map(|x| (i, (0..3).map(|k| x[k] * arrival[k]).sum())))
+                .max_by(|a: &(usize, f32), b| a.1.total_cmp(&b.1))
+                .map(|x| x.0)
+        };
+
+        let mut added = vec![];
+        for direction in &directions {
+            let mut ears = [vec![], vec![]];
+
+            for (delay, gain, arrival) in direction.map(|x| room.reflections(x)).unwrap_or_default()
+            {
+                let speaker = match nearest(arrival) {
+                    Some(speaker) => &self.speakers[speaker],
+                    None => continue,
+                };
+                let delay = (delay * self.sample_rate as f32).round() as usize;
+
+                for (ear, ir) in ears.iter_mut().zip([&speaker.left, &speaker.right]) {
+                    if ear.len() < delay + ir.len() {
+                        ear.resize(delay + ir.len(), 0f32);
+                    }
+
+                    for (sample, ir) in ear[delay..].iter_mut().zip(ir) {
+                        *sample += ir * gain;
+                    }
+                }
+            }
+
+            added.push(ears);
+        }
+
+        let length = added
+            .iter()
+            .flatten()
+            .map(Vec::len)
+            .fold(self.ir_length(), usize::max);
+
+        for (speaker, [left, right]) in self.speakers.iter_mut().zip(added) {
+            for (ir, added) in [(&mut speaker.left, left), (&mut speaker.right, right)] {
+                ir.resize(length, 0f32);
+                for (sample, added) in ir.iter_mut().zip(added) {
+                    *sample += added;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RoomModel;
+    use crate::hrir::{Hrir, SpeakerIr};
+    use crate::Speaker;
+
+    #[test]
+    fn mirrors_the_speakers_in_the_walls() {
+        let mut impulse = vec![0f32; 64];
+        impulse[0] = 1.0;
+        let speaker = |position| SpeakerIr {
+            position,
+            left: impulse.clone(),
+            right: impulse.clone(),
+        };
+
+        let mut hrir = Hrir {
+            sample_rate: 48000,
+            speakers: vec![
+                speaker(Speaker::FrontCenter),
+                speaker(Speaker::LowFrequency),
+            ],
+        };
+        hrir.add_early_reflections(&RoomModel {
+            length: 10.0,
+            width: 10.0,
+            height: 10.0,
+            absorption: 0.0,
+            speaker_distance: 2.0,
+            order: 1,
+        });
+
+        // the front wall is 3 meters behind the speaker, so 6 meters more to travel, the back
+        // wall 10 meters
+        let left = &hrir.speakers[0].left;
+        let at = |meters: f32| (meters / 343.0 * 48000.0).round() as usize;
+        assert_eq!(left[0], 1.0);
+        assert!((left[at(6.0)] - 2.0 / 8.0).abs() < 1e-6);
+        assert!((left[at(10.0)] - 2.0 / 12.0).abs() < 1e-6);
+        assert_eq!(left.iter().filter(|x| **x != 0.0).count(), 4);
+        assert_eq!(hrir.ir_length(), at(10.0) + 64);
+
+        assert_eq!(hrir.speakers[1].left[..64], impulse[..]);
+        assert!(hrir.speakers[1].left[64..].iter().all(|x| *x == 0.0));
+    }
+}
@@ -0,0 +1,207 @@
+use crate::hrtf::SpeakerDirection;
+use crate::{Smoothed, Speaker, BLOCK_SIZE};
+use std::f32::consts::FRAC_PI_2;
+
+/// `degrees` wrapped to 0..360
+fn wrap(degrees: f32) -> f32 {
+    degrees.rem_euclid(360.0)
+}
+
+/// Turns the speakers of a filter against the head for head tracking. The feed of every speaker
+/// is panned with constant power between the two speakers at its height it ends up between, so
+/// only the yaw is followed. The LFE and speakers alone at their height stay where they are.
+#[derive(Debug, Clone)]
+pub(crate) struct SpeakerRotation {
+    /// speakers at the same elevation and their azimuths in 0..360, by azimuth
+    rings: Vec<Vec<(usize, f32)>>,
+    yaw: f32,
+    /// row per speaker fed, column per speaker of the input
+    gains: Vec<f32>,
+    previous: Vec<f32>,
+    fade: Smoothed,
+    /// a block of every speaker before it's rotated
+    space: Vec<Vec<f32>>,
+}
+
+impl SpeakerRotation {
+    pub fn new<I: Iterator<Item = Speaker>>(positions: I) -> Self {
+        let positions = positions.collect::<Vec<_>>();
+        let channels = positions.len();
+
+        let mut rings: Vec<(f32, Vec<(usize, f32)>)> = vec![];
+        for (channel, position) in positions.iter().enumerate() {
+            let direction = match SpeakerDirection::standard(*position) {
+                Some(direction) if !position.is_lfe() => direction,
+                _ => continue,
+            };
+
+            let speaker = (channel, wrap(direction.azimuth));
+            match rings.iter_mut().find(|x| x.0 == direction.elevation) {
+                Some((_, ring)) => ring.push(speaker),
+                None => rings.push((direction.elevation, vec![speaker])),
+            }
+        }
+
+        let mut rings = rings
+            .into_iter()
+            .map(|x| x.1)
+            .filter(|x| x.len() > 1)
+            .collect::<Vec<_>>();
+        for ring in &mut rings {
+            ring.sort_by(|a, b| a.1.total_cmp(&b.1));
+        }
+
+        let mut rotation = SpeakerRotation {
+            rings,
+            yaw: 0.0,
+            gains: vec![0f32; channels * channels],
+            previous: vec![0f32; channels * channels],
+            fade: Smoothed::new(1.0, 0),
+            space: vec![vec![0f32; BLOCK_SIZE]; channels],
+        };
+        rotation.update_gains();
+        rotation.previous.copy_from_slice(&rotation.gains);
+
+        rotation
+    }
+
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
+
+    /// Turn against a head at `yaw` degrees, crossfading from the gains the speakers are at now
+    /// over `ramp` samples. Doesn't allocate.
+    pub fn set_yaw(&mut self, yaw: f32, ramp: usize) {
+        if yaw == self.yaw {
+            return;
+        }
+
+        let fade = self.fade.value();
+        for (previous, gain) in self.previous.iter_mut().zip(&self.gains) {
+            *previous += (gain - *previous) * fade;
+        }
+
+        self.yaw = yaw;
+        self.update_gains();
+        self.fade.set_ramp(ramp);
+        self.fade.set_immediately(0.0);
+        self.fade.set(1.0);
+    }
+
+    /// finish a crossfade under way, e.g. once the state of the filter is cleared
+    pub fn settle(&mut self) {
+        self.fade.set_immediately(1.0);
+    }
+
+    fn update_gains(&mut self) {
+        let channels = self.space.len();
+        self.gains.fill(0f32);
+        for channel in 0..channels {
+            self.gains[channel * channels + channel] = 1.0;
+        }
+
+        for ring in &self.rings {
+            for (input, azimuth) in ring {
+                // a speaker moves against the head, and is panned between the two around it
+                let target = wrap(azimuth - self.yaw);
+                let low = ring
+                    .iter()
+                    .rposition(|(_, x)| *x <= target)
+                    .unwrap_or(ring.len() - 1);
+                let high = (low + 1) % ring.len();
+                let gap = wrap(ring[high].1 - ring[low].1);
+                let position = if gap > 0.0 {
+                    wrap(target - ring[low].1) / gap
+                } else {
+                    0.0
+                };
+
+                self.gains[input * channels + input] = 0.0;
+                self.gains[ring[low].0 * channels + input] += (position * FRAC_PI_2).cos();
+                self.gains[ring[high].0 * channels + input] += (position * FRAC_PI_2).sin();
+            }
+        }
+    }
+
+    /// rotate `frames` samples of every speaker in `speakers` from `start` in place
+    pub fn process(&mut self, speakers: &mut [Vec<f32>], start: usize, frames: usize) {
+        if self.yaw == 0.0 && !self.fade.is_ramping() {
+            return;
+        }
+
+        let channels = self.space.len();
+        let end = start + frames;
+        for block in (start..end).step_by(BLOCK_SIZE) {
+            let length = (end - block).min(BLOCK_SIZE);
+            for (space, speaker) in self.space.iter_mut().zip(speakers.iter()) {
+                space[..length].copy_from_slice(&speaker[block..block + length]);
+            }
+
+            for s in 0..length {
+                let fade = self.fade.next_value();
+                for (o, speaker) in speakers[..channels].iter_mut().enumerate() {
+                    let row = o * channels..(o + 1) * channels;
+                    speaker[block + s] = self.gains[row.clone()]
+                        .iter()
+                        .zip(&self.previous[row])
+                        .zip(&self.space)
+                        .map(|((gain, from), input)| (from + (gain - from) * fade) * input[s])
+                        .sum();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpeakerRotation;
+    use crate::Speaker::*;
+    use crate::BLOCK_SIZE;
+
+    /// where a block of 1.0 on `input` ends up
+    fn rotated(rotation: &mut SpeakerRotation, input: usize, channels: usize) -> Vec<f32> {
+        let mut speakers = vec![vec![0f32; BLOCK_SIZE]; channels];
+        speakers[input].fill(1.0);
+        rotation.process(&mut speakers, 0, BLOCK_SIZE);
+
+        speakers.iter().map(|x| x[BLOCK_SIZE - 1]).collect()
+    }
+
+    #[test]
+    fn pans_speakers_against_the_head() {
+        let layout = [
+            FrontLeft,
+            FrontRight,
+            FrontCenter,
+            LowFrequency,
+            BackLeft,
+            BackRight,
+            SideLeft,
+            SideRight,
+        ];
+        let mut rotation = SpeakerRotation::new(layout.iter().copied());
+        assert_eq!(
+            rotated(&mut rotation, 2, 8),
+            [0., 0., 1., 0., 0., 0., 0., 0.]
+        );
+
+        // turned left by 30 degrees the center ends up where the front right is, the LFE stays
+        rotation.set_yaw(30.0, 0);
+        let center = rotated(&mut rotation, 2, 8);
+        assert!((center[1] - 1.0).abs() < 1e-6, "{:?}", center);
+        assert_eq!(rotated(&mut rotation, 3, 8)[3], 1.0);
+
+        // half way between the side and back left, with constant power
+        rotation.set_yaw(-30.0, 0);
+        let side = rotated(&mut rotation, 6, 8);
+        assert!((side[6] - side[4]).abs() < 1e-6, "{:?}", side);
+        assert!((side[6].powi(2) + side[4].powi(2) - 1.0).abs() < 1e-5);
+
+        // crossfades back over the ramp
+        rotation.set_yaw(0.0, BLOCK_SIZE * 2);
+        let fading = rotated(&mut rotation, 6, 8);
+        assert!(fading[6] > side[6] && fading[6] < 1.0, "{:?}", fading);
+        assert!((rotated(&mut rotation, 6, 8)[6] - 1.0).abs() < 1e-6);
+    }
+}
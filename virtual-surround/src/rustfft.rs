@@ -1,26 +1,88 @@
 #![cfg(feature = "rustfft")]
 
-use crate::{FFTLogic, BLOCK_SIZE, MAX_CHANNELS};
+use crate::engine::check_window;
+use crate::planner::real_plans;
+use crate::{ConvolutionEngine, FFTLogic, BLOCK_SIZE};
 use anyhow::Context;
 use realfft::num_complex::Complex;
-use realfft::{ComplexToReal, ComplexToRealEven, RealToComplex, RealToComplexEven};
-use rustfft::num_complex::Complex32;
-use rustfft::FftPlanner;
+use realfft::{ComplexToReal, RealToComplex};
+use rustfft::FftNum;
 use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
 
-pub struct RustFFTLogic {
+/// Sample types [`RustFFTLogic`] can convolve in, the filter hands it f32 either way
+pub trait FFTSample: FftNum {
+    fn from_sample(sample: f32) -> Self;
+    fn to_sample(self) -> f32;
+
+    /// add the products of `input` with the impulse responses of both ears, stored next to each
+    /// other per bin, to `left` and `right`
+    fn multiply_accumulate_ears(
+        left: &mut [Complex<Self>],
+        right: &mut [Complex<Self>],
+        ir: &[[Complex<Self>; 2]],
+        input: &[Complex<Self>],
+    ) {
+        for (((left, right), ir), input) in left.iter_mut().zip(right.iter_mut()).zip(ir).zip(input)
+        {
+            *left = *left + ir[0] * *input;
+            *right = *right + ir[1] * *input;
+        }
+    }
+}
+
+impl FFTSample for f32 {
+    fn from_sample(sample: f32) -> Self {
+        sample
+    }
+
+    fn to_sample(self) -> f32 {
+        self
+    }
+
+    fn multiply_accumulate_ears(
+        left: &mut [Complex<f32>],
+        right: &mut [Complex<f32>],
+        ir: &[[Complex<f32>; 2]],
+        input: &[Complex<f32>],
+    ) {
+        crate::kernel::multiply_accumulate_ears(left, right, ir, input);
+    }
+}
+
+impl FFTSample for f64 {
+    fn from_sample(sample: f32) -> Self {
+        sample as f64
+    }
+
+    fn to_sample(self) -> f32 {
+        self as f32
+    }
+}
+
+/// [`RustFFTLogic`] doing the ffts and spectrum products in double precision, for offline
+/// rendering where the lower noise floor is worth twice the CPU time
+pub type RustFFT64Logic = RustFFTLogic<f64>;
+
+pub struct RustFFTLogic<T: FFTSample = f32> {
     length: usize,
-    length_if: f32,
-    input: Vec<Complex32>,
-    output: Vec<Complex32>,
-    ir: [Vec<Complex32>; MAX_CHANNELS * 2],
-    forward_plan: RealToComplexEven<f32>,
-    backward_plan: ComplexToRealEven<f32>,
-    pub forward_scratch: Vec<Complex<f32>>,
-    pub backward_scratch: Vec<Complex<f32>>,
+    length_if: T,
+    window: Vec<T>,
+    rev_space: Vec<T>,
+    input: Vec<Complex<T>>,
+    /// spectra of the output of both ears, summed over the channels of a block
+    output: [Vec<Complex<T>>; 2],
+    /// spectra per channel, the bins of both ears next to each other so a block is multiplied in
+    /// one pass. Shared with the engines [`clone_fresh`](ConvolutionEngine::clone_fresh) made
+    /// until one of them loads another impulse response
+    ir: Arc<Vec<Vec<[Complex<T>; 2]>>>,
+    forward_plan: Arc<dyn RealToComplex<T>>,
+    backward_plan: Arc<dyn ComplexToReal<T>>,
+    pub forward_scratch: Vec<Complex<T>>,
+    pub backward_scratch: Vec<Complex<T>>,
 }
 
-impl Debug for RustFFTLogic {
+impl<T: FFTSample> Debug for RustFFTLogic<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("RustFFTLogic")
             .field("input", &self.input)
@@ -30,85 +92,295 @@ impl Debug for RustFFTLogic {
     }
 }
 
-impl FFTLogic for RustFFTLogic {
-    fn new(channels: usize, length: usize) -> Self {
-        let input = vec![Complex32::default(); (length / 2) + 1];
-        let output = vec![Complex32::default(); (length / 2) + 1];
-
-        const EMPTY_VEC: Vec<Complex32> = Vec::new();
-        let mut ir: [Vec<Complex32>; MAX_CHANNELS * 2] = [EMPTY_VEC; MAX_CHANNELS * 2];
-
-        for i in 0..(channels * 2) {
-            ir[i] = vec![Complex32::new(0f32, 0f32); (length / 2) + 1];
-        }
+impl<T: FFTSample> FFTLogic for RustFFTLogic<T> {
+    fn new(channels: usize, length: usize) -> anyhow::Result<Self> {
+        check_window(length)?;
+        trace_span!(DEBUG, "plan_fft", channels, length);
+        let zero = Complex::new(T::zero(), T::zero());
+        let bins = (length / 2) + 1;
 
-        let mut planner = FftPlanner::<f32>::new();
+        let ir = Arc::new(vec![vec![[zero; 2]; bins]; channels]);
 
-        let forward_plan = RealToComplexEven::new(length, &mut planner);
-        let backward_plan = ComplexToRealEven::new(length, &mut planner);
+        let (forward_plan, backward_plan) = real_plans::<T>(length);
 
         let backward_scratch = backward_plan.make_scratch_vec();
         let forward_scratch = forward_plan.make_scratch_vec();
 
-        RustFFTLogic {
+        Ok(RustFFTLogic {
             length,
-            length_if: 1.0 / length as f32,
-            input,
-            output,
+            length_if: T::one() / T::from_usize(length).unwrap(),
+            window: vec![T::zero(); length],
+            rev_space: vec![T::zero(); length],
+            input: vec![zero; bins],
+            output: [vec![zero; bins], vec![zero; bins]],
             ir,
             forward_plan,
             forward_scratch,
             backward_plan,
             backward_scratch,
+        })
+    }
+}
+
+impl<T: FFTSample> RustFFTLogic<T> {
+    fn fill_window(&mut self, samples: &[f32]) {
+        for (window, sample) in self.window.iter_mut().zip(samples) {
+            *window = T::from_sample(*sample);
         }
     }
 
-    fn init_ir(&mut self, impulse: &mut [f32], ir_index: usize) -> anyhow::Result<()> {
+    /// spectrum of `impulse` with space of its own, so impulse responses can be transformed
+    /// side by side
+    #[cfg(feature = "parallel")]
+    fn ir_spectrum(&self, impulse: &[f32]) -> anyhow::Result<Vec<Complex<T>>> {
+        let mut window = vec![T::zero(); self.length];
+        for (window, sample) in window.iter_mut().zip(impulse) {
+            *window = T::from_sample(*sample);
+        }
+
+        let mut spectrum = self.forward_plan.make_output_vec();
         self.forward_plan
-            .process_with_scratch(impulse, &mut self.ir[ir_index], &mut self.forward_scratch)
+            .process_with_scratch(
+                &mut window,
+                &mut spectrum,
+                &mut self.forward_plan.make_scratch_vec(),
+            )
             .map_err(|err| anyhow::Error::msg(err.to_string()))
             .context("Failed to process IR")?;
-        Ok(())
+
+        Ok(spectrum)
     }
 
-    fn process_channel(
-        &mut self,
-        channel: usize,
-        samples: &mut [f32],
-        rev_space: &mut [f32],
-        left_output: &mut [f32],
-        right_output: &mut [f32],
-    ) -> anyhow::Result<()> {
+    /// keep `spectrum` as the one of `ir_index`
+    fn store_ir(&mut self, ir_index: usize, spectrum: &[Complex<T>]) {
+        let ir = &mut Arc::make_mut(&mut self.ir)[ir_index / 2];
+        for (bins, value) in ir.iter_mut().zip(spectrum) {
+            bins[ir_index % 2] = *value;
+        }
+    }
+
+    /// transform the window `samples` of `channel` and add its product with both impulse
+    /// responses to the spectra of the output
+    fn accumulate(&mut self, channel: usize, samples: &[f32]) -> anyhow::Result<()> {
+        self.fill_window(samples);
         self.forward_plan
-            .process_with_scratch(samples, &mut self.input, &mut self.forward_scratch)
+            .process_with_scratch(&mut self.window, &mut self.input, &mut self.forward_scratch)
             .map_err(|err| anyhow::Error::msg(err.to_string()))
             .context("Failed to process channel")?;
 
-        for ear in 0..2 {
-            let ir = &mut self.ir[channel * 2 + ear];
-            let out_space = if ear == 0 {
-                &mut *left_output
-            } else {
-                &mut *right_output
-            };
+        let [left, right] = &mut self.output;
+        T::multiply_accumulate_ears(left, right, &self.ir[channel], &self.input);
 
-            for s in 0..(self.length / 2) + 1 {
-                let re = ir[s].re * self.input[s].re - ir[s].im * self.input[s].im;
-                let im = ir[s].im * self.input[s].re + ir[s].re * self.input[s].im;
-
-                self.output[s] = Complex32::new(re, im);
-            }
+        Ok(())
+    }
 
+    /// inverse transform the spectra of the output, adding the newest block to the outputs
+    fn finish(&mut self, left_output: &mut [f32], right_output: &mut [f32]) -> anyhow::Result<()> {
+        for (ear, out_space) in [left_output, right_output].iter_mut().enumerate() {
             self.backward_plan
-                .process_with_scratch(&mut self.output, rev_space, &mut self.backward_scratch)
+                .process_with_scratch(
+                    &mut self.output[ear],
+                    &mut self.rev_space,
+                    &mut self.backward_scratch,
+                )
                 .map_err(|err| anyhow::Error::msg(err.to_string()))
                 .context("Failed to process channel")?;
 
             for s in 0..BLOCK_SIZE {
-                out_space[s] += rev_space[(self.length - BLOCK_SIZE) + s] * self.length_if;
+                out_space[s] +=
+                    (self.rev_space[(self.length - BLOCK_SIZE) + s] * self.length_if).to_sample();
             }
         }
 
         Ok(())
     }
+
+    fn clear_output(&mut self) {
+        for output in &mut self.output {
+            output.fill(Complex::new(T::zero(), T::zero()));
+        }
+    }
+}
+
+impl<T: FFTSample> ConvolutionEngine for RustFFTLogic<T> {
+    fn init_ir(&mut self, impulse: &[f32], ir_index: usize) -> anyhow::Result<()> {
+        // realfft uses the input as scratch space
+        self.fill_window(impulse);
+        self.forward_plan
+            .process_with_scratch(&mut self.window, &mut self.input, &mut self.forward_scratch)
+            .map_err(|err| anyhow::Error::msg(err.to_string()))
+            .context("Failed to process IR")?;
+
+        let spectrum = std::mem::take(&mut self.input);
+        self.store_ir(ir_index, &spectrum);
+        self.input = spectrum;
+        Ok(())
+    }
+
+    #[cfg(feature = "parallel")]
+    fn init_irs(&mut self, impulses: &[(usize, Vec<f32>)]) -> anyhow::Result<()> {
+        use rayon::prelude::*;
+
+        let spectra = impulses
+            .par_iter()
+            .map(|(_, impulse)| self.ir_spectrum(impulse))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        for ((ir_index, _), spectrum) in impulses.iter().zip(spectra) {
+            self.store_ir(*ir_index, &spectrum);
+        }
+
+        Ok(())
+    }
+
+    /// the spectrum, interleaved real and imaginary parts
+    fn export_ir(&self, ir_index: usize) -> Option<Vec<f32>> {
+        Some(
+            self.ir[ir_index / 2]
+                .iter()
+                .map(|x| x[ir_index % 2])
+                .flat_map(|x| [x.re.to_sample(), x.im.to_sample()])
+                .collect(),
+        )
+    }
+
+    fn import_ir(&mut self, prepared: &[f32], ir_index: usize) -> anyhow::Result<()> {
+        let ir = &mut Arc::make_mut(&mut self.ir)[ir_index / 2];
+        if prepared.len() != ir.len() * 2 {
+            anyhow::bail!(
+                "Prepared spectrum has {} bins, the engine uses {}",
+                prepared.len() / 2,
+                ir.len()
+            );
+        }
+
+        for (bins, value) in ir.iter_mut().zip(prepared.chunks_exact(2)) {
+            bins[ir_index % 2] = Complex::new(T::from_sample(value[0]), T::from_sample(value[1]));
+        }
+
+        Ok(())
+    }
+
+    fn clone_fresh(&self) -> Option<Box<dyn ConvolutionEngine>> {
+        // an engine which fails to set up again leaves it to the factory to report why
+        let mut engine = Self::new(self.ir.len(), self.length).ok()?;
+        engine.ir = self.ir.clone();
+
+        Some(Box::new(engine))
+    }
+
+    fn process(
+        &mut self,
+        channel: usize,
+        samples: &[f32],
+        left_output: &mut [f32],
+        right_output: &mut [f32],
+    ) -> anyhow::Result<()> {
+        self.clear_output();
+        self.accumulate(channel, samples)?;
+        self.finish(left_output, right_output)
+    }
+
+    /// the channels are summed in the spectrum, so a block takes two inverse ffts in total
+    fn process_channels(
+        &mut self,
+        channels: &[usize],
+        input: &[&[f32]],
+        left_output: &mut [f32],
+        right_output: &mut [f32],
+    ) -> anyhow::Result<()> {
+        self.clear_output();
+        for channel in channels {
+            self.accumulate(*channel, input[*channel])?;
+        }
+
+        self.finish(left_output, right_output)
+    }
+
+    fn latency(&self) -> usize {
+        self.length - BLOCK_SIZE
+    }
+
+    fn reset(&mut self) {
+        self.window.fill(T::zero());
+        self.rev_space.fill(T::zero());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RustFFT64Logic, RustFFTLogic};
+    use crate::{ConvolutionEngine, FFTLogic, BLOCK_SIZE};
+    use std::sync::Arc;
+
+    /// worst difference to a direct convolution in f64 of the newest block
+    fn error<T: ConvolutionEngine>(mut engine: T, length: usize) -> f64 {
+        let mut state = 0x1234_5678u32;
+        let mut noise = || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+        };
+
+        let mut impulse = vec![0f32; length];
+        for sample in &mut impulse[..length - BLOCK_SIZE] {
+            *sample = noise();
+        }
+        let window = (0..length).map(|_| noise()).collect::<Vec<_>>();
+
+        engine.init_ir(&impulse, 0).unwrap();
+        engine.init_ir(&impulse, 1).unwrap();
+        let mut left = vec![0f32; BLOCK_SIZE];
+        let mut right = vec![0f32; BLOCK_SIZE];
+        engine.process(0, &window, &mut left, &mut right).unwrap();
+
+        (0..BLOCK_SIZE)
+            .map(|s| {
+                let n = length - BLOCK_SIZE + s;
+                let expected = (0..=n)
+                    .map(|k| impulse[k] as f64 * window[n - k] as f64)
+                    .sum::<f64>();
+                (left[s] as f64 - expected).abs()
+            })
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn fresh_clones_share_the_spectra() {
+        let length = 4096;
+        let mut impulse = vec![0f32; length];
+        impulse[10] = 1.0;
+        let mut engine = RustFFTLogic::<f32>::new(1, length).unwrap();
+        engine.init_ir(&impulse, 0).unwrap();
+
+        let clone = engine.clone_fresh().unwrap();
+        assert_eq!(clone.export_ir(0), engine.export_ir(0));
+        let shared = engine.ir.clone();
+        assert_eq!(Arc::strong_count(&shared), 3);
+
+        // loading another impulse response copies them first
+        engine.init_ir(&impulse, 1).unwrap();
+        assert!(!Arc::ptr_eq(&engine.ir, &shared));
+        assert_eq!(Arc::strong_count(&shared), 2);
+    }
+
+    #[test]
+    fn double_precision_is_closer_to_direct_convolution() {
+        let length = 8192;
+        let single = error(RustFFTLogic::<f32>::new(1, length).unwrap(), length);
+        let double = error(RustFFT64Logic::new(1, length).unwrap(), length);
+
+        // what's left in double precision is rounding the output to f32
+        assert!(double < single / 4.0);
+        assert!(double < 1e-5);
+    }
+
+    #[test]
+    fn rejects_windows_without_room_for_a_block() {
+        use crate::{new_engine, PartitionedLogic, UniformLogic};
+
+        assert!(RustFFTLogic::<f32>::new(2, BLOCK_SIZE).is_err());
+        assert!(UniformLogic::new(2, BLOCK_SIZE).is_err());
+        assert!(PartitionedLogic::new(2, 100).is_err());
+        assert!(new_engine::<RustFFTLogic>(2, BLOCK_SIZE * 2).is_ok());
+    }
 }
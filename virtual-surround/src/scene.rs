@@ -0,0 +1,562 @@
+use crate::ambisonic::fibonacci_grid;
+use crate::distance::{DelayLine, SPEED_OF_SOUND};
+use crate::hrtf::{HrtfSource, SpeakerDirection};
+use crate::{
+    fft_len_for, no_alloc, ConvolutionEngine, EngineFactory, Smoothed, Speaker, BLOCK_SIZE,
+    REFERENCE_DISTANCE,
+};
+use std::fmt::{Debug, Formatter};
+
+/// closer than this a source stays as loud as here, instead of growing without bound
+const MIN_DISTANCE: f32 = 0.1;
+
+/// directions probed for the longest impulse response of an [`HrtfSource`]
+const PROBES: usize = 64;
+
+/// Where a source of a [`BinauralScene`] is, the direction in degrees like [`SpeakerDirection`]
+/// and the distance in meters from the center of the head
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SourcePosition {
+    pub azimuth: f32,
+    pub elevation: f32,
+    pub distance: f32,
+}
+
+impl SourcePosition {
+    pub fn new(azimuth: f32, elevation: f32, distance: f32) -> Self {
+        SourcePosition {
+            azimuth,
+            elevation,
+            distance,
+        }
+    }
+
+    fn gain(&self) -> f32 {
+        REFERENCE_DISTANCE / self.distance.max(MIN_DISTANCE)
+    }
+
+    /// samples the sound takes from the source to the center of the head
+    fn travel(&self, sample_rate: usize) -> f32 {
+        self.distance / SPEED_OF_SOUND * sample_rate as f32
+    }
+}
+
+/// A source of a [`BinauralScene`], from [`add_source`](BinauralScene::add_source). It stops
+/// working once the source is removed, also when another source takes its place.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SourceHandle {
+    slot: usize,
+    generation: u32,
+}
+
+#[derive(Debug)]
+struct Source {
+    position: SourcePosition,
+    /// convolves with the impulse responses of the position on the `active` channel and the
+    /// previous ones on the other
+    engine: Box<dyn ConvolutionEngine>,
+    active: usize,
+    /// the direction changed since the last block, which crossfades to the other channel
+    moved: bool,
+    window: Vec<f32>,
+    gain: Smoothed,
+    /// the travel time of the distance with doppler
+    delay: DelayLine,
+}
+
+/// Renders any number of mono sources at directions and distances which can change every block,
+/// for games and other dynamic 3D audio rather than fixed speaker layouts. Every source is
+/// convolved with the impulse responses the [`HrtfSource`] interpolates for its direction, and
+/// crossfades to new ones over a block when it moves. The level follows the distance, it's
+/// unchanged at [`REFERENCE_DISTANCE`], and with [`set_doppler`](BinauralScene::set_doppler)
+/// so does the time the sound takes to arrive.
+pub struct BinauralScene {
+    hrtf: Box<dyn HrtfSource + Send>,
+    engine: EngineFactory,
+    fft_len: usize,
+    ir_length: usize,
+    /// sources by the slot of their handle, and the generation the slot is at
+    slots: Vec<(u32, Option<Source>)>,
+    doppler: bool,
+    /// output of the current and previous impulse responses of a source, left then right
+    space: [Vec<f32>; 4],
+}
+
+impl Debug for BinauralScene {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BinauralScene")
+            .field("sample_rate", &self.hrtf.sample_rate())
+            .field("fft_len", &self.fft_len)
+            .field("sources", &self.len())
+            .field("doppler", &self.doppler)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BinauralScene {
+    /// scene rendering at the sample rate of `hrtf`, convolving every source with an `engine`
+    pub fn new<S: HrtfSource + Send + 'static>(
+        hrtf: S,
+        engine: EngineFactory,
+    ) -> anyhow::Result<Self> {
+        // long enough for every direction, a few samples more of delay elsewhere are cut off
+        let mut ir_length = 0;
+        for [x, y, z] in fibonacci_grid(PROBES) {
+            let direction = SpeakerDirection::new(
+                Speaker::DirectOut,
+                y.atan2(x).to_degrees() as f32,
+                z.asin().to_degrees() as f32,
+            );
+            let (left, right) = hrtf.impulse(&direction)?;
+            ir_length = ir_length.max(left.len()).max(right.len());
+        }
+
+        if ir_length == 0 {
+            anyhow::bail!("HRTF has empty impulse responses");
+        }
+
+        Ok(BinauralScene {
+            hrtf: Box::new(hrtf),
+            engine,
+            fft_len: fft_len_for(ir_length),
+            ir_length,
+            slots: vec![],
+            doppler: false,
+            space: [
+                vec![0f32; BLOCK_SIZE],
+                vec![0f32; BLOCK_SIZE],
+                vec![0f32; BLOCK_SIZE],
+                vec![0f32; BLOCK_SIZE],
+            ],
+        })
+    }
+
+    pub fn sample_rate(&self) -> usize {
+        self.hrtf.sample_rate() as usize
+    }
+
+    pub fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    /// sources in the scene
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|x| x.1.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Delay every source by the time its sound travels, which shifts the pitch of sources
+    /// moving towards or away from the listener like the Doppler effect. The delays are set
+    /// right away, so better before any audio is rendered. Off by default, since it delays the
+    /// output of far away sources by up to a few blocks.
+    pub fn set_doppler(&mut self, enabled: bool) {
+        self.doppler = enabled;
+        let rate = self.sample_rate();
+        for (_, source) in &mut self.slots {
+            if let Some(source) = source {
+                source.delay.reset();
+                source.delay.set_delay(
+                    if enabled {
+                        source.position.travel(rate)
+                    } else {
+                        0.0
+                    },
+                    0,
+                );
+            }
+        }
+    }
+
+    pub fn doppler(&self) -> bool {
+        self.doppler
+    }
+
+    /// impulse responses for the direction of `position`, zero padded to the window like the
+    /// filter hands them to engines
+    fn impulses(&self, position: &SourcePosition) -> anyhow::Result<[Vec<f32>; 2]> {
+        if !(position.distance > 0.0 && position.distance.is_finite()) {
+            anyhow::bail!(
+                "Sources need a distance above 0m, got {}m",
+                position.distance
+            );
+        }
+
+        let direction =
+            SpeakerDirection::new(Speaker::DirectOut, position.azimuth, position.elevation);
+        let (mut left, mut right) = self.hrtf.impulse(&direction)?;
+        left.resize(self.ir_length, 0f32);
+        right.resize(self.ir_length, 0f32);
+        left.resize(self.fft_len, 0f32);
+        right.resize(self.fft_len, 0f32);
+
+        Ok([left, right])
+    }
+
+    fn source(&mut self, handle: SourceHandle) -> anyhow::Result<&mut Source> {
+        match self.slots.get_mut(handle.slot) {
+            Some((generation, Some(source))) if *generation == handle.generation => Ok(source),
+            _ => anyhow::bail!("Source was removed from the scene"),
+        }
+    }
+
+    /// Add a source at `position`, it starts out silent until the next
+    /// [`render`](BinauralScene::render) hands it input. Builds an engine and looks up the
+    /// impulse responses, so better not on the audio thread.
+    pub fn add_source(&mut self, position: SourcePosition) -> anyhow::Result<SourceHandle> {
+        let irs = self.impulses(&position)?;
+        let mut engine = (self.engine)(2, self.fft_len)?;
+        for channel in 0..2 {
+            for (ear, ir) in irs.iter().enumerate() {
+                engine.init_ir(ir, channel * 2 + ear)?;
+            }
+        }
+
+        let mut delay = DelayLine::new(BLOCK_SIZE);
+        if self.doppler {
+            delay.set_delay(position.travel(self.sample_rate()), 0);
+        }
+
+        let source = Source {
+            position,
+            engine,
+            active: 0,
+            moved: false,
+            window: vec![0f32; self.fft_len],
+            gain: Smoothed::new(position.gain(), BLOCK_SIZE),
+            delay,
+        };
+
+        let slot = match self.slots.iter().position(|x| x.1.is_none()) {
+            Some(slot) => slot,
+            None => {
+                self.slots.push((0, None));
+                self.slots.len() - 1
+            }
+        };
+
+        self.slots[slot].1 = Some(source);
+        Ok(SourceHandle {
+            slot,
+            generation: self.slots[slot].0,
+        })
+    }
+
+    pub fn remove_source(&mut self, handle: SourceHandle) -> anyhow::Result<()> {
+        self.source(handle)?;
+        let slot = &mut self.slots[handle.slot];
+        slot.0 = slot.0.wrapping_add(1);
+        slot.1 = None;
+
+        Ok(())
+    }
+
+    pub fn position(&self, handle: SourceHandle) -> anyhow::Result<SourcePosition> {
+        match self.slots.get(handle.slot) {
+            Some((generation, Some(source))) if *generation == handle.generation => {
+                Ok(source.position)
+            }
+            _ => anyhow::bail!("Source was removed from the scene"),
+        }
+    }
+
+    /// Move a source, the next block crossfades to its new direction and ramps to the level of
+    /// its new distance, and with doppler to its delay, so the pitch follows the speed it moved
+    /// at. Moving it every block keeps fast sources from clicking. A new direction is looked up
+    /// in the [`HrtfSource`] and a further distance may grow the delay, which both may allocate.
+    pub fn set_position(
+        &mut self,
+        handle: SourceHandle,
+        azimuth: f32,
+        elevation: f32,
+        distance: f32,
+    ) -> anyhow::Result<()> {
+        let position = SourcePosition::new(azimuth, elevation, distance);
+        let previous = self.source(handle)?.position;
+        let irs = if (previous.azimuth, previous.elevation) != (azimuth, elevation) {
+            Some(self.impulses(&position)?)
+        } else {
+            None
+        };
+
+        let (doppler, rate) = (self.doppler, self.sample_rate());
+        let source = self.source(handle)?;
+        if let Some(irs) = irs {
+            // a move since the last block replaces the impulse responses it would fade to
+            let next = 1 - source.active;
+            for (ear, ir) in irs.iter().enumerate() {
+                source.engine.init_ir(ir, next * 2 + ear)?;
+            }
+            source.moved = true;
+        }
+
+        source.position = position;
+        source.gain.set(position.gain());
+        if doppler {
+            source.delay.set_delay(position.travel(rate), BLOCK_SIZE);
+        }
+
+        Ok(())
+    }
+
+    /// Render a block of [`block_size`](BinauralScene::block_size) samples of every source in
+    /// `inputs` and mix them into `left` and `right`, sources left out are silent this block.
+    /// Output follows input without latency.
+    pub fn render(
+        &mut self,
+        inputs: &[(SourceHandle, &[f32])],
+        left: &mut [f32],
+        right: &mut [f32],
+    ) -> anyhow::Result<()> {
+        for (handle, _) in inputs {
+            self.source(*handle)?;
+        }
+
+        self.render_with(
+            |handle| inputs.iter().find(|x| x.0 == handle).map(|x| x.1),
+            left,
+            right,
+        )
+    }
+
+    /// like [`render`](BinauralScene::render) with the block of every source looked up by
+    /// `input`, `None` keeps it silent. Callers which keep the blocks next to the handles don't
+    /// have to collect them every block.
+    pub fn render_with<'a, F: Fn(SourceHandle) -> Option<&'a [f32]>>(
+        &mut self,
+        input: F,
+        left: &mut [f32],
+        right: &mut [f32],
+    ) -> anyhow::Result<()> {
+        if left.len() < BLOCK_SIZE || right.len() < BLOCK_SIZE {
+            anyhow::bail!("render takes outputs of {} samples", BLOCK_SIZE);
+        }
+
+        let handle = |slot: usize, generation: u32| SourceHandle { slot, generation };
+        for (slot, (generation, source)) in self.slots.iter().enumerate() {
+            if source.is_none() {
+                continue;
+            }
+
+            if let Some(block) = input(handle(slot, *generation)) {
+                if block.len() != BLOCK_SIZE {
+                    anyhow::bail!(
+                        "render takes blocks of {} samples, got {}",
+                        BLOCK_SIZE,
+                        block.len()
+                    );
+                }
+            }
+        }
+
+        no_alloc(|| {
+            left[..BLOCK_SIZE].fill(0f32);
+            right[..BLOCK_SIZE].fill(0f32);
+            let start = self.fft_len - BLOCK_SIZE;
+            let [current_left, current_right, previous_left, previous_right] = &mut self.space;
+
+            for (slot, (generation, source)) in self.slots.iter_mut().enumerate() {
+                let source = match source {
+                    Some(source) => source,
+                    None => continue,
+                };
+
+                source.window.copy_within(BLOCK_SIZE.., 0);
+                match input(handle(slot, *generation)) {
+                    Some(block) => source.window[start..].copy_from_slice(block),
+                    None => source.window[start..].fill(0f32),
+                }
+                if self.doppler {
+                    source.delay.process(&mut source.window[start..]);
+                }
+
+                // both channels every block, engines which keep the spectra of past blocks need
+                // them whichever is heard
+                for space in [
+                    &mut *current_left,
+                    &mut *current_right,
+                    &mut *previous_left,
+                    &mut *previous_right,
+                ] {
+                    space.fill(0f32);
+                }
+
+                // the impulse responses of the position, and the ones faded from after a move
+                let heard = if source.moved {
+                    1 - source.active
+                } else {
+                    source.active
+                };
+                source
+                    .engine
+                    .process(heard, &source.window, current_left, current_right)?;
+                source
+                    .engine
+                    .process(1 - heard, &source.window, previous_left, previous_right)?;
+
+                for s in 0..BLOCK_SIZE {
+                    let gain = source.gain.next_value();
+                    let previous = if source.moved {
+                        1.0 - (s + 1) as f32 / BLOCK_SIZE as f32
+                    } else {
+                        0.0
+                    };
+                    left[s] +=
+                        (current_left[s] + (previous_left[s] - current_left[s]) * previous) * gain;
+                    right[s] += (current_right[s]
+                        + (previous_right[s] - current_right[s]) * previous)
+                        * gain;
+                }
+
+                source.active = heard;
+                source.moved = false;
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(all(test, feature = "rustfft"))]
+mod tests {
+    use super::{BinauralScene, SourcePosition};
+    use crate::distance::SPEED_OF_SOUND;
+    use crate::hrtf::{HrtfSource, SpeakerDirection, SphericalHead};
+    use crate::{ConvolutionStrategy, Speaker, BLOCK_SIZE};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn renders_and_moves_sources() {
+        let head = SphericalHead::new(8.75, 48000);
+        let blocks = 8;
+        let mut state = 0x1234_5678u32;
+        let mut noise = || {
+            (0..BLOCK_SIZE * blocks)
+                .map(|_| {
+                    state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    (state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+                })
+                .collect::<Vec<_>>()
+        };
+        let inputs = [noise(), noise()];
+
+        // sample `n` of `input` through the impulse responses of `azimuth`, times `gain`
+        let convolved = |input: &[f32], azimuth: f32, gain: f32, n: usize| {
+            let direction = SpeakerDirection::new(Speaker::DirectOut, azimuth, 0.0);
+            let (left, right) = head.impulse(&direction).unwrap();
+            let sum = |ir: &[f32]| {
+                ir.iter()
+                    .take(n + 1)
+                    .enumerate()
+                    .map(|(k, x)| x * input[n - k])
+                    .sum::<f32>()
+                    * gain
+            };
+            (sum(&left), sum(&right))
+        };
+
+        // the overlap strategies keep past blocks, the direction moved to needs them too
+        for strategy in [
+            ConvolutionStrategy::Window,
+            ConvolutionStrategy::OverlapSave,
+            ConvolutionStrategy::OverlapAdd,
+        ] {
+            let mut scene = BinauralScene::new(head, strategy.factory()).unwrap();
+            let near = scene
+                .add_source(SourcePosition::new(30.0, 0.0, 0.5))
+                .unwrap();
+            let moving = scene
+                .add_source(SourcePosition::new(-60.0, 0.0, 1.0))
+                .unwrap();
+
+            let mut left = vec![0f32; BLOCK_SIZE];
+            let mut right = vec![0f32; BLOCK_SIZE];
+            for block in 0..blocks {
+                if block == 4 {
+                    scene.set_position(moving, 90.0, 0.0, 1.0).unwrap();
+                }
+
+                let range = block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE;
+                let input = [
+                    (near, &inputs[0][range.clone()]),
+                    (moving, &inputs[1][range.clone()]),
+                ];
+                scene.render(&input, &mut left, &mut right).unwrap();
+
+                // the block after the move crossfades
+                if block == 4 {
+                    continue;
+                }
+
+                let azimuth = if block < 4 { -60.0 } else { 90.0 };
+                for (s, n) in range.enumerate() {
+                    let a = convolved(&inputs[0], 30.0, 2.0, n);
+                    let b = convolved(&inputs[1], azimuth, 1.0, n);
+                    assert!((left[s] - a.0 - b.0).abs() < 1e-4, "{:?} {}", strategy, n);
+                    assert!((right[s] - a.1 - b.1).abs() < 1e-4, "{:?} {}", strategy, n);
+                }
+            }
+
+            // a removed source's handle doesn't reach the source taking its slot
+            scene.remove_source(near).unwrap();
+            let other = scene
+                .add_source(SourcePosition::new(0.0, 0.0, 1.0))
+                .unwrap();
+            assert!(scene.position(near).is_err());
+            assert_eq!(scene.position(other).unwrap().azimuth, 0.0);
+            let input = [(near, &inputs[0][..BLOCK_SIZE])];
+            assert!(scene.render(&input, &mut left, &mut right).is_err());
+            assert_eq!(scene.len(), 2);
+        }
+    }
+
+    #[test]
+    fn doppler_shifts_approaching_sources_up() {
+        let rate = 48000;
+        let blocks = 48;
+        let block_seconds = BLOCK_SIZE as f32 / rate as f32;
+        let tone = (0..BLOCK_SIZE * blocks)
+            .map(|s| (2.0 * PI * 1000.0 * s as f32 / rate as f32).sin() * 0.1)
+            .collect::<Vec<_>>();
+
+        // rising zero crossings per second of the output while approaching at a tenth of the
+        // speed of sound, from 20m
+        let frequency = |doppler: bool| {
+            let mut scene = BinauralScene::new(
+                SphericalHead::new(8.75, rate),
+                ConvolutionStrategy::Window.factory(),
+            )
+            .unwrap();
+            scene.set_doppler(doppler);
+            assert_eq!(scene.doppler(), doppler);
+            let source = scene
+                .add_source(SourcePosition::new(0.0, 0.0, 20.0))
+                .unwrap();
+
+            let mut output = vec![];
+            let (mut left, mut right) = (vec![0f32; BLOCK_SIZE], vec![0f32; BLOCK_SIZE]);
+            for (block, input) in tone.chunks_exact(BLOCK_SIZE).enumerate() {
+                let distance = 20.0 - SPEED_OF_SOUND * 0.1 * block_seconds * (block + 1) as f32;
+                scene.set_position(source, 0.0, 0.0, distance).unwrap();
+                scene
+                    .render(&[(source, input)], &mut left, &mut right)
+                    .unwrap();
+                output.extend_from_slice(&left);
+            }
+
+            let settled = &output[BLOCK_SIZE * 16..];
+            let crossings = settled
+                .windows(2)
+                .filter(|x| x[0] < 0.0 && x[1] >= 0.0)
+                .count();
+            crossings as f32 * rate as f32 / settled.len() as f32
+        };
+
+        let still = frequency(false);
+        assert!((still - 1000.0).abs() < 5.0, "{}", still);
+        let shifted = frequency(true);
+        assert!((shifted - 1100.0).abs() < 5.0, "{}", shifted);
+    }
+}
@@ -0,0 +1,116 @@
+/// A parameter which ramps linearly to a new value over a number of samples instead of jumping,
+/// so changing it while audio runs doesn't click. Stages step it a sample at a time with
+/// [`next_value`](Smoothed::next_value), the gain, mix, rotation and speaker distances of the
+/// filters all use it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Smoothed {
+    value: f32,
+    target: f32,
+    step: f32,
+    ramp: usize,
+    remaining: usize,
+}
+
+impl Smoothed {
+    /// starting out at `value`, changes take `ramp` samples
+    pub fn new(value: f32, ramp: usize) -> Self {
+        Smoothed {
+            value,
+            target: value,
+            step: 0.0,
+            ramp,
+            remaining: 0,
+        }
+    }
+
+    /// samples the next change takes, 0 jumps right to it. A ramp under way isn't affected.
+    pub fn set_ramp(&mut self, ramp: usize) {
+        self.ramp = ramp;
+    }
+
+    pub fn ramp(&self) -> usize {
+        self.ramp
+    }
+
+    /// ramp from the current value to `target`
+    pub fn set(&mut self, target: f32) {
+        if target == self.target {
+            return;
+        }
+
+        if self.ramp == 0 {
+            self.set_immediately(target);
+            return;
+        }
+
+        self.target = target;
+        self.step = (target - self.value) / self.ramp as f32;
+        self.remaining = self.ramp;
+    }
+
+    /// jump to `value`, e.g. before any audio went through
+    pub fn set_immediately(&mut self, value: f32) {
+        self.value = value;
+        self.target = value;
+        self.remaining = 0;
+    }
+
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn is_ramping(&self) -> bool {
+        self.remaining > 0
+    }
+
+    /// step a sample ahead and return the value for it
+    pub fn next_value(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.value = if self.remaining == 0 {
+                self.target
+            } else {
+                self.value + self.step
+            };
+        }
+
+        self.value
+    }
+}
+
+/// samples in `ms` at `sample_rate`
+pub(crate) fn ramp_samples(ms: f32, sample_rate: usize) -> usize {
+    (ms.max(0.0) / 1000.0 * sample_rate as f32).round() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Smoothed;
+
+    #[test]
+    fn ramps_linearly_and_lands_on_the_target() {
+        let mut gain = Smoothed::new(1.0, 4);
+        gain.set(0.0);
+        assert_eq!(gain.value(), 1.0);
+
+        let ramp = (0..6).map(|_| gain.next_value()).collect::<Vec<_>>();
+        assert_eq!(ramp, vec![0.75, 0.5, 0.25, 0.0, 0.0, 0.0]);
+        assert!(!gain.is_ramping());
+
+        // a change mid ramp continues from where it got to
+        gain.set(1.0);
+        gain.next_value();
+        gain.set(0.25);
+        assert_eq!(gain.next_value(), 0.25);
+        assert_eq!(gain.target(), 0.25);
+
+        gain.set_ramp(0);
+        gain.set(2.0);
+        assert_eq!(gain.value(), 2.0);
+        assert!(!gain.is_ramping());
+    }
+}
@@ -0,0 +1,303 @@
+use bwavfile::ChannelMask;
+
+/// Position of a speaker, the 18 a WAVE_FORMAT_EXTENSIBLE channel mask can describe plus the
+/// extra ones of 22.2 and Atmos beds. A channel mask has no bits for the extra ones, HRIRs with
+/// them are made from an HRTF or have their layout given explicitly.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Speaker {
+    DirectOut,
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    LowFrequency,
+    BackLeft,
+    BackRight,
+    FrontCenterLeft,
+    FrontCenterRight,
+    BackCenter,
+    SideLeft,
+    SideRight,
+    TopCenter,
+    TopFrontLeft,
+    TopFrontCenter,
+    TopFrontRight,
+    TopBackLeft,
+    TopBackCenter,
+    TopBackRight,
+    /// between the front and side speakers, Atmos' wides
+    FrontWideLeft,
+    FrontWideRight,
+    /// the Atmos top middle and 22.2 top side speakers
+    TopSideLeft,
+    TopSideRight,
+    BottomFrontLeft,
+    BottomFrontCenter,
+    BottomFrontRight,
+    /// second LFE of 22.2, on the right
+    LowFrequency2,
+}
+
+impl Speaker {
+    /// the channel mask bit of the speaker, `None` for the ones a mask can't describe
+    pub fn mask(self) -> Option<ChannelMask> {
+        let mask = match self {
+            Speaker::DirectOut => ChannelMask::DirectOut,
+            Speaker::FrontLeft => ChannelMask::FrontLeft,
+            Speaker::FrontRight => ChannelMask::FrontRight,
+            Speaker::FrontCenter => ChannelMask::FrontCenter,
+            Speaker::LowFrequency => ChannelMask::LowFrequency,
+            Speaker::BackLeft => ChannelMask::BackLeft,
+            Speaker::BackRight => ChannelMask::BackRight,
+            Speaker::FrontCenterLeft => ChannelMask::FrontCenterLeft,
+            Speaker::FrontCenterRight => ChannelMask::FrontCenterRight,
+            Speaker::BackCenter => ChannelMask::BackCenter,
+            Speaker::SideLeft => ChannelMask::SideLeft,
+            Speaker::SideRight => ChannelMask::SideRight,
+            Speaker::TopCenter => ChannelMask::TopCenter,
+            Speaker::TopFrontLeft => ChannelMask::TopFrontLeft,
+            Speaker::TopFrontCenter => ChannelMask::TopFrontCenter,
+            Speaker::TopFrontRight => ChannelMask::TopFrontRight,
+            Speaker::TopBackLeft => ChannelMask::TopBackLeft,
+            Speaker::TopBackCenter => ChannelMask::TopBackCenter,
+            Speaker::TopBackRight => ChannelMask::TopBackRight,
+            _ => return None,
+        };
+
+        Some(mask)
+    }
+
+    pub fn is_lfe(self) -> bool {
+        matches!(self, Speaker::LowFrequency | Speaker::LowFrequency2)
+    }
+}
+
+/// Speakers of the usual layout with `channels` channels in WAVE_FORMAT_EXTENSIBLE order, for
+/// files and devices which don't describe their channels. `None` for counts without one.
+pub fn standard_layout(channels: usize) -> Option<&'static [Speaker]> {
+    use Speaker::*;
+
+    let layout: &'static [Speaker] = match channels {
+        2 => &[FrontLeft, FrontRight],
+        3 => &[FrontLeft, FrontRight, FrontCenter],
+        4 => &[FrontLeft, FrontRight, BackLeft, BackRight],
+        5 => &[FrontLeft, FrontRight, FrontCenter, BackLeft, BackRight],
+        6 => &[
+            FrontLeft,
+            FrontRight,
+            FrontCenter,
+            LowFrequency,
+            BackLeft,
+            BackRight,
+        ],
+        7 => &[
+            FrontLeft,
+            FrontRight,
+            FrontCenter,
+            LowFrequency,
+            BackCenter,
+            SideLeft,
+            SideRight,
+        ],
+        8 => &[
+            FrontLeft,
+            FrontRight,
+            FrontCenter,
+            LowFrequency,
+            BackLeft,
+            BackRight,
+            SideLeft,
+            SideRight,
+        ],
+        // 5.1.4
+        10 => &[
+            FrontLeft,
+            FrontRight,
+            FrontCenter,
+            LowFrequency,
+            BackLeft,
+            BackRight,
+            TopFrontLeft,
+            TopFrontRight,
+            TopBackLeft,
+            TopBackRight,
+        ],
+        // 7.1.4
+        12 => &[
+            FrontLeft,
+            FrontRight,
+            FrontCenter,
+            LowFrequency,
+            BackLeft,
+            BackRight,
+            SideLeft,
+            SideRight,
+            TopFrontLeft,
+            TopFrontRight,
+            TopBackLeft,
+            TopBackRight,
+        ],
+        // 22.2, in the order of the mask bits followed by the ones a mask can't describe
+        24 => &[
+            FrontLeft,
+            FrontRight,
+            FrontCenter,
+            LowFrequency,
+            BackLeft,
+            BackRight,
+            FrontCenterLeft,
+            FrontCenterRight,
+            BackCenter,
+            SideLeft,
+            SideRight,
+            TopCenter,
+            TopFrontLeft,
+            TopFrontCenter,
+            TopFrontRight,
+            TopBackLeft,
+            TopBackCenter,
+            TopBackRight,
+            TopSideLeft,
+            TopSideRight,
+            BottomFrontLeft,
+            BottomFrontCenter,
+            BottomFrontRight,
+            LowFrequency2,
+        ],
+        _ => return None,
+    };
+
+    Some(layout)
+}
+
+/// Speakers of the channels described by the WAVE_FORMAT_EXTENSIBLE bits of `mask`, which FLAC,
+/// Vorbis and most decoders use as well, in the order of the bits. Masks which are empty or don't
+/// have a bit per channel get the [`standard_layout`], `None` when there's none for `channels`.
+pub fn layout_from_mask(mask: u32, channels: usize) -> Option<Vec<Speaker>> {
+    let layout = (0..18)
+        .map(|bit| 1u32 << bit)
+        .filter(|bit| mask & bit != 0)
+        .map(|bit| Speaker::from(ChannelMask::from(bit)))
+        .collect::<Vec<_>>();
+
+    if layout.len() == channels {
+        Some(layout)
+    } else {
+        standard_layout(channels).map(|x| x.to_vec())
+    }
+}
+
+impl From<ChannelMask> for Speaker {
+    fn from(mask: ChannelMask) -> Self {
+        match mask {
+            ChannelMask::DirectOut => Speaker::DirectOut,
+            ChannelMask::FrontLeft => Speaker::FrontLeft,
+            ChannelMask::FrontRight => Speaker::FrontRight,
+            ChannelMask::FrontCenter => Speaker::FrontCenter,
+            ChannelMask::LowFrequency => Speaker::LowFrequency,
+            ChannelMask::BackLeft => Speaker::BackLeft,
+            ChannelMask::BackRight => Speaker::BackRight,
+            ChannelMask::FrontCenterLeft => Speaker::FrontCenterLeft,
+            ChannelMask::FrontCenterRight => Speaker::FrontCenterRight,
+            ChannelMask::BackCenter => Speaker::BackCenter,
+            ChannelMask::SideLeft => Speaker::SideLeft,
+            ChannelMask::SideRight => Speaker::SideRight,
+            ChannelMask::TopCenter => Speaker::TopCenter,
+            ChannelMask::TopFrontLeft => Speaker::TopFrontLeft,
+            ChannelMask::TopFrontCenter => Speaker::TopFrontCenter,
+            ChannelMask::TopFrontRight => Speaker::TopFrontRight,
+            ChannelMask::TopBackLeft => Speaker::TopBackLeft,
+            ChannelMask::TopBackCenter => Speaker::TopBackCenter,
+            ChannelMask::TopBackRight => Speaker::TopBackRight,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{layout_from_mask, standard_layout, Speaker};
+    use crate::{channel_from_name, get_channel_name, mirror_channel};
+
+    const ALL: [Speaker; 27] = [
+        Speaker::DirectOut,
+        Speaker::FrontLeft,
+        Speaker::FrontRight,
+        Speaker::FrontCenter,
+        Speaker::LowFrequency,
+        Speaker::BackLeft,
+        Speaker::BackRight,
+        Speaker::FrontCenterLeft,
+        Speaker::FrontCenterRight,
+        Speaker::BackCenter,
+        Speaker::SideLeft,
+        Speaker::SideRight,
+        Speaker::TopCenter,
+        Speaker::TopFrontLeft,
+        Speaker::TopFrontCenter,
+        Speaker::TopFrontRight,
+        Speaker::TopBackLeft,
+        Speaker::TopBackCenter,
+        Speaker::TopBackRight,
+        Speaker::FrontWideLeft,
+        Speaker::FrontWideRight,
+        Speaker::TopSideLeft,
+        Speaker::TopSideRight,
+        Speaker::BottomFrontLeft,
+        Speaker::BottomFrontCenter,
+        Speaker::BottomFrontRight,
+        Speaker::LowFrequency2,
+    ];
+
+    #[test]
+    fn names_masks_and_mirrors_round_trip() {
+        for speaker in ALL {
+            assert_eq!(channel_from_name(get_channel_name(speaker)), Some(speaker));
+            assert_eq!(mirror_channel(mirror_channel(speaker)), speaker);
+
+            if let Some(mask) = speaker.mask() {
+                assert_eq!(Speaker::from(mask), speaker);
+            }
+        }
+
+        assert_eq!(mirror_channel(Speaker::TopSideLeft), Speaker::TopSideRight);
+        assert_eq!(Speaker::BottomFrontCenter.mask(), None);
+    }
+
+    #[test]
+    fn standard_layouts_are_symmetrical() {
+        for channels in 0..=24 {
+            if let Some(layout) = standard_layout(channels) {
+                assert_eq!(layout.len(), channels);
+                for speaker in layout {
+                    assert!(layout.contains(&mirror_channel(*speaker)));
+                    assert_eq!(layout.iter().filter(|x| *x == speaker).count(), 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn layouts_follow_the_mask() {
+        // 5.1 with side speakers, the way FLAC describes it
+        let mask = 0x1 | 0x2 | 0x4 | 0x8 | 0x200 | 0x400;
+        assert_eq!(
+            layout_from_mask(mask, 6).unwrap(),
+            [
+                Speaker::FrontLeft,
+                Speaker::FrontRight,
+                Speaker::FrontCenter,
+                Speaker::LowFrequency,
+                Speaker::SideLeft,
+                Speaker::SideRight,
+            ]
+        );
+        assert_eq!(layout_from_mask(0x4, 1).unwrap(), [Speaker::FrontCenter]);
+
+        // without a bit per channel it's the usual layout, or none
+        assert_eq!(layout_from_mask(0, 6).unwrap(), standard_layout(6).unwrap());
+        assert_eq!(
+            layout_from_mask(0x3, 4).unwrap(),
+            standard_layout(4).unwrap()
+        );
+        assert_eq!(layout_from_mask(0, 9), None);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::dsp::fft;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// set on the middle slot of the triple buffer when the analyzer put a spectrum there the reader
+/// didn't take yet
+const NEW: usize = 4;
+/// floor of the magnitudes, so silence doesn't read as -inf
+const FLOOR_DB: f32 = -200.0;
+
+/// Magnitude spectrum of the binaural output published by a [`SpectrumTap`]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Spectrum {
+    pub sample_rate: usize,
+    /// level per bin from 0hz up to half the sample rate, a full scale sine reads 0dB
+    pub magnitudes_db: Vec<f32>,
+    /// counts up with every spectrum the tap published
+    pub sequence: u64,
+}
+
+impl Spectrum {
+    /// center frequency of `bin`
+    pub fn frequency(&self, bin: usize) -> f32 {
+        let fft_len = (self.magnitudes_db.len().max(2) - 1) * 2;
+        bin as f32 * self.sample_rate as f32 / fft_len as f32
+    }
+}
+
+/// Three slots handed between the analyzer and the tap so neither ever waits for the other, the
+/// locks only satisfy the borrow checker as a slot belongs to one side at a time
+#[derive(Debug)]
+struct TripleBuffer {
+    slots: [Mutex<Spectrum>; 3],
+    /// index of the slot neither side holds, with [`NEW`]
+    middle: AtomicUsize,
+}
+
+/// Reading end of the spectrum tap of a [`VirtualSurroundFilter`](crate::VirtualSurroundFilter),
+/// taken with [`spectrum_tap`](crate::VirtualSurroundFilter::spectrum_tap). Lives on the UI
+/// thread, the audio thread never waits for it.
+#[derive(Debug)]
+pub struct SpectrumTap {
+    buffer: Arc<TripleBuffer>,
+    read: usize,
+}
+
+impl SpectrumTap {
+    /// the newest spectrum, `None` when none was published since the last call
+    pub fn latest(&mut self) -> Option<Spectrum> {
+        if self.buffer.middle.load(Ordering::Acquire) & NEW == 0 {
+            return None;
+        }
+
+        self.read = self.buffer.middle.swap(self.read, Ordering::AcqRel) & !NEW;
+        let spectrum = self.buffer.slots[self.read].lock().ok()?;
+        Some(spectrum.clone())
+    }
+}
+
+/// The audio thread side of a [`SpectrumTap`], a windowed fft of the mono sum of the output every
+/// `decimation` blocks. The engines only have the circular unwindowed spectra of their segments
+/// in hand, which leak too much to show.
+#[derive(Debug)]
+pub(crate) struct SpectrumAnalyzer {
+    buffer: Arc<TripleBuffer>,
+    write: usize,
+    window: Vec<f64>,
+    /// scales a full scale sine to 1
+    scale: f64,
+    history: Vec<f32>,
+    decimation: usize,
+    blocks: usize,
+    sequence: u64,
+    re: Vec<f64>,
+    im: Vec<f64>,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(sample_rate: usize, fft_len: usize, decimation: usize) -> anyhow::Result<Self> {
+        if !fft_len.is_power_of_two() || fft_len < 64 {
+            anyhow::bail!(
+                "Spectrum size has to be a power of two of at least 64, not {}",
+                fft_len
+            );
+        }
+
+        let window = (0..fft_len)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f64 / fft_len as f64).cos())
+            .collect::<Vec<_>>();
+        let scale = 2.0 / window.iter().sum::<f64>();
+
+        let empty = || {
+            Mutex::new(Spectrum {
+                sample_rate,
+                magnitudes_db: vec![FLOOR_DB; fft_len / 2 + 1],
+                sequence: 0,
+            })
+        };
+
+        Ok(SpectrumAnalyzer {
+            buffer: Arc::new(TripleBuffer {
+                slots: [empty(), empty(), empty()],
+                middle: AtomicUsize::new(1),
+            }),
+            write: 0,
+            window,
+            scale,
+            history: vec![0f32; fft_len],
+            decimation: decimation.max(1),
+            blocks: 0,
+            sequence: 0,
+            re: vec![0f64; fft_len],
+            im: vec![0f64; fft_len],
+        })
+    }
+
+    /// the reading end, the analyzer only ever has one
+    pub fn tap(&self) -> SpectrumTap {
+        SpectrumTap {
+            buffer: self.buffer.clone(),
+            read: 2,
+        }
+    }
+
+    pub fn process(&mut self, left: &[f32], right: &[f32]) {
+        let count = left.len().min(self.history.len());
+        self.history.copy_within(count.., 0);
+        let start = self.history.len() - count;
+        for (sample, (l, r)) in self.history[start..].iter_mut().zip(
+            left[left.len() - count..]
+                .iter()
+                .zip(&right[right.len() - count..]),
+        ) {
+            *sample = (l + r) / 2.0;
+        }
+
+        self.blocks += 1;
+        if self.blocks < self.decimation {
+            return;
+        }
+        self.blocks = 0;
+
+        for (i, (x, w)) in self.history.iter().zip(&self.window).enumerate() {
+            self.re[i] = *x as f64 * w;
+            self.im[i] = 0.0;
+        }
+        fft(&mut self.re, &mut self.im, false);
+
+        let mut spectrum = match self.buffer.slots[self.write].try_lock() {
+            Ok(spectrum) => spectrum,
+            Err(_) => return,
+        };
+        self.sequence += 1;
+        spectrum.sequence = self.sequence;
+        for (bin, magnitude) in spectrum.magnitudes_db.iter_mut().enumerate() {
+            let amplitude = self.re[bin].hypot(self.im[bin]) * self.scale;
+            *magnitude = (20.0 * amplitude.log10() as f32).max(FLOOR_DB);
+        }
+        drop(spectrum);
+
+        self.write = self.buffer.middle.swap(self.write | NEW, Ordering::AcqRel) & !NEW;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpectrumAnalyzer;
+
+    #[test]
+    fn publishes_the_spectrum_of_the_output() {
+        let rate = 48000;
+        let mut analyzer = SpectrumAnalyzer::new(rate, 1024, 2).unwrap();
+        let mut tap = analyzer.tap();
+        assert!(tap.latest().is_none());
+        assert!(SpectrumAnalyzer::new(rate, 1000, 2).is_err());
+
+        // a 3khz sine, right on bin 64
+        let mut s = 0;
+        let mut block = || {
+            let block = (s..s + 512)
+                .map(|s| (s as f32 * 3000.0 / rate as f32 * std::f32::consts::TAU).sin() * 0.5)
+                .collect::<Vec<_>>();
+            s += 512;
+            block
+        };
+
+        let first = block();
+        analyzer.process(&first, &first);
+        assert!(tap.latest().is_none());
+
+        for _ in 0..3 {
+            let next = block();
+            analyzer.process(&next, &next);
+        }
+
+        // only the newest of the two published is read
+        let spectrum = tap.latest().unwrap();
+        assert_eq!(spectrum.sequence, 2);
+        assert!(tap.latest().is_none());
+
+        let peak = (0..spectrum.magnitudes_db.len())
+            .max_by(|a, b| spectrum.magnitudes_db[*a].total_cmp(&spectrum.magnitudes_db[*b]))
+            .unwrap();
+        assert_eq!(spectrum.frequency(peak), 3000.0);
+        assert!((spectrum.magnitudes_db[peak] - 20.0 * 0.5f32.log10()).abs() < 0.1);
+        assert!(spectrum.magnitudes_db[peak + 10] < -60.0);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::bass::BassManager;
+use crate::blend::BlendProcessor;
+use crate::dcblock::DcBlocker;
+use crate::distance::SpeakerDistances;
+use crate::eq::EqState;
+use crate::loudness::LoudnessMatcher;
+use crate::protection::OutputProtector;
+use crate::reverb::FdnReverb;
+use crate::transaural::CrosstalkCanceller;
+use crate::upmix::Upmixer;
+use crate::{PcmConverter, VirtualSurroundFilter};
+use std::any::Any;
+use std::fmt::{Debug, Formatter};
+
+/// Everything a [`VirtualSurroundFilter`] carries from one block to the next, taken by
+/// [`save_state`](VirtualSurroundFilter::save_state)
+pub struct FilterState {
+    available_data: usize,
+    in_space: Vec<Vec<f32>>,
+    chunk_space: Vec<f32>,
+    chunk_fill: usize,
+    faded: usize,
+    drained: usize,
+    silent_frames: usize,
+    engines: Vec<Option<Box<dyn Any + Send>>>,
+    upmixer: Upmixer,
+    distances: SpeakerDistances,
+    bass: Option<BassManager>,
+    eq: Option<EqState>,
+    blend: Option<BlendProcessor>,
+    reverb: Option<FdnReverb>,
+    dc_block: Option<DcBlocker>,
+    transaural: Option<CrosstalkCanceller>,
+    loudness: Option<LoudnessMatcher>,
+    bypass_matching: Option<LoudnessMatcher>,
+    protection: OutputProtector,
+    passthrough_protection: Vec<OutputProtector>,
+    pcm: PcmConverter,
+}
+
+impl Debug for FilterState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FilterState")
+            .field("available_data", &self.available_data)
+            .field("channels", &self.in_space.len())
+            .field("chunk_fill", &self.chunk_fill)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VirtualSurroundFilter {
+    /// Snapshot of the input window, the state of the convolution engine and every stage after
+    /// it, so a host can seek back or render ahead and resume exactly where the snapshot was
+    /// taken with [`load_state`](VirtualSurroundFilter::load_state).
+    pub fn save_state(&self) -> FilterState {
+        FilterState {
+            available_data: self.available_data,
+            in_space: self.in_space.clone(),
+            chunk_space: self.chunk_space.clone(),
+            chunk_fill: self.chunk_fill,
+            faded: self.faded,
+            drained: self.drained,
+            silent_frames: self.silent_frames,
+            engines: self.inner.save_state(),
+            upmixer: self.upmixer.clone(),
+            distances: self.distances.clone(),
+            bass: self.bass.clone(),
+            eq: self.eq.as_ref().map(|x| x.save_state()),
+            blend: self.blend.clone(),
+            reverb: self.reverb.clone(),
+            dc_block: self.dc_block.clone(),
+            transaural: self.transaural.clone(),
+            loudness: self.loudness.clone(),
+            bypass_matching: self.bypass_matching.clone(),
+            protection: self.protection.clone(),
+            passthrough_protection: self.passthrough_protection.clone(),
+            pcm: self.pcm.clone(),
+        }
+    }
+
+    /// Restore a snapshot [`save_state`](VirtualSurroundFilter::save_state) took from this filter,
+    /// or one built the same way. The stages carrying state (upmix, speaker distances, bass
+    /// management, headphone EQ, blend, reverb, DC blocker, crosstalk cancellation, loudness
+    /// matching and output protection) come back as they were saved, settings changed since
+    /// included. Mix, bypass and the fade-in length are kept.
+    pub fn load_state(&mut self, state: &FilterState) -> anyhow::Result<()> {
+        if state.in_space.len() != self.channels()
+            || state.in_space[0].len() != self.samples_required()
+        {
+            anyhow::bail!(
+                "State was saved by a filter with {} channels of {} samples, this one has {} of {}",
+                state.in_space.len(),
+                state.in_space[0].len(),
+                self.channels(),
+                self.samples_required()
+            );
+        }
+
+        match (&mut self.eq, &state.eq) {
+            (Some(eq), Some(saved)) => eq.load_state(saved)?,
+            (None, None) => {}
+            _ => anyhow::bail!("Headphone EQ was turned on or off since the state was saved"),
+        }
+
+        self.inner.load_state(&state.engines)?;
+
+        self.available_data = state.available_data;
+        for (space, saved) in self.in_space.iter_mut().zip(&state.in_space) {
+            space.copy_from_slice(saved);
+        }
+        self.chunk_space.clone_from(&state.chunk_space);
+        self.chunk_fill = state.chunk_fill;
+        self.faded = state.faded;
+        self.drained = state.drained;
+        self.silent_frames = state.silent_frames;
+        self.upmixer.clone_from(&state.upmixer);
+        self.distances.clone_from(&state.distances);
+        self.bass.clone_from(&state.bass);
+        self.blend.clone_from(&state.blend);
+        self.reverb.clone_from(&state.reverb);
+        self.dc_block.clone_from(&state.dc_block);
+        self.transaural.clone_from(&state.transaural);
+        self.loudness.clone_from(&state.loudness);
+        self.bypass_matching.clone_from(&state.bypass_matching);
+        self.protection.clone_from(&state.protection);
+        self.passthrough_protection
+            .clone_from(&state.passthrough_protection);
+        self.pcm.clone_from(&state.pcm);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::{get_channel_name, Speaker};
+use std::f32::consts::PI;
+
+/// peak level of the test signals, -12dBFS
+const LEVEL: f32 = 0.25;
+/// fade at the edges of each burst or syllable so they don't click
+const FADE_S: f32 = 0.005;
+
+/// What [`generate_test_signal`] plays on a speaker
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SignalKind {
+    /// quarter second bursts of pink noise every half second, broadband so the HRTF cues of
+    /// every band are there to localize it
+    #[default]
+    PinkNoiseBursts,
+    /// a harmonic glide through the range of a voice in syllable long pieces, closer to what
+    /// listeners are used to placing
+    VoiceChirp,
+}
+
+/// `seconds` of `kind` on `speaker` and silence on every other channel of `layout`, interleaved
+/// the way [`VirtualSurroundFilter::transform`](crate::VirtualSurroundFilter::transform) takes
+/// it. Played through the filter it lets a listener check where each virtual speaker ends up and
+/// that the channels are mapped right, see
+/// [`VirtualSurroundFilter::test_signal`](crate::VirtualSurroundFilter::test_signal).
+pub fn generate_test_signal(
+    speaker: Speaker,
+    kind: SignalKind,
+    layout: &[Speaker],
+    sample_rate: usize,
+    seconds: f32,
+) -> anyhow::Result<Vec<f32>> {
+    let channel = match layout.iter().position(|x| *x == speaker) {
+        Some(channel) => channel,
+        None => anyhow::bail!("Layout has no {} speaker", get_channel_name(speaker)),
+    };
+
+    let frames = (seconds.max(0.0) * sample_rate as f32) as usize;
+    let signal = match kind {
+        SignalKind::PinkNoiseBursts => pink_noise_bursts(frames, sample_rate),
+        SignalKind::VoiceChirp => voice_chirp(frames, sample_rate),
+    };
+
+    let mut output = vec![0f32; frames * layout.len()];
+    for (frame, sample) in output.chunks_exact_mut(layout.len()).zip(signal) {
+        frame[channel] = sample;
+    }
+
+    Ok(output)
+}
+
+/// gain of the fade in and out of a piece `length` seconds long at `t` seconds into it
+fn envelope(t: f32, length: f32) -> f32 {
+    if !(0.0..length).contains(&t) {
+        return 0.0;
+    }
+
+    (t / FADE_S).min((length - t) / FADE_S).min(1.0)
+}
+
+fn pink_noise_bursts(frames: usize, sample_rate: usize) -> Vec<f32> {
+    let mut state = 0x2545_f491u32;
+    // Paul Kellet's economy filter, white noise to -3dB per octave
+    let mut b = [0f32; 3];
+
+    (0..frames)
+        .map(|s| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let white = (state >> 8) as f32 / (1u32 << 23) as f32 - 1.0;
+
+            b[0] = 0.99765 * b[0] + white * 0.0990460;
+            b[1] = 0.96300 * b[1] + white * 0.2965164;
+            b[2] = 0.57000 * b[2] + white * 1.0526913;
+            let pink = ((b[0] + b[1] + b[2] + white * 0.1848) / 4.0).clamp(-1.0, 1.0);
+
+            let t = s as f32 / sample_rate as f32;
+            pink * envelope(t % 0.5, 0.25) * LEVEL
+        })
+        .collect()
+}
+
+fn voice_chirp(frames: usize, sample_rate: usize) -> Vec<f32> {
+    let mut phase = 0f32;
+
+    (0..frames)
+        .map(|s| {
+            let t = s as f32 / sample_rate as f32;
+            // a syllable every 0.4s, gliding up an octave from 150hz
+            let syllable = t % 0.4;
+            let frequency = 150.0 * 2f32.powf(syllable / 0.3);
+            phase = (phase + frequency / sample_rate as f32).fract();
+
+            let harmonics = (1..=8)
+                .map(|k| (2.0 * PI * phase * k as f32).sin() / k as f32)
+                .sum::<f32>();
+
+            // a band limited sawtooth, which overshoots to about 1.85
+            harmonics / 1.9 * envelope(syllable, 0.3) * LEVEL
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_test_signal, SignalKind, LEVEL};
+    use crate::Speaker;
+
+    #[test]
+    fn plays_only_on_the_speaker() {
+        let layout = [
+            Speaker::FrontLeft,
+            Speaker::FrontRight,
+            Speaker::FrontCenter,
+        ];
+
+        for kind in [SignalKind::PinkNoiseBursts, SignalKind::VoiceChirp] {
+            let signal =
+                generate_test_signal(Speaker::FrontRight, kind, &layout, 48000, 1.0).unwrap();
+            assert_eq!(signal.len(), 48000 * 3);
+
+            let channel = |c: usize| {
+                signal
+                    .iter()
+                    .skip(c)
+                    .step_by(3)
+                    .copied()
+                    .collect::<Vec<_>>()
+            };
+            assert!(channel(0).iter().chain(&channel(2)).all(|x| *x == 0.0));
+
+            let right = channel(1);
+            assert!(right.iter().all(|x| x.abs() <= LEVEL));
+            assert!(right[..12000].iter().any(|x| x.abs() > LEVEL / 4.0));
+            // silent between the bursts and syllables, starting out faded in
+            assert!(right[14500..19000].iter().all(|x| *x == 0.0));
+            assert!(right[0].abs() < 1e-3);
+        }
+
+        assert!(generate_test_signal(
+            Speaker::BackLeft,
+            SignalKind::default(),
+            &layout,
+            48000,
+            1.0
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,390 @@
+#[cfg(any(feature = "serial-imu", feature = "webcam"))]
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(any(feature = "serial-imu", feature = "webcam"))]
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// how often [`HeadTracker::subscribe`] polls its tracker
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Head orientation in degrees, yaw turning left, pitch looking up and roll tilting to the right
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Orientation {
+    pub yaw: f32,
+    pub pitch: f32,
+    pub roll: f32,
+}
+
+impl Orientation {
+    pub fn new(yaw: f32, pitch: f32, roll: f32) -> Self {
+        Orientation { yaw, pitch, roll }
+    }
+
+    /// `yaw pitch roll` separated by whitespace or commas, e.g. `12.5, -3, 0`
+    pub fn parse(line: &str) -> Option<Orientation> {
+        let mut fields = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|x| !x.is_empty())
+            .map(|x| x.parse::<f32>());
+
+        let orientation = Orientation {
+            yaw: fields.next()?.ok()?,
+            pitch: fields.next()?.ok()?,
+            roll: fields.next()?.ok()?,
+        };
+
+        if fields.next().is_some()
+            || !(orientation.yaw.is_finite()
+                && orientation.pitch.is_finite()
+                && orientation.roll.is_finite())
+        {
+            return None;
+        }
+
+        Some(orientation)
+    }
+}
+
+/// A source of head orientation, e.g. for
+/// [`AmbisonicVirtualizer::set_head_orientation`](crate::AmbisonicVirtualizer::set_head_orientation)
+pub trait HeadTracker: Send {
+    /// newest orientation since the last poll, `None` when nothing new arrived. Never blocks, so
+    /// it can be called once per block.
+    fn poll(&mut self) -> anyhow::Result<Option<Orientation>>;
+
+    /// Poll on a thread of its own and pass every new orientation to `callback`, until the
+    /// returned [`Subscription`] is stopped or dropped.
+    fn subscribe<F: FnMut(Orientation) + Send + 'static>(mut self, mut callback: F) -> Subscription
+    where
+        Self: Sized + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        let thread = std::thread::spawn(move || {
+            while !stopped.load(Ordering::Relaxed) {
+                if let Some(orientation) = self.poll()? {
+                    callback(orientation);
+                }
+
+                std::thread::sleep(POLL_INTERVAL);
+            }
+
+            Ok(())
+        });
+
+        Subscription {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<T: HeadTracker + ?Sized> HeadTracker for Box<T> {
+    fn poll(&mut self) -> anyhow::Result<Option<Orientation>> {
+        (**self).poll()
+    }
+}
+
+/// A tracker polled in the background by [`HeadTracker::subscribe`]
+pub struct Subscription {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Subscription {
+    /// whether the tracker is still polled, it stops when polling fails
+    pub fn is_running(&self) -> bool {
+        self.thread.as_ref().is_some_and(|x| !x.is_finished())
+    }
+
+    /// stop polling, returns the error polling stopped with if it failed
+    pub fn stop(mut self) -> anyhow::Result<()> {
+        self.join()
+    }
+
+    fn join(&mut self) -> anyhow::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+
+        match self.thread.take().map(|x| x.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => anyhow::bail!("Head tracker thread panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+/// Orientations parsed by [`Orientation::parse`] from the lines of a stream read on a thread of
+/// its own. Lines which don't parse, e.g. a device's startup messages, are skipped.
+#[cfg(any(feature = "serial-imu", feature = "webcam"))]
+struct LineReader {
+    receiver: Receiver<std::io::Result<Orientation>>,
+    stop: Arc<AtomicBool>,
+}
+
+#[cfg(any(feature = "serial-imu", feature = "webcam"))]
+impl LineReader {
+    fn spawn<R: Read + Send + 'static>(mut reader: R) -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; 256];
+            let mut line = Vec::new();
+
+            while !stopped.load(Ordering::Relaxed) {
+                let read = match reader.read(&mut buffer) {
+                    Ok(0) => return,
+                    Ok(read) => read,
+                    // serial ports time out reads, which lets the thread notice it was stopped
+                    Err(err)
+                        if err.kind() == std::io::ErrorKind::TimedOut
+                            || err.kind() == std::io::ErrorKind::Interrupted =>
+                    {
+                        continue
+                    }
+                    Err(err) => {
+                        let _ = sender.send(Err(err));
+                        return;
+                    }
+                };
+
+                for byte in &buffer[..read] {
+                    if *byte != b'\n' {
+                        line.push(*byte);
+                        continue;
+                    }
+
+                    let parsed = std::str::from_utf8(&line).ok().and_then(Orientation::parse);
+                    line.clear();
+
+                    if let Some(orientation) = parsed {
+                        if sender.send(Ok(orientation)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        LineReader { receiver, stop }
+    }
+
+    fn poll(&mut self) -> anyhow::Result<Option<Orientation>> {
+        let mut newest = None;
+
+        loop {
+            match self.receiver.try_recv() {
+                Ok(Ok(orientation)) => newest = Some(orientation),
+                Ok(Err(err)) => return Err(err.into()),
+                Err(TryRecvError::Empty) => return Ok(newest),
+                Err(TryRecvError::Disconnected) if newest.is_some() => return Ok(newest),
+                Err(TryRecvError::Disconnected) => anyhow::bail!("Head tracker stream ended"),
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "serial-imu", feature = "webcam"))]
+impl Drop for LineReader {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Receives opentrack's "UDP over network" output, 6 little endian doubles per packet: x, y and
+/// z in centimeters followed by yaw, pitch and roll in degrees.
+#[cfg(feature = "opentrack")]
+pub struct OpenTrackTracker {
+    socket: std::net::UdpSocket,
+}
+
+#[cfg(feature = "opentrack")]
+impl OpenTrackTracker {
+    /// opentrack's default port
+    pub const PORT: u16 = 4242;
+
+    /// listen on `address`, e.g. `("0.0.0.0", OpenTrackTracker::PORT)`
+    pub fn bind<A: std::net::ToSocketAddrs>(address: A) -> anyhow::Result<Self> {
+        use anyhow::Context;
+
+        let socket =
+            std::net::UdpSocket::bind(address).context("Failed to bind opentrack socket")?;
+        socket.set_nonblocking(true)?;
+
+        Ok(OpenTrackTracker { socket })
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<std::net::SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+}
+
+#[cfg(feature = "opentrack")]
+impl HeadTracker for OpenTrackTracker {
+    fn poll(&mut self) -> anyhow::Result<Option<Orientation>> {
+        let mut packet = [0u8; 48];
+        let mut newest = None;
+
+        loop {
+            match self.socket.recv(&mut packet) {
+                Ok(48) => {
+                    let field = |x: usize| {
+                        let mut bytes = [0u8; 8];
+                        bytes.copy_from_slice(&packet[x * 8..(x + 1) * 8]);
+                        f64::from_le_bytes(bytes) as f32
+                    };
+
+                    newest = Some(Orientation::new(field(3), field(4), field(5)));
+                }
+                // not from opentrack
+                Ok(_) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => return Ok(newest),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// An IMU on a serial port printing `yaw pitch roll` lines in degrees, see
+/// [`Orientation::parse`]
+#[cfg(feature = "serial-imu")]
+pub struct SerialImuTracker {
+    reader: LineReader,
+}
+
+#[cfg(feature = "serial-imu")]
+impl SerialImuTracker {
+    pub fn open(path: &str, baud_rate: u32) -> anyhow::Result<Self> {
+        use anyhow::Context;
+
+        let port = serialport::new(path, baud_rate)
+            .timeout(Duration::from_millis(100))
+            .open()
+            .with_context(|| format!("Failed to open serial port {}", path))?;
+
+        Ok(SerialImuTracker {
+            reader: LineReader::spawn(port),
+        })
+    }
+
+    /// read the line protocol from any stream, e.g. a port opened and configured elsewhere
+    pub fn from_reader<R: Read + Send + 'static>(reader: R) -> Self {
+        SerialImuTracker {
+            reader: LineReader::spawn(reader),
+        }
+    }
+}
+
+#[cfg(feature = "serial-imu")]
+impl HeadTracker for SerialImuTracker {
+    fn poll(&mut self) -> anyhow::Result<Option<Orientation>> {
+        self.reader.poll()
+    }
+}
+
+/// Bridge to a webcam face tracker running as a separate process, which prints a
+/// `yaw pitch roll` line in degrees on stdout per frame (see [`Orientation::parse`]). The
+/// process is killed when the tracker is dropped.
+#[cfg(feature = "webcam")]
+pub struct WebcamTracker {
+    child: std::process::Child,
+    reader: LineReader,
+}
+
+#[cfg(feature = "webcam")]
+impl WebcamTracker {
+    pub fn spawn(command: &mut std::process::Command) -> anyhow::Result<Self> {
+        use anyhow::Context;
+
+        let mut child = command
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .context("Failed to start webcam tracker")?;
+
+        let stdout = child.stdout.take().unwrap();
+
+        Ok(WebcamTracker {
+            child,
+            reader: LineReader::spawn(stdout),
+        })
+    }
+}
+
+#[cfg(feature = "webcam")]
+impl HeadTracker for WebcamTracker {
+    fn poll(&mut self) -> anyhow::Result<Option<Orientation>> {
+        self.reader.poll()
+    }
+}
+
+#[cfg(feature = "webcam")]
+impl Drop for WebcamTracker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Orientation;
+
+    #[test]
+    fn parses_lines() {
+        assert_eq!(
+            Orientation::parse("12.5, -3,0\r"),
+            Some(Orientation::new(12.5, -3.0, 0.0))
+        );
+        assert_eq!(
+            Orientation::parse(" 1 2 3 "),
+            Some(Orientation::new(1.0, 2.0, 3.0))
+        );
+        assert_eq!(Orientation::parse("IMU ready"), None);
+        assert_eq!(Orientation::parse("1 2"), None);
+        assert_eq!(Orientation::parse("1 2 3 4"), None);
+        assert_eq!(Orientation::parse("1 nan 3"), None);
+    }
+
+    #[cfg(feature = "opentrack")]
+    #[test]
+    fn receives_opentrack_packets() {
+        use super::{HeadTracker, OpenTrackTracker};
+
+        let mut tracker = OpenTrackTracker::bind("127.0.0.1:0").unwrap();
+        assert_eq!(tracker.poll().unwrap(), None);
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        for yaw in [10.0, 20.0] {
+            let packet = [0.0, 0.0, 0.0, yaw, -5.0, 2.0f64]
+                .iter()
+                .flat_map(|x| x.to_le_bytes())
+                .collect::<Vec<_>>();
+            socket
+                .send_to(&packet, tracker.local_addr().unwrap())
+                .unwrap();
+        }
+
+        let mut orientation = None;
+        for _ in 0..100 {
+            orientation = tracker.poll().unwrap().or(orientation);
+            if orientation.map(|x| x.yaw) == Some(20.0) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        assert_eq!(orientation, Some(Orientation::new(20.0, -5.0, 2.0)));
+    }
+}
@@ -0,0 +1,216 @@
+use crate::biquad::{Biquad, BUTTERWORTH_Q};
+use crate::distance::SPEED_OF_SOUND;
+use crate::hrir::KEMAR_HEAD_RADIUS_CM;
+
+/// below this the speakers are too close together to cancel anything, above it the ear positions
+/// aren't known well enough
+const CANCEL_BAND: (f32, f32) = (200.0, 6000.0);
+
+/// Settings of the crosstalk cancellation
+/// [`VirtualSurroundFilter::set_transaural`](crate::VirtualSurroundFilter::set_transaural) plays
+/// the virtualized output on a stereo speaker pair with, in front of the listener and at the
+/// height of their ears
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Transaural {
+    /// degrees each speaker is off center, 10 to 30 on a desk
+    pub speaker_angle: f32,
+    /// meters from the center of the head to the speakers
+    pub listener_distance_m: f32,
+    /// from 0 to 1, share of the crosstalk cancelled, less is more forgiving of head movement
+    pub strength: f32,
+}
+
+impl Default for Transaural {
+    /// a desktop pair at arm's length
+    fn default() -> Self {
+        Transaural {
+            speaker_angle: 15.0,
+            listener_distance_m: 0.6,
+            strength: 0.85,
+        }
+    }
+}
+
+impl Transaural {
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        if !(self.speaker_angle > 0.0 && self.speaker_angle < 90.0) {
+            anyhow::bail!(
+                "Transaural speakers have to be between 0 and 90 degrees off center, got {}",
+                self.speaker_angle
+            );
+        }
+
+        if !(self.listener_distance_m > KEMAR_HEAD_RADIUS_CM / 100.0
+            && self.listener_distance_m.is_finite())
+        {
+            anyhow::bail!(
+                "Transaural speakers have to be further away than the ears, got {}m",
+                self.listener_distance_m
+            );
+        }
+
+        if !(0.0..1.0).contains(&self.strength) {
+            anyhow::bail!(
+                "Crosstalk cancellation strength goes from 0 up to 1, got {}",
+                self.strength
+            );
+        }
+
+        Ok(())
+    }
+
+    /// distances from a speaker to the ear on its side and to the other one, in meters
+    fn paths(&self) -> (f32, f32) {
+        let radius = KEMAR_HEAD_RADIUS_CM / 100.0;
+        let distance = self.listener_distance_m;
+        let offset = 2.0 * radius * distance * self.speaker_angle.to_radians().sin();
+        let base = distance * distance + radius * radius;
+
+        ((base - offset).sqrt(), (base + offset).sqrt())
+    }
+}
+
+/// Recursive crosstalk canceller: each speaker plays the output of the other delayed by the
+/// extra path to the far ear and attenuated by its extra distance, inverted, which cancels
+/// the crosstalk of the other speaker at that ear. It feeds back, so the cancellation signal's
+/// own crosstalk is cancelled too.
+#[derive(Debug, Clone)]
+pub(crate) struct CrosstalkCanceller {
+    settings: Transaural,
+    /// in samples, at least one
+    delay: f32,
+    gain: f32,
+    /// past output of the left and right speaker
+    history: [Vec<f32>; 2],
+    position: usize,
+    /// band of the cancellation signal for each speaker, high pass then low pass
+    band: [[Biquad; 2]; 2],
+}
+
+impl CrosstalkCanceller {
+    pub(crate) fn new(settings: Transaural, sample_rate: usize) -> Self {
+        let (near, far) = settings.paths();
+        let delay = ((far - near) / SPEED_OF_SOUND * sample_rate as f32).max(1.0);
+        let band = [
+            Biquad::high_pass(sample_rate, CANCEL_BAND.0, BUTTERWORTH_Q),
+            Biquad::low_pass(sample_rate, CANCEL_BAND.1, BUTTERWORTH_Q),
+        ];
+
+        CrosstalkCanceller {
+            settings,
+            delay,
+            gain: settings.strength * near / far,
+            history: [
+                vec![0f32; delay as usize + 2],
+                vec![0f32; delay as usize + 2],
+            ],
+            position: 0,
+            band: [band, band],
+        }
+    }
+
+    pub(crate) fn settings(&self) -> Transaural {
+        self.settings
+    }
+
+    pub(crate) fn reset(&mut self) {
+        for history in &mut self.history {
+            history.fill(0f32);
+        }
+
+        for band in self.band.iter_mut().flatten() {
+            band.reset();
+        }
+    }
+
+    /// output of `speaker` `self.delay` samples ago, linearly interpolated
+    fn delayed(&self, speaker: usize) -> f32 {
+        let history = &self.history[speaker];
+        let length = history.len();
+        let whole = self.delay as usize;
+        let fraction = self.delay - whole as f32;
+        // the newest output is at the position, a sample back
+        let at = |back: usize| history[(self.position + 1 + length - back) % length];
+
+        at(whole) * (1.0 - fraction) + at(whole + 1) * fraction
+    }
+
+    pub(crate) fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+            let mut cancel = [self.delayed(1), self.delayed(0)];
+            for (sample, band) in cancel.iter_mut().zip(&mut self.band) {
+                let high_passed = band[0].process(*sample);
+                *sample = band[1].process(high_passed);
+            }
+
+            *left -= self.gain * cancel[0];
+            *right -= self.gain * cancel[1];
+
+            self.position = (self.position + 1) % self.history[0].len();
+            self.history[0][self.position] = *left;
+            self.history[1][self.position] = *right;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CrosstalkCanceller, Transaural};
+    use crate::distance::SPEED_OF_SOUND;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn cancels_the_crosstalk_at_the_far_ear() {
+        let rate = 48000;
+        let settings = Transaural {
+            strength: 0.95,
+            ..Transaural::default()
+        };
+        let (near, far) = settings.paths();
+        let delay = (far - near) / SPEED_OF_SOUND * rate as f32;
+        assert!(delay > 1.0 && delay < 10.0);
+
+        // a 1khz tone for the left ear only, through the speakers and the air to the right ear
+        let tone = (0..rate)
+            .map(|s| (2.0 * PI * 1000.0 * s as f32 / rate as f32).sin())
+            .collect::<Vec<_>>();
+        let right_ear = |left: &[f32], right: &[f32]| {
+            (rate / 2..rate)
+                .map(|s| {
+                    let at = s as f32 - delay;
+                    let fraction = at - at.floor();
+                    let crosstalk =
+                        left[at as usize] * (1.0 - fraction) + left[at as usize + 1] * fraction;
+                    (right[s] + crosstalk * near / far).abs()
+                })
+                .fold(0f32, f32::max)
+        };
+
+        let silence = vec![0f32; rate];
+        let uncancelled = right_ear(&tone, &silence);
+        assert!(uncancelled > 0.8);
+
+        let mut canceller = CrosstalkCanceller::new(settings, rate);
+        let (mut left, mut right) = (tone.clone(), silence.clone());
+        canceller.process(&mut left, &mut right);
+        assert!(right_ear(&left, &right) < uncancelled * 0.25);
+
+        assert!(Transaural {
+            strength: 1.0,
+            ..settings
+        }
+        .validate()
+        .is_err());
+        assert!(Transaural {
+            listener_distance_m: 0.05,
+            ..settings
+        }
+        .validate()
+        .is_err());
+    }
+}
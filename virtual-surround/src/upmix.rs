@@ -0,0 +1,164 @@
+use crate::biquad::{Biquad, BUTTERWORTH_Q};
+use crate::Speaker;
+
+/// Passive matrix upmix of stereo to the HRIR's speakers, the correlated part of the signal is
+/// partly steered to the center and the difference signal feeds the surrounds as ambience
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Upmix {
+    /// share of the mid signal moved from the front pair to the center, 0.0 to 1.0
+    pub center: f32,
+    /// level of the ambience in the surrounds
+    pub surround_db: f32,
+    /// delay of the surrounds, so the precedence effect keeps the image in front
+    pub surround_delay_ms: f32,
+    /// the ambience is low passed here, like matrix decoders do
+    pub surround_cutoff: f32,
+}
+
+impl Default for Upmix {
+    fn default() -> Self {
+        Upmix {
+            center: 0.5,
+            surround_db: -3.0,
+            surround_delay_ms: 12.0,
+            surround_cutoff: 7000.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Role {
+    Left,
+    Right,
+    Center,
+    Surround(f32),
+    Silent,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Upmixer {
+    roles: Vec<Role>,
+    center: f32,
+    has_center: bool,
+    has_front: bool,
+    delay: Vec<f32>,
+    position: usize,
+    low_pass: Biquad,
+}
+
+impl Upmixer {
+    /// `None` only places left and right on the front pair
+    pub fn new<I: Iterator<Item = Speaker>>(
+        upmix: Option<Upmix>,
+        positions: I,
+        sample_rate: usize,
+    ) -> Self {
+        let positions = positions.collect::<Vec<_>>();
+        let config = upmix.unwrap_or(Upmix {
+            center: 0.0,
+            surround_db: f32::NEG_INFINITY,
+            ..Upmix::default()
+        });
+
+        let is_left = |x: &Speaker| matches!(x, Speaker::BackLeft | Speaker::SideLeft);
+        let is_right = |x: &Speaker| matches!(x, Speaker::BackRight | Speaker::SideRight);
+        let gain = |count: usize| 10f32.powf(config.surround_db / 20.0) / (count as f32).sqrt();
+        let left_gain = gain(positions.iter().filter(|x| is_left(x)).count());
+        let right_gain = gain(positions.iter().filter(|x| is_right(x)).count());
+
+        let roles = positions
+            .iter()
+            .map(|x| match x {
+                Speaker::FrontLeft => Role::Left,
+                Speaker::FrontRight => Role::Right,
+                Speaker::FrontCenter => Role::Center,
+                x if upmix.is_some() && is_left(x) => Role::Surround(left_gain),
+                x if upmix.is_some() && is_right(x) => Role::Surround(-right_gain),
+                _ => Role::Silent,
+            })
+            .collect::<Vec<_>>();
+
+        let delay = (config.surround_delay_ms / 1000.0 * sample_rate as f32).round() as usize;
+
+        Upmixer {
+            has_center: roles.contains(&Role::Center),
+            has_front: roles.contains(&Role::Left) && roles.contains(&Role::Right),
+            roles,
+            center: config.center.clamp(0.0, 1.0),
+            delay: vec![0f32; delay.max(1)],
+            position: 0,
+            low_pass: Biquad::low_pass(sample_rate, config.surround_cutoff, BUTTERWORTH_Q),
+        }
+    }
+
+    /// whether there's a front pair to put the stereo input on
+    pub fn has_front(&self) -> bool {
+        self.has_front
+    }
+
+    pub fn reset(&mut self) {
+        self.delay.fill(0f32);
+        self.low_pass.reset();
+    }
+
+    /// interleaved stereo `input` to interleaved frames of every speaker in `output`
+    pub fn process(&mut self, input: &[f32], output: &mut [f32]) {
+        let channels = self.roles.len();
+        let center = if self.has_center { self.center } else { 0.0 };
+
+        for (frame, stereo) in output.chunks_exact_mut(channels).zip(input.chunks_exact(2)) {
+            let (left, right) = (stereo[0], stereo[1]);
+            let mid = (left + right) * 0.5;
+
+            // delay line holds the side signal, the oldest sample is at the write position
+            let ambience = self.low_pass.process(self.delay[self.position]);
+            self.delay[self.position] = (left - right) * 0.5;
+            self.position = (self.position + 1) % self.delay.len();
+
+            for (sample, role) in frame.iter_mut().zip(&self.roles) {
+                *sample = match role {
+                    Role::Left => left - mid * center,
+                    Role::Right => right - mid * center,
+                    Role::Center => mid * center * std::f32::consts::SQRT_2,
+                    Role::Surround(gain) => ambience * gain,
+                    Role::Silent => 0.0,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Upmix, Upmixer};
+    use crate::Speaker;
+
+    #[test]
+    fn correlated_stays_in_front_and_difference_goes_around() {
+        let positions = [
+            Speaker::FrontLeft,
+            Speaker::FrontRight,
+            Speaker::FrontCenter,
+            Speaker::BackLeft,
+            Speaker::BackRight,
+        ];
+        let mut upmixer = Upmixer::new(Some(Upmix::default()), positions.iter().copied(), 48000);
+
+        let frames = 2048;
+        let mut output = vec![0f32; frames * 5];
+        upmixer.process(&vec![0.5; frames * 2], &mut output);
+
+        let last = &output[(frames - 1) * 5..];
+        assert!((last[0] - 0.25).abs() < 1e-6);
+        assert!((last[2] - 0.25 * std::f32::consts::SQRT_2).abs() < 1e-6);
+        assert_eq!(last[3], 0.0);
+
+        let anti_phase = (0..frames).flat_map(|_| [0.5, -0.5]).collect::<Vec<_>>();
+        upmixer.process(&anti_phase, &mut output);
+
+        let last = &output[(frames - 1) * 5..];
+        assert_eq!(last[2], 0.0);
+        assert!(last[3] > 0.1);
+        assert!((last[3] + last[4]).abs() < 1e-6);
+    }
+}
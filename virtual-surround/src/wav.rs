@@ -0,0 +1,92 @@
+use crate::{SampleFormat, Speaker};
+use bwavfile::WaveReader;
+use std::convert::TryFrom;
+use std::io::{Read, Seek, Write};
+
+/// interleaved float samples of a wav file
+#[derive(Debug, Clone)]
+pub(crate) struct WavData {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub positions: Vec<Speaker>,
+    pub data: Vec<f32>,
+}
+
+impl WavData {
+    pub fn read<R: Read + Seek>(reader: R) -> anyhow::Result<WavData> {
+        let mut item = WaveReader::new(reader)?;
+
+        let fmt = item.format()?;
+        SampleFormat::try_from(fmt)?;
+
+        let positions = item
+            .channels()?
+            .iter()
+            .map(|x| Speaker::from(x.speaker))
+            .collect::<Vec<_>>();
+        let channels = fmt.channel_count as usize;
+
+        let mut reader = item.audio_frame_reader()?;
+        let mut buffer = vec![0f32; channels];
+        let mut data = Vec::new();
+
+        while let Ok(1) = reader.read_float_frame(&mut buffer) {
+            data.extend_from_slice(&buffer);
+        }
+
+        Ok(WavData {
+            sample_rate: fmt.sample_rate,
+            channels,
+            positions,
+            data,
+        })
+    }
+
+    pub fn column(&self, index: usize) -> Vec<f32> {
+        self.data
+            .iter()
+            .skip(index)
+            .step_by(self.channels)
+            .copied()
+            .collect()
+    }
+}
+
+/// KSDATAFORMAT_SUBTYPE_IEEE_FLOAT
+const FLOAT_SUBTYPE: [u8; 16] = [
+    0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// write interleaved `data` as a WAVE_FORMAT_EXTENSIBLE 32 bit float wav with channel `mask`
+pub(crate) fn write_float<W: Write>(
+    mut writer: W,
+    sample_rate: u32,
+    channels: usize,
+    mask: u32,
+    data: &[f32],
+) -> anyhow::Result<()> {
+    let data_len = u32::try_from(data.len() * 4)?;
+    let block_align = channels as u16 * 4;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(4 + 8 + 40 + 8 + data_len).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&40u32.to_le_bytes())?;
+    writer.write_all(&0xfffeu16.to_le_bytes())?;
+    writer.write_all(&(channels as u16).to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&32u16.to_le_bytes())?;
+    writer.write_all(&22u16.to_le_bytes())?;
+    writer.write_all(&32u16.to_le_bytes())?;
+    writer.write_all(&mask.to_le_bytes())?;
+    writer.write_all(&FLOAT_SUBTYPE)?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    for sample in data {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+
+    Ok(writer.flush()?)
+}
@@ -0,0 +1,145 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::fs::File;
+use virtual_surround::hrtf::{HrtfSource, SpeakerDirection};
+use virtual_surround::{
+    new_engine, AmbisonicFormat, AmbisonicVirtualizer, CurrentFFTLogic, FilterBuilder, Orientation,
+};
+
+const HRIR: &str = "../resources/hrir_kemar/hrir-kemar.wav";
+
+fn virtualizer(order: usize) -> AmbisonicVirtualizer {
+    FilterBuilder::new()
+        .build_ambisonic(File::open(HRIR).unwrap(), order)
+        .unwrap()
+}
+
+/// ears which pick up everything from their own side, as cardioids pointing left and right
+struct CardioidEars;
+
+impl HrtfSource for CardioidEars {
+    fn sample_rate(&self) -> u32 {
+        48000
+    }
+
+    fn impulse(&self, direction: &SpeakerDirection) -> anyhow::Result<(Vec<f32>, Vec<f32>)> {
+        let [_, y, _] = direction.cartesian();
+        Ok((vec![(1.0 + y) / 2.0, 0.0], vec![(1.0 - y) / 2.0, 0.0]))
+    }
+}
+
+/// AmbiX encoding of a horizontal plane wave from `azimuth` radians, up to third order
+fn encode(azimuth: f32, channels: usize) -> Vec<f32> {
+    let (s, c) = azimuth.sin_cos();
+    let (s2, c2) = (2.0 * azimuth).sin_cos();
+    let (s3, c3) = (3.0 * azimuth).sin_cos();
+    let half3 = 3f32.sqrt() / 2.0;
+    let (a, b) = ((5f32 / 8.0).sqrt(), (3f32 / 8.0).sqrt());
+
+    let all = [
+        1.0,
+        s,
+        0.0,
+        c,
+        half3 * s2,
+        0.0,
+        -0.5,
+        0.0,
+        half3 * c2,
+        a * s3,
+        0.0,
+        -b * s,
+        0.0,
+        -b * c,
+        0.0,
+        a * c3,
+    ];
+
+    all[..channels].to_vec()
+}
+
+/// energy of the left and right ear for a noise plane wave from `azimuth` degrees
+fn ear_energy(virtualizer: &mut AmbisonicVirtualizer, azimuth: f32) -> (f32, f32) {
+    let block = virtualizer.block_size();
+    let channels = virtualizer.channels();
+    let mut gains = encode(azimuth.to_radians(), channels);
+
+    if virtualizer.format() == AmbisonicFormat::FuMa {
+        gains = vec![gains[0] * FRAC_1_SQRT_2, gains[3], gains[1], gains[2]];
+    }
+
+    let (mut left, mut right) = (0f32, 0f32);
+    let mut state = 0x1234_5678u32;
+    let mut output = vec![0f32; block * 2];
+
+    for _ in 0..16 {
+        let input = (0..block)
+            .flat_map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let sample = (state >> 8) as f32 / (1u32 << 24) as f32 * 0.2 - 0.1;
+                gains.iter().map(move |gain| sample * gain)
+            })
+            .collect::<Vec<_>>();
+
+        virtualizer.transform(&input, &mut output).unwrap();
+
+        for frame in output.chunks_exact(2) {
+            left += frame[0] * frame[0];
+            right += frame[1] * frame[1];
+        }
+    }
+
+    (left, right)
+}
+
+#[test]
+fn left_source_is_louder_on_the_left() {
+    let mut virtualizer = virtualizer(1);
+    let (left, right) = ear_energy(&mut virtualizer, 90.0);
+    assert!(left > right * 2.0);
+
+    virtualizer.set_format(AmbisonicFormat::FuMa);
+    virtualizer.reset();
+    let (fuma_left, fuma_right) = ear_energy(&mut virtualizer, 90.0);
+    assert!((fuma_left - left).abs() < left * 1e-3);
+    assert!((fuma_right - right).abs() < right * 1e-3);
+}
+
+#[test]
+fn rotation_turns_the_scene() {
+    for order in 1..=3 {
+        let mut virtualizer =
+            AmbisonicVirtualizer::from_hrtf(&CardioidEars, order, new_engine::<CurrentFFTLogic>)
+                .unwrap();
+        assert_eq!(virtualizer.channels(), (order + 1) * (order + 1));
+
+        let (left, right) = ear_energy(&mut virtualizer, 90.0);
+        assert!(left > right * 100.0);
+
+        virtualizer.set_rotation(180.0, 0.0, 0.0);
+        virtualizer.reset();
+        let (left, right) = ear_energy(&mut virtualizer, 90.0);
+        assert!(right > left * 100.0);
+    }
+}
+
+#[test]
+fn head_orientation_keeps_the_scene_in_place() {
+    let mut virtualizer =
+        AmbisonicVirtualizer::from_hrtf(&CardioidEars, 1, new_engine::<CurrentFFTLogic>).unwrap();
+
+    // turned to the left, what's in front ends up on the right
+    virtualizer.set_head_orientation(Orientation::new(90.0, 0.0, 0.0));
+    let (left, right) = ear_energy(&mut virtualizer, 0.0);
+    assert!(right > left * 100.0);
+}
+
+#[test]
+fn rejects_unsupported_orders() {
+    let builder = FilterBuilder::new();
+    assert!(builder
+        .build_ambisonic(File::open(HRIR).unwrap(), 0)
+        .is_err());
+    assert!(builder
+        .build_ambisonic(File::open(HRIR).unwrap(), 4)
+        .is_err());
+}
@@ -0,0 +1,96 @@
+use virtual_surround::BlockAdapter;
+
+mod common;
+
+use common::{filter, noise};
+
+/// interleaved stereo output of feeding `input` through an adapter in buffers of `buffer_size`,
+/// planar or interleaved
+fn render(input: &[f32], buffer_size: usize, planar: bool) -> (Vec<f32>, usize) {
+    let mut vsf = filter();
+    let channels = vsf.channels();
+    let mut adapter = BlockAdapter::new(channels, vsf.block_size(), buffer_size).unwrap();
+    let mut output = vec![];
+
+    for buffer in input.chunks_exact(buffer_size * channels) {
+        let mut out = vec![0f32; buffer_size * 2];
+
+        if planar {
+            let planes = (0..channels)
+                .map(|c| buffer.iter().skip(c).step_by(channels).copied().collect())
+                .collect::<Vec<Vec<f32>>>();
+            let planes = planes.iter().map(|x| x.as_slice()).collect::<Vec<_>>();
+            let mut left = vec![0f32; buffer_size];
+            let mut right = vec![0f32; buffer_size];
+
+            adapter
+                .process_planar(&planes, &mut left, &mut right, |input, output| {
+                    vsf.transform(input, output)
+                })
+                .unwrap();
+
+            for (frame, (l, r)) in out.chunks_exact_mut(2).zip(left.iter().zip(&right)) {
+                frame[0] = *l;
+                frame[1] = *r;
+            }
+        } else {
+            adapter
+                .process(buffer, &mut out, |input, output| {
+                    vsf.transform(input, output)
+                })
+                .unwrap();
+        }
+
+        output.extend(out);
+    }
+
+    (output, adapter.latency())
+}
+
+#[test]
+fn any_buffer_size_matches_blocks_modulo_latency() {
+    let vsf = filter();
+    let channels = vsf.channels();
+    let block = vsf.block_size();
+    let frames = block * 24;
+    let input = noise(channels, frames);
+
+    let (reference, latency) = render(&input, block, false);
+    assert_eq!(latency, 0);
+    assert!(reference.iter().any(|x| *x != 0.0));
+
+    for (buffer_size, expected_latency) in [
+        (256, 256),
+        (32, 480),
+        (384, 384),
+        (100, 508),
+        (1, 511),
+        (1024, 0),
+        (1000, 504),
+    ] {
+        for planar in [false, true] {
+            let (output, latency) = render(&input, buffer_size, planar);
+            assert_eq!(latency, expected_latency);
+
+            let produced = output.len();
+            assert!(output[..latency * 2].iter().all(|x| *x == 0.0));
+            assert_eq!(&output[latency * 2..], &reference[..produced - latency * 2]);
+        }
+    }
+}
+
+#[test]
+fn rejects_mismatched_buffers() {
+    let mut adapter = BlockAdapter::new(2, 512, 128).unwrap();
+    let mut output = vec![0f32; 256];
+    let render = |_: &[f32], _: &mut [f32]| Ok(());
+
+    assert!(adapter
+        .process(&[0f32; 2 * 64], &mut output, render)
+        .is_err());
+    assert!(adapter
+        .process(&[0f32; 2 * 128], &mut output, render)
+        .is_ok());
+    assert!(adapter.set_buffer_size(0).is_err());
+    assert!(BlockAdapter::new(2, 0, 128).is_err());
+}
@@ -0,0 +1,515 @@
+use std::fs::File;
+use virtual_surround::{
+    ConvolutionStrategy, FilterBuilder, ProcessStatus, RawVirtualSurroundFilter, Speaker,
+    VirtualSurroundFilter,
+};
+
+mod common;
+
+use common::{filter, noise, HRIR};
+
+fn raw_filter() -> RawVirtualSurroundFilter {
+    FilterBuilder::new()
+        .build_raw(File::open(HRIR).unwrap())
+        .unwrap()
+}
+
+/// interleaved stereo output of feeding `input` block by block
+fn render(filter: &mut VirtualSurroundFilter, input: &[f32], channels: usize) -> Vec<f32> {
+    let block = filter.block_size();
+    let mut output = vec![];
+
+    for chunk in input.chunks_exact(block * channels) {
+        let mut out = vec![0f32; block * 2];
+        if channels == 1 {
+            filter.transform_mono(chunk, &mut out).unwrap();
+        } else {
+            filter.transform(chunk, &mut out).unwrap();
+        }
+        output.extend(out);
+    }
+
+    output
+}
+
+#[test]
+fn filter_matches_raw_window() {
+    let mut filter = filter();
+    filter.set_fade_in(0.0);
+    let mut raw = raw_filter();
+    let channels = raw.channels();
+    let block = raw.block_size();
+    let window = raw.samples_required();
+    let input = noise(channels, window * 4);
+
+    let output = render(&mut filter, &input, channels);
+
+    let planar = (0..channels)
+        .map(|c| {
+            input
+                .iter()
+                .skip(c)
+                .step_by(channels)
+                .copied()
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    // the first output appears once the window is filled
+    let first = window / block - 1;
+
+    for index in first..input.len() / channels / block {
+        let end = (index + 1) * block;
+        let windows = planar
+            .iter()
+            .map(|x| &x[end - window..end])
+            .collect::<Vec<_>>();
+
+        let mut left = vec![0f32; block];
+        let mut right = vec![0f32; block];
+        raw.transform(&windows, (&mut left, &mut right)).unwrap();
+
+        for s in 0..block {
+            assert_eq!(output[(index * block + s) * 2], left[s]);
+            assert_eq!(output[(index * block + s) * 2 + 1], right[s]);
+        }
+    }
+}
+
+#[test]
+fn mono_matches_expanded_input() {
+    let mut mono_filter = filter();
+    let mut filter = filter();
+    let channels = filter.channels();
+    let center = filter
+        .positions()
+        .position(|x| x == virtual_surround::Speaker::FrontCenter)
+        .unwrap();
+
+    let mono = noise(1, filter.samples_required() * 3);
+    let mut expanded = vec![0f32; mono.len() * channels];
+    for (frame, sample) in expanded.chunks_exact_mut(channels).zip(&mono) {
+        frame[center] = *sample;
+    }
+
+    assert_eq!(
+        render(&mut mono_filter, &mono, 1),
+        render(&mut filter, &expanded, channels)
+    );
+}
+
+#[test]
+fn stereo_matches_expanded_input() {
+    let mut stereo_filter = filter();
+    let mut filter = filter();
+    let channels = filter.channels();
+    let position = |mask| filter.positions().position(|x| x == mask).unwrap();
+    let (left, right) = (
+        position(virtual_surround::Speaker::FrontLeft),
+        position(virtual_surround::Speaker::FrontRight),
+    );
+
+    let stereo = noise(2, filter.samples_required() * 3);
+    let mut expanded = vec![0f32; stereo.len() / 2 * channels];
+    for (frame, sample) in expanded
+        .chunks_exact_mut(channels)
+        .zip(stereo.chunks_exact(2))
+    {
+        frame[left] = sample[0];
+        frame[right] = sample[1];
+    }
+
+    let block = filter.block_size();
+    let mut output = vec![];
+    for chunk in stereo.chunks_exact(block * 2) {
+        let mut out = vec![0f32; block * 2];
+        stereo_filter.transform_stereo(chunk, &mut out).unwrap();
+        output.extend(out);
+    }
+
+    assert_eq!(output, render(&mut filter, &expanded, channels));
+}
+
+#[test]
+fn planar_matches_interleaved() {
+    let mut planar_filter = filter();
+    let mut filter = filter();
+    let channels = filter.channels();
+    let block = filter.block_size();
+    let input = noise(channels, filter.samples_required() * 3);
+
+    let mut output = vec![];
+    for chunk in input.chunks_exact(block * channels) {
+        let planar = (0..channels)
+            .map(|c| {
+                chunk
+                    .iter()
+                    .skip(c)
+                    .step_by(channels)
+                    .copied()
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let planar = planar.iter().map(|x| x.as_slice()).collect::<Vec<_>>();
+
+        let (mut left, mut right) = (vec![0f32; block], vec![0f32; block]);
+        planar_filter
+            .transform_planar(&planar, &mut left, &mut right)
+            .unwrap();
+        output.extend(left.iter().zip(&right).flat_map(|(l, r)| [*l, *r]));
+    }
+
+    assert_eq!(output, render(&mut filter, &input, channels));
+    assert!(planar_filter
+        .transform_planar(&[&[0f32; 512]], &mut [0f32; 512], &mut [0f32; 512])
+        .is_err());
+}
+
+#[test]
+fn integer_matches_float() {
+    let mut integer_filter = filter();
+    let mut filter = filter();
+    let channels = filter.channels();
+    let block = filter.block_size();
+    let input = noise(channels, filter.samples_required() * 3)
+        .iter()
+        .map(|x| (x * 32768.0) as i16)
+        .collect::<Vec<_>>();
+    let float = input
+        .iter()
+        .map(|x| *x as f32 / 32768.0)
+        .collect::<Vec<_>>();
+
+    let mut output = vec![];
+    for chunk in input.chunks_exact(block * channels) {
+        let mut out = vec![0i16; block * 2];
+        integer_filter.transform_i16(chunk, &mut out).unwrap();
+        output.extend(out);
+    }
+
+    let expected = render(&mut filter, &float, channels)
+        .iter()
+        .map(|x| (x * 32768.0).round().clamp(-32768.0, 32767.0) as i16)
+        .collect::<Vec<_>>();
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn chunks_of_any_size_match_blocks() {
+    let mut chunk_filter = filter();
+    let mut filter = filter();
+    let channels = filter.channels();
+    let block = filter.block_size();
+    let input = noise(channels, filter.samples_required() * 3);
+    let priming = filter.samples_required() / block - 1;
+
+    let mut output = vec![];
+    let mut out = vec![0f32; block * 4];
+    let mut remaining = input.as_slice();
+    for size in [1, 100, 700, 511, 1300, 3].iter().cycle() {
+        if remaining.is_empty() {
+            break;
+        }
+
+        let frames = (*size).min(remaining.len() / channels);
+        let mut chunk = &remaining[..frames * channels];
+        remaining = &remaining[frames * channels..];
+
+        while !chunk.is_empty() {
+            let status = chunk_filter.transform_chunk(chunk, &mut out).unwrap();
+            output.extend_from_slice(&out[..status.produced * 2]);
+            chunk = &chunk[status.consumed * channels..];
+        }
+    }
+
+    assert_eq!(
+        output,
+        render(&mut filter, &input, channels)[priming * block * 2..]
+    );
+
+    // no room for a block, so the frame completing one stays put
+    let mut small = vec![0f32; block];
+    let status = chunk_filter
+        .transform_chunk(&input[..block * channels], &mut small)
+        .unwrap();
+    assert_eq!(status.consumed, block - 1);
+    assert_eq!(status.produced, 0);
+}
+
+#[test]
+fn reports_priming() {
+    let mut filter = filter();
+    let channels = filter.channels();
+    let block = filter.block_size();
+    let input = noise(channels, block);
+    let priming = filter.samples_required() / block - 1;
+
+    for index in 0..priming + 2 {
+        let mut output = vec![1f32; block * 2];
+        let status = filter.transform(&input, &mut output).unwrap();
+
+        if index < priming {
+            assert_eq!(status, ProcessStatus::Priming);
+            assert!(output.iter().all(|x| *x == 0.0));
+        } else {
+            assert_eq!(status, ProcessStatus::Rendered);
+            assert_eq!(status.frames(), block);
+        }
+    }
+}
+
+#[test]
+fn fades_in_once_rendering() {
+    let mut faded = filter();
+    let mut plain = filter();
+    plain.set_fade_in(0.0);
+    faded.set_fade_in(5.0);
+    let channels = plain.channels();
+    let block = plain.block_size();
+    let fade = faded.sample_rate() * 5 / 1000;
+    let input = noise(channels, plain.samples_required() * 2);
+
+    let faded = render(&mut faded, &input, channels);
+    let plain = render(&mut plain, &input, channels);
+    let first = (plain.iter().position(|x| *x != 0.0).unwrap() / 2) / block * block;
+
+    for s in 0..faded.len() / 2 {
+        let gain = (s.saturating_sub(first) as f32 / fade as f32).min(1.0);
+        for c in 0..2 {
+            assert!((faded[s * 2 + c] - plain[s * 2 + c] * gain).abs() < 1e-6);
+        }
+    }
+}
+
+/// output of `input` followed by the drained tail
+fn render_and_drain(filter: &mut VirtualSurroundFilter, input: &[f32]) -> (Vec<f32>, Vec<f32>) {
+    let output = render(filter, input, filter.channels());
+    let mut tail = vec![];
+    let mut block = vec![0f32; filter.block_size() * 2];
+
+    loop {
+        let frames = filter.drain(&mut block).unwrap();
+        if frames == 0 {
+            break;
+        }
+
+        tail.extend_from_slice(&block[..frames * 2]);
+    }
+
+    (output, tail)
+}
+
+#[test]
+fn drain_renders_the_tail() {
+    let mut filter = filter();
+    let channels = filter.channels();
+    let block = filter.block_size();
+    let input = noise(channels, filter.samples_required() * 2);
+
+    let (output, tail) = render_and_drain(&mut filter, &input);
+    let latency = filter.sample_latency();
+    assert_eq!(tail.len() / 2, latency.div_ceil(block) * block);
+    assert!(tail[..block * 2].iter().any(|x| x.abs() > 1e-3));
+    // a drained filter stays drained until it gets input again, and has nothing left to render
+    let mut rest = vec![1f32; block * 2];
+    assert_eq!(filter.drain(&mut rest).unwrap(), 0);
+    filter
+        .transform(&vec![0f32; block * channels], &mut rest)
+        .unwrap();
+    assert!(rest.iter().all(|x| x.abs() < 1e-6));
+
+    // the next stream renders exactly like the first one did
+    filter.reset();
+    assert_eq!(render_and_drain(&mut filter, &input), (output, tail));
+}
+
+#[test]
+fn low_latency_renders_from_the_first_block() {
+    let mut low_latency = filter();
+    low_latency.set_fade_in(0.0);
+    let mut filter = filter();
+    filter.set_fade_in(0.0);
+    low_latency.set_low_latency(true);
+
+    let channels = filter.channels();
+    let block = filter.block_size();
+    let window = filter.samples_required();
+    let input = noise(channels, window * 3);
+
+    let mut out = vec![0f32; block * 2];
+    assert_eq!(
+        low_latency
+            .transform(&input[..block * channels], &mut out)
+            .unwrap(),
+        ProcessStatus::Rendered
+    );
+    assert!(out.iter().any(|x| x.abs() > 1e-3));
+    assert_eq!(low_latency.sample_latency(), 0);
+    low_latency.reset();
+
+    // once the window is full both hear the same input
+    let expected = render(&mut filter, &input, channels);
+    let output = render(&mut low_latency, &input, channels);
+    let primed = (window - block) * 2;
+    assert!(expected[..primed].iter().all(|x| *x == 0.0));
+    for (x, y) in output[primed..].iter().zip(&expected[primed..]) {
+        assert!((x - y).abs() < 1e-5, "{} {}", x, y);
+    }
+
+    // the tail is as long as the impulse responses either way
+    let (_, tail) = render_and_drain(&mut low_latency, &input);
+    assert_eq!(tail.len() / 2, window - block);
+}
+
+#[test]
+fn resumes_from_a_saved_state() {
+    let mut filter = filter();
+    let channels = filter.channels();
+    let block = filter.block_size();
+    let input = noise(channels, filter.samples_required() * 4);
+    let (head, rest) = input.split_at(input.len() / 2);
+
+    render(&mut filter, head, channels);
+    let state = filter.save_state();
+    let output = render(&mut filter, rest, channels);
+
+    // render something else in between, then go back
+    render(&mut filter, &vec![0.05; block * channels * 3], channels);
+    filter.load_state(&state).unwrap();
+    assert_eq!(render(&mut filter, rest, channels), output);
+}
+
+#[test]
+fn loads_a_prepared_filter() {
+    use virtual_surround::{new_engine, RustFFTLogic};
+
+    // the default engine transforms the saved impulse responses, RustFFTLogic takes its spectra
+    for builder in [
+        FilterBuilder::new(),
+        FilterBuilder::new().engine(new_engine::<RustFFTLogic>),
+    ] {
+        let mut filter = builder.build(File::open(HRIR).unwrap()).unwrap();
+        let mut prepared = vec![];
+        filter.save_prepared(&mut prepared, "kemar").unwrap();
+
+        let mut loaded = builder
+            .build_prepared(prepared.as_slice(), "kemar")
+            .unwrap();
+        assert_eq!(loaded.sample_rate(), filter.sample_rate());
+        assert_eq!(loaded.ir_delay(), filter.ir_delay());
+        assert!(loaded.positions().eq(filter.positions()));
+
+        let channels = filter.channels();
+        let input = noise(channels, filter.samples_required() * 3);
+        assert_eq!(
+            render(&mut loaded, &input, channels),
+            render(&mut filter, &input, channels)
+        );
+
+        // other settings, another sample rate or a broken file
+        assert!(builder
+            .build_prepared(prepared.as_slice(), "other")
+            .is_err());
+        assert!(builder
+            .clone()
+            .sample_rate(48000)
+            .build_prepared(prepared.as_slice(), "kemar")
+            .is_err());
+        assert!(builder
+            .build_prepared(&prepared[..prepared.len() / 2], "kemar")
+            .is_err());
+    }
+}
+
+#[test]
+fn fresh_clone_renders_like_a_new_filter() {
+    let configure = |filter: &mut VirtualSurroundFilter| {
+        filter
+            .set_speaker_distance(Speaker::FrontLeft, 0.5)
+            .unwrap();
+        filter.set_mix(0.8);
+    };
+
+    let mut filter = filter();
+    configure(&mut filter);
+    let channels = filter.channels();
+    let input = noise(channels, filter.samples_required() * 3);
+    render(&mut filter, &input, channels);
+
+    let mut clone = filter.clone_fresh().unwrap();
+    let mut expected = self::filter();
+    configure(&mut expected);
+
+    assert_eq!(
+        render(&mut clone, &input, channels),
+        render(&mut expected, &input, channels)
+    );
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn threads_match_a_single_thread() {
+    let mut single = filter();
+    let mut threaded = FilterBuilder::new()
+        .threads(4)
+        .build(File::open(HRIR).unwrap())
+        .unwrap();
+    let channels = single.channels();
+    let input = noise(channels, single.samples_required() * 3);
+
+    let expected = render(&mut single, &input, channels);
+    let output = render(&mut threaded, &input, channels);
+    assert!(output
+        .iter()
+        .zip(&expected)
+        .all(|(x, y)| (x - y).abs() < 1e-6));
+}
+
+#[test]
+fn takes_other_lengths_up_to_the_window() {
+    let window_filter = || {
+        let mut filter = FilterBuilder::new()
+            .strategy(ConvolutionStrategy::Window)
+            .build(File::open(HRIR).unwrap())
+            .unwrap();
+        filter.set_fade_in(0.0);
+        filter
+    };
+    let mut blocks = window_filter();
+    let mut whole = window_filter();
+    let channels = blocks.channels();
+    let block = blocks.block_size();
+    let mut output = vec![0f32; block * 2];
+
+    for frames in [block / 2, block - 1, block + 1, block * 2] {
+        let input = vec![0f32; frames * channels];
+        assert!(filter().transform(&input, &mut output).is_ok());
+    }
+
+    let too_long = vec![0f32; (blocks.samples_required() + 1) * channels];
+    assert!(blocks.transform(&too_long, &mut output).is_err());
+    let input = vec![0f32; block * channels];
+    assert!(blocks.transform(&input, &mut output[..block]).is_err());
+
+    // two blocks at once leave the window as two calls of one do
+    let primer = noise(channels, blocks.samples_required());
+    let input = noise(channels, block * 3);
+    let mut expected = vec![0f32; block * 2];
+    let mut actual = vec![0f32; block * 2];
+    blocks.transform(&primer, &mut expected).unwrap();
+    whole.transform(&primer, &mut actual).unwrap();
+
+    for chunk in input.chunks(block * channels) {
+        blocks.transform(chunk, &mut expected).unwrap();
+    }
+    whole
+        .transform(&input[..block * channels], &mut actual)
+        .unwrap();
+    whole
+        .transform(&input[block * channels..], &mut actual)
+        .unwrap();
+
+    for (a, b) in expected.iter().zip(&actual) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+}
@@ -0,0 +1,25 @@
+// every test binary compiles this, most use only some of it
+#![allow(dead_code)]
+
+use std::fs::File;
+use virtual_surround::{FilterBuilder, VirtualSurroundFilter};
+
+pub const HRIR: &str = "../resources/hrir_kemar/hrir-kemar.wav";
+
+/// deterministic quiet noise, so nothing reaches the output protection
+pub fn noise(channels: usize, frames: usize) -> Vec<f32> {
+    let mut state = 0x1234_5678u32;
+
+    (0..channels * frames)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 8) as f32 / (1u32 << 24) as f32 * 0.2 - 0.1
+        })
+        .collect()
+}
+
+pub fn filter() -> VirtualSurroundFilter {
+    FilterBuilder::new()
+        .build(File::open(HRIR).unwrap())
+        .unwrap()
+}
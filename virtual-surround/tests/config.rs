@@ -0,0 +1,176 @@
+#![cfg(feature = "config")]
+
+use virtual_surround::hrir::Normalization;
+use virtual_surround::{
+    channel_from_name, Engine, EqBand, FilterConfig, HeadphoneEq, OutputProtection,
+    ResampleQuality, Reverb, BLOCK_SIZE,
+};
+
+const HRIR: &str = "../resources/hrir_kemar/hrir-kemar.wav";
+
+#[test]
+fn reads_and_applies_a_config() {
+    let config: FilterConfig = toml::from_str(&format!(
+        r#"
+hrir = "{}"
+layout = ["FL", "FR", "FC", "LFE", "BL", "BR"]
+mix = 0.5
+protection = {{ lookahead_limiter = {{ lookahead_ms = 5.0, release_ms = 50.0, ceiling_db = -1.0 }} }}
+
+[speaker_distances]
+FC = 3.0
+
+[reverb]
+decay_s = 0.8
+
+[reverb_sends]
+FC = 0.5
+
+[headphone_eq.parametric]
+preamp_db = -3.0
+bands = [{{ peaking = {{ frequency = 1000.0, q = 1.0, gain_db = -3.0 }} }}]
+"#,
+        HRIR
+    ))
+    .unwrap();
+
+    // whatever is left out keeps its default
+    assert_eq!(config.block_size, BLOCK_SIZE);
+    assert_eq!(config.normalization, Normalization::default());
+    assert_eq!(config.protection, OutputProtection::lookahead_limiter());
+    assert_eq!(
+        config.headphone_eq,
+        Some(HeadphoneEq::parametric(
+            -3.0,
+            vec![EqBand::Peaking {
+                frequency: 1000.0,
+                q: 1.0,
+                gain_db: -3.0
+            }]
+        ))
+    );
+
+    let filter = config.build().unwrap();
+    assert_eq!(filter.mix(), 0.5);
+    assert_eq!(
+        filter.speaker_distance(channel_from_name("FC").unwrap()),
+        Some(3.0)
+    );
+    assert_eq!(filter.reverb().unwrap().decay_s, 0.8);
+    assert_eq!(filter.reverb().unwrap().damping, Reverb::default().damping);
+    assert_eq!(
+        filter.reverb_send(channel_from_name("FC").unwrap()),
+        Some(0.5)
+    );
+    assert_eq!(
+        filter.reverb_send(channel_from_name("LFE").unwrap()),
+        Some(0.0)
+    );
+
+    let saved: FilterConfig = toml::from_str(&toml::to_string(&config).unwrap()).unwrap();
+    assert_eq!(saved, config);
+}
+
+#[test]
+fn rejects_other_block_sizes() {
+    let config = FilterConfig {
+        hrir: Some(HRIR.into()),
+        block_size: 256,
+        ..FilterConfig::default()
+    };
+
+    assert!(config.build().is_err());
+}
+
+#[test]
+fn resamples_with_the_configured_quality() {
+    let config: FilterConfig = toml::from_str(&format!(
+        r#"
+hrir = "{}"
+sample_rate = 48000
+resample_quality = "fastest"
+"#,
+        HRIR
+    ))
+    .unwrap();
+    assert_eq!(config.resample_quality, ResampleQuality::Fastest);
+
+    let filter = config.build().unwrap();
+    assert_eq!(filter.sample_rate(), 48000);
+}
+
+#[test]
+fn caches_the_prepared_filter() {
+    use virtual_surround::FilterBuilder;
+
+    let cache = std::env::temp_dir().join(format!("vsf-prepared-{}", std::process::id()));
+    let mut config = FilterConfig {
+        hrir: Some(HRIR.into()),
+        mix: 0.5,
+        ..FilterConfig::default()
+    };
+
+    let built = config
+        .build_with_cache(FilterBuilder::from_config(&config).unwrap(), &cache)
+        .unwrap();
+    let saved = std::fs::read(&cache).unwrap();
+    let loaded = config
+        .build_with_cache(FilterBuilder::from_config(&config).unwrap(), &cache)
+        .unwrap();
+    assert_eq!(loaded.mix(), 0.5);
+    assert_eq!(loaded.positions().count(), built.positions().count());
+    assert_eq!(std::fs::read(&cache).unwrap(), saved);
+
+    // settings which change the impulse responses replace it
+    config.minimum_phase = true;
+    config
+        .build_with_cache(FilterBuilder::from_config(&config).unwrap(), &cache)
+        .unwrap();
+    assert_ne!(std::fs::read(&cache).unwrap(), saved);
+
+    std::fs::remove_file(&cache).unwrap();
+}
+
+#[test]
+fn picks_the_first_compiled_in_engine() {
+    let config: FilterConfig = toml::from_str(&format!(
+        r#"
+hrir = "{}"
+engines = ["fftw", "rustfft-f64", "rustfft"]
+"#,
+        HRIR
+    ))
+    .unwrap();
+    assert!(config.build().is_ok());
+    assert_eq!(
+        Engine::preferred(&["fftw", "rustfft-f64", "rustfft"])
+            .unwrap()
+            .name(),
+        "rustfft-f64"
+    );
+
+    let missing = FilterConfig {
+        engines: vec!["fftw".to_string()],
+        ..config
+    };
+    assert!(missing.build().is_err());
+}
+
+#[test]
+fn loads_profiles() {
+    let config: FilterConfig = toml::from_str(&format!(
+        r#"
+hrir = "{0}"
+profile = "second"
+
+[profiles]
+second = "{0}"
+"#,
+        HRIR
+    ))
+    .unwrap();
+
+    let filter = config.build().unwrap();
+    assert_eq!(filter.profile(), "second");
+    assert_eq!(filter.profiles().count(), 2);
+}
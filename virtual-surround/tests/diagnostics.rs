@@ -0,0 +1,163 @@
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fs::File;
+use std::io::Cursor;
+use std::sync::mpsc::channel;
+use virtual_surround::hrir::{EarLayout, Hrir};
+use virtual_surround::{Diagnostic, FilterBuilder, Speaker};
+
+#[test]
+fn kemar_has_nothing_to_report() {
+    let (sender, receiver) = channel();
+    FilterBuilder::new()
+        .diagnostics(sender)
+        .build(File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap())
+        .unwrap();
+
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![]);
+}
+
+/// wav with the left ear of the kemar speaker at each position, FL standing in for others
+fn left_ears(layout: &[Speaker]) -> Cursor<Vec<u8>> {
+    let file = File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap();
+    let hrir = Hrir::from_wav(file, EarLayout::Mirrored).unwrap();
+
+    let columns = layout
+        .iter()
+        .map(|x| {
+            let speaker = hrir.speakers.iter().find(|y| y.position == *x);
+            &speaker.unwrap_or(&hrir.speakers[0]).left
+        })
+        .collect::<Vec<_>>();
+
+    let mut wav = Cursor::new(vec![]);
+    let spec = WavSpec {
+        channels: layout.len() as u16,
+        sample_rate: hrir.sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::new(&mut wav, spec).unwrap();
+    for i in 0..hrir.ir_length() {
+        for column in &columns {
+            writer.write_sample(column[i]).unwrap();
+        }
+    }
+    writer.finalize().unwrap();
+    wav.set_position(0);
+    wav
+}
+
+#[test]
+fn missing_mirror_is_reported_instead_of_failing() {
+    // FL, FC and BL without their right side counterparts
+    let layout = [Speaker::FrontLeft, Speaker::FrontCenter, Speaker::BackLeft];
+    let builder = FilterBuilder::new().with_layout(&layout);
+    assert!(builder.build(left_ears(&layout)).is_err());
+
+    let (sender, receiver) = channel();
+    let filter = builder
+        .diagnostics(sender)
+        .build(left_ears(&layout))
+        .unwrap();
+    assert_eq!(filter.channels(), 3);
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![
+            Diagnostic::MissingMirror {
+                speaker: Speaker::FrontLeft
+            },
+            Diagnostic::MissingMirror {
+                speaker: Speaker::BackLeft
+            },
+        ]
+    );
+}
+
+#[test]
+fn unusable_channels_are_skipped() {
+    let layout = [
+        Speaker::FrontLeft,
+        Speaker::FrontRight,
+        Speaker::DirectOut,
+        Speaker::FrontCenter,
+        Speaker::SideLeft,
+    ];
+    let builder = FilterBuilder::new().with_layout(&layout);
+    assert!(builder.build(left_ears(&layout)).is_err());
+
+    let (sender, receiver) = channel();
+    let filter = builder
+        .skip_unusable_channels(true)
+        .diagnostics(sender)
+        .build(left_ears(&layout))
+        .unwrap();
+    assert_eq!(
+        filter.positions().collect::<Vec<_>>(),
+        vec![
+            Speaker::FrontLeft,
+            Speaker::FrontRight,
+            Speaker::FrontCenter
+        ]
+    );
+    assert_eq!(
+        receiver.try_iter().collect::<Vec<_>>(),
+        vec![
+            Diagnostic::SkippedChannel {
+                channel: 2,
+                position: Speaker::DirectOut
+            },
+            Diagnostic::SkippedChannel {
+                channel: 4,
+                position: Speaker::SideLeft
+            },
+        ]
+    );
+
+    // skipping without a sink is silent
+    let filter = FilterBuilder::new()
+        .with_layout(&layout)
+        .skip_unusable_channels(true)
+        .build(left_ears(&layout))
+        .unwrap();
+    assert_eq!(filter.channels(), 3);
+}
+
+#[test]
+fn leading_silence_is_trimmed() {
+    let file = File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap();
+    let kemar = Hrir::from_wav(file, EarLayout::Mirrored).unwrap();
+    let onsets = kemar.onsets();
+
+    let mut hrir = kemar.clone();
+    for speaker in &mut hrir.speakers {
+        speaker.left.splice(0..0, vec![0f32; 441]);
+        speaker.right.splice(0..0, vec![0f32; 441]);
+    }
+
+    let (sender, receiver) = channel();
+    let builder = FilterBuilder::new()
+        .trim_leading_silence(true)
+        .diagnostics(sender);
+    let trimmed = builder.build_from_hrir(hrir.clone()).unwrap();
+    let padded = FilterBuilder::new().build_from_hrir(hrir.clone()).unwrap();
+    assert!(trimmed.ir_delay() + 400 < padded.ir_delay());
+
+    let reports = receiver.try_iter().collect::<Vec<_>>();
+    assert!(matches!(reports[0], Diagnostic::LeadingSilence { .. }));
+    match reports[1] {
+        Diagnostic::TrimmedSilence { ms } => assert!(ms > 9.0 && ms <= 10.0),
+        ref x => panic!("{:?}", x),
+    }
+
+    // the delays between the ears and speakers stay
+    let cut = hrir.trim_leading_silence();
+    let shift = 441 - cut;
+    assert_eq!(
+        hrir.onsets(),
+        onsets
+            .iter()
+            .map(|(l, r)| (l + shift, r + shift))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(hrir.trim_leading_silence(), 0);
+}
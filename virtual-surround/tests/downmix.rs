@@ -0,0 +1,124 @@
+use virtual_surround::{MixingMatrix, Speaker, VirtualSurroundFilter};
+
+mod common;
+
+use common::filter;
+
+const LAYOUT_7_1: [Speaker; 8] = [
+    Speaker::FrontLeft,
+    Speaker::FrontRight,
+    Speaker::FrontCenter,
+    Speaker::LowFrequency,
+    Speaker::BackLeft,
+    Speaker::BackRight,
+    Speaker::SideLeft,
+    Speaker::SideRight,
+];
+
+/// output of an impulse on `column` of input with `channels` per frame
+fn render(filter: &mut VirtualSurroundFilter, channels: usize, column: usize) -> Vec<f32> {
+    let block = filter.block_size();
+    let mut output = vec![];
+
+    for index in 0..filter.samples_required() / block * 2 {
+        let mut input = vec![0f32; block * channels];
+        if index == 0 {
+            input[column] = 0.5;
+        }
+
+        let mut out = vec![0f32; block * 2];
+        filter.transform(&input, &mut out).unwrap();
+        output.extend(out);
+    }
+
+    output
+}
+
+#[test]
+fn side_channels_fold_into_the_back() {
+    let mut downmixed = filter();
+    let positions = downmixed.positions().collect::<Vec<_>>();
+    downmixed
+        .set_mixing_matrix(Some(MixingMatrix::automatic(&LAYOUT_7_1, &positions)))
+        .unwrap();
+    assert_eq!(downmixed.input_channels(), 8);
+
+    let mut filter = filter();
+    let back_left = positions
+        .iter()
+        .position(|x| *x == Speaker::BackLeft)
+        .unwrap();
+
+    let output = render(&mut downmixed, 8, 6);
+    assert!(output.iter().any(|x| *x != 0.0));
+    assert_eq!(output, render(&mut filter, 6, back_left));
+}
+
+#[test]
+fn matrix_has_to_end_at_the_hrir_speakers() {
+    let mut filter = filter();
+    let matrix = MixingMatrix::automatic(&LAYOUT_7_1, &LAYOUT_7_1);
+    assert!(filter.set_mixing_matrix(Some(matrix)).is_err());
+    assert_eq!(filter.input_channels(), 6);
+}
+
+#[test]
+fn input_layout_reorders_channels() {
+    let mut reordered = filter();
+    let mut layout = reordered.positions().collect::<Vec<_>>();
+    layout.reverse();
+    reordered.set_input_layout(&layout).unwrap();
+    assert_eq!(reordered.input_layout(), layout);
+
+    let mut filter = filter();
+    let output = render(&mut reordered, 6, 0);
+    assert!(output.iter().any(|x| *x != 0.0));
+    assert_eq!(output, render(&mut filter, 6, 5));
+
+    assert!(filter
+        .set_input_layout(&[Speaker::FrontLeft, Speaker::FrontLeft])
+        .is_err());
+    assert!(filter
+        .set_input_layout(&[Speaker::FrontLeft, Speaker::SideLeft])
+        .is_err());
+}
+
+#[test]
+fn mixing_gains_ramp() {
+    // largest jump between two left samples after fading the FL input out
+    let largest_step = |ramp_ms: f32| {
+        let mut filter = filter();
+        let positions = filter.positions().collect::<Vec<_>>();
+        filter
+            .set_mixing_matrix(Some(MixingMatrix::automatic(&positions, &positions)))
+            .unwrap();
+        filter.set_ramp_time(ramp_ms);
+
+        let block = filter.block_size();
+        let blocks = filter.samples_required() / block + 2;
+        let input = (0..block * 6)
+            .map(|x| if x % 6 == 0 { 0.5 } else { 0.0 })
+            .collect::<Vec<_>>();
+        let mut output = vec![0f32; block * 2];
+        for _ in 0..blocks {
+            filter.transform(&input, &mut output).unwrap();
+        }
+
+        let mut left = vec![output[output.len() - 2]];
+        filter.set_mixing_gain(0, 0, 0.0).unwrap();
+        for _ in 0..blocks {
+            filter.transform(&input, &mut output).unwrap();
+            left.extend(output.iter().step_by(2));
+        }
+
+        assert!(left[left.len() - block..].iter().all(|x| x.abs() < 1e-6));
+        left.windows(2)
+            .map(|x| (x[1] - x[0]).abs())
+            .fold(0f32, f32::max)
+    };
+
+    assert!(largest_step(20.0) * 4.0 < largest_step(0.0));
+
+    let mut filter = filter();
+    assert!(filter.set_mixing_gain(0, 0, 1.0).is_err());
+}
@@ -0,0 +1,120 @@
+#![cfg(feature = "assert-no-alloc")]
+
+use assert_no_alloc::AllocDisabler;
+use std::fs::File;
+use virtual_surround::hrtf::SphericalHead;
+use virtual_surround::{
+    BinauralScene, ConvolutionStrategy, SourcePosition, Speaker, Upmix, VsfProcessor,
+};
+
+mod common;
+
+use common::{filter, noise, HRIR};
+
+// aborts the test binary when a block allocates
+#[global_allocator]
+static ALLOCATOR: AllocDisabler = AllocDisabler;
+
+#[test]
+fn renders_without_allocating() {
+    let mut filter = filter();
+    filter
+        .add_profile("again", File::open(HRIR).unwrap())
+        .unwrap();
+    let channels = filter.input_channels();
+    let block = filter.block_size();
+    let input = noise(channels, block);
+    let planar = input.chunks_exact(block).take(channels).collect::<Vec<_>>();
+    let pcm = input
+        .iter()
+        .map(|x| (x * i16::MAX as f32) as i16)
+        .collect::<Vec<_>>();
+    let mut output = vec![0f32; block * 2];
+    let mut pcm_output = vec![0i16; block * 2];
+    let (mut left, mut right) = (vec![0f32; block], vec![0f32; block]);
+
+    // long enough to prime the window and crossfade between the profiles
+    for index in 0..32 {
+        if index == 12 {
+            filter.set_profile("again").unwrap();
+        }
+
+        filter.transform(&input, &mut output).unwrap();
+        filter
+            .transform_planar(&planar, &mut left, &mut right)
+            .unwrap();
+        filter.transform_i16(&pcm, &mut pcm_output).unwrap();
+    }
+
+    filter.set_upmix(Some(Upmix::default()));
+    for _ in 0..4 {
+        filter
+            .transform_stereo(&input[..block * 2], &mut output)
+            .unwrap();
+        filter.transform_mono(&input[..block], &mut output).unwrap();
+    }
+
+    while filter.drain(&mut output).unwrap() > 0 {}
+}
+
+#[test]
+fn passes_channels_through_without_allocating() {
+    let mut filter = filter();
+    filter
+        .set_virtualized_channels(Some(&[Speaker::BackLeft, Speaker::BackRight]))
+        .unwrap();
+    let block = filter.block_size();
+    let input = noise(filter.input_channels(), block);
+    let mut output = vec![0f32; block * filter.passthrough_positions().len()];
+
+    for _ in 0..16 {
+        filter.transform_passthrough(&input, &mut output).unwrap();
+    }
+}
+
+#[test]
+fn processor_swaps_filters_without_allocating() {
+    let (mut processor, mut controller) = VsfProcessor::new(filter());
+    let channels = processor.filter().input_channels();
+    let block = processor.filter().block_size();
+    let input = noise(channels, block);
+    let mut output = vec![0f32; block * 2];
+
+    for index in 0..32 {
+        if index == 12 {
+            controller.swap_filter(filter()).unwrap();
+            controller.set_gain(-6.0).unwrap();
+        }
+
+        processor.transform(&input, &mut output).unwrap();
+    }
+}
+
+#[test]
+fn scene_renders_moving_sources_without_allocating() {
+    let mut scene = BinauralScene::new(
+        SphericalHead::new(8.75, 48000),
+        ConvolutionStrategy::OverlapSave.factory(),
+    )
+    .unwrap();
+    scene.set_doppler(true);
+    let sources = (0..4)
+        .map(|x| {
+            scene
+                .add_source(SourcePosition::new(x as f32 * 90.0, 0.0, 2.0))
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    let block = scene.block_size();
+    let input = noise(1, block);
+    let inputs = sources.iter().map(|x| (*x, &input[..])).collect::<Vec<_>>();
+    let (mut left, mut right) = (vec![0f32; block], vec![0f32; block]);
+
+    // a source approaching every block ramps its delay and level while rendering
+    for index in 0..16 {
+        scene
+            .set_position(sources[0], 0.0, 0.0, 2.0 - index as f32 * 0.1)
+            .unwrap();
+        scene.render(&inputs, &mut left, &mut right).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use std::fs::File;
+use virtual_surround::{FilterBuilder, Speaker, VirtualSurroundFilter};
+
+mod common;
+
+use common::HRIR;
+
+fn filter() -> VirtualSurroundFilter {
+    let mut filter = common::filter();
+
+    filter
+        .set_virtualized_channels(Some(&[Speaker::BackLeft, Speaker::BackRight]))
+        .unwrap();
+
+    filter
+}
+
+/// passthrough output of an impulse on `speaker` sent once the window is filled
+fn render(filter: &mut VirtualSurroundFilter, speaker: Speaker) -> Vec<f32> {
+    let channels = filter.channels();
+    let block = filter.block_size();
+    let column = filter.positions().position(|x| x == speaker).unwrap();
+    let outputs = filter.passthrough_positions().len();
+    let start = filter.samples_required() / block;
+    let mut output = vec![];
+
+    for index in 0..start * 3 {
+        let mut input = vec![0f32; block * channels];
+        if index == start {
+            input[column] = 0.5;
+        }
+
+        let mut out = vec![0f32; block * outputs];
+        filter.transform_passthrough(&input, &mut out).unwrap();
+        output.extend(out);
+    }
+
+    output
+}
+
+#[test]
+fn passes_front_channels_through() {
+    let mut filter = filter();
+    assert_eq!(
+        filter.passthrough_positions(),
+        [
+            Speaker::FrontLeft,
+            Speaker::FrontRight,
+            Speaker::FrontCenter,
+            Speaker::LowFrequency
+        ]
+    );
+
+    let output = render(&mut filter, Speaker::FrontCenter);
+    let nonzero = output
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| **x != 0.0)
+        .collect::<Vec<_>>();
+
+    // only the center column, untouched
+    assert_eq!(nonzero.len(), 1);
+    assert_eq!(nonzero[0].0 % 4, 2);
+    assert_eq!(*nonzero[0].1, 0.5);
+}
+
+#[test]
+fn virtualized_channels_land_in_the_front_pair() {
+    let mut filter = filter();
+    let output = render(&mut filter, Speaker::BackLeft);
+
+    let energy = |column: usize| {
+        output
+            .iter()
+            .skip(column)
+            .step_by(4)
+            .map(|x| x * x)
+            .sum::<f32>()
+    };
+
+    assert!(energy(0) > 0.0);
+    assert!(energy(1) > 0.0);
+    assert_eq!(energy(2), 0.0);
+    assert_eq!(energy(3), 0.0);
+}
+
+#[test]
+fn front_pair_has_to_pass_through() {
+    let mut filter = FilterBuilder::new()
+        .build(File::open(HRIR).unwrap())
+        .unwrap();
+
+    let block = filter.block_size();
+    let input = vec![0f32; block * filter.channels()];
+    let mut output = vec![0f32; block * 2];
+    assert!(filter.transform_passthrough(&input, &mut output).is_err());
+
+    assert!(filter
+        .set_virtualized_channels(Some(&[Speaker::FrontLeft]))
+        .is_err());
+    assert!(filter
+        .set_virtualized_channels(Some(&[Speaker::TopCenter]))
+        .is_err());
+    assert!(filter.set_virtualized_channels(None).is_ok());
+    assert!(filter.passthrough_positions().is_empty());
+}
@@ -0,0 +1,161 @@
+use std::fs::File;
+use virtual_surround::hrir::Hrir;
+use virtual_surround::{
+    ConvolutionStrategy, FilterBuilder, RawVirtualSurroundFilter, VirtualSurroundFilter,
+    VsfProcessor, DEFAULT_PROFILE,
+};
+
+mod common;
+
+use common::{filter, noise, HRIR};
+
+/// the KEMAR with the ears swapped, prepared
+fn swapped() -> Hrir {
+    let mut hrir = FilterBuilder::new()
+        .load_hrir(File::open(HRIR).unwrap())
+        .unwrap();
+    for speaker in &mut hrir.speakers {
+        std::mem::swap(&mut speaker.left, &mut speaker.right);
+    }
+
+    hrir
+}
+
+#[test]
+fn switches_to_another_profile() {
+    let mut reference = filter();
+    let mut filter = filter();
+    filter.add_profile_from_hrir("swapped", swapped()).unwrap();
+    assert_eq!(
+        filter.profiles().collect::<Vec<_>>(),
+        [DEFAULT_PROFILE, "swapped"]
+    );
+
+    let mut expected = VirtualSurroundFilter::from_raw(
+        RawVirtualSurroundFilter::from_hrir_with_engine(
+            &swapped(),
+            ConvolutionStrategy::default().factory(),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+
+    let channels = filter.channels();
+    let block = filter.block_size();
+    let window = filter.samples_required() / block;
+    let input = noise(channels, filter.samples_required() * 6);
+    let mut output = vec![0f32; block * 2];
+    let mut before = vec![0f32; block * 2];
+    let mut after = vec![0f32; block * 2];
+
+    for (index, chunk) in input.chunks_exact(block * channels).enumerate() {
+        if index == window * 2 {
+            filter.set_profile("swapped").unwrap();
+            assert_eq!(filter.profile(), "swapped");
+        }
+
+        filter.transform(chunk, &mut output).unwrap();
+        reference.transform(chunk, &mut before).unwrap();
+        expected.transform(chunk, &mut after).unwrap();
+
+        // the profile switched from renders until the new one caught up, then they're crossfaded
+        if index < window * 3 - 1 {
+            assert_eq!(output, before);
+        } else if index > window * 3 {
+            assert!(output.iter().zip(&after).all(|(x, y)| (x - y).abs() < 1e-5));
+        }
+    }
+
+    filter.set_profile(DEFAULT_PROFILE).unwrap();
+    assert!(filter.set_profile("other").is_err());
+}
+
+#[test]
+fn keeps_the_tail_of_longer_profiles() {
+    let mut filter = filter();
+
+    // an echo after the impulse responses of the filter end, still inside its window
+    let mut longer = swapped();
+    let length = filter.samples_required() - filter.block_size() - 1;
+    for speaker in &mut longer.speakers {
+        for ir in [&mut speaker.left, &mut speaker.right] {
+            let direct = ir.clone();
+            ir.resize(length, 0.0);
+            for (sample, echo) in ir[length - direct.len()..].iter_mut().zip(&direct) {
+                *sample += echo * 0.5;
+            }
+        }
+    }
+    filter
+        .add_profile_from_hrir("longer", longer.clone())
+        .unwrap();
+    filter.set_profile("longer").unwrap();
+
+    let mut expected = VirtualSurroundFilter::from_raw(
+        RawVirtualSurroundFilter::from_hrir_with_engine(
+            &longer,
+            ConvolutionStrategy::default().factory(),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(expected.samples_required(), filter.samples_required());
+
+    let channels = filter.channels();
+    let block = filter.block_size();
+    let window = filter.samples_required() / block;
+    let input = noise(channels, filter.samples_required() * 4);
+    let mut output = vec![0f32; block * 2];
+    let mut after = vec![0f32; block * 2];
+
+    for (index, chunk) in input.chunks_exact(block * channels).enumerate() {
+        filter.transform(chunk, &mut output).unwrap();
+        expected.transform(chunk, &mut after).unwrap();
+
+        if index > window * 2 {
+            assert!(output.iter().zip(&after).all(|(x, y)| (x - y).abs() < 1e-5));
+        }
+    }
+}
+
+#[test]
+fn rejects_profiles_which_dont_fit() {
+    let mut filter = filter();
+    filter
+        .add_profile("kemar", File::open(HRIR).unwrap())
+        .unwrap();
+    assert!(filter.add_profile_from_hrir("kemar", swapped()).is_err());
+
+    let mut missing = swapped();
+    missing.speakers.pop();
+    assert!(filter.add_profile_from_hrir("missing", missing).is_err());
+
+    let mut longer = swapped();
+    for speaker in &mut longer.speakers {
+        speaker.left.resize(filter.samples_required(), 0.0);
+        speaker.right.resize(filter.samples_required(), 0.0);
+    }
+    assert!(filter.add_profile_from_hrir("longer", longer).is_err());
+}
+
+#[test]
+fn controller_switches_profiles() {
+    let mut filter = filter();
+    filter.add_profile_from_hrir("swapped", swapped()).unwrap();
+    let channels = filter.channels();
+    let block = filter.block_size();
+    let (mut processor, mut controller) = VsfProcessor::new(filter);
+
+    assert_eq!(
+        controller.profiles().collect::<Vec<_>>(),
+        [DEFAULT_PROFILE, "swapped"]
+    );
+    assert!(controller.set_profile("other").is_err());
+    controller.set_profile("swapped").unwrap();
+
+    let mut output = vec![0f32; block * 2];
+    processor
+        .transform(&noise(channels, block), &mut output)
+        .unwrap();
+    assert_eq!(processor.filter().profile(), "swapped");
+}
@@ -0,0 +1,240 @@
+use std::fs::File;
+use virtual_surround::{
+    FilterBuilder, Orientation, ProcessStatus, VirtualSurroundFilter, VsfProcessor,
+};
+
+mod common;
+
+use common::{filter, noise, HRIR};
+
+#[test]
+fn gain_ramps_to_the_target() {
+    let mut plain = filter();
+    let (mut processor, mut controller) = VsfProcessor::new(filter());
+    let channels = plain.input_channels();
+    let block = plain.block_size();
+    let input = noise(channels, block * 8);
+    let mut expected = vec![0f32; block * 2];
+    let mut output = vec![0f32; block * 2];
+
+    for (index, chunk) in input.chunks_exact(block * channels).enumerate() {
+        if index == 5 {
+            controller.set_gain(-20.0).unwrap();
+        }
+
+        plain.transform(chunk, &mut expected).unwrap();
+        processor.transform(chunk, &mut output).unwrap();
+
+        match index {
+            0..=4 => assert_eq!(output, expected),
+            5 => assert!((output[0] - expected[0] * (1.0 - 0.9 / block as f32)).abs() < 1e-6),
+            _ => assert!(output
+                .iter()
+                .zip(&expected)
+                .all(|(x, y)| (x - y * 0.1).abs() < 1e-6)),
+        }
+    }
+}
+
+#[test]
+fn orientation_turns_the_speakers() {
+    let mut plain = filter();
+    let (mut processor, mut controller) = VsfProcessor::new(filter());
+    let channels = plain.input_channels();
+    let block = plain.block_size();
+    let input = noise(channels, block * 8);
+    let mut expected = vec![0f32; block * 2];
+    let mut output = vec![0f32; block * 2];
+    let turned = Orientation::new(45.0, 0.0, 0.0);
+
+    for (index, chunk) in input.chunks_exact(block * channels).enumerate() {
+        if index == 5 {
+            plain.set_head_orientation(turned);
+            controller.set_head_orientation(turned).unwrap();
+        }
+
+        plain.transform(chunk, &mut expected).unwrap();
+        processor.transform(chunk, &mut output).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    assert_eq!(processor.filter().head_yaw(), 45.0);
+
+    // the yaw the head is at when recentering becomes the front
+    controller.recenter().unwrap();
+    controller
+        .set_head_orientation(Orientation::new(50.0, 0.0, 0.0))
+        .unwrap();
+    controller.set_head_orientation(turned).unwrap();
+    processor
+        .transform(&input[..block * channels], &mut output)
+        .unwrap();
+    assert!((processor.filter().head_yaw() + 5.0).abs() < 1e-4);
+}
+
+#[test]
+fn bypass_ramps_over_the_ramp_time() {
+    let ramp_time = |filter: &VirtualSurroundFilter| {
+        let block = filter.block_size() as f32;
+        block * 2.0 * 1000.0 / filter.sample_rate() as f32
+    };
+
+    let mut wet = filter();
+    let mut dry = filter();
+    dry.set_bypass(true);
+    let mut filter = FilterBuilder::new()
+        .ramp_time(ramp_time(&wet))
+        .build(File::open(HRIR).unwrap())
+        .unwrap();
+
+    let channels = wet.input_channels();
+    let block = wet.block_size();
+    let input = noise(channels, block * 8);
+    let mut expected = [vec![0f32; block * 2], vec![0f32; block * 2]];
+    let mut output = vec![0f32; block * 2];
+
+    for (index, chunk) in input.chunks_exact(block * channels).enumerate() {
+        if index == 5 {
+            filter.set_bypass(true);
+        }
+
+        wet.transform(chunk, &mut expected[0]).unwrap();
+        dry.transform(chunk, &mut expected[1]).unwrap();
+        filter.transform(chunk, &mut output).unwrap();
+
+        for (s, frame) in output.chunks_exact(2).enumerate() {
+            let mix = match index {
+                0..=4 => 1.0,
+                5 | 6 => 1.0 - ((index - 5) * block + s + 1) as f32 / (block * 2) as f32,
+                _ => 0.0,
+            };
+
+            for ear in 0..2 {
+                let blended =
+                    expected[0][s * 2 + ear] * mix + expected[1][s * 2 + ear] * (1.0 - mix);
+                assert!((frame[ear] - blended).abs() < 1e-5);
+            }
+        }
+    }
+}
+
+#[test]
+fn bypass_is_in_time_and_level_matched() {
+    use virtual_surround::LoudnessMeter;
+
+    let mut wet = filter();
+    let mut dry = filter();
+    dry.set_bypass(true);
+    dry.set_bypass_level_matching(true);
+    let channels = wet.input_channels();
+    let block = wet.block_size();
+    let rate = wet.sample_rate();
+
+    // a click on the front left, then noise on it for a few seconds
+    let click = wet.samples_required() * 2;
+    let frames = (rate * 4 / block + 1) * block;
+    let noise = noise(1, frames);
+    let mut input = vec![0f32; frames * channels];
+    input[click * channels] = 0.5;
+    for (frame, sample) in input
+        .chunks_exact_mut(channels)
+        .zip(&noise)
+        .skip(click + rate / 10)
+    {
+        frame[0] = *sample;
+    }
+
+    let mut outputs = [vec![], vec![]];
+    for chunk in input.chunks_exact(block * channels) {
+        for (filter, output) in [&mut wet, &mut dry].iter_mut().zip(&mut outputs) {
+            let mut out = vec![0f32; block * 2];
+            filter.transform(chunk, &mut out).unwrap();
+            output.extend(out);
+        }
+    }
+
+    // the click arrives in the downmix with the direct sound of the virtualized output
+    let peak = |output: &[f32]| {
+        (0..click + rate / 10)
+            .max_by(|x, y| output[x * 2].abs().total_cmp(&output[y * 2].abs()))
+            .unwrap()
+    };
+    assert_eq!(peak(&outputs[1]), click + wet.ir_delay());
+    assert!(peak(&outputs[0]).abs_diff(peak(&outputs[1])) < rate / 1000);
+
+    let loudness = |output: &[f32]| {
+        let mut meter = LoudnessMeter::new(rate);
+        let (left, right): (Vec<f32>, Vec<f32>) = output[output.len() / 2..]
+            .chunks_exact(2)
+            .map(|x| (x[0], x[1]))
+            .unzip();
+        meter.process(&left, &right);
+        meter.integrated()
+    };
+    assert!((loudness(&outputs[0]) - loudness(&outputs[1])).abs() < 1.0);
+    assert!(dry.bypass_gain_db().unwrap().abs() > 0.5);
+}
+
+#[test]
+fn swapped_filter_takes_over_once_primed() {
+    let (mut processor, mut controller) = VsfProcessor::new(filter());
+    let channels = processor.filter().input_channels();
+    let block = processor.filter().block_size();
+    let blocks = processor.filter().samples_required() / block;
+    let input = noise(channels, block * (blocks * 3));
+    let mut output = vec![0f32; block * 2];
+
+    let mut bypassed = filter();
+    bypassed.set_bypass(true);
+    let mut reference = filter();
+    reference.set_bypass(true);
+    let mut expected = vec![0f32; block * 2];
+
+    let chunks = input.chunks_exact(block * channels).collect::<Vec<_>>();
+    for chunk in &chunks[..blocks] {
+        processor.transform(chunk, &mut output).unwrap();
+    }
+
+    controller.swap_filter(bypassed).unwrap();
+    for (index, chunk) in chunks[blocks..].iter().enumerate() {
+        let status = processor.transform(chunk, &mut output).unwrap();
+        assert_eq!(status, ProcessStatus::Rendered);
+        reference.transform(chunk, &mut expected).unwrap();
+
+        if index >= blocks {
+            assert_eq!(output, expected);
+        }
+    }
+
+    assert!(processor.filter().bypass());
+    assert_eq!(controller.collect(), 1);
+
+    let mut mono = FilterBuilder::new()
+        .build(File::open(HRIR).unwrap())
+        .unwrap();
+    mono.set_input_layout(&[virtual_surround::Speaker::FrontCenter])
+        .unwrap();
+    assert!(controller.swap_filter(mono).is_err());
+}
+
+#[test]
+fn times_the_rendered_blocks() {
+    let mut filter = filter();
+    let load = filter.dsp_load();
+    let channels = filter.input_channels();
+    let block = filter.block_size();
+    let blocks = filter.samples_required() / block * 2;
+    let mut output = vec![0f32; block * 2];
+
+    for chunk in noise(channels, block * blocks).chunks_exact(block * channels) {
+        filter.transform(chunk, &mut output).unwrap();
+    }
+
+    let snapshot = load.snapshot();
+    assert!(snapshot.blocks > 0 && snapshot.blocks <= blocks as u64);
+    assert!(snapshot.min <= snapshot.mean && snapshot.mean <= snapshot.max);
+    assert!(snapshot.p99 <= snapshot.max);
+    let budget = block as f32 / filter.sample_rate() as f32;
+    assert!((snapshot.budget.as_secs_f32() - budget).abs() < 1e-6);
+    assert_eq!(filter.dsp_load().snapshot().blocks, 0);
+}
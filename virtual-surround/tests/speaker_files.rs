@@ -0,0 +1,64 @@
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fs::File;
+use std::path::Path;
+use virtual_surround::get_channel_name;
+use virtual_surround::hrir::{EarLayout, Hrir};
+
+fn write(path: &Path, sample_rate: u32, ears: &[&[f32]]) {
+    let spec = WavSpec {
+        channels: ears.len() as u16,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(path, spec).unwrap();
+    for i in 0..ears[0].len() {
+        for ear in ears {
+            writer.write_sample(ear[i]).unwrap();
+        }
+    }
+    writer.finalize().unwrap();
+}
+
+#[test]
+fn loads_a_wav_per_speaker() {
+    let file = File::open("../resources/hrir_kemar/hrir-kemar.wav").unwrap();
+    let hrir = Hrir::from_wav(file, EarLayout::Mirrored).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("vsf-speaker-files-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("README.txt"), "not an HRIR").unwrap();
+
+    for speaker in &hrir.speakers {
+        let path = dir.join(format!("{}.wav", get_channel_name(speaker.position)));
+        // only the left ear of the left speakers, the right ear comes from the mirrored one
+        if get_channel_name(speaker.position).ends_with('L') {
+            write(&path, hrir.sample_rate, &[&speaker.left]);
+        } else {
+            write(&path, hrir.sample_rate, &[&speaker.left, &speaker.right]);
+        }
+    }
+
+    let loaded = Hrir::from_dir(&dir);
+    let bad_rate = {
+        write(&dir.join("FC.wav"), 48000, &[&hrir.speakers[2].left]);
+        Hrir::from_dir(&dir)
+    };
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let loaded = loaded.unwrap();
+    assert_eq!(loaded.sample_rate, hrir.sample_rate);
+    assert_eq!(loaded.speakers.len(), hrir.speakers.len());
+    for speaker in &hrir.speakers {
+        let other = loaded
+            .speakers
+            .iter()
+            .find(|x| x.position == speaker.position)
+            .unwrap();
+        assert_eq!(other.left, speaker.left);
+        assert_eq!(other.right, speaker.right);
+    }
+
+    assert!(bad_rate.is_err());
+    assert!(Hrir::from_files(&["../resources/hrir_kemar/hrir-kemar.wav"]).is_err());
+}